@@ -1,7 +1,7 @@
 "use client";
 
 import { useRouter } from "next/navigation";
-import { useEffect, useState } from "react";
+import { useEffect, useRef, useState } from "react";
 import { Badge } from "@/components/ui/badge";
 import { Button } from "@/components/ui/button";
 import { Card, CardContent, CardDescription, CardHeader, CardTitle } from "@/components/ui/card";
@@ -21,6 +21,7 @@ export default function GeneratePage() {
   const [uploadsLoading, setUploadsLoading] = useState(false);
   const [uploadsOffset, setUploadsOffset] = useState(0);
   const [uploadsHasMore, setUploadsHasMore] = useState(true);
+  const lastEventId = useRef<number | null>(null);
 
   useEffect(() => {
     if (!getToken()) {
@@ -79,40 +80,82 @@ export default function GeneratePage() {
     }
 
     const wsBase = base.replace(/^https?/i, (match) => (match === "https" ? "wss" : "ws"));
-    const socket = new WebSocket(`${wsBase}/ws/uploads?token=${encodeURIComponent(token)}`);
+    let socket: WebSocket | null = null;
+    let reconnectTimer: ReturnType<typeof setTimeout> | null = null;
+    let closed = false;
 
-    socket.onmessage = (event) => {
-      try {
-        const payload = JSON.parse(event.data);
-        if (payload?.event !== "upload.updated" || !payload.data?.id) {
-          return;
+    const applyUpload = (item: (typeof uploads)[number]) => {
+      if (lastUploadId && item.id === lastUploadId) {
+        if (item.status === "ready") {
+          setStatus("Video is ready. You can download it below.");
+          setStatusTone("success");
+        } else if (item.status === "failed") {
+          setStatus("Processing failed. Please try another link.");
+          setStatusTone("error");
         }
-        const item = payload.data;
-        if (lastUploadId && item.id === lastUploadId) {
-          if (item.status === "ready") {
-            setStatus("Video is ready. You can download it below.");
-            setStatusTone("success");
-          } else if (item.status === "failed") {
-            setStatus("Processing failed. Please try another link.");
-            setStatusTone("error");
-          }
+      }
+      setUploads((prev) => {
+        const idx = prev.findIndex((row) => row.id === item.id);
+        if (idx === -1) {
+          return [item, ...prev];
         }
-        setUploads((prev) => {
-          const idx = prev.findIndex((row) => row.id === item.id);
-          if (idx === -1) {
-            return [item, ...prev];
+        const next = [...prev];
+        next[idx] = { ...next[idx], ...item };
+        return next;
+      });
+    };
+
+    const connect = () => {
+      socket = new WebSocket(`${wsBase}/ws/uploads`);
+
+      // The token goes in the first message, not in the URL
+      socket.onopen = () => {
+        socket?.send(
+          JSON.stringify({
+            type: "auth",
+            request_id: "auth",
+            token,
+            ...(lastEventId.current === null ? {} : { last_event_id: lastEventId.current }),
+          })
+        );
+        socket?.send(JSON.stringify({ type: "subscribe", request_id: "subscribe", topics: ["uploads"] }));
+      };
+
+      socket.onmessage = (event) => {
+        try {
+          const payload = JSON.parse(event.data);
+          if (typeof payload?.id === "number") {
+            lastEventId.current = Math.max(lastEventId.current ?? 0, payload.id);
           }
-          const next = [...prev];
-          next[idx] = { ...next[idx], ...item };
-          return next;
-        });
-      } catch {
-        return;
-      }
+          if (payload?.event === "snapshot") {
+            (payload.data?.uploads ?? []).forEach(applyUpload);
+            return;
+          }
+          if (payload?.event !== "upload.updated" || !payload.data?.id) {
+            return;
+          }
+          applyUpload(payload.data);
+        } catch {
+          return;
+        }
+      };
+
+      // Missed events are replayed by the server from last_event_id
+      socket.onclose = () => {
+        if (!closed) {
+          reconnectTimer = setTimeout(connect, 2000);
+        }
+      };
     };
 
+    connect();
+
     return () => {
-      socket.close();
+      closed = true;
+      if (reconnectTimer) {
+        clearTimeout(reconnectTimer);
+      }
+      socket?.close();
     };
   }, [lastUploadId]);
 
@@ -130,8 +173,13 @@ export default function GeneratePage() {
       const result = await uploadVideo({
         url: url.trim(),
       });
-      setStatus(`Processing started. Task ID: ${result.task_id}`);
-      setStatusTone(null);
+      if (result.cached) {
+        setStatus("This video was already processed. You can download it below.");
+        setStatusTone("success");
+      } else {
+        setStatus("Queued for processing. The status below updates automatically.");
+        setStatusTone(null);
+      }
       setLastUploadId(result.upload_id);
       refreshUploads();
     } catch (err) {
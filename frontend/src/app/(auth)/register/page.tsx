@@ -22,7 +22,13 @@ export default function RegisterPage() {
     setError(null);
     setLoading(true);
     try {
-      const result = await register({ email, password, username: username || undefined });
+      const result = await register({
+        email,
+        password,
+        username: username || undefined,
+        // Email language; the server falls back to its default for unsupported ones
+        locale: navigator.language,
+      });
       if (result.token) {
         setToken(result.token);
         router.push("/dashboard");
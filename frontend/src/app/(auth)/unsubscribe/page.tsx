@@ -0,0 +1,76 @@
+"use client";
+
+import { Suspense, useState } from "react";
+import { useSearchParams } from "next/navigation";
+import { Button } from "@/components/ui/button";
+import { Card, CardContent, CardDescription, CardHeader, CardTitle } from "@/components/ui/card";
+import { unsubscribeEmail } from "@/lib/api";
+
+const CATEGORY_LABELS: Record<string, string> = {
+  upload_completed: "video ready notifications",
+  upload_failed: "processing failure notifications",
+  subscription_renewed: "subscription renewal notifications",
+  low_balance: "low balance reminders",
+};
+
+function UnsubscribeContent() {
+  const searchParams = useSearchParams();
+  const token = searchParams.get("token");
+  const category = searchParams.get("category");
+  const [status, setStatus] = useState<string | null>(null);
+  const [error, setError] = useState<string | null>(null);
+  const [loading, setLoading] = useState(false);
+
+  const label = (category && CATEGORY_LABELS[category]) || "all optional emails";
+
+  // Only on click: mail scanners open links from emails on their own
+  const handleUnsubscribe = async () => {
+    if (!token) {
+      return;
+    }
+    setLoading(true);
+    setError(null);
+    try {
+      await unsubscribeEmail(token, category);
+      setStatus(`You will no longer receive ${label}.`);
+    } catch (err) {
+      setError(err instanceof Error ? err.message : "Request failed");
+    } finally {
+      setLoading(false);
+    }
+  };
+
+  return (
+    <Card className="border-border/60 bg-white/80">
+      <CardHeader>
+        <CardTitle className="text-2xl font-[var(--font-display)]">Unsubscribe</CardTitle>
+        <CardDescription>
+          Receipts and important subscription emails are always sent.
+        </CardDescription>
+      </CardHeader>
+      <CardContent className="space-y-4">
+        {!token ? (
+          <p className="text-sm text-destructive">This unsubscribe link is invalid.</p>
+        ) : status ? (
+          <p className="text-sm text-foreground">{status}</p>
+        ) : (
+          <>
+            <p className="text-sm text-muted-foreground">Stop receiving {label}?</p>
+            {error && <p className="text-sm text-destructive">{error}</p>}
+            <Button onClick={handleUnsubscribe} disabled={loading}>
+              {loading ? "Unsubscribing..." : "Unsubscribe"}
+            </Button>
+          </>
+        )}
+      </CardContent>
+    </Card>
+  );
+}
+
+export default function UnsubscribePage() {
+  return (
+    <Suspense fallback={<div className="text-sm text-muted-foreground">Loading...</div>}>
+      <UnsubscribeContent />
+    </Suspense>
+  );
+}
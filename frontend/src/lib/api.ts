@@ -39,7 +39,9 @@ export type AuthResponse = {
 export type UploadResponse = {
   message: string;
   upload_id: number;
-  task_id: string;
+  task_id: string | null;
+  cached: boolean;
+  cleaned_url: string | null;
 };
 
 export type CreditsStatus = {
@@ -102,6 +104,7 @@ export async function register(payload: {
   email: string;
   password: string;
   username?: string;
+  locale?: string;
 }): Promise<AuthResponse> {
   return apiFetch<AuthResponse>("/auth/register", {
     method: "POST",
@@ -127,6 +130,16 @@ export async function resendVerification(email: string) {
   });
 }
 
+export async function unsubscribeEmail(token: string, category?: string | null) {
+  const params = new URLSearchParams({ token });
+  if (category) {
+    params.set("category", category);
+  }
+  return apiFetch<{ ok: boolean; category: string }>("/email/unsubscribe?" + params.toString(), {
+    method: "POST",
+  });
+}
+
 export async function getProducts(): Promise<Product[]> {
   return apiFetch<Product[]>("/api/products", { auth: true });
 }
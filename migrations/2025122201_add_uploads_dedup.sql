@@ -0,0 +1,8 @@
+-- Source fingerprints for reusing already cleaned videos
+
+ALTER TABLE uploads
+    ADD COLUMN IF NOT EXISTS source_fingerprint VARCHAR(64),
+    ADD COLUMN IF NOT EXISTS cached_from_upload_id INTEGER REFERENCES uploads(id) ON DELETE SET NULL;
+
+CREATE INDEX IF NOT EXISTS idx_uploads_source_fingerprint
+    ON uploads(source_fingerprint) WHERE status = 'ready';
@@ -0,0 +1,4 @@
+-- Per-upload secret for authenticating KIE callbacks
+
+ALTER TABLE uploads
+    ADD COLUMN IF NOT EXISTS callback_token VARCHAR(64);
@@ -0,0 +1,6 @@
+-- Which watermark removal backend processed the upload
+
+ALTER TABLE uploads
+    ADD COLUMN IF NOT EXISTS provider VARCHAR(32);
+
+UPDATE uploads SET provider = 'kie' WHERE provider IS NULL AND task_id IS NOT NULL;
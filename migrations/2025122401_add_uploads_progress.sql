@@ -0,0 +1,4 @@
+-- Processing progress reported by the backend (percent), NULL if unknown
+
+ALTER TABLE uploads
+    ADD COLUMN IF NOT EXISTS progress SMALLINT;
@@ -0,0 +1,33 @@
+-- Processing profiles: KIE model, extra createTask input and credit cost of one upload
+CREATE TABLE IF NOT EXISTS processing_profiles (
+    id SERIAL PRIMARY KEY,
+    slug VARCHAR(50) UNIQUE NOT NULL,
+    name VARCHAR(255) NOT NULL,
+    description TEXT,
+    kie_model VARCHAR(100) NOT NULL,
+    kie_input JSONB NOT NULL DEFAULT '{}'::jsonb,             -- merged into createTask input
+    credit_cost INTEGER NOT NULL DEFAULT 1 CHECK (credit_cost > 0),
+    is_public BOOLEAN NOT NULL DEFAULT false,                 -- available without a plan
+    is_active BOOLEAN NOT NULL DEFAULT true,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Non-public profiles are available to subscribers of the linked products
+CREATE TABLE IF NOT EXISTS product_processing_profiles (
+    product_id INTEGER NOT NULL REFERENCES products(id) ON DELETE CASCADE,
+    profile_id INTEGER NOT NULL REFERENCES processing_profiles(id) ON DELETE CASCADE,
+    PRIMARY KEY (product_id, profile_id)
+);
+
+-- Profile used for the plan's uploads when none is requested
+ALTER TABLE products
+    ADD COLUMN IF NOT EXISTS default_profile_id INTEGER REFERENCES processing_profiles(id) ON DELETE SET NULL;
+
+INSERT INTO processing_profiles (slug, name, description, kie_model, credit_cost, is_public)
+VALUES ('standard', 'Standard', 'Sora watermark removal', 'sora-watermark-remover', 1, true)
+    ON CONFLICT (slug) DO NOTHING;
+
+ALTER TABLE uploads
+    ADD COLUMN IF NOT EXISTS processing_profile VARCHAR(50),
+    ADD COLUMN IF NOT EXISTS model VARCHAR(100),
+    ADD COLUMN IF NOT EXISTS credit_cost INTEGER NOT NULL DEFAULT 1;
@@ -0,0 +1,15 @@
+-- Uploads wait in status 'queued' until a worker hands them to a backend.
+-- source_url keeps the canonical user link: signed video URLs of some sources expire,
+-- so the submitter resolves it again right before creating the task
+
+ALTER TABLE uploads
+    ADD COLUMN IF NOT EXISTS source_kind VARCHAR(20),
+    ADD COLUMN IF NOT EXISTS source_url TEXT,
+    ADD COLUMN IF NOT EXISTS watermark_region VARCHAR(50),
+    ADD COLUMN IF NOT EXISTS submit_attempts INTEGER NOT NULL DEFAULT 0,
+    ADD COLUMN IF NOT EXISTS next_submit_at TIMESTAMP WITH TIME ZONE,
+    ADD COLUMN IF NOT EXISTS last_error TEXT;
+
+CREATE INDEX IF NOT EXISTS idx_uploads_queued_next_submit
+    ON uploads(next_submit_at)
+    WHERE status = 'queued';
@@ -0,0 +1,21 @@
+-- Job queue on Postgres (used when RabbitMQ is not configured)
+
+CREATE TABLE IF NOT EXISTS job_queue (
+    id BIGSERIAL PRIMARY KEY,
+    queue VARCHAR(100) NOT NULL,
+    payload JSONB NOT NULL,
+    -- Same key in the same queue is enqueued only once until the job is done
+    dedup_key VARCHAR(255),
+    attempts INTEGER NOT NULL DEFAULT 0,
+    run_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    -- Visibility timeout of a reserved job
+    locked_until TIMESTAMP WITH TIME ZONE,
+    last_error TEXT,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_job_queue_dedup
+    ON job_queue(queue, dedup_key)
+    WHERE dedup_key IS NOT NULL;
+
+CREATE INDEX IF NOT EXISTS idx_job_queue_ready ON job_queue(queue, run_at);
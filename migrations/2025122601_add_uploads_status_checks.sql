@@ -0,0 +1,16 @@
+-- Per-upload status polling schedule: the next check moves further out with every check,
+-- and a task stuck in processing longer than the maximum age times out
+
+ALTER TABLE uploads
+    ADD COLUMN IF NOT EXISTS processing_started_at TIMESTAMP WITH TIME ZONE,
+    ADD COLUMN IF NOT EXISTS status_checks INTEGER NOT NULL DEFAULT 0,
+    ADD COLUMN IF NOT EXISTS next_check_at TIMESTAMP WITH TIME ZONE;
+
+UPDATE uploads
+SET processing_started_at = COALESCE(processing_started_at, created_at),
+    next_check_at = COALESCE(next_check_at, NOW())
+WHERE status = 'processing';
+
+CREATE INDEX IF NOT EXISTS idx_uploads_processing_next_check
+    ON uploads(next_check_at)
+    WHERE status = 'processing';
@@ -0,0 +1,12 @@
+-- Log of WebSocket events: ids are monotonic, clients resume with last_event_id
+
+CREATE TABLE IF NOT EXISTS ws_events (
+    id BIGSERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    event VARCHAR(100) NOT NULL,
+    payload JSONB NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_ws_events_user_id ON ws_events(user_id, id);
+CREATE INDEX IF NOT EXISTS idx_ws_events_created_at ON ws_events(created_at);
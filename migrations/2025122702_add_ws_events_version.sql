@@ -0,0 +1,4 @@
+-- Schema version of logged events (the `version` field clients see)
+
+ALTER TABLE ws_events
+    ADD COLUMN IF NOT EXISTS version SMALLINT NOT NULL DEFAULT 1;
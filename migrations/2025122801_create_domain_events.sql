@@ -0,0 +1,31 @@
+-- Transactional outbox of domain events: written in the same transaction as the change,
+-- dispatched to subscribers (WebSocket, metrics, ...) by the outbox worker
+
+CREATE TABLE IF NOT EXISTS domain_events (
+    id BIGSERIAL PRIMARY KEY,
+    event_type VARCHAR(100) NOT NULL,
+    user_id INTEGER REFERENCES users(id) ON DELETE CASCADE,
+    payload JSONB NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    -- Not before this moment; also the lease of a claimed event
+    next_attempt_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    last_error TEXT,
+    -- All subscribers got the event
+    processed_at TIMESTAMP WITH TIME ZONE,
+    -- Gave up after OUTBOX_MAX_ATTEMPTS
+    dead_at TIMESTAMP WITH TIME ZONE,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_domain_events_pending
+    ON domain_events(next_attempt_at, id)
+    WHERE processed_at IS NULL AND dead_at IS NULL;
+CREATE INDEX IF NOT EXISTS idx_domain_events_created_at ON domain_events(created_at);
+
+-- Subscribers that already handled the event: a retry goes only to the ones that failed
+CREATE TABLE IF NOT EXISTS domain_event_deliveries (
+    event_id BIGINT NOT NULL REFERENCES domain_events(id) ON DELETE CASCADE,
+    subscriber VARCHAR(100) NOT NULL,
+    delivered_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (event_id, subscriber)
+);
@@ -0,0 +1,56 @@
+-- Outgoing webhooks registered by users: domain events are POSTed to their endpoints,
+-- signed with the endpoint secret
+
+CREATE TABLE IF NOT EXISTS webhook_endpoints (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    url TEXT NOT NULL,
+    -- HMAC-SHA256 key for X-Webhook-Signature
+    secret VARCHAR(100) NOT NULL,
+    -- Domain event types to deliver; empty means all
+    event_types TEXT[] NOT NULL DEFAULT '{}',
+    is_active BOOLEAN NOT NULL DEFAULT TRUE,
+    -- Failed attempts in a row; a successful delivery resets it
+    consecutive_failures INTEGER NOT NULL DEFAULT 0,
+    disabled_at TIMESTAMP WITH TIME ZONE,
+    disabled_reason TEXT,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_webhook_endpoints_user_id ON webhook_endpoints(user_id);
+
+-- One row per delivery of an event to an endpoint; also the delivery log shown to the user
+CREATE TABLE IF NOT EXISTS webhook_deliveries (
+    id BIGSERIAL PRIMARY KEY,
+    endpoint_id INTEGER NOT NULL REFERENCES webhook_endpoints(id) ON DELETE CASCADE,
+    -- domain_events.id; no foreign key, processed events are pruned earlier than the log
+    event_id BIGINT,
+    event_type VARCHAR(100) NOT NULL,
+    -- Exact JSON body that is sent and signed
+    payload JSONB NOT NULL,
+    -- pending, succeeded or failed
+    status VARCHAR(20) NOT NULL DEFAULT 'pending',
+    attempts INTEGER NOT NULL DEFAULT 0,
+    -- Not before this moment; also the lease of a claimed delivery
+    next_attempt_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    -- Last attempt: HTTP status, start of the response body, error and duration
+    response_status INTEGER,
+    response_body TEXT,
+    last_error TEXT,
+    duration_ms INTEGER,
+    -- Manual redelivery: the delivery it repeats
+    redelivery_of BIGINT REFERENCES webhook_deliveries(id) ON DELETE SET NULL,
+    delivered_at TIMESTAMP WITH TIME ZONE,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP
+);
+
+-- An event is queued for an endpoint once, even if the outbox hands it over again
+CREATE UNIQUE INDEX IF NOT EXISTS uq_webhook_deliveries_event
+    ON webhook_deliveries(endpoint_id, event_id)
+    WHERE event_id IS NOT NULL AND redelivery_of IS NULL;
+CREATE INDEX IF NOT EXISTS idx_webhook_deliveries_pending
+    ON webhook_deliveries(next_attempt_at, id)
+    WHERE status = 'pending';
+CREATE INDEX IF NOT EXISTS idx_webhook_deliveries_endpoint_id ON webhook_deliveries(endpoint_id, id);
+CREATE INDEX IF NOT EXISTS idx_webhook_deliveries_created_at ON webhook_deliveries(created_at);
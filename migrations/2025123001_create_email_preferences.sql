@@ -0,0 +1,14 @@
+-- Per-user settings for transactional email. Receipts, canceled subscriptions and failing
+-- subscription payments are always sent; the categories below can be turned off.
+-- A row is created on first use, missing row means everything is enabled.
+
+CREATE TABLE IF NOT EXISTS email_preferences (
+    user_id INTEGER PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    upload_completed BOOLEAN NOT NULL DEFAULT TRUE,
+    upload_failed BOOLEAN NOT NULL DEFAULT TRUE,
+    subscription_renewed BOOLEAN NOT NULL DEFAULT TRUE,
+    low_balance BOOLEAN NOT NULL DEFAULT TRUE,
+    -- Secret for one-click unsubscribe links; works without signing in
+    unsubscribe_token UUID NOT NULL UNIQUE,
+    updated_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP
+);
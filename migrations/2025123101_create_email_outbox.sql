@@ -0,0 +1,39 @@
+-- Outgoing email queue: emails are rendered and stored here (in the transaction of the change
+-- that triggers them, where possible) and sent by the email worker with retries
+
+CREATE TABLE IF NOT EXISTS email_outbox (
+    id BIGSERIAL PRIMARY KEY,
+    user_id INTEGER REFERENCES users(id) ON DELETE CASCADE,
+    -- domain_events.id the email was built from; no foreign key, events are pruned earlier
+    event_id BIGINT,
+    -- Template name: verify_email, receipt, upload_completed, ...
+    kind VARCHAR(50) NOT NULL,
+    locale VARCHAR(10) NOT NULL,
+    recipient VARCHAR(255) NOT NULL,
+    subject TEXT NOT NULL,
+    text_body TEXT NOT NULL,
+    html_body TEXT NOT NULL,
+    -- One-click unsubscribe target for the List-Unsubscribe header; only for optional emails
+    unsubscribe_url TEXT,
+    -- pending, sent or failed
+    status VARCHAR(20) NOT NULL DEFAULT 'pending',
+    attempts INTEGER NOT NULL DEFAULT 0,
+    -- Not before this moment; also the lease of a claimed email
+    next_attempt_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    last_error TEXT,
+    sent_at TIMESTAMP WITH TIME ZONE,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP
+);
+
+-- An event produces its email once, even if the outbox hands it over again
+CREATE UNIQUE INDEX IF NOT EXISTS uq_email_outbox_event
+    ON email_outbox(event_id, kind)
+    WHERE event_id IS NOT NULL;
+CREATE INDEX IF NOT EXISTS idx_email_outbox_pending
+    ON email_outbox(next_attempt_at, id)
+    WHERE status = 'pending';
+CREATE INDEX IF NOT EXISTS idx_email_outbox_created_at ON email_outbox(created_at);
+
+-- Language of emails (en, ru); NULL means EMAIL_DEFAULT_LOCALE
+ALTER TABLE users
+    ADD COLUMN IF NOT EXISTS locale VARCHAR(10);
@@ -8,9 +8,6 @@ use bcrypt::{DEFAULT_COST, hash, verify};
 use chrono::{Duration, Utc};
 use futures_util::future::{LocalBoxFuture, Ready, ready};
 use jsonwebtoken::{DecodingKey, EncodingKey, Header, Validation, decode, encode};
-use lettre::message::{Mailbox, Message};
-use lettre::transport::smtp::authentication::Credentials;
-use lettre::{AsyncSmtpTransport, AsyncTransport, Tokio1Executor};
 use serde::{Deserialize, Serialize};
 use sqlx::Row;
 use std::task::{Context, Poll};
@@ -18,6 +15,7 @@ use utoipa::ToSchema;
 use uuid::Uuid;
 
 use crate::AppState;
+use crate::emails::{self, templates};
 
 #[derive(Debug, Serialize, Deserialize)]
 struct Claims {
@@ -30,6 +28,8 @@ pub struct RegisterRequest {
     pub email: String,
     pub password: String,
     pub username: Option<String>,
+    /// Language of emails: `en` (default) or `ru`
+    pub locale: Option<String>,
 }
 
 #[derive(Debug, Deserialize, ToSchema)]
@@ -69,15 +69,27 @@ pub async fn register(
         }
     };
 
+    let mut tx = match state.pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => {
+            eprintln!("register db error: {e}");
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    let locale = payload
+        .locale
+        .as_deref()
+        .map(|l| templates::resolve_locale(Some(l)));
     let row = match sqlx::query(
-        r#"INSERT INTO users (username, email, password_hash, credits)
-           VALUES ($1, $2, $3, 0)
+        r#"INSERT INTO users (username, email, password_hash, credits, locale)
+           VALUES ($1, $2, $3, 0, $4)
            RETURNING id"#,
     )
     .bind(payload.username.as_deref())
     .bind(&payload.email)
     .bind(password_hash)
-    .fetch_one(&state.pool)
+    .bind(locale)
+    .fetch_one(&mut *tx)
     .await
     {
         Ok(r) => r,
@@ -91,9 +103,16 @@ pub async fn register(
 
     let user_id: i32 = row.get("id");
 
-    if let Err(e) = create_and_send_verification(&state.pool, user_id, &payload.email).await {
-        eprintln!("send verification error: {e}");
+    // Письмо ставится в очередь в той же транзакции: без него пользователь не создаётся
+    if let Err(e) = queue_verification(&mut tx, user_id, &payload.email, locale).await {
+        eprintln!("queue verification error: {e}");
+        return HttpResponse::InternalServerError().finish();
+    }
+    if let Err(e) = tx.commit().await {
+        eprintln!("register commit error: {e}");
+        return HttpResponse::InternalServerError().finish();
     }
+    state.email_wakeup.notify_one();
 
     HttpResponse::Ok().json(AuthResponse {
         token: None,
@@ -197,7 +216,7 @@ pub async fn resend_verification(
     payload: web::Json<ResendVerificationRequest>,
 ) -> impl Responder {
     let row = match sqlx::query(
-        r#"SELECT id, email_verified
+        r#"SELECT id, email_verified, locale
            FROM users
            WHERE email = $1"#,
     )
@@ -222,10 +241,18 @@ pub async fn resend_verification(
         return HttpResponse::Ok().json(serde_json::json!({"ok": true, "already_verified": true}));
     }
 
-    if let Err(e) = create_and_send_verification(&state.pool, user_id, &payload.email).await {
+    let locale: Option<String> = row.get("locale");
+    let result = async {
+        let mut tx = state.pool.begin().await.map_err(|e| e.to_string())?;
+        queue_verification(&mut tx, user_id, &payload.email, locale.as_deref()).await?;
+        tx.commit().await.map_err(|e| e.to_string())
+    }
+    .await;
+    if let Err(e) = result {
         eprintln!("resend verification error: {e}");
         return HttpResponse::InternalServerError().finish();
     }
+    state.email_wakeup.notify_one();
 
     HttpResponse::Ok().json(serde_json::json!({"ok": true}))
 }
@@ -314,17 +341,20 @@ pub async fn verify_email(
     HttpResponse::Ok().json(serde_json::json!({"ok": true}))
 }
 
-async fn create_and_send_verification(
-    pool: &sqlx::PgPool,
+/// Новый токен подтверждения (старые удаляются) и письмо со ссылкой в `email_outbox`.
+/// Отправит его воркер писем, с повторами; после коммита — `email_wakeup`.
+async fn queue_verification(
+    conn: &mut sqlx::PgConnection,
     user_id: i32,
     email: &str,
+    locale: Option<&str>,
 ) -> Result<(), String> {
     let token = Uuid::new_v4();
     let expires_at = Utc::now() + Duration::hours(24);
 
     let _ = sqlx::query("DELETE FROM email_verification_tokens WHERE user_id = $1")
         .bind(user_id)
-        .execute(pool)
+        .execute(&mut *conn)
         .await
         .map_err(|e| e.to_string())?;
 
@@ -335,69 +365,14 @@ async fn create_and_send_verification(
     .bind(token)
     .bind(user_id)
     .bind(expires_at)
-    .execute(pool)
+    .execute(&mut *conn)
     .await
     .map_err(|e| e.to_string())?;
 
     let app_base = std::env::var("APP_BASE_URL").map_err(|_| "APP_BASE_URL must be set".to_string())?;
     let verify_url = format!("{app_base}/verify-email?token={token}");
 
-    let smtp_host = std::env::var("SMTP_HOST").ok();
-    let smtp_user = std::env::var("SMTP_USER").ok();
-    let smtp_pass = std::env::var("SMTP_PASS").ok();
-    let smtp_from = std::env::var("SMTP_FROM").ok();
-    let smtp_port = std::env::var("SMTP_PORT").ok().and_then(|p| p.parse::<u16>().ok());
-
-    if smtp_host.is_none() || smtp_from.is_none() {
-        eprintln!("SMTP not configured. Verification link: {verify_url}");
-        return Ok(());
-    }
-
-    let from: Mailbox = smtp_from
-        .clone()
-        .unwrap()
-        .parse::<Mailbox>()
-        .map_err(|e| e.to_string())?;
-    let to: Mailbox = email.parse::<Mailbox>().map_err(|e| e.to_string())?;
-
-    let email_message = Message::builder()
-        .from(from)
-        .to(to)
-        .subject("Confirm your email for Sora Clean")
-        .body(format!(
-            "Welcome to Sora Clean!\n\nPlease confirm your email by clicking the link:\n{verify_url}\n\nIf you did not request this, you can ignore this email."
-        ))
-        .map_err(|e| e.to_string())?;
-
-    let smtp_port = smtp_port.unwrap_or(587);
-    let smtp_security = std::env::var("SMTP_SECURITY").unwrap_or_else(|_| {
-        if smtp_port == 465 {
-            "ssl".to_string()
-        } else {
-            "starttls".to_string()
-        }
-    });
-
-    let mut builder = match smtp_security.as_str() {
-        "ssl" => AsyncSmtpTransport::<Tokio1Executor>::relay(&smtp_host.clone().unwrap())
-            .map_err(|e| e.to_string())?,
-        "none" => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(
-            smtp_host.clone().unwrap(),
-        ),
-        _ => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&smtp_host.clone().unwrap())
-            .map_err(|e| e.to_string())?,
-    };
-    builder = builder.port(smtp_port);
-
-    let mailer = if let (Some(user), Some(pass)) = (smtp_user, smtp_pass) {
-        let creds = Credentials::new(user, pass);
-        builder.credentials(creds).build()
-    } else {
-        builder.build()
-    };
-
-    mailer.send(email_message).await.map_err(|e| e.to_string())?;
-    Ok(())
+    emails::queue_verification(&mut *conn, user_id, email, locale, &verify_url).await
 }
 
 fn generate_jwt(user_id: i32) -> Result<String, jsonwebtoken::errors::Error> {
@@ -486,7 +461,7 @@ where
                 Ok(token_data) => {
                     req.extensions_mut().insert(token_data.claims.sub);
                     let fut = self.service.call(req);
-                    return Box::pin(async move { fut.await });
+                    return Box::pin(fut);
                 }
                 Err(_) => {
                     return Box::pin(async move {
@@ -0,0 +1,127 @@
+// src/api/email_preferences.rs
+//
+// Настройки транзакционных писем и отписка в один клик по ссылке из письма.
+// Сами письма — в `crate::emails`.
+
+use actix_web::{HttpResponse, Responder, get, post, put, web};
+use serde::Deserialize;
+use utoipa::IntoParams;
+use uuid::Uuid;
+
+use crate::AppState;
+use crate::emails::{self, EmailPreferencesUpdate, templates};
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct UnsubscribeQuery {
+    /// Token from the unsubscribe link
+    token: String,
+    /// `upload_completed`, `upload_failed`, `subscription_renewed`, `low_balance`,
+    /// or `all` (default) for every optional email
+    category: Option<String>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/email-preferences",
+    tag = "emails",
+    responses(
+        (status = 200, description = "Optional emails the user receives", body = EmailPreferences),
+        (status = 401, description = "Unauthorized")
+    )
+)]
+#[get("/email-preferences")]
+pub async fn get_email_preferences(
+    state: web::Data<AppState>,
+    user_id: web::ReqData<i32>,
+) -> impl Responder {
+    match emails::get_preferences(&state.pool, *user_id).await {
+        Ok(preferences) => HttpResponse::Ok().json(preferences),
+        Err(e) => {
+            log::error!("get_email_preferences db error: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/email-preferences",
+    tag = "emails",
+    request_body = EmailPreferencesUpdate,
+    responses(
+        (status = 200, description = "Updated preferences; missing fields are left unchanged", body = EmailPreferences),
+        (status = 400, description = "Unsupported locale"),
+        (status = 401, description = "Unauthorized")
+    )
+)]
+#[put("/email-preferences")]
+pub async fn update_email_preferences(
+    state: web::Data<AppState>,
+    user_id: web::ReqData<i32>,
+    payload: web::Json<EmailPreferencesUpdate>,
+) -> impl Responder {
+    if let Some(locale) = payload.locale.as_deref()
+        && !templates::LOCALES.contains(&locale)
+    {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "error": format!("locale must be one of: {}", templates::LOCALES.join(", "))
+        }));
+    }
+
+    match emails::update_preferences(&state.pool, *user_id, &payload).await {
+        Ok(preferences) => HttpResponse::Ok().json(preferences),
+        Err(e) => {
+            log::error!("update_email_preferences db error: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/email/unsubscribe",
+    tag = "emails",
+    params(UnsubscribeQuery),
+    responses(
+        (status = 200, description = "Unsubscribed; also the RFC 8058 one-click target of `List-Unsubscribe`"),
+        (status = 400, description = "Unknown category"),
+        (status = 404, description = "Unknown token")
+    )
+)]
+#[post("/email/unsubscribe")]
+pub async fn unsubscribe(
+    state: web::Data<AppState>,
+    query: web::Query<UnsubscribeQuery>,
+) -> impl Responder {
+    // Тело запроса (`List-Unsubscribe=One-Click`) не нужно: всё есть в ссылке
+    let category = match query.category.as_deref() {
+        None | Some("all") => None,
+        Some(category) if emails::category_column(category).is_some() => Some(category),
+        Some(_) => {
+            return HttpResponse::BadRequest().json(serde_json::json!({
+                "error": format!("category must be one of: all, {}", emails::CATEGORIES.join(", "))
+            }));
+        }
+    };
+
+    let unknown = || {
+        HttpResponse::NotFound().json(serde_json::json!({
+            "error": "unknown unsubscribe link"
+        }))
+    };
+    let Ok(token) = Uuid::parse_str(&query.token) else {
+        return unknown();
+    };
+
+    match emails::unsubscribe(&state.pool, token, category).await {
+        Ok(true) => HttpResponse::Ok().json(serde_json::json!({
+            "ok": true,
+            "category": category.unwrap_or("all"),
+        })),
+        Ok(false) => unknown(),
+        Err(e) => {
+            log::error!("unsubscribe db error: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
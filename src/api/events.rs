@@ -0,0 +1,140 @@
+// src/api/events.rs
+//
+// `GET /api/events` — те же события, что и `/ws/uploads`, но через Server-Sent Events:
+// для клиентов за прокси, которые рвут WebSocket. Авторизация — обычный Bearer JWT,
+// переподключение — заголовок `Last-Event-ID` (EventSource шлёт его сам).
+
+use actix::{
+    Actor, ActorContext, ActorFutureExt, AsyncContext, ContextFutureSpawner, Handler, WrapFuture,
+};
+use actix_web::web::ReqData;
+use actix_web::{HttpRequest, Responder, get, web};
+use actix_web_lab::sse;
+use sqlx::PgPool;
+use tokio::sync::mpsc;
+
+use crate::AppState;
+use crate::ws::{
+    Connect, Disconnect, ReplayFilter, WsConfig, WsHub, WsMessage, load_replay, next_session_id,
+};
+
+/// Запас очереди сверх доигрываемых событий; если клиент не успевает и она переполнилась,
+/// соединение закрывается и клиент переподключается с `Last-Event-ID`
+const LIVE_BUFFER: usize = 64;
+
+struct SseSession {
+    user_id: i32,
+    session_id: usize,
+    hub: actix::Addr<WsHub>,
+    pool: PgPool,
+    config: WsConfig,
+    resume_from: Option<i64>,
+    replayed: ReplayFilter,
+    tx: mpsc::Sender<sse::Event>,
+}
+
+impl SseSession {
+    /// `false`, если клиент ушёл или не успевает читать.
+    fn send(&self, id: Option<i64>, payload: String) -> bool {
+        let mut data = sse::Data::new(payload);
+        if let Some(id) = id {
+            data.set_id(id.to_string());
+        }
+        self.tx.try_send(data.into()).is_ok()
+    }
+}
+
+impl Actor for SseSession {
+    type Context = actix::Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.hub.do_send(Connect {
+            user_id: self.user_id,
+            session_id: self.session_id,
+            addr: ctx.address().recipient(),
+        });
+
+        // Отключение клиента видно только по закрытому каналу
+        ctx.run_interval(self.config.heartbeat_interval, |act, ctx| {
+            if act.tx.is_closed() {
+                ctx.stop();
+            }
+        });
+
+        let Some(after) = self.resume_from else {
+            return;
+        };
+        let pool = self.pool.clone();
+        let config = self.config.clone();
+        let user_id = self.user_id;
+        // Как и в WebSocket-сессии: живые события ждут в очереди, пока не уйдут пропущенные
+        async move { load_replay(&pool, user_id, after, &config).await }
+            .into_actor(self)
+            .map(|result, act, ctx| match result {
+                Ok(replay) => {
+                    for (id, payload) in act.replayed.record(replay) {
+                        if !act.send(Some(id), payload) {
+                            ctx.stop();
+                            return;
+                        }
+                    }
+                }
+                Err(e) => {
+                    log::error!("sse replay error user_id={} error={}", act.user_id, e);
+                    ctx.stop();
+                }
+            })
+            .wait(ctx);
+    }
+
+    fn stopped(&mut self, _: &mut Self::Context) {
+        self.hub.do_send(Disconnect {
+            user_id: self.user_id,
+            session_id: self.session_id,
+        });
+    }
+}
+
+impl Handler<WsMessage> for SseSession {
+    type Result = ();
+
+    fn handle(&mut self, msg: WsMessage, ctx: &mut Self::Context) -> Self::Result {
+        if !self.replayed.is_duplicate(msg.id) && !self.send(msg.id, msg.payload) {
+            ctx.stop();
+        }
+    }
+}
+
+#[get("/events")]
+pub async fn events_sse(
+    req: HttpRequest,
+    state: web::Data<AppState>,
+    user_id: ReqData<i32>,
+) -> impl Responder {
+    let resume_from = req
+        .headers()
+        .get("Last-Event-ID")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse::<i64>().ok());
+    let config = WsConfig::from_env();
+    let (tx, rx) = mpsc::channel(config.replay_limit.max(0) as usize + LIVE_BUFFER);
+    let keep_alive = config.heartbeat_interval;
+
+    SseSession {
+        user_id: user_id.into_inner(),
+        session_id: next_session_id(),
+        hub: state.ws_hub.clone(),
+        pool: state.pool.clone(),
+        config,
+        resume_from,
+        replayed: ReplayFilter::default(),
+        tx,
+    }
+    .start();
+
+    sse::Sse::from_infallible_receiver(rx)
+        .with_keep_alive(keep_alive)
+        .customize()
+        // nginx иначе копит ответ в буфере
+        .insert_header(("X-Accel-Buffering", "no"))
+}
@@ -3,27 +3,45 @@
 use actix_multipart::Multipart;
 use actix_web::{HttpResponse, Responder, get, post, web};
 use futures_util::StreamExt;
-use reqwest::Client;
 use serde::Serialize;
-use serde_json::{Value, json};
+use serde_json::json;
 use utoipa::ToSchema;
 
 use crate::AppState; // AppState в main.rs
-use crate::billing::{can_remove_watermark, consume_credit};
+use crate::api::webhooks::new_callback_token;
+use crate::billing::{can_remove_watermark, consume_credit, notify_credits};
+use crate::db;
+use crate::dedup;
+use crate::providers::WatermarkRegion;
+use crate::safe_http::{self, FetchPolicy};
+use crate::ws::notify_upload;
 use actix_web::web::ReqData;
 use sqlx::Row;
 
 #[derive(ToSchema)]
 pub struct UrlUploadBody {
-    /// Public video URL (Sora share link or direct MP4 URL)
+    /// Public video URL: Sora share link, Google Drive or Dropbox file link, or direct video file URL
     pub url: String,
+    /// Optional watermark area in pixels, `x,y,width,height` (used by the local ffmpeg backend;
+    /// by default the known Sora watermark positions are cleaned)
+    pub watermark_region: Option<String>,
+    /// Processing profile slug (see `GET /api/processing-profiles`); the plan default if omitted
+    pub profile: Option<String>,
 }
 
 #[derive(Serialize, ToSchema)]
 pub struct UploadResponse {
     pub message: String,
     pub upload_id: i32,
-    pub task_id: String,
+    /// Always `null`: the task is created by the submit worker and its id arrives over WebSocket (cached results have no task)
+    pub task_id: Option<String>,
+    /// The result was reused from an earlier upload of the same source
+    pub cached: bool,
+    pub cleaned_url: Option<String>,
+    /// Processing profile the upload is processed with
+    pub processing_profile: Option<String>,
+    /// Credits charged for the upload
+    pub credits_charged: i32,
 }
 
 #[derive(Serialize, ToSchema)]
@@ -40,6 +58,8 @@ pub struct UploadItemResponse {
     pub status: String,
     pub original_filename: String,
     pub cleaned_url: Option<String>,
+    /// Processing progress in percent, if the backend reports it
+    pub progress: Option<i16>,
     pub created_at: Option<chrono::DateTime<chrono::Utc>>,
 }
 
@@ -52,7 +72,8 @@ pub struct UploadItemResponse {
         content_type = "multipart/form-data"
     ),
     responses(
-        (status = 200, description = "Upload accepted", body = UploadResponse),
+        (status = 200, description = "Upload queued (status `queued`) or cached result reused", body = UploadResponse),
+        (status = 400, description = "Missing or unsupported video URL, or unavailable processing profile"),
         (status = 401, description = "Unauthorized"),
         (status = 402, description = "Insufficient credits"),
         (status = 500, description = "Server error")
@@ -67,24 +88,10 @@ pub async fn upload(
     let user_id = user_id.into_inner();
     log::info!("upload start user_id={}", user_id);
 
-    // Проверка кредитов
-    let credit_type = match can_remove_watermark(&state.pool, user_id).await {
-        Ok(Some(t)) => t,
-        Ok(None) => {
-            log::warn!("upload no credits user_id={}", user_id);
-            return HttpResponse::PaymentRequired().json(json!({
-                "error": "Insufficient credits"
-            }));
-        }
-        Err(e) => {
-            log::error!("upload billing error user_id={} error={}", user_id, e);
-            return HttpResponse::InternalServerError().body("Billing error");
-        }
-    };
-
-    // Чтение формы (только URL)
-    let mut original_filename = "video.mp4".to_string();
+    // Чтение формы (URL, необязательные область водяного знака и профиль обработки)
     let mut url_value: Option<String> = None;
+    let mut region_value: Option<String> = None;
+    let mut profile_value: Option<String> = None;
     let mut file_provided = false;
 
     while let Some(item) = payload.next().await {
@@ -93,18 +100,23 @@ pub async fn upload(
             Err(_) => continue,
         };
 
-        let field_name = field.name();
-        if field_name == "url" {
-            let mut url_bytes: Vec<u8> = Vec::new();
+        let field_name = field.name().to_string();
+        if matches!(field_name.as_str(), "url" | "watermark_region" | "profile") {
+            let mut value_bytes: Vec<u8> = Vec::new();
             while let Some(chunk) = field.next().await {
                 if let Ok(data) = chunk {
-                    url_bytes.extend_from_slice(&data);
+                    value_bytes.extend_from_slice(&data);
                 }
             }
-            if let Ok(url_str) = String::from_utf8(url_bytes) {
-                let trimmed = url_str.trim();
+            if let Ok(value_str) = String::from_utf8(value_bytes) {
+                let trimmed = value_str.trim();
                 if !trimmed.is_empty() {
-                    url_value = Some(trimmed.to_string());
+                    let target = match field_name.as_str() {
+                        "url" => &mut url_value,
+                        "watermark_region" => &mut region_value,
+                        _ => &mut profile_value,
+                    };
+                    *target = Some(trimmed.to_string());
                 }
             }
             continue;
@@ -121,32 +133,223 @@ pub async fn upload(
         return HttpResponse::BadRequest().body("File uploads are disabled. Please provide a video URL.");
     }
 
-    let external_url: Option<String> = if let Some(url) = url_value.as_deref() {
-        log::info!("upload using external url user_id={} url={}", user_id, url);
-        if let Some(name) = url.rsplit('/').next() {
-            let name = name.split('?').next().unwrap_or(name);
-            let name = name.split('#').next().unwrap_or(name);
-            if !name.is_empty() {
-                original_filename = sanitize(name);
+    let Some(url) = url_value else {
+        return HttpResponse::BadRequest().body("Video URL is required");
+    };
+    let region = match region_value.as_deref().map(WatermarkRegion::parse).transpose() {
+        Ok(region) => region,
+        Err(e) => return HttpResponse::BadRequest().json(json!({ "error": e })),
+    };
+    log::info!("upload using external url user_id={} url={}", user_id, url);
+
+    // Тариф определяет доступные профили обработки
+    let plan = match db::get_user_plan_slug(&state.pool, user_id).await {
+        Ok(plan) => plan,
+        Err(e) => {
+            log::warn!("upload plan lookup error user_id={} error={}", user_id, e);
+            None
+        }
+    };
+    let profile = match db::resolve_processing_profile(
+        &state.pool,
+        plan.as_deref(),
+        profile_value.as_deref(),
+    )
+    .await
+    {
+        Ok(Some(profile)) => Some(profile),
+        Ok(None) if profile_value.is_some() => {
+            log::warn!("upload unavailable profile user_id={} profile={:?}", user_id, profile_value);
+            return HttpResponse::BadRequest().json(json!({
+                "error": "Unknown or unavailable processing profile"
+            }));
+        }
+        // Профили не настроены: работаем как раньше, один кредит и модель бэкенда по умолчанию
+        Ok(None) => None,
+        Err(e) => {
+            log::error!("upload profile lookup error user_id={} error={}", user_id, e);
+            return HttpResponse::InternalServerError().body("DB error");
+        }
+    };
+    let credit_cost = profile.as_ref().map(|p| p.credit_cost).unwrap_or(1);
+    let profile_slug = profile.as_ref().map(|p| p.slug.clone());
+
+    // Разбираем ссылку до списания кредита: невалидные ссылки отклоняем сразу
+    let source = match state.source_resolvers.resolve(&url).await {
+        Ok(s) => s,
+        Err(e) => {
+            log::warn!("upload invalid source user_id={} url={} error={}", user_id, url, e);
+            return HttpResponse::BadRequest().json(json!({ "error": e.to_string() }));
+        }
+    };
+    let original_filename = source.original_filename.clone();
+
+    // Сохраняем URL, который уйдёт на обработку, как источник
+    let original_key = source.video_url.clone();
+
+    // Отпечаток ссылки для дедупликации
+    let Some(normalized_url) = dedup::normalize_source_url(&source.canonical_url) else {
+        return HttpResponse::BadRequest().body("Invalid video URL");
+    };
+    // Своя область водяного знака или другой профиль дают другой результат: кешируем отдельно
+    let mut fingerprint_input = normalized_url;
+    if let Some(region) = region {
+        fingerprint_input.push_str(&format!("#region={region}"));
+    }
+    if let Some(slug) = profile_slug.as_deref().filter(|s| *s != db::DEFAULT_PROFILE_SLUG) {
+        fingerprint_input.push_str(&format!("#profile={slug}"));
+    }
+    let source_fingerprint = dedup::url_fingerprint(&fingerprint_input);
+
+    let cached = match dedup::find_cached_result(
+        &state.pool,
+        dedup::DedupScope::from_env(),
+        user_id,
+        &source_fingerprint,
+    )
+    .await
+    {
+        Ok(c) => c,
+        Err(e) => {
+            // Кеш — оптимизация, при ошибке просто обрабатываем заново
+            log::error!("upload dedup lookup error user_id={} error={}", user_id, e);
+            None
+        }
+    };
+    let charge = cached.is_none() || dedup::charge_cached_from_env();
+
+    // Ссылка уйдёт во внешний сервис, а результат потом скачиваем мы: внутренние адреса не пускаем
+    if cached.is_none()
+        && let Err(e) = safe_http::validate_url(&source.video_url, &FetchPolicy::from_env()).await
+    {
+        log::warn!("upload blocked source user_id={} url={} error={}", user_id, url, e);
+        return HttpResponse::BadRequest().json(json!({ "error": e.to_string() }));
+    }
+
+    // Проверка кредитов
+    let credit_type = if charge {
+        match can_remove_watermark(&state.pool, user_id, credit_cost).await {
+            Ok(Some(t)) => Some(t),
+            Ok(None) => {
+                log::warn!("upload no credits user_id={}", user_id);
+                return HttpResponse::PaymentRequired().json(json!({
+                    "error": "Insufficient credits"
+                }));
+            }
+            Err(e) => {
+                log::error!("upload billing error user_id={} error={}", user_id, e);
+                return HttpResponse::InternalServerError().body("Billing error");
             }
         }
-        Some(url.to_string())
     } else {
-        return HttpResponse::BadRequest().body("Video URL is required");
+        None
+    };
+    let credits_charged = if credit_type.is_some() { credit_cost } else { 0 };
+
+    let mut tx = match state.pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => {
+            log::error!("upload db begin error user_id={} error={}", user_id, e);
+            return HttpResponse::InternalServerError().body("DB error");
+        }
+    };
+    // Кредиты списываем в транзакции вставки: параллельная загрузка не пройдёт проверку
+    // дважды, а если вставка не удалась, списание откатится вместе с ней
+    let low_balance = match credit_type.as_deref() {
+        Some(credit_type) => {
+            match reserve_credits(&mut tx, user_id, credit_type, credits_charged).await {
+                Ok(crossed) => crossed,
+                Err(resp) => return resp,
+            }
+        }
+        None => false,
     };
 
-    // Сохраняем URL как источник
-    let original_key = external_url.clone().unwrap_or_default();
+    if let Some(cached) = cached {
+        let upload_id: i32 = match sqlx::query(
+            r#"INSERT INTO uploads
+               (user_id, original_filename, original_s3_key, status, used_credit_type,
+                source_fingerprint, cached_from_upload_id, cleaned_s3_key, cleaned_url,
+                processing_profile, credit_cost)
+               VALUES ($1, $2, $3, 'ready', $4, $5, $6, $7, $8, $9, $10)
+               RETURNING id"#,
+        )
+        .bind(user_id)
+        .bind(&original_filename)
+        .bind(&original_key)
+        .bind(credit_type.as_deref())
+        .bind(&source_fingerprint)
+        .bind(cached.upload_id)
+        .bind(&cached.cleaned_s3_key)
+        .bind(&cached.cleaned_url)
+        .bind(profile_slug.as_deref())
+        .bind(credits_charged)
+        .fetch_one(&mut *tx)
+        .await
+        {
+            Ok(row) => row.get("id"),
+            Err(e) => {
+                log::error!("upload db insert error user_id={} error={}", user_id, e);
+                return HttpResponse::InternalServerError().body("DB error");
+            }
+        };
+        if let Err(e) = tx.commit().await {
+            log::error!("upload db commit error user_id={} error={}", user_id, e);
+            return HttpResponse::InternalServerError().body("DB error");
+        }
 
-    // Вставка в БД (runtime query, чтобы сборка не зависела от наличия таблиц в DEV БД)
+        if low_balance {
+            state.outbox_wakeup.notify_one();
+        }
+        if credit_type.is_some() {
+            notify_credits(&state.pool, &state.ws_hub, user_id).await;
+        }
+
+        log::info!(
+            "upload reused cached result user_id={} upload_id={} cached_from={}",
+            user_id,
+            upload_id,
+            cached.upload_id
+        );
+        notify_upload(&state.pool, &state.ws_hub, upload_id).await;
+
+        return HttpResponse::Ok().json(UploadResponse {
+            message: "Cached result reused".to_string(),
+            upload_id,
+            task_id: None,
+            cached: true,
+            cleaned_url: Some(cached.cleaned_url),
+            processing_profile: profile_slug,
+            credits_charged,
+        });
+    }
+    let credit_type = credit_type.unwrap_or_default();
+
+    // Секрет, по которому проверяем колбэк бэкенда для этой загрузки
+    let callback_token = new_callback_token();
+
+    // Вставка в БД (runtime query, чтобы сборка не зависела от наличия таблиц в DEV БД).
+    // Задачу у бэкенда создаёт воркер отправки (src/submitter.rs), здесь только ставим в очередь.
     let upload_id: i32 = match sqlx::query(
-        "INSERT INTO uploads (user_id, original_filename, original_s3_key, status, used_credit_type)\n         VALUES ($1, $2, $3, 'processing', $4) RETURNING id",
+        r#"INSERT INTO uploads
+           (user_id, original_filename, original_s3_key, status, used_credit_type, source_fingerprint,
+            callback_token, processing_profile, credit_cost, source_kind, source_url,
+            watermark_region, next_submit_at)
+           VALUES ($1, $2, $3, 'queued', $4, $5, $6, $7, $8, $9, $10, $11, NOW())
+           RETURNING id"#,
     )
     .bind(user_id)
     .bind(&original_filename)
     .bind(&original_key)
     .bind(&credit_type)
-    .fetch_one(&state.pool)
+    .bind(&source_fingerprint)
+    .bind(&callback_token)
+    .bind(profile_slug.as_deref())
+    .bind(credits_charged)
+    .bind(source.kind.as_str())
+    .bind(&source.canonical_url)
+    .bind(region.map(|r| r.to_string()))
+    .fetch_one(&mut *tx)
     .await
     {
         Ok(row) => row.get("id"),
@@ -155,34 +358,51 @@ pub async fn upload(
             return HttpResponse::InternalServerError().body("DB error");
         }
     };
+    // Если ни один бэкенд так и не примет задачу, воркер вернёт `credit_cost`
+    if let Err(e) = tx.commit().await {
+        log::error!("upload db commit error user_id={} error={}", user_id, e);
+        return HttpResponse::InternalServerError().body("DB error");
+    }
 
-    // Списываем кредит
-    let _ = consume_credit(&state.pool, user_id, &credit_type).await;
-
-    // Запуск Kie.ai
-    let public_url = external_url.unwrap_or_default();
-    let callback_url = format!("{}/api/watermark-callback", state.callback_base_url);
-
-    match start_remove_watermark(&state.kie_api_key, &public_url, &callback_url).await {
-        Ok(task_id) => {
-            log::info!("upload started task user_id={} upload_id={} task_id={}", user_id, upload_id, task_id);
-            let _ = sqlx::query("UPDATE uploads SET task_id = $1 WHERE id = $2")
-                .bind(&task_id)
-                .bind(upload_id)
-                .execute(&state.pool)
-                .await;
-
-            let response = UploadResponse {
-                message: "Processing started".to_string(),
-                upload_id,
-                task_id,
-            };
-
-            HttpResponse::Ok().json(response)
+    if low_balance {
+        state.outbox_wakeup.notify_one();
+    }
+    notify_credits(&state.pool, &state.ws_hub, user_id).await;
+
+    log::info!("upload queued user_id={} upload_id={}", user_id, upload_id);
+    notify_upload(&state.pool, &state.ws_hub, upload_id).await;
+    state.submit_wakeup.notify_one();
+
+    HttpResponse::Ok().json(UploadResponse {
+        message: "Queued for processing".to_string(),
+        upload_id,
+        task_id: None,
+        cached: false,
+        cleaned_url: None,
+        processing_profile: profile_slug,
+        credits_charged,
+    })
+}
+
+/// Списывает кредиты в транзакции загрузки. `Err` — готовый ответ (402, если кредиты
+/// успел потратить параллельный запрос); транзакция при этом откатывается на drop.
+async fn reserve_credits(
+    tx: &mut sqlx::PgConnection,
+    user_id: i32,
+    credit_type: &str,
+    amount: i32,
+) -> Result<bool, HttpResponse> {
+    match consume_credit(tx, user_id, credit_type, amount).await {
+        Ok(Some(crossed)) => Ok(crossed),
+        Ok(None) => {
+            log::warn!("upload no credits user_id={} credit_type={}", user_id, credit_type);
+            Err(HttpResponse::PaymentRequired().json(json!({
+                "error": "Insufficient credits"
+            })))
         }
         Err(e) => {
-            log::error!("upload kie error user_id={} upload_id={} error={}", user_id, upload_id, e);
-            HttpResponse::InternalServerError().json(json!({ "error": e }))
+            log::error!("upload billing error user_id={} error={}", user_id, e);
+            Err(HttpResponse::InternalServerError().body("Billing error"))
         }
     }
 }
@@ -238,7 +458,7 @@ pub async fn list_uploads(
         .max(0);
 
     let rows = match sqlx::query(
-        r#"SELECT id, task_id, status, original_filename, cleaned_url, created_at
+        r#"SELECT id, task_id, status, original_filename, cleaned_url, progress, created_at
            FROM uploads
            WHERE user_id = $1
            ORDER BY created_at DESC
@@ -265,53 +485,10 @@ pub async fn list_uploads(
             status: row.get("status"),
             original_filename: row.get("original_filename"),
             cleaned_url: row.get("cleaned_url"),
+            progress: row.get("progress"),
             created_at: row.get("created_at"),
         })
         .collect();
 
     HttpResponse::Ok().json(items)
 }
-
-// Санитизация имени файла
-fn sanitize(filename: &str) -> String {
-    filename
-        .chars()
-        .filter(|c| c.is_alphanumeric() || *c == '.' || *c == '_' || *c == '-')
-        .collect()
-}
-
-// Запуск задачи в Kie.ai
-pub async fn start_remove_watermark(
-    api_key: &str,
-    video_url: &str,
-    callback_url: &str,
-) -> Result<String, String> {
-    let client = Client::new();
-    let base_url = std::env::var("KIE_API_BASE_URL").unwrap_or_else(|_| "https://api.kie.ai".to_string());
-    let body = json!({
-        "model": "sora-watermark-remover",
-        "input": { "video_url": video_url },
-        "callBackUrl": callback_url
-    });
-
-    let resp = client
-        .post(format!("{base_url}/api/v1/jobs/createTask"))
-        .header("Authorization", format!("Bearer {}", api_key))
-        .json(&body)
-        .send()
-        .await
-        .map_err(|e| e.to_string())?;
-
-    let status = resp.status();
-    let text = resp.text().await.map_err(|e| e.to_string())?;
-    if !status.is_success() {
-        return Err(format!("KIE error status={} body={}", status, text));
-    }
-
-    let json: Value = serde_json::from_str(&text)
-        .map_err(|e| format!("error decoding response body: {e}; body={text}"))?;
-    json["data"]["taskId"]
-        .as_str()
-        .map(|s| s.to_string())
-        .ok_or_else(|| format!("No taskId in response; body={text}"))
-}
@@ -126,6 +126,7 @@ pub async fn cancel_subscription(
 /// - получить список продуктов/офферов в lava.top
 /// - для нужного оффера скопировать UUID
 /// - вставить сюда
+#[allow(clippy::match_single_binding)]
 pub fn offer_id_for_product_slug(product_slug: &str) -> Option<&'static str> {
     match product_slug {
         // примеры:
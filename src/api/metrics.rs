@@ -0,0 +1,32 @@
+// src/api/metrics.rs
+//
+// `GET /metrics` для Prometheus. Включается только с `METRICS_TOKEN`:
+// без него эндпоинт отвечает 404, чтобы счётчики не торчали наружу.
+
+use actix_web::{HttpRequest, HttpResponse, get, http::header};
+
+use crate::api::webhooks::tokens_match;
+use crate::outbox;
+
+#[get("/metrics")]
+pub async fn metrics(req: HttpRequest) -> HttpResponse {
+    let Some(expected) = std::env::var("METRICS_TOKEN")
+        .ok()
+        .filter(|t| !t.is_empty())
+    else {
+        return HttpResponse::NotFound().finish();
+    };
+
+    let provided = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+    if !provided.is_some_and(|token| tokens_match(&expected, token)) {
+        return HttpResponse::Unauthorized().finish();
+    }
+
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(outbox::metrics::render())
+}
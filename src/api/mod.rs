@@ -1,10 +1,14 @@
 pub mod auth;
 pub mod config;
+pub mod email_preferences;
+pub mod events;
 pub mod handlers;
 pub mod lava;
 pub mod lava_client;
+pub mod metrics;
 pub mod payments;
 pub mod products;
 pub mod subscriptions;
+pub mod user_webhooks;
 pub mod webhooks;
 pub mod webhooks_lava;
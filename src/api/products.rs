@@ -1,5 +1,6 @@
 // src/api/products.rs
 
+use actix_web::web::ReqData;
 use actix_web::{HttpResponse, Responder, get, web};
 
 use crate::{AppState, db};
@@ -19,3 +20,27 @@ pub async fn list_products(state: web::Data<AppState>) -> impl Responder {
         }
     }
 }
+
+/// Профили обработки, доступные на тарифе пользователя.
+#[get("/processing-profiles")]
+pub async fn list_processing_profiles(
+    state: web::Data<AppState>,
+    user_id: ReqData<i32>,
+) -> impl Responder {
+    let user_id = user_id.into_inner();
+    let plan = match db::get_user_plan_slug(&state.pool, user_id).await {
+        Ok(plan) => plan,
+        Err(e) => {
+            eprintln!("list_processing_profiles plan error: {e}");
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    match db::list_processing_profiles(&state.pool, plan.as_deref()).await {
+        Ok(profiles) => HttpResponse::Ok().json(profiles),
+        Err(e) => {
+            eprintln!("list_processing_profiles db error: {e}");
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
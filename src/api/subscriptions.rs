@@ -3,6 +3,7 @@
 use actix_web::{HttpResponse, Responder, get, post, web};
 use serde::Deserialize;
 
+use crate::outbox::{self, DomainEvent};
 use crate::{AppState, db, models::Subscription};
 
 #[get("/subscriptions")]
@@ -44,8 +45,25 @@ pub async fn cancel_subscription(
         }));
     }
 
-    match db::cancel_user_subscription(&state.pool, user_id, payload.subscription_id).await {
-        Ok(()) => HttpResponse::Ok().json(serde_json::json!({"status": "canceled"})),
+    let subscription_id = payload.subscription_id;
+    let result = async {
+        let mut tx = state.pool.begin().await?;
+        if db::cancel_user_subscription(&mut *tx, user_id, subscription_id).await? {
+            let event = DomainEvent::SubscriptionCanceled {
+                subscription_id,
+                user_id,
+            };
+            outbox::record(&mut *tx, &event).await?;
+        }
+        tx.commit().await
+    }
+    .await;
+
+    match result {
+        Ok(()) => {
+            state.outbox_wakeup.notify_one();
+            HttpResponse::Ok().json(serde_json::json!({"status": "canceled"}))
+        }
         Err(e) => {
             eprintln!("cancel_subscription db error: {e}");
             HttpResponse::InternalServerError().finish()
@@ -0,0 +1,298 @@
+// src/api/user_webhooks.rs
+//
+// Вебхуки пользователя: регистрация адресов, журнал доставок и повторная отправка.
+// Сама доставка — в `crate::user_webhooks`.
+
+use actix_web::{HttpResponse, Responder, delete, get, patch, post, web};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::AppState;
+use crate::user_webhooks::{self, EndpointUpdate, WebhookConfig, WebhookEndpoint};
+
+const DEFAULT_DELIVERIES_LIMIT: i64 = 50;
+const MAX_DELIVERIES_LIMIT: i64 = 200;
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct CreateWebhookRequest {
+    /// Public http(s) URL that receives POST requests
+    pub url: String,
+    /// Event types to deliver; empty or missing for all
+    #[serde(default)]
+    pub event_types: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct UpdateWebhookRequest {
+    pub url: Option<String>,
+    pub event_types: Option<Vec<String>>,
+    /// `true` enables the endpoint again and resets its failure counter
+    pub is_active: Option<bool>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct CreatedWebhook {
+    pub endpoint: WebhookEndpoint,
+    /// Signing secret; shown only once
+    pub secret: String,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct DeliveriesQuery {
+    /// Page size, 50 by default, at most 200
+    limit: Option<i64>,
+    /// Return deliveries with a smaller id (next page)
+    before: Option<i64>,
+}
+
+fn bad_request(error: String) -> HttpResponse {
+    HttpResponse::BadRequest().json(serde_json::json!({ "error": error }))
+}
+
+fn not_found() -> HttpResponse {
+    HttpResponse::NotFound().json(serde_json::json!({ "error": "webhook not found" }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/webhooks",
+    tag = "user-webhooks",
+    responses(
+        (status = 200, description = "Registered webhook endpoints", body = [WebhookEndpoint]),
+        (status = 401, description = "Unauthorized")
+    )
+)]
+#[get("/webhooks")]
+pub async fn list_webhooks(
+    state: web::Data<AppState>,
+    user_id: web::ReqData<i32>,
+) -> impl Responder {
+    match user_webhooks::list_endpoints(&state.pool, *user_id).await {
+        Ok(endpoints) => HttpResponse::Ok().json(endpoints),
+        Err(e) => {
+            eprintln!("list_webhooks db error: {e}");
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/webhooks",
+    tag = "user-webhooks",
+    request_body = CreateWebhookRequest,
+    responses(
+        (status = 201, description = "Endpoint registered; the secret is not shown again", body = CreatedWebhook),
+        (status = 400, description = "Invalid URL or event type, or too many endpoints"),
+        (status = 401, description = "Unauthorized")
+    )
+)]
+#[post("/webhooks")]
+pub async fn create_webhook(
+    state: web::Data<AppState>,
+    user_id: web::ReqData<i32>,
+    payload: web::Json<CreateWebhookRequest>,
+) -> impl Responder {
+    let user_id = *user_id;
+    let url = match user_webhooks::validate_endpoint_url(&payload.url).await {
+        Ok(url) => url,
+        Err(e) => return bad_request(e),
+    };
+    let event_types = match user_webhooks::validate_event_types(&payload.event_types) {
+        Ok(event_types) => event_types,
+        Err(e) => return bad_request(e),
+    };
+
+    let max_endpoints = WebhookConfig::from_env().max_endpoints;
+    match user_webhooks::count_endpoints(&state.pool, user_id).await {
+        Ok(count) if count >= max_endpoints => {
+            return bad_request(format!("at most {max_endpoints} webhooks per account"));
+        }
+        Ok(_) => {}
+        Err(e) => {
+            eprintln!("create_webhook count error: {e}");
+            return HttpResponse::InternalServerError().finish();
+        }
+    }
+
+    match user_webhooks::create_endpoint(&state.pool, user_id, &url, &event_types).await {
+        Ok((endpoint, secret)) => HttpResponse::Created().json(CreatedWebhook { endpoint, secret }),
+        Err(e) => {
+            eprintln!("create_webhook db error: {e}");
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[utoipa::path(
+    patch,
+    path = "/api/webhooks/{id}",
+    tag = "user-webhooks",
+    params(("id" = i32, Path, description = "Webhook endpoint id")),
+    request_body = UpdateWebhookRequest,
+    responses(
+        (status = 200, description = "Updated endpoint", body = WebhookEndpoint),
+        (status = 400, description = "Invalid URL or event type"),
+        (status = 401, description = "Unauthorized"),
+        (status = 404, description = "Not found")
+    )
+)]
+#[patch("/webhooks/{id}")]
+pub async fn update_webhook(
+    state: web::Data<AppState>,
+    user_id: web::ReqData<i32>,
+    path: web::Path<i32>,
+    payload: web::Json<UpdateWebhookRequest>,
+) -> impl Responder {
+    let payload = payload.into_inner();
+    let mut update = EndpointUpdate {
+        is_active: payload.is_active,
+        ..EndpointUpdate::default()
+    };
+    if let Some(url) = payload.url {
+        match user_webhooks::validate_endpoint_url(&url).await {
+            Ok(url) => update.url = Some(url),
+            Err(e) => return bad_request(e),
+        }
+    }
+    if let Some(event_types) = payload.event_types {
+        match user_webhooks::validate_event_types(&event_types) {
+            Ok(event_types) => update.event_types = Some(event_types),
+            Err(e) => return bad_request(e),
+        }
+    }
+
+    match user_webhooks::update_endpoint(&state.pool, *user_id, path.into_inner(), &update).await {
+        Ok(Some(endpoint)) => {
+            if update.is_active == Some(true) {
+                // Доставки, которые ждали включения адреса
+                state.webhook_wakeup.notify_one();
+            }
+            HttpResponse::Ok().json(endpoint)
+        }
+        Ok(None) => not_found(),
+        Err(e) => {
+            eprintln!("update_webhook db error: {e}");
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/webhooks/{id}",
+    tag = "user-webhooks",
+    params(("id" = i32, Path, description = "Webhook endpoint id")),
+    responses(
+        (status = 204, description = "Endpoint and its delivery log deleted"),
+        (status = 401, description = "Unauthorized"),
+        (status = 404, description = "Not found")
+    )
+)]
+#[delete("/webhooks/{id}")]
+pub async fn delete_webhook(
+    state: web::Data<AppState>,
+    user_id: web::ReqData<i32>,
+    path: web::Path<i32>,
+) -> impl Responder {
+    match user_webhooks::delete_endpoint(&state.pool, *user_id, path.into_inner()).await {
+        Ok(true) => HttpResponse::NoContent().finish(),
+        Ok(false) => not_found(),
+        Err(e) => {
+            eprintln!("delete_webhook db error: {e}");
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/webhooks/{id}/deliveries",
+    tag = "user-webhooks",
+    params(("id" = i32, Path, description = "Webhook endpoint id"), DeliveriesQuery),
+    responses(
+        (status = 200, description = "Delivery log, newest first", body = [WebhookDelivery]),
+        (status = 401, description = "Unauthorized"),
+        (status = 404, description = "Not found")
+    )
+)]
+#[get("/webhooks/{id}/deliveries")]
+pub async fn list_webhook_deliveries(
+    state: web::Data<AppState>,
+    user_id: web::ReqData<i32>,
+    path: web::Path<i32>,
+    query: web::Query<DeliveriesQuery>,
+) -> impl Responder {
+    let endpoint_id = path.into_inner();
+    match user_webhooks::get_endpoint(&state.pool, *user_id, endpoint_id).await {
+        Ok(Some(_)) => {}
+        Ok(None) => return not_found(),
+        Err(e) => {
+            eprintln!("list_webhook_deliveries db error: {e}");
+            return HttpResponse::InternalServerError().finish();
+        }
+    }
+
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_DELIVERIES_LIMIT)
+        .clamp(1, MAX_DELIVERIES_LIMIT);
+    match user_webhooks::list_deliveries(&state.pool, endpoint_id, limit, query.before).await {
+        Ok(deliveries) => HttpResponse::Ok().json(deliveries),
+        Err(e) => {
+            eprintln!("list_webhook_deliveries db error: {e}");
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/webhooks/{id}/deliveries/{delivery_id}/redeliver",
+    tag = "user-webhooks",
+    params(
+        ("id" = i32, Path, description = "Webhook endpoint id"),
+        ("delivery_id" = i64, Path, description = "Delivery to send again")
+    ),
+    responses(
+        (status = 202, description = "A new delivery with the same payload is queued", body = WebhookDelivery),
+        (status = 401, description = "Unauthorized"),
+        (status = 404, description = "Not found"),
+        (status = 409, description = "The endpoint is disabled")
+    )
+)]
+#[post("/webhooks/{id}/deliveries/{delivery_id}/redeliver")]
+pub async fn redeliver_webhook(
+    state: web::Data<AppState>,
+    user_id: web::ReqData<i32>,
+    path: web::Path<(i32, i64)>,
+) -> impl Responder {
+    let (endpoint_id, delivery_id) = path.into_inner();
+    match user_webhooks::get_endpoint(&state.pool, *user_id, endpoint_id).await {
+        Ok(Some(endpoint)) if !endpoint.is_active => {
+            return HttpResponse::Conflict().json(serde_json::json!({
+                "error": "webhook is disabled, enable it first"
+            }));
+        }
+        Ok(Some(_)) => {}
+        Ok(None) => return not_found(),
+        Err(e) => {
+            eprintln!("redeliver_webhook db error: {e}");
+            return HttpResponse::InternalServerError().finish();
+        }
+    }
+
+    match user_webhooks::redeliver(&state.pool, endpoint_id, delivery_id).await {
+        Ok(Some(delivery)) => {
+            state.webhook_wakeup.notify_one();
+            HttpResponse::Accepted().json(delivery)
+        }
+        Ok(None) => HttpResponse::NotFound().json(serde_json::json!({
+            "error": "delivery not found"
+        })),
+        Err(e) => {
+            eprintln!("redeliver_webhook db error: {e}");
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
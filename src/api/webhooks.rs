@@ -1,145 +1,235 @@
 // src/api/webhooks.rs
 
-use crate::{AppState, s3_utils::build_public_url, ws::notify_upload_by_task};
+use crate::AppState;
+use crate::billing::fail_upload_with_refund;
+use crate::finalize::{FinalizeOutcome, finalize_upload};
+use crate::providers::TaskStatus;
+use crate::ws::notify_upload;
 use actix_web::{HttpResponse, post, web};
-use aws_sdk_s3::primitives::ByteStream;
-use reqwest::Client as HttpClient;
 use serde::Deserialize;
-use utoipa::ToSchema;
-
-#[derive(Deserialize, Debug, ToSchema)] // Добавили Debug
-pub struct CallbackPayload {
-    code: i32,
-    #[allow(dead_code)]
-    msg: Option<String>,
-    data: CallbackData,
+use serde_json::Value;
+use sqlx::Row;
+use utoipa::IntoParams;
+use uuid::Uuid;
+
+/// Параметры, которые мы сами кладём в `callBackUrl` при создании задачи.
+#[derive(Deserialize, Debug, IntoParams)]
+pub struct CallbackQuery {
+    /// Upload the callback belongs to
+    upload_id: Option<i32>,
+    /// Per-upload secret issued together with the task
+    token: Option<String>,
 }
 
-#[derive(Deserialize, Debug, ToSchema)]
-pub struct CallbackData {
-    #[serde(rename = "taskId")]
-    task_id: String,
-    #[serde(default)]
-    status: Option<String>,
-    #[serde(default)]
-    state: Option<String>,
-    #[serde(rename = "outputUrl")]
-    output_url: Option<String>,
-    #[serde(default)]
-    resultJson: Option<String>,
+/// Случайный секрет для `callBackUrl` (256 бит).
+pub fn new_callback_token() -> String {
+    format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple())
 }
 
-async fn handle_watermark_callback(
-    payload: web::Json<CallbackPayload>,
-    state: web::Data<AppState>,
-) -> HttpResponse {
-    let payload = payload.into_inner();
+pub fn watermark_callback_url(callback_base_url: &str, upload_id: i32, token: &str) -> String {
+    format!(
+        "{}/api/watermark-callback?upload_id={}&token={}",
+        callback_base_url.trim_end_matches('/'),
+        upload_id,
+        urlencoding::encode(token)
+    )
+}
 
-    if payload.code != 200 {
-        log::warn!("kie callback error payload={:?}", payload);
-        return HttpResponse::BadRequest().body("Task failed");
+/// Сравнение без раннего выхода, чтобы по времени ответа нельзя было подбирать токен.
+pub(crate) fn tokens_match(expected: &str, provided: &str) -> bool {
+    let (a, b) = (expected.as_bytes(), provided.as_bytes());
+    if a.len() != b.len() {
+        return false;
     }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+struct VerifiedUpload {
+    id: i32,
+    task_id: Option<String>,
+    provider: Option<String>,
+}
+
+/// Проверяет токен колбэка и возвращает загрузку, к которой он относится.
+async fn verify_callback(
+    state: &AppState,
+    query: &CallbackQuery,
+) -> Result<VerifiedUpload, HttpResponse> {
+    let (Some(upload_id), Some(token)) = (query.upload_id, query.token.as_deref()) else {
+        log::warn!("watermark callback without token");
+        return Err(HttpResponse::Unauthorized().finish());
+    };
 
-    if let Some(status) = payload.data.status.as_deref() {
-        if status != "success" {
-            log::warn!("kie callback status not success: {}", status);
-            return HttpResponse::BadRequest().body("Task failed");
+    let row = match sqlx::query("SELECT task_id, callback_token, provider FROM uploads WHERE id = $1")
+        .bind(upload_id)
+        .fetch_optional(&state.pool)
+        .await
+    {
+        Ok(row) => row,
+        Err(e) => {
+            log::error!("watermark callback db error upload_id={} error={}", upload_id, e);
+            return Err(HttpResponse::InternalServerError().finish());
         }
-    }
+    };
+
+    let verified = row.filter(|row| {
+        let expected: Option<String> = row.get("callback_token");
+        expected.is_some_and(|expected| tokens_match(&expected, token))
+    });
 
-    if let Some(state) = payload.data.state.as_deref() {
-        if state != "success" {
-            log::warn!("kie callback state not success: {}", state);
-            return HttpResponse::BadRequest().body("Task failed");
+    match verified {
+        Some(row) => Ok(VerifiedUpload {
+            id: upload_id,
+            task_id: row.get("task_id"),
+            provider: row.get("provider"),
+        }),
+        None => {
+            log::warn!("watermark callback rejected upload_id={}", upload_id);
+            Err(HttpResponse::Unauthorized().finish())
         }
     }
+}
 
-    let output_url = if let Some(url) = payload.data.output_url.clone() {
-        Some(url)
-    } else if let Some(result_json) = payload.data.resultJson.as_deref() {
-        match serde_json::from_str::<serde_json::Value>(result_json) {
-            Ok(value) => value
-                .get("resultUrls")
-                .and_then(|v| v.as_array())
-                .and_then(|arr| arr.first())
-                .and_then(|v| v.as_str())
-                .map(|s| s.to_string()),
-            Err(e) => {
-                log::warn!("kie callback resultJson parse error: {}", e);
-                None
+/// Промежуточный колбэк: сохраняем процент и шлём событие, если он изменился.
+async fn record_progress(state: &AppState, upload_id: i32, progress: u8) -> HttpResponse {
+    let updated = sqlx::query(
+        r#"UPDATE uploads
+           SET progress = $1
+           WHERE id = $2 AND status = 'processing' AND progress IS DISTINCT FROM $1"#,
+    )
+    .bind(progress as i16)
+    .bind(upload_id)
+    .execute(&state.pool)
+    .await;
+
+    match updated {
+        Ok(result) => {
+            if result.rows_affected() > 0 {
+                notify_upload(&state.pool, &state.ws_hub, upload_id).await;
             }
+            HttpResponse::Ok().body("OK")
         }
-    } else {
-        None
-    };
+        Err(e) => {
+            log::error!("watermark callback progress db error upload_id={} error={}", upload_id, e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
 
-    if output_url.is_none() {
-        log::warn!("kie callback error payload={:?}", payload);
-        return HttpResponse::BadRequest().body("Task failed");
+/// Бэкенд сообщил об ошибке: закрываем загрузку и возвращаем кредиты, как при опросе статуса.
+async fn record_failure(state: &AppState, upload_id: i32, reason: Option<&str>) -> HttpResponse {
+    let reason = reason.unwrap_or("provider task failed");
+    match close_failed(state, upload_id, reason).await {
+        Ok(true) => {
+            state.outbox_wakeup.notify_one();
+            HttpResponse::Ok().body("OK")
+        }
+        Ok(false) => {
+            log::info!("watermark callback failure for settled upload ignored upload_id={}", upload_id);
+            HttpResponse::Ok().body("OK")
+        }
+        Err(e) => {
+            log::error!("watermark callback failure db error upload_id={} error={}", upload_id, e);
+            HttpResponse::InternalServerError().finish()
+        }
     }
+}
 
-    let cleaned_url = output_url.unwrap();
+async fn close_failed(state: &AppState, upload_id: i32, reason: &str) -> Result<bool, sqlx::Error> {
+    let mut tx = state.pool.begin().await?;
+    let closed = fail_upload_with_refund(&mut tx, upload_id, "processing", reason).await?;
+    tx.commit().await?;
+    Ok(closed)
+}
 
-    let task_id = payload.data.task_id.clone();
-    let s3_key = format!("cleaned/{}.mp4", task_id);
+async fn handle_watermark_callback(
+    body: web::Json<Value>,
+    query: web::Query<CallbackQuery>,
+    state: web::Data<AppState>,
+) -> HttpResponse {
+    let upload = match verify_callback(&state, &query).await {
+        Ok(upload) => upload,
+        Err(resp) => return resp,
+    };
 
-    if std::env::var("MOCK_S3").unwrap_or_default() != "true" {
-        let resp = match HttpClient::new().get(&cleaned_url).send().await {
-            Ok(r) if r.status().is_success() => r,
-            Ok(r) => {
-                log::error!("kie download failed status={}", r.status());
-                return HttpResponse::InternalServerError().finish();
+    let Some(remover) = state.removers.for_upload(upload.provider.as_deref()) else {
+        log::error!(
+            "watermark callback for unknown provider upload_id={} provider={:?}",
+            upload.id,
+            upload.provider
+        );
+        return HttpResponse::InternalServerError().finish();
+    };
+
+    let callback = match remover.parse_callback(&body) {
+        Ok(c) => c,
+        Err(e) => {
+            log::warn!("watermark callback invalid payload provider={} error={}", remover.name(), e);
+            return HttpResponse::BadRequest().body("Invalid payload");
+        }
+    };
+    let task_id = callback.task_id;
+
+    // Задача должна совпадать с сохранённой (или ещё не успела сохраниться после создания)
+    if upload.task_id.as_deref().is_some_and(|stored| stored != task_id) {
+        log::warn!(
+            "watermark callback task mismatch upload_id={} task_id={}",
+            upload.id,
+            task_id
+        );
+        return HttpResponse::Unauthorized().finish();
+    }
+
+    let mut result_url = match (callback.status, callback.progress) {
+        (TaskStatus::Succeeded { result_url }, _) => result_url,
+        (TaskStatus::Pending, Some(progress)) => {
+            return record_progress(&state, upload.id, progress).await;
+        }
+        (TaskStatus::Failed { reason }, _) => {
+            log::warn!("watermark callback task failed task_id={} reason={:?}", task_id, reason);
+            return record_failure(&state, upload.id, reason.as_deref()).await;
+        }
+        (TaskStatus::Pending, None) => {
+            log::warn!("watermark callback without result task_id={}", task_id);
+            return HttpResponse::BadRequest().body("Task not finished");
+        }
+    };
+
+    // Дополнительно сверяемся с бэкендом: задача действительно завершена, берём ссылку оттуда
+    if remover.verify_callbacks() {
+        match remover.poll(&task_id).await {
+            Ok(TaskStatus::Succeeded { result_url: url }) => {
+                if url != result_url {
+                    log::warn!("watermark callback result url differs from provider task_id={}", task_id);
+                }
+                result_url = url;
             }
-            Err(e) => {
-                log::error!("kie download request error: {}", e);
-                return HttpResponse::InternalServerError().finish();
+            Ok(status) => {
+                log::warn!("watermark callback not confirmed task_id={} status={:?}", task_id, status);
+                return HttpResponse::BadRequest().body("Task not confirmed");
             }
-        };
-
-        let bytes = match resp.bytes().await {
-            Ok(b) => b,
             Err(e) => {
-                log::error!("kie download bytes error: {}", e);
+                log::error!("watermark callback poll error task_id={} error={}", task_id, e);
                 return HttpResponse::InternalServerError().finish();
             }
-        };
-
-        let stream = ByteStream::from(bytes);
-
-        if let Err(e) = state
-            .s3_client
-            .put_object()
-            .bucket(&state.s3_bucket)
-            .key(&s3_key)
-            .content_type("video/mp4")
-            .body(stream)
-            .send()
-            .await
-        {
-            log::error!("kie s3 upload failed: {}", e);
-            return HttpResponse::InternalServerError().finish();
         }
     }
 
-    // Обновляем БД (runtime query, чтобы сборка не зависела от наличия таблиц в DEV БД)
-    let cleaned_url = if std::env::var("MOCK_S3").unwrap_or_default() == "true" {
-        cleaned_url
-    } else {
-        build_public_url(&state.s3_public_base_url, &state.s3_bucket, &s3_key)
-    };
-
-    let _ = sqlx::query(
-        "UPDATE uploads SET cleaned_s3_key = $1, cleaned_url = $2, status = 'ready' WHERE task_id = $3",
-    )
-    .bind(&s3_key)
-    .bind(cleaned_url)
-    .bind(&task_id)
-    .execute(&state.pool)
-    .await;
-
-    notify_upload_by_task(&state.pool, &state.ws_hub, &task_id).await;
+    match finalize_upload(&state, upload.id, &task_id, &result_url).await {
+        Ok(FinalizeOutcome::Finalized { .. }) => {}
+        Ok(FinalizeOutcome::AlreadyFinalized) => {
+            log::info!("watermark callback for already finalized task_id={}", task_id);
+        }
+        Ok(FinalizeOutcome::Closed) => {
+            log::warn!("watermark callback for closed upload ignored task_id={}", task_id);
+        }
+        Err(e) => {
+            log::error!("watermark callback finalize failed task_id={} error={}", task_id, e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    }
 
-    log::info!("kie callback processed task_id={}", task_id);
+    log::info!("watermark callback processed provider={} task_id={}", remover.name(), task_id);
     HttpResponse::Ok().body("OK")
 }
 
@@ -147,36 +237,42 @@ async fn handle_watermark_callback(
     post,
     path = "/api/watermark-callback",
     tag = "webhooks",
-    request_body = CallbackPayload,
+    params(CallbackQuery),
+    request_body = crate::providers::kie::CallbackPayload,
     responses(
-        (status = 200, description = "Callback processed"),
-        (status = 400, description = "Task failed or invalid payload"),
+        (status = 200, description = "Callback processed: result stored, failure recorded with refund, or progress recorded"),
+        (status = 400, description = "Invalid payload or task not finished"),
+        (status = 401, description = "Missing or invalid callback token"),
         (status = 500, description = "Server error")
     )
 )]
 #[post("/api/watermark-callback")]
 pub async fn watermark_callback(
-    payload: web::Json<CallbackPayload>,
+    body: web::Json<Value>,
+    query: web::Query<CallbackQuery>,
     state: web::Data<AppState>,
 ) -> HttpResponse {
-    handle_watermark_callback(payload, state).await
+    handle_watermark_callback(body, query, state).await
 }
 
 #[utoipa::path(
     post,
     path = "/callback/api/watermark-callback",
     tag = "webhooks",
-    request_body = CallbackPayload,
+    params(CallbackQuery),
+    request_body = crate::providers::kie::CallbackPayload,
     responses(
-        (status = 200, description = "Callback processed"),
-        (status = 400, description = "Task failed or invalid payload"),
+        (status = 200, description = "Callback processed: result stored, failure recorded with refund, or progress recorded"),
+        (status = 400, description = "Invalid payload or task not finished"),
+        (status = 401, description = "Missing or invalid callback token"),
         (status = 500, description = "Server error")
     )
 )]
 #[post("/callback/api/watermark-callback")]
 pub async fn watermark_callback_alias(
-    payload: web::Json<CallbackPayload>,
+    body: web::Json<Value>,
+    query: web::Query<CallbackQuery>,
     state: web::Data<AppState>,
 ) -> HttpResponse {
-    handle_watermark_callback(payload, state).await
+    handle_watermark_callback(body, query, state).await
 }
@@ -8,6 +8,7 @@ use sqlx::Row;
 use std::collections::HashMap;
 use utoipa::ToSchema;
 
+use crate::outbox::{self, DomainEvent};
 use crate::{AppState, billing, db};
 
 /// Важно: точный payload Lava может отличаться.
@@ -166,12 +167,11 @@ fn value_to_string(value: &Value) -> Option<String> {
 
 fn extract_string(raw: &Value, keys: &[&str]) -> Option<String> {
     for key in keys {
-        if let Some(v) = get_nested(raw, key) {
-            if let Some(s) = value_to_string(v) {
-                if !s.is_empty() {
-                    return Some(s);
-                }
-            }
+        if let Some(v) = get_nested(raw, key)
+            && let Some(s) = value_to_string(v)
+            && !s.is_empty()
+        {
+            return Some(s);
         }
     }
     None
@@ -269,10 +269,10 @@ pub fn extract_api_key(req: &HttpRequest, payload: &Value) -> Option<String> {
         return Some(header.to_string());
     }
 
-    if let Ok(query) = serde_urlencoded::from_str::<HashMap<String, String>>(req.query_string()) {
-        if let Some(key) = query.get("api_key").or_else(|| query.get("apiKey")) {
-            return Some(key.clone());
-        }
+    if let Ok(query) = serde_urlencoded::from_str::<HashMap<String, String>>(req.query_string())
+        && let Some(key) = query.get("api_key").or_else(|| query.get("apiKey"))
+    {
+        return Some(key.clone());
     }
 
     extract_string(payload, &["apiKey", "api_key", "key"])
@@ -325,18 +325,13 @@ pub async fn lava_webhook(
     if is_event_cancelled(event_type) {
         let contract_id = provider_parent_order_id
             .as_deref()
-            .or_else(|| provider_order_id.as_deref());
+            .or(provider_order_id.as_deref());
 
-        if let Some(contract_id) = contract_id {
-            let _ = sqlx::query(
-                r#"UPDATE subscriptions
-                   SET status = 'canceled', canceled_at = NOW()
-                   WHERE provider = $1 AND provider_subscription_id = $2"#,
-            )
-            .bind(provider)
-            .bind(contract_id)
-            .execute(&state.pool)
-            .await;
+        if let Some(contract_id) = contract_id
+            && let Err(e) = cancel_subscriptions(&state, provider, contract_id).await
+        {
+            eprintln!("lava_webhook cancel subscription error: {e}");
+            return HttpResponse::InternalServerError().finish();
         }
 
         return HttpResponse::Ok().json(serde_json::json!({"ok": true, "canceled": true}));
@@ -538,7 +533,7 @@ pub async fn lava_webhook(
             };
 
             let _ = insert_result;
-            let tx_row = match sqlx::query(
+            match sqlx::query(
                 r#"SELECT id, user_id, product_id, status, amount::text as amount, currency
                    FROM transactions
                    WHERE provider = $1 AND provider_order_id = $2"#,
@@ -557,9 +552,7 @@ pub async fn lava_webhook(
                     eprintln!("lava_webhook select tx after insert error: {e}");
                     return HttpResponse::InternalServerError().finish();
                 }
-            };
-
-            tx_row
+            }
         }
     };
 
@@ -579,45 +572,44 @@ pub async fn lava_webhook(
                     .map(|existing| existing != order_id)
                     .unwrap_or(false)
             })
+        && let Some(order_id) = provider_order_id.as_deref()
     {
-        if let Some(order_id) = provider_order_id.as_deref() {
-            let amount = payload
-                .amount
-                .clone()
-                .unwrap_or_else(|| tx_row.get::<String, _>("amount"));
-            let currency = payload
-                .currency
-                .clone()
-                .unwrap_or_else(|| tx_row.get::<String, _>("currency"));
-
-            let insert_row = match sqlx::query(
-                r#"INSERT INTO transactions
-                   (user_id, product_id, provider, provider_order_id, provider_parent_order_id, amount, currency, status, type, payload)
-                   VALUES ($1, $2, $3, $4, $5, $6::numeric, $7, 'pending', 'payment', $8)
-                   RETURNING id"#,
-            )
-            .bind(user_id)
-            .bind(product_id)
-            .bind(provider)
-            .bind(order_id)
-            .bind(provider_parent_order_id.as_deref())
-            .bind(amount)
-            .bind(currency)
-            .bind(payload.raw.clone())
-            .fetch_one(&state.pool)
-            .await
-            {
-                Ok(r) => r,
-                Err(e) => {
-                    eprintln!("lava_webhook insert recurring tx error: {e}");
-                    return HttpResponse::InternalServerError().finish();
-                }
-            };
+        let amount = payload
+            .amount
+            .clone()
+            .unwrap_or_else(|| tx_row.get::<String, _>("amount"));
+        let currency = payload
+            .currency
+            .clone()
+            .unwrap_or_else(|| tx_row.get::<String, _>("currency"));
+
+        let insert_row = match sqlx::query(
+            r#"INSERT INTO transactions
+               (user_id, product_id, provider, provider_order_id, provider_parent_order_id, amount, currency, status, type, payload)
+               VALUES ($1, $2, $3, $4, $5, $6::numeric, $7, 'pending', 'payment', $8)
+               RETURNING id"#,
+        )
+        .bind(user_id)
+        .bind(product_id)
+        .bind(provider)
+        .bind(order_id)
+        .bind(provider_parent_order_id.as_deref())
+        .bind(amount)
+        .bind(currency)
+        .bind(payload.raw.clone())
+        .fetch_one(&state.pool)
+        .await
+        {
+            Ok(r) => r,
+            Err(e) => {
+                eprintln!("lava_webhook insert recurring tx error: {e}");
+                return HttpResponse::InternalServerError().finish();
+            }
+        };
 
-            tx_id = insert_row.get("id");
-            current_status = "pending".to_string();
-            created_tx = true;
-        }
+        tx_id = insert_row.get("id");
+        current_status = "pending".to_string();
+        created_tx = true;
     }
 
     if !created_tx && (current_status == "succeeded" || current_status == "failed") {
@@ -625,19 +617,34 @@ pub async fn lava_webhook(
     }
 
     if is_failed {
-        let _ = sqlx::query(
-            r#"UPDATE transactions
-               SET status = 'failed',
-                   provider_parent_order_id = COALESCE(provider_parent_order_id, $1),
-                   payload = COALESCE(payload, '{}'::jsonb) || $2::jsonb
-               WHERE id = $3"#,
-        )
-        .bind(provider_parent_order_id.as_deref())
-        .bind(payload.raw.clone())
-        .bind(tx_id)
-        .execute(&state.pool)
+        let result = async {
+            let mut db_tx = state.pool.begin().await?;
+            sqlx::query(
+                r#"UPDATE transactions
+                   SET status = 'failed',
+                       provider_parent_order_id = COALESCE(provider_parent_order_id, $1),
+                       payload = COALESCE(payload, '{}'::jsonb) || $2::jsonb
+                   WHERE id = $3"#,
+            )
+            .bind(provider_parent_order_id.as_deref())
+            .bind(payload.raw.clone())
+            .bind(tx_id)
+            .execute(&mut *db_tx)
+            .await?;
+            let event = DomainEvent::PaymentFailed {
+                transaction_id: tx_id,
+                user_id,
+            };
+            outbox::record(&mut *db_tx, &event).await?;
+            db_tx.commit().await
+        }
         .await;
+        if let Err(e) = result {
+            eprintln!("lava_webhook mark tx failed error: {e}");
+            return HttpResponse::InternalServerError().finish();
+        }
 
+        state.outbox_wakeup.notify_one();
         return HttpResponse::Ok().json(serde_json::json!({"ok": true}));
     }
 
@@ -646,19 +653,34 @@ pub async fn lava_webhook(
     }
 
     let paid_at = Utc::now();
-    let _ = sqlx::query(
-        r#"UPDATE transactions
-           SET status = 'succeeded', paid_at = $1,
-               provider_parent_order_id = COALESCE(provider_parent_order_id, $2),
-               payload = COALESCE(payload, '{}'::jsonb) || $3::jsonb
-           WHERE id = $4"#,
-    )
-    .bind(paid_at)
-    .bind(provider_parent_order_id.as_deref())
-    .bind(payload.raw.clone())
-    .bind(tx_id)
-    .execute(&state.pool)
+    let result = async {
+        let mut db_tx = state.pool.begin().await?;
+        sqlx::query(
+            r#"UPDATE transactions
+               SET status = 'succeeded', paid_at = $1,
+                   provider_parent_order_id = COALESCE(provider_parent_order_id, $2),
+                   payload = COALESCE(payload, '{}'::jsonb) || $3::jsonb
+               WHERE id = $4"#,
+        )
+        .bind(paid_at)
+        .bind(provider_parent_order_id.as_deref())
+        .bind(payload.raw.clone())
+        .bind(tx_id)
+        .execute(&mut *db_tx)
+        .await?;
+        let event = DomainEvent::PaymentSucceeded {
+            transaction_id: tx_id,
+            user_id,
+        };
+        outbox::record(&mut *db_tx, &event).await?;
+        db_tx.commit().await
+    }
     .await;
+    if let Err(e) = result {
+        eprintln!("lava_webhook mark tx succeeded error: {e}");
+        return HttpResponse::InternalServerError().finish();
+    }
+    state.outbox_wakeup.notify_one();
 
     let Some(product_id) = product_id else {
         return HttpResponse::Ok().json(serde_json::json!({"ok": true}));
@@ -708,10 +730,24 @@ pub async fn lava_webhook(
 
     if product_type == "one_time" {
         if let Some(c) = credits_granted {
-            if let Err(e) = billing::grant_one_time_credits(&state.pool, user_id, c).await {
+            let result = async {
+                let mut db_tx = state.pool.begin().await?;
+                billing::grant_one_time_credits(&mut *db_tx, user_id, c).await?;
+                let event = DomainEvent::CreditsGranted {
+                    user_id,
+                    transaction_id: Some(tx_id),
+                    credit_type: "one_time".to_string(),
+                    credits: c,
+                };
+                outbox::record(&mut *db_tx, &event).await?;
+                db_tx.commit().await
+            }
+            .await;
+            if let Err(e) = result {
                 eprintln!("grant_one_time_credits error: {e}");
                 return HttpResponse::InternalServerError().finish();
             }
+            state.outbox_wakeup.notify_one();
         }
         return HttpResponse::Ok().json(serde_json::json!({"ok": true}));
     }
@@ -720,38 +756,84 @@ pub async fn lava_webhook(
     let period_end = paid_at + Duration::days(30);
     let provider_subscription_id = provider_parent_order_id
         .as_deref()
-        .or_else(|| provider_order_id.as_deref());
-
-    let sub_id = match db::upsert_subscription_active(
-        &state.pool,
-        user_id,
-        product_id,
-        provider,
-        provider_subscription_id,
-        period_start,
-        period_end,
-    )
-    .await
-    {
-        Ok(id) => id,
-        Err(e) => {
-            eprintln!("upsert_subscription_active error: {e}");
-            return HttpResponse::InternalServerError().finish();
-        }
-    };
-
-    let _ = sqlx::query("UPDATE transactions SET subscription_id = $1 WHERE id = $2")
-        .bind(sub_id)
-        .bind(tx_id)
-        .execute(&state.pool)
-        .await;
-
-    if let Some(mc) = monthly_credits {
-        if let Err(e) = billing::set_subscription_monthly_quota(&state.pool, user_id, mc).await {
-            eprintln!("set_subscription_monthly_quota error: {e}");
-            return HttpResponse::InternalServerError().finish();
+        .or(provider_order_id.as_deref());
+
+    // Подписка, квота и события — одной транзакцией
+    let result = async {
+        let mut db_tx = state.pool.begin().await?;
+        let sub_id = db::upsert_subscription_active(
+            &mut *db_tx,
+            user_id,
+            product_id,
+            provider,
+            provider_subscription_id,
+            period_start,
+            period_end,
+        )
+        .await?;
+
+        sqlx::query("UPDATE transactions SET subscription_id = $1 WHERE id = $2")
+            .bind(sub_id)
+            .bind(tx_id)
+            .execute(&mut *db_tx)
+            .await?;
+        let event = DomainEvent::SubscriptionRenewed {
+            subscription_id: sub_id,
+            user_id,
+            transaction_id: Some(tx_id),
+        };
+        outbox::record(&mut *db_tx, &event).await?;
+
+        if let Some(mc) = monthly_credits {
+            billing::set_subscription_monthly_quota(&mut *db_tx, user_id, mc).await?;
+            let event = DomainEvent::CreditsGranted {
+                user_id,
+                transaction_id: Some(tx_id),
+                credit_type: "monthly".to_string(),
+                credits: mc,
+            };
+            outbox::record(&mut *db_tx, &event).await?;
         }
+        db_tx.commit().await
+    }
+    .await;
+    if let Err(e) = result {
+        eprintln!("lava_webhook subscription activation error: {e}");
+        return HttpResponse::InternalServerError().finish();
     }
+    state.outbox_wakeup.notify_one();
 
     HttpResponse::Ok().json(serde_json::json!({"ok": true}))
 }
+
+/// Отменяет подписки по контракту Lava и пишет `subscription.canceled` для каждой.
+async fn cancel_subscriptions(
+    state: &AppState,
+    provider: &str,
+    contract_id: &str,
+) -> Result<(), sqlx::Error> {
+    let mut db_tx = state.pool.begin().await?;
+    let canceled = sqlx::query(
+        r#"UPDATE subscriptions
+           SET status = 'canceled', canceled_at = NOW()
+           WHERE provider = $1 AND provider_subscription_id = $2
+           RETURNING id, user_id"#,
+    )
+    .bind(provider)
+    .bind(contract_id)
+    .fetch_all(&mut *db_tx)
+    .await?;
+
+    for row in &canceled {
+        let event = DomainEvent::SubscriptionCanceled {
+            subscription_id: row.get("id"),
+            user_id: row.get("user_id"),
+        };
+        outbox::record(&mut *db_tx, &event).await?;
+    }
+    db_tx.commit().await?;
+    if !canceled.is_empty() {
+        state.outbox_wakeup.notify_one();
+    }
+    Ok(())
+}
@@ -1,14 +1,19 @@
 // src/billing.rs
 
+use actix::Addr;
 use chrono::{DateTime, Duration, Utc};
-use sqlx::{PgPool, Row};
+use sqlx::{PgConnection, PgExecutor, PgPool, Row};
 
 use crate::db;
+use crate::events::{self, AccountEvent};
+use crate::outbox::{self, DomainEvent};
+use crate::ws::WsHub;
 
 /// Обновляет месячную квоту пользователю при наличии активной подписки.
 /// Логика:
 /// - если есть эффективная подписка (active/canceled, но период не закончился)
 /// - и quota_reset_at NULL или <= now
+///
 /// тогда ставим monthly_quota = monthly_credits и quota_reset_at = now + 30 дней.
 ///
 /// Примечание: мы используем фиксированные 30 дней (без привязки к календарным месяцам),
@@ -46,13 +51,15 @@ pub async fn refresh_monthly_quota(pool: &PgPool, user_id: i32) -> Result<(), sq
     Ok(())
 }
 
-/// Возвращает тип кредита, который можно списать сейчас:
-/// - "monthly" если есть месячная квота (>0)
-/// - иначе "one_time" если есть разовые кредиты (>0)
+/// Возвращает тип кредита, которым можно оплатить обработку стоимостью `cost`:
+/// - "monthly" если месячной квоты хватает
+/// - иначе "one_time" если хватает разовых кредитов
+/// - иначе "free" (бесплатная обработка покрывает только стоимость 1)
 /// - иначе None
 pub async fn can_remove_watermark(
     pool: &PgPool,
     user_id: i32,
+    cost: i32,
 ) -> Result<Option<String>, sqlx::Error> {
     // На всякий случай обновим квоту перед проверкой
     let _ = refresh_monthly_quota(pool, user_id).await;
@@ -65,40 +72,121 @@ pub async fn can_remove_watermark(
     let credits: i32 = row.get("credits");
     let monthly_quota: i32 = row.get("monthly_quota");
     let free_generation_used: bool = row.get("free_generation_used");
+    let cost = cost.max(1);
 
-    if monthly_quota > 0 {
+    if monthly_quota >= cost {
         Ok(Some("monthly".to_string()))
-    } else if credits > 0 {
+    } else if credits >= cost {
         Ok(Some("one_time".to_string()))
-    } else if !free_generation_used {
+    } else if !free_generation_used && cost == 1 {
         Ok(Some("free".to_string()))
     } else {
         Ok(None)
     }
 }
 
+/// Порог «заканчиваются кредиты» из `LOW_BALANCE_THRESHOLD` (3 по умолчанию, 0 — выключено).
+pub fn low_balance_threshold() -> i32 {
+    std::env::var("LOW_BALANCE_THRESHOLD")
+        .ok()
+        .and_then(|v| v.trim().parse::<i32>().ok())
+        .unwrap_or(3)
+        .max(0)
+}
+
+/// Списывает `amount` кредитов указанного типа (для "free" — отмечает бесплатную обработку)
+/// в транзакции вызывающего. Если кредитов этого типа не хватает, ничего не меняет и
+/// возвращает `None`: транзакцию нужно откатить. `Some(true)` — баланс (`credits` +
+/// `monthly_quota`) опустился ниже `low_balance_threshold()` и в той же транзакции записан
+/// `credits.low`; после коммита нужно разбудить outbox.
 pub async fn consume_credit(
-    pool: &PgPool,
+    conn: &mut PgConnection,
     user_id: i32,
     credit_type: &str,
-) -> Result<(), sqlx::Error> {
-    match credit_type {
+    amount: i32,
+) -> Result<Option<bool>, sqlx::Error> {
+    let before: i32 =
+        sqlx::query("SELECT credits + monthly_quota AS balance FROM users WHERE id = $1 FOR UPDATE")
+            .bind(user_id)
+            .fetch_one(&mut *conn)
+            .await?
+            .get("balance");
+
+    let charged = match credit_type {
         "monthly" => {
             sqlx::query(
-                "UPDATE users SET monthly_quota = GREATEST(monthly_quota - 1, 0) WHERE id = $1",
+                "UPDATE users SET monthly_quota = monthly_quota - $1 WHERE id = $2 AND monthly_quota >= $1",
             )
+            .bind(amount)
             .bind(user_id)
-            .execute(pool)
-            .await?;
+            .execute(&mut *conn)
+            .await?
+        }
+        "free" => {
+            sqlx::query(
+                "UPDATE users SET free_generation_used = true WHERE id = $1 AND NOT free_generation_used",
+            )
+            .bind(user_id)
+            .execute(&mut *conn)
+            .await?
+        }
+        _ => {
+            sqlx::query("UPDATE users SET credits = credits - $1 WHERE id = $2 AND credits >= $1")
+                .bind(amount)
+                .bind(user_id)
+                .execute(&mut *conn)
+                .await?
+        }
+    };
+    if charged.rows_affected() == 0 {
+        return Ok(None);
+    }
+
+    let row = sqlx::query("SELECT credits, monthly_quota FROM users WHERE id = $1")
+        .bind(user_id)
+        .fetch_one(&mut *conn)
+        .await?;
+    let credits: i32 = row.get("credits");
+    let monthly_quota: i32 = row.get("monthly_quota");
+    let threshold = low_balance_threshold();
+    // Только при пересечении порога, чтобы не слать письмо на каждое списание
+    let crossed = before >= threshold && credits + monthly_quota < threshold;
+    if crossed {
+        let event = DomainEvent::CreditsLow {
+            user_id,
+            credits,
+            monthly_quota,
+        };
+        outbox::record(&mut *conn, &event).await?;
+    }
+
+    Ok(Some(crossed))
+}
+
+/// Возвращает `amount` кредитов, списанных `consume_credit` (задачу так и не удалось запустить).
+pub async fn refund_credit<'e>(
+    pool: impl PgExecutor<'e>,
+    user_id: i32,
+    credit_type: &str,
+    amount: i32,
+) -> Result<(), sqlx::Error> {
+    match credit_type {
+        "monthly" => {
+            sqlx::query("UPDATE users SET monthly_quota = monthly_quota + $1 WHERE id = $2")
+                .bind(amount)
+                .bind(user_id)
+                .execute(pool)
+                .await?;
         }
         "free" => {
-            sqlx::query("UPDATE users SET free_generation_used = true WHERE id = $1")
+            sqlx::query("UPDATE users SET free_generation_used = false WHERE id = $1")
                 .bind(user_id)
                 .execute(pool)
                 .await?;
         }
         _ => {
-            sqlx::query("UPDATE users SET credits = GREATEST(credits - 1, 0) WHERE id = $1")
+            sqlx::query("UPDATE users SET credits = credits + $1 WHERE id = $2")
+                .bind(amount)
                 .bind(user_id)
                 .execute(pool)
                 .await?;
@@ -108,8 +196,52 @@ pub async fn consume_credit(
     Ok(())
 }
 
-pub async fn grant_one_time_credits(
-    pool: &PgPool,
+/// Переводит загрузку из `from_status` в `failed`, возвращает списанный за неё `credit_cost`
+/// и записывает `upload.failed` — всё в транзакции вызывающего. `false`, если загрузка уже
+/// не в `from_status` (её закрыл другой путь). После коммита нужно разбудить outbox.
+pub async fn fail_upload_with_refund(
+    conn: &mut PgConnection,
+    upload_id: i32,
+    from_status: &str,
+    reason: &str,
+) -> Result<bool, sqlx::Error> {
+    let Some(row) = sqlx::query(
+        r#"UPDATE uploads
+           SET status = 'failed', last_error = $3, next_check_at = NULL, next_submit_at = NULL
+           WHERE id = $1 AND status = $2
+           RETURNING user_id, used_credit_type, credit_cost"#,
+    )
+    .bind(upload_id)
+    .bind(from_status)
+    .bind(reason)
+    .fetch_optional(&mut *conn)
+    .await?
+    else {
+        return Ok(false);
+    };
+    let user_id: i32 = row.get("user_id");
+    let used_credit_type: Option<String> = row.get("used_credit_type");
+    let credit_cost: i32 = row.get("credit_cost");
+
+    let mut refunded = false;
+    if let Some(credit_type) = used_credit_type.as_deref()
+        && credit_cost > 0
+    {
+        refund_credit(&mut *conn, user_id, credit_type, credit_cost).await?;
+        refunded = true;
+    }
+    let event = DomainEvent::UploadFailed {
+        upload_id,
+        user_id,
+        reason: Some(reason.to_string()),
+        refunded,
+    };
+    outbox::record(&mut *conn, &event).await?;
+    Ok(true)
+}
+
+pub async fn grant_one_time_credits<'e>(
+    pool: impl PgExecutor<'e>,
     user_id: i32,
     credits: i32,
 ) -> Result<(), sqlx::Error> {
@@ -122,8 +254,19 @@ pub async fn grant_one_time_credits(
     Ok(())
 }
 
-pub async fn set_subscription_monthly_quota(
-    pool: &PgPool,
+/// Отправляет пользователю `credits.updated` с текущим балансом (после списания,
+/// возврата или покупки), чтобы дашборду не нужно было опрашивать `/api/credits`.
+pub async fn notify_credits(pool: &PgPool, hub: &Addr<WsHub>, user_id: i32) {
+    match events::load_credits(pool, user_id).await {
+        Ok(credits) => {
+            events::emit(pool, hub, user_id, &AccountEvent::CreditsUpdated(credits)).await
+        }
+        Err(e) => log::error!("credits event error user_id={} error={}", user_id, e),
+    }
+}
+
+pub async fn set_subscription_monthly_quota<'e>(
+    pool: impl PgExecutor<'e>,
     user_id: i32,
     monthly_credits: i32,
 ) -> Result<(), sqlx::Error> {
@@ -0,0 +1,78 @@
+// src/bin/worker.rs
+//
+// Отдельный процесс для фоновых воркеров: отправка загрузок бэкендам, проверка статусов
+// задач, обслуживание. API в этом случае запускается с DISABLE_WORKERS=true, а воркеров
+// можно держать сколько угодно (очереди разбираются с `FOR UPDATE SKIP LOCKED`).
+// Останавливается по SIGINT/SIGTERM: воркеры доделывают текущие пачки, но не дольше
+// WORKER_SHUTDOWN_TIMEOUT_SECS.
+
+use actix_web::rt;
+use dotenvy::dotenv;
+use futures_util::future::select;
+use sqlx::PgPool;
+use std::env;
+use std::pin::pin;
+
+use sora_watermark_remov::{AppState, worker};
+
+async fn wait_for_signal() {
+    let ctrl_c = pin!(async {
+        let _ = rt::signal::ctrl_c().await;
+    });
+
+    #[cfg(unix)]
+    {
+        use rt::signal::unix::{SignalKind, signal};
+        match signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                let terminate = pin!(async move {
+                    sigterm.recv().await;
+                });
+                select(ctrl_c, terminate).await;
+            }
+            Err(e) => {
+                log::warn!("SIGTERM handler error: {e}");
+                ctrl_c.await;
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    ctrl_c.await;
+}
+
+#[actix_web::main]
+async fn main() -> std::io::Result<()> {
+    dotenv().ok();
+    env_logger::init_from_env(env_logger::Env::default().default_filter_or("info"));
+
+    let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+    let pool = PgPool::connect(&database_url)
+        .await
+        .expect("Failed to connect to DB");
+
+    sqlx::migrate!()
+        .run(&pool)
+        .await
+        .expect("Failed to run migrations");
+
+    let state = AppState::from_env(pool).await;
+    let config = worker::WorkerConfig::from_env();
+    let (shutdown_trigger, shutdown) = worker::shutdown_channel();
+    let workers = worker::spawn_workers(&state, &config, &shutdown);
+    if workers.is_empty() {
+        log::warn!("all workers are disabled, nothing to do");
+        return Ok(());
+    }
+
+    wait_for_signal().await;
+    log::info!(
+        "shutting down workers, waiting up to {}s",
+        config.shutdown_timeout.as_secs()
+    );
+    shutdown_trigger.trigger();
+    worker::join_workers(workers, config.shutdown_timeout).await;
+    state.removers.shutdown(config.shutdown_timeout).await;
+    log::info!("worker stopped");
+    Ok(())
+}
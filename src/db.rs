@@ -1,9 +1,9 @@
 // src/db.rs
 
 use chrono::{DateTime, Utc};
-use sqlx::{PgPool, Row};
+use sqlx::{PgExecutor, PgPool, Row};
 
-use crate::models::{Product, Subscription};
+use crate::models::{ProcessingProfile, Product, Subscription};
 
 pub async fn list_active_products(pool: &PgPool) -> Result<Vec<Product>, sqlx::Error> {
     let rows = sqlx::query(
@@ -67,6 +67,123 @@ pub async fn get_product_by_slug(
     }))
 }
 
+/// slug продукта действующей подписки пользователя (тариф), если она есть.
+pub async fn get_user_plan_slug(pool: &PgPool, user_id: i32) -> Result<Option<String>, sqlx::Error> {
+    let row = sqlx::query(
+        r#"SELECT p.slug
+           FROM subscriptions s
+           JOIN products p ON p.id = s.product_id
+           WHERE s.user_id = $1
+             AND s.status IN ('active', 'canceled')
+             AND (s.current_period_end IS NULL OR s.current_period_end > NOW())
+           ORDER BY s.created_at DESC
+           LIMIT 1"#,
+    )
+    .bind(user_id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row.map(|r| r.get("slug")))
+}
+
+/// Профиль, который используется, если ни тариф, ни запрос его не задают.
+pub const DEFAULT_PROFILE_SLUG: &str = "standard";
+
+const PROFILE_COLUMNS: &str = r#"pp.id, pp.slug, pp.name, pp.description, pp.kie_model,
+                  pp.kie_input::text AS kie_input, pp.credit_cost, pp.is_public"#;
+
+fn profile_from_row(r: sqlx::postgres::PgRow) -> ProcessingProfile {
+    let slug: String = r.get("slug");
+    let raw_input: String = r.get("kie_input");
+    let kie_input = match serde_json::from_str::<serde_json::Value>(&raw_input) {
+        Ok(serde_json::Value::Object(map)) => map,
+        _ => {
+            log::warn!("processing profile {} has invalid kie_input, ignoring it", slug);
+            serde_json::Map::new()
+        }
+    };
+    ProcessingProfile {
+        id: r.get("id"),
+        slug,
+        name: r.get("name"),
+        description: r.get("description"),
+        kie_model: r.get("kie_model"),
+        kie_input,
+        credit_cost: r.get("credit_cost"),
+        is_public: r.get("is_public"),
+    }
+}
+
+/// Профили, доступные на тарифе `plan` (публичные + привязанные к продукту тарифа).
+pub async fn list_processing_profiles(
+    pool: &PgPool,
+    plan: Option<&str>,
+) -> Result<Vec<ProcessingProfile>, sqlx::Error> {
+    let rows = sqlx::query(&format!(
+        r#"SELECT {PROFILE_COLUMNS}
+           FROM processing_profiles pp
+           WHERE pp.is_active = true
+             AND (pp.is_public
+                  OR EXISTS (SELECT 1
+                             FROM product_processing_profiles ppp
+                             JOIN products p ON p.id = ppp.product_id
+                             WHERE ppp.profile_id = pp.id AND p.slug = $1))
+           ORDER BY pp.credit_cost ASC, pp.id ASC"#
+    ))
+    .bind(plan)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows.into_iter().map(profile_from_row).collect())
+}
+
+/// Профиль для новой загрузки.
+/// Запрошенный `slug` должен быть доступен на тарифе, иначе `None`.
+/// Без запроса — профиль тарифа по умолчанию, затем `DEFAULT_PROFILE_SLUG`.
+pub async fn resolve_processing_profile(
+    pool: &PgPool,
+    plan: Option<&str>,
+    slug: Option<&str>,
+) -> Result<Option<ProcessingProfile>, sqlx::Error> {
+    if let Some(slug) = slug {
+        let available = list_processing_profiles(pool, plan).await?;
+        return Ok(available.into_iter().find(|p| p.slug == slug));
+    }
+
+    let row = sqlx::query(&format!(
+        r#"SELECT {PROFILE_COLUMNS}
+           FROM processing_profiles pp
+           LEFT JOIN products p ON p.default_profile_id = pp.id AND p.slug = $1
+           WHERE pp.is_active = true
+             AND (p.id IS NOT NULL OR pp.slug = $2)
+           ORDER BY (p.id IS NOT NULL) DESC
+           LIMIT 1"#
+    ))
+    .bind(plan)
+    .bind(DEFAULT_PROFILE_SLUG)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row.map(profile_from_row))
+}
+
+/// Профиль по slug без проверки тарифа (профиль уже выбран при создании загрузки).
+pub async fn get_processing_profile(
+    pool: &PgPool,
+    slug: &str,
+) -> Result<Option<ProcessingProfile>, sqlx::Error> {
+    let row = sqlx::query(&format!(
+        r#"SELECT {PROFILE_COLUMNS}
+           FROM processing_profiles pp
+           WHERE pp.slug = $1"#
+    ))
+    .bind(slug)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row.map(profile_from_row))
+}
+
 /// Возвращает подписку, которая даёт доступ к квоте прямо сейчас.
 /// Важно: `status = 'canceled'` всё ещё считается активной до конца оплаченного периода.
 pub async fn get_effective_subscription(
@@ -145,12 +262,13 @@ pub async fn list_user_subscriptions(
         .collect())
 }
 
-pub async fn cancel_user_subscription(
-    pool: &PgPool,
+/// `false`, если подписки нет или она чужая.
+pub async fn cancel_user_subscription<'e>(
+    pool: impl PgExecutor<'e>,
     user_id: i32,
     subscription_id: i32,
-) -> Result<(), sqlx::Error> {
-    sqlx::query(
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
         r#"UPDATE subscriptions
            SET status = 'canceled', canceled_at = NOW()
            WHERE id = $1 AND user_id = $2"#,
@@ -160,11 +278,11 @@ pub async fn cancel_user_subscription(
     .execute(pool)
     .await?;
 
-    Ok(())
+    Ok(result.rows_affected() > 0)
 }
 
-pub async fn upsert_subscription_active(
-    pool: &PgPool,
+pub async fn upsert_subscription_active<'e>(
+    pool: impl PgExecutor<'e>,
     user_id: i32,
     product_id: i32,
     provider: &str,
@@ -0,0 +1,145 @@
+// src/dedup.rs
+//
+// Дедупликация источников: одну и ту же ссылку не отправляем в KIE повторно, если готовый
+// результат уже лежит у нас в хранилище. Загрузки приходят только ссылками, а видео качает
+// бэкенд уже после решения о дедупликации, поэтому сравниваются только отпечатки
+// нормализованных ссылок: одно и то же видео по разным ссылкам обработается заново.
+
+use reqwest::Url;
+use sha2::{Digest, Sha256};
+use sqlx::{PgPool, Row};
+
+/// Query-параметры, которые не влияют на само видео (трекинг, шаринг).
+const IGNORED_QUERY_PARAMS: &[&str] = &["fbclid", "gclid", "yclid", "ref", "si"];
+
+/// Где искать готовый результат.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DedupScope {
+    /// Дедупликация выключена
+    Off,
+    /// Только среди загрузок того же пользователя (по умолчанию)
+    User,
+    /// Среди всех загрузок сервиса
+    Global,
+}
+
+impl DedupScope {
+    /// `DEDUP_SCOPE=off|user|global`
+    pub fn from_env() -> Self {
+        match std::env::var("DEDUP_SCOPE")
+            .unwrap_or_default()
+            .trim()
+            .to_lowercase()
+            .as_str()
+        {
+            "off" | "false" | "none" => Self::Off,
+            "global" => Self::Global,
+            _ => Self::User,
+        }
+    }
+}
+
+/// Списывать ли кредит, когда отдаём закешированный результат (`DEDUP_CHARGE_CACHED`).
+pub fn charge_cached_from_env() -> bool {
+    std::env::var("DEDUP_CHARGE_CACHED").unwrap_or_default() == "true"
+}
+
+/// Приводит ссылку к каноническому виду для сравнения:
+/// - схема http/https считается одинаковой
+/// - host в нижнем регистре, без `www.` и порта по умолчанию
+/// - без фрагмента, трекинговых параметров и завершающего `/`
+/// - оставшиеся query-параметры отсортированы
+///
+/// Возвращает `None`, если строка не является http(s) ссылкой.
+pub fn normalize_source_url(raw: &str) -> Option<String> {
+    let url = Url::parse(raw.trim()).ok()?;
+    if url.scheme() != "http" && url.scheme() != "https" {
+        return None;
+    }
+
+    let host = url.host_str()?.to_lowercase();
+    let host = host.strip_prefix("www.").unwrap_or(&host);
+
+    let mut path = url.path().to_string();
+    while path.len() > 1 && path.ends_with('/') {
+        path.pop();
+    }
+
+    let mut params: Vec<(String, String)> = url
+        .query_pairs()
+        .filter(|(k, _)| {
+            let key = k.to_lowercase();
+            !key.starts_with("utm_") && !IGNORED_QUERY_PARAMS.contains(&key.as_str())
+        })
+        .map(|(k, v)| (k.into_owned(), v.into_owned()))
+        .collect();
+    params.sort();
+
+    let mut normalized = format!("https://{host}");
+    if let Some(port) = url.port() {
+        normalized.push_str(&format!(":{port}"));
+    }
+    normalized.push_str(&path);
+    if !params.is_empty() {
+        let query = serde_urlencoded::to_string(&params).unwrap_or_default();
+        normalized.push('?');
+        normalized.push_str(&query);
+    }
+
+    Some(normalized)
+}
+
+/// Отпечаток нормализованной ссылки (sha256 hex). Содержимое видео не учитывается.
+pub fn url_fingerprint(normalized_url: &str) -> String {
+    content_hash(normalized_url.as_bytes())
+}
+
+/// Хеш произвольных байтов (sha256 hex).
+pub fn content_hash(bytes: &[u8]) -> String {
+    hex::encode(Sha256::digest(bytes))
+}
+
+#[derive(Debug)]
+pub struct CachedResult {
+    pub upload_id: i32,
+    pub cleaned_s3_key: String,
+    pub cleaned_url: String,
+}
+
+/// Ищет последнюю готовую загрузку с тем же отпечатком ссылки.
+/// Учитываются только результаты, сохранённые в нашем хранилище: временные ссылки KIE
+/// со временем протухают.
+pub async fn find_cached_result(
+    pool: &PgPool,
+    scope: DedupScope,
+    user_id: i32,
+    source_fingerprint: &str,
+) -> Result<Option<CachedResult>, sqlx::Error> {
+    let owner = match scope {
+        DedupScope::Off => return Ok(None),
+        DedupScope::User => Some(user_id),
+        DedupScope::Global => None,
+    };
+
+    let row = sqlx::query(
+        r#"SELECT id, cleaned_s3_key, cleaned_url
+           FROM uploads
+           WHERE status = 'ready'
+             AND cleaned_s3_key IS NOT NULL
+             AND cleaned_url IS NOT NULL
+             AND source_fingerprint = $1
+             AND ($2::int IS NULL OR user_id = $2)
+           ORDER BY created_at DESC
+           LIMIT 1"#,
+    )
+    .bind(source_fingerprint)
+    .bind(owner)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row.map(|r| CachedResult {
+        upload_id: r.get("id"),
+        cleaned_s3_key: r.get("cleaned_s3_key"),
+        cleaned_url: r.get("cleaned_url"),
+    }))
+}
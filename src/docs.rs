@@ -8,7 +8,16 @@ use utoipa::OpenApi;
         crate::api::handlers::upload,
         crate::api::auth::verify_email,
         crate::api::webhooks::watermark_callback,
-        crate::api::webhooks::watermark_callback_alias
+        crate::api::webhooks::watermark_callback_alias,
+        crate::api::user_webhooks::list_webhooks,
+        crate::api::user_webhooks::create_webhook,
+        crate::api::user_webhooks::update_webhook,
+        crate::api::user_webhooks::delete_webhook,
+        crate::api::user_webhooks::list_webhook_deliveries,
+        crate::api::user_webhooks::redeliver_webhook,
+        crate::api::email_preferences::get_email_preferences,
+        crate::api::email_preferences::update_email_preferences,
+        crate::api::email_preferences::unsubscribe
     ),
     components(
         schemas(
@@ -17,15 +26,30 @@ use utoipa::OpenApi;
             crate::api::auth::AuthResponse,
             crate::api::handlers::UrlUploadBody,
             crate::api::handlers::UploadResponse,
-            crate::api::webhooks::CallbackPayload,
-            crate::api::webhooks::CallbackData,
-            crate::api::webhooks_lava::LavaWebhook
+            crate::providers::kie::CallbackPayload,
+            crate::providers::kie::CallbackData,
+            crate::api::webhooks_lava::LavaWebhook,
+            crate::events::EventEnvelope,
+            crate::ws::UploadEventData,
+            crate::events::CreditsEvent,
+            crate::events::PaymentEvent,
+            crate::events::SubscriptionEvent,
+            crate::api::user_webhooks::CreateWebhookRequest,
+            crate::api::user_webhooks::UpdateWebhookRequest,
+            crate::api::user_webhooks::CreatedWebhook,
+            crate::user_webhooks::WebhookEndpoint,
+            crate::user_webhooks::WebhookDelivery,
+            crate::emails::EmailPreferences,
+            crate::emails::EmailPreferencesUpdate
         )
     ),
     tags(
         (name = "auth", description = "Authentication"),
         (name = "uploads", description = "Video uploads"),
-        (name = "webhooks", description = "Callbacks from Kie.ai")
+        (name = "webhooks", description = "Callbacks from Kie.ai"),
+        (name = "user-webhooks", description = "Outgoing webhooks: domain events of the account are POSTed to registered endpoints, signed with `X-Webhook-Signature`"),
+        (name = "emails", description = "Transactional email preferences and one-click unsubscribe"),
+        (name = "events", description = "Realtime events over WebSocket `/ws/uploads` and SSE `/api/events`, schema version 1: see `EventEnvelope` and the `*Event` schemas")
     )
 )]
 pub struct ApiDoc;
@@ -0,0 +1,259 @@
+// src/emails/mailer.rs
+//
+// Куда уходят письма: SMTP (прод), файлы `.eml` или лог (разработка), память (тесты).
+// Выбирается `MAILER`; отправляет письма только воркер `email_outbox` (`super::sender`).
+
+use async_trait::async_trait;
+use lettre::message::header::{HeaderName, HeaderValue};
+use lettre::message::{Mailbox, Message, MultiPart};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Tokio1Executor};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use uuid::Uuid;
+
+use super::RenderedEmail;
+
+/// Ошибка отправки. `permanent` — повтор не поможет (адрес отклонён, «жёсткий» отказ SMTP 5xx).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MailError {
+    pub message: String,
+    pub permanent: bool,
+}
+
+impl MailError {
+    pub fn temporary(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            permanent: false,
+        }
+    }
+
+    pub fn permanent(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            permanent: true,
+        }
+    }
+}
+
+impl std::fmt::Display for MailError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    async fn send(&self, email: &RenderedEmail) -> Result<(), MailError>;
+}
+
+/// `MAILER`: `smtp`, `file` (`EMAIL_FILE_DIR`) или `log`. По умолчанию SMTP, если заданы
+/// `SMTP_HOST` и `SMTP_FROM`, иначе лог.
+pub fn from_env() -> Arc<dyn Mailer> {
+    let smtp_configured = std::env::var("SMTP_HOST").is_ok() && std::env::var("SMTP_FROM").is_ok();
+    let kind = std::env::var("MAILER")
+        .ok()
+        .map(|v| v.trim().to_ascii_lowercase())
+        .unwrap_or_else(|| if smtp_configured { "smtp" } else { "log" }.to_string());
+    match kind.as_str() {
+        "smtp" => Arc::new(SmtpMailer::from_env().expect("MAILER=smtp requires valid SMTP_*")),
+        "file" => Arc::new(FileMailer::new(
+            std::env::var("EMAIL_FILE_DIR").unwrap_or_else(|_| "tmp/emails".to_string()),
+        )),
+        "log" => Arc::new(LogMailer),
+        other => panic!("unknown MAILER {other}, expected smtp, file or log"),
+    }
+}
+
+/// Отправитель из `SMTP_FROM`; для файлового и лог-режима подойдёт и заглушка.
+fn from_mailbox() -> Result<Mailbox, String> {
+    std::env::var("SMTP_FROM")
+        .unwrap_or_else(|_| "Sora Clean <no-reply@localhost>".to_string())
+        .parse::<Mailbox>()
+        .map_err(|e| format!("invalid SMTP_FROM: {e}"))
+}
+
+/// Письмо lettre: text + HTML (`multipart/alternative`) и `List-Unsubscribe` для
+/// необязательных писем.
+fn build_message(from: Mailbox, email: &RenderedEmail) -> Result<Message, MailError> {
+    let to: Mailbox = email
+        .to
+        .parse::<Mailbox>()
+        .map_err(|e| MailError::permanent(format!("invalid recipient: {e}")))?;
+    let mut builder = Message::builder().from(from).to(to).subject(&email.subject);
+    if let Some(url) = &email.unsubscribe_url {
+        builder = builder
+            .raw_header(HeaderValue::new(
+                HeaderName::new_from_ascii_str("List-Unsubscribe"),
+                format!("<{url}>"),
+            ))
+            .raw_header(HeaderValue::new(
+                HeaderName::new_from_ascii_str("List-Unsubscribe-Post"),
+                "List-Unsubscribe=One-Click".to_string(),
+            ));
+    }
+    builder
+        .multipart(MultiPart::alternative_plain_html(
+            email.text.clone(),
+            email.html.clone(),
+        ))
+        .map_err(|e| MailError::permanent(e.to_string()))
+}
+
+/// SMTP из `SMTP_HOST`, `SMTP_PORT`, `SMTP_SECURITY` (`ssl`, `starttls`, `none`),
+/// `SMTP_USER`, `SMTP_PASS`, `SMTP_FROM`. Транспорт с пулом соединений создаётся один раз.
+pub struct SmtpMailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+impl SmtpMailer {
+    pub fn from_env() -> Result<Self, String> {
+        let host = std::env::var("SMTP_HOST").map_err(|_| "SMTP_HOST must be set".to_string())?;
+        if std::env::var("SMTP_FROM").is_err() {
+            return Err("SMTP_FROM must be set".to_string());
+        }
+        let from = from_mailbox()?;
+        let port = std::env::var("SMTP_PORT")
+            .ok()
+            .and_then(|p| p.parse::<u16>().ok())
+            .unwrap_or(587);
+        let security = std::env::var("SMTP_SECURITY").unwrap_or_else(|_| {
+            if port == 465 {
+                "ssl".to_string()
+            } else {
+                "starttls".to_string()
+            }
+        });
+
+        let builder = match security.as_str() {
+            "ssl" => {
+                AsyncSmtpTransport::<Tokio1Executor>::relay(&host).map_err(|e| e.to_string())?
+            }
+            "none" => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host),
+            _ => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&host)
+                .map_err(|e| e.to_string())?,
+        };
+        let builder = builder.port(port);
+        let transport = match (std::env::var("SMTP_USER"), std::env::var("SMTP_PASS")) {
+            (Ok(user), Ok(pass)) => builder.credentials(Credentials::new(user, pass)).build(),
+            _ => builder.build(),
+        };
+        Ok(Self { transport, from })
+    }
+}
+
+#[async_trait]
+impl Mailer for SmtpMailer {
+    fn name(&self) -> &'static str {
+        "smtp"
+    }
+
+    async fn send(&self, email: &RenderedEmail) -> Result<(), MailError> {
+        let message = build_message(self.from.clone(), email)?;
+        self.transport
+            .send(message)
+            .await
+            .map(|_| ())
+            .map_err(|e| MailError {
+                permanent: e.is_permanent(),
+                message: e.to_string(),
+            })
+    }
+}
+
+/// Пишет каждое письмо в `<dir>/<время>-<uuid>.eml`; файл открывается почтовым клиентом.
+pub struct FileMailer {
+    dir: PathBuf,
+}
+
+impl FileMailer {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+}
+
+#[async_trait]
+impl Mailer for FileMailer {
+    fn name(&self) -> &'static str {
+        "file"
+    }
+
+    async fn send(&self, email: &RenderedEmail) -> Result<(), MailError> {
+        let from = from_mailbox().map_err(MailError::permanent)?;
+        let message = build_message(from, email)?;
+        std::fs::create_dir_all(&self.dir).map_err(|e| MailError::temporary(e.to_string()))?;
+        let path = self.dir.join(format!(
+            "{}-{}.eml",
+            chrono::Utc::now().format("%Y%m%dT%H%M%S"),
+            Uuid::new_v4().simple()
+        ));
+        std::fs::write(&path, message.formatted())
+            .map_err(|e| MailError::temporary(e.to_string()))?;
+        log::info!("email {} written to {}", email.kind, path.display());
+        Ok(())
+    }
+}
+
+/// Печатает письмо (текстовую часть) в лог — для локальной разработки без SMTP.
+pub struct LogMailer;
+
+#[async_trait]
+impl Mailer for LogMailer {
+    fn name(&self) -> &'static str {
+        "log"
+    }
+
+    async fn send(&self, email: &RenderedEmail) -> Result<(), MailError> {
+        log::info!(
+            "email {} to {}: {}\n{}",
+            email.kind,
+            email.to,
+            email.subject,
+            email.text
+        );
+        Ok(())
+    }
+}
+
+/// Складывает письма в память; `fail_next` — ошибки для следующих отправок (тесты).
+#[derive(Default)]
+pub struct MemoryMailer {
+    sent: Mutex<Vec<RenderedEmail>>,
+    failures: Mutex<Vec<MailError>>,
+}
+
+impl MemoryMailer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn sent(&self) -> Vec<RenderedEmail> {
+        self.sent.lock().unwrap().clone()
+    }
+
+    pub fn fail_next(&self, error: MailError) {
+        self.failures.lock().unwrap().push(error);
+    }
+}
+
+#[async_trait]
+impl Mailer for MemoryMailer {
+    fn name(&self) -> &'static str {
+        "memory"
+    }
+
+    async fn send(&self, email: &RenderedEmail) -> Result<(), MailError> {
+        {
+            let mut failures = self.failures.lock().unwrap();
+            if !failures.is_empty() {
+                return Err(failures.remove(0));
+            }
+        }
+        self.sent.lock().unwrap().push(email.clone());
+        Ok(())
+    }
+}
@@ -0,0 +1,532 @@
+// src/emails/mod.rs
+//
+// Транзакционные письма: подтверждение email, готовность и ошибка обработки, чек об оплате,
+// продление, отмена и неудачный платёж подписки, заканчивающиеся кредиты. Письма по доменным
+// событиям строит подписчик `outbox::email`, данные берутся из БД на момент обработки события.
+// Текст и HTML — из шаблонов (`templates`) на языке пользователя; готовое письмо ложится
+// в `email_outbox`, а отправляет его воркер (`sender`) через `Mailer` (`mailer`).
+// Чеки и письма о подписке, которая отменена или не оплачивается, важны для денег
+// пользователя и уходят всегда; остальные категории отключаются в настройках или
+// ссылкой «отписаться» в один клик (`List-Unsubscribe`, RFC 8058).
+
+pub mod mailer;
+pub mod sender;
+pub mod templates;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{PgExecutor, PgPool, Row};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::AppState;
+use crate::outbox::{DomainEvent, StoredEvent};
+use templates::Vars;
+
+/// Категории, от которых можно отписаться; совпадают с колонками `email_preferences`.
+pub const CATEGORIES: &[&str] = &[
+    "upload_completed",
+    "upload_failed",
+    "subscription_renewed",
+    "low_balance",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmailKind {
+    UploadCompleted,
+    UploadFailed,
+    /// Чек об успешной оплате
+    Receipt,
+    SubscriptionRenewed,
+    SubscriptionCanceled,
+    /// Не прошёл платёж по подписке
+    SubscriptionFailing,
+    LowBalance,
+    /// Подтверждение адреса при регистрации
+    VerifyEmail,
+}
+
+impl EmailKind {
+    /// Имя шаблона в `templates/emails/<locale>/`; оно же `kind` в `email_outbox`.
+    pub fn template(&self) -> &'static str {
+        match self {
+            EmailKind::UploadCompleted => "upload_completed",
+            EmailKind::UploadFailed => "upload_failed",
+            EmailKind::Receipt => "receipt",
+            EmailKind::SubscriptionRenewed => "subscription_renewed",
+            EmailKind::SubscriptionCanceled => "subscription_canceled",
+            EmailKind::SubscriptionFailing => "subscription_failing",
+            EmailKind::LowBalance => "low_balance",
+            EmailKind::VerifyEmail => "verify_email",
+        }
+    }
+
+    /// Категория в `email_preferences`; `None` — обязательное письмо, отписаться нельзя.
+    pub fn category(&self) -> Option<&'static str> {
+        match self {
+            EmailKind::UploadCompleted => Some("upload_completed"),
+            EmailKind::UploadFailed => Some("upload_failed"),
+            EmailKind::SubscriptionRenewed => Some("subscription_renewed"),
+            EmailKind::LowBalance => Some("low_balance"),
+            EmailKind::Receipt
+            | EmailKind::SubscriptionCanceled
+            | EmailKind::SubscriptionFailing
+            | EmailKind::VerifyEmail => None,
+        }
+    }
+}
+
+/// Готовое письмо.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RenderedEmail {
+    /// Имя шаблона (`EmailKind::template`)
+    pub kind: String,
+    pub locale: String,
+    pub to: String,
+    pub subject: String,
+    pub text: String,
+    pub html: String,
+    /// Для `List-Unsubscribe`; только у необязательных писем
+    pub unsubscribe_url: Option<String>,
+}
+
+/// Отрисовывает письмо `kind` на языке `locale`. В шаблонах всегда доступна `app_url`.
+pub fn render(
+    kind: EmailKind,
+    locale: &str,
+    to: &str,
+    mut vars: Vars,
+    unsubscribe: Option<(String, String)>,
+) -> Result<RenderedEmail, String> {
+    vars.entry("app_url").or_insert_with(app_base_url);
+    let unsubscribe_url = unsubscribe.map(|(page_url, url)| {
+        vars.insert("unsubscribe_page_url", page_url);
+        url
+    });
+    let rendered = templates::render(kind.template(), locale, &vars)?;
+    Ok(RenderedEmail {
+        kind: kind.template().to_string(),
+        locale: locale.to_string(),
+        to: to.to_string(),
+        subject: rendered.subject,
+        text: rendered.text,
+        html: rendered.html,
+        unsubscribe_url,
+    })
+}
+
+/// Ставит в очередь письмо подтверждения адреса. Вызывать в транзакции, где сохранён токен;
+/// после коммита — `state.email_wakeup.notify_one()`.
+pub async fn queue_verification<'e>(
+    executor: impl PgExecutor<'e>,
+    user_id: i32,
+    to: &str,
+    locale: Option<&str>,
+    verify_url: &str,
+) -> Result<(), String> {
+    let locale = templates::resolve_locale(locale);
+    let vars = Vars::from([("verify_url", verify_url.to_string())]);
+    let email = render(EmailKind::VerifyEmail, locale, to, vars, None)?;
+    sender::enqueue(executor, Some(user_id), None, &email)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// Какие необязательные письма пользователь получает.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct EmailPreferences {
+    /// The cleaned video is ready
+    pub upload_completed: bool,
+    /// Processing failed
+    pub upload_failed: bool,
+    /// The subscription was activated or renewed
+    pub subscription_renewed: bool,
+    /// Credits are running out
+    pub low_balance: bool,
+    /// Language of emails: `en` or `ru`
+    pub locale: String,
+}
+
+impl EmailPreferences {
+    pub fn allows(&self, kind: EmailKind) -> bool {
+        match kind.category() {
+            Some("upload_completed") => self.upload_completed,
+            Some("upload_failed") => self.upload_failed,
+            Some("subscription_renewed") => self.subscription_renewed,
+            Some("low_balance") => self.low_balance,
+            _ => true,
+        }
+    }
+}
+
+/// Изменение настроек: `None` — оставить как есть.
+#[derive(Debug, Clone, Default, Deserialize, ToSchema)]
+pub struct EmailPreferencesUpdate {
+    pub upload_completed: Option<bool>,
+    pub upload_failed: Option<bool>,
+    pub subscription_renewed: Option<bool>,
+    pub low_balance: Option<bool>,
+    /// One of the supported locales
+    pub locale: Option<String>,
+}
+
+/// Настройки пользователя; без строки в `email_preferences` — всё включено.
+pub async fn get_preferences<'e>(
+    pool: impl PgExecutor<'e>,
+    user_id: i32,
+) -> Result<EmailPreferences, sqlx::Error> {
+    let row = sqlx::query(
+        r#"SELECT u.locale,
+                  COALESCE(ep.upload_completed, TRUE) AS upload_completed,
+                  COALESCE(ep.upload_failed, TRUE) AS upload_failed,
+                  COALESCE(ep.subscription_renewed, TRUE) AS subscription_renewed,
+                  COALESCE(ep.low_balance, TRUE) AS low_balance
+           FROM users u
+           LEFT JOIN email_preferences ep ON ep.user_id = u.id
+           WHERE u.id = $1"#,
+    )
+    .bind(user_id)
+    .fetch_one(pool)
+    .await?;
+
+    let locale: Option<String> = row.get("locale");
+    Ok(EmailPreferences {
+        upload_completed: row.get("upload_completed"),
+        upload_failed: row.get("upload_failed"),
+        subscription_renewed: row.get("subscription_renewed"),
+        low_balance: row.get("low_balance"),
+        locale: templates::resolve_locale(locale.as_deref()).to_string(),
+    })
+}
+
+/// `update.locale` должна быть из `templates::LOCALES`.
+pub async fn update_preferences(
+    pool: &PgPool,
+    user_id: i32,
+    update: &EmailPreferencesUpdate,
+) -> Result<EmailPreferences, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    sqlx::query(
+        r#"INSERT INTO email_preferences
+                (user_id, upload_completed, upload_failed, subscription_renewed, low_balance,
+                 unsubscribe_token)
+           VALUES ($1, COALESCE($2, TRUE), COALESCE($3, TRUE), COALESCE($4, TRUE),
+                   COALESCE($5, TRUE), $6)
+           ON CONFLICT (user_id) DO UPDATE SET
+               upload_completed = COALESCE($2, email_preferences.upload_completed),
+               upload_failed = COALESCE($3, email_preferences.upload_failed),
+               subscription_renewed = COALESCE($4, email_preferences.subscription_renewed),
+               low_balance = COALESCE($5, email_preferences.low_balance),
+               updated_at = NOW()"#,
+    )
+    .bind(user_id)
+    .bind(update.upload_completed)
+    .bind(update.upload_failed)
+    .bind(update.subscription_renewed)
+    .bind(update.low_balance)
+    .bind(Uuid::new_v4())
+    .execute(&mut *tx)
+    .await?;
+
+    if let Some(locale) = &update.locale {
+        sqlx::query("UPDATE users SET locale = $2, updated_at = NOW() WHERE id = $1")
+            .bind(user_id)
+            .bind(locale)
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    let preferences = get_preferences(&mut *tx, user_id).await?;
+    tx.commit().await?;
+    Ok(preferences)
+}
+
+/// Токен для ссылок отписки; строка настроек создаётся при первом обращении.
+pub async fn unsubscribe_token(pool: &PgPool, user_id: i32) -> Result<Uuid, sqlx::Error> {
+    sqlx::query(
+        r#"INSERT INTO email_preferences (user_id, unsubscribe_token)
+           VALUES ($1, $2)
+           ON CONFLICT (user_id) DO NOTHING"#,
+    )
+    .bind(user_id)
+    .bind(Uuid::new_v4())
+    .execute(pool)
+    .await?;
+
+    let row = sqlx::query("SELECT unsubscribe_token FROM email_preferences WHERE user_id = $1")
+        .bind(user_id)
+        .fetch_one(pool)
+        .await?;
+    Ok(row.get("unsubscribe_token"))
+}
+
+/// Колонка `email_preferences` для категории; в SQL подставляется только она.
+pub fn category_column(category: &str) -> Option<&'static str> {
+    match category {
+        "upload_completed" => Some("upload_completed"),
+        "upload_failed" => Some("upload_failed"),
+        "subscription_renewed" => Some("subscription_renewed"),
+        "low_balance" => Some("low_balance"),
+        _ => None,
+    }
+}
+
+/// Отписка по токену от `category` или, если её нет, от всех необязательных писем.
+/// `false` — токен неизвестен; неизвестная категория — ошибка.
+pub async fn unsubscribe<'e>(
+    pool: impl PgExecutor<'e>,
+    token: Uuid,
+    category: Option<&str>,
+) -> Result<bool, sqlx::Error> {
+    let set = match category {
+        Some(category) => {
+            let column = category_column(category)
+                .ok_or_else(|| sqlx::Error::ColumnNotFound(category.to_string()))?;
+            format!("{column} = FALSE")
+        }
+        None => CATEGORIES
+            .iter()
+            .map(|c| format!("{c} = FALSE"))
+            .collect::<Vec<_>>()
+            .join(", "),
+    };
+    let result = sqlx::query(&format!(
+        "UPDATE email_preferences SET {set}, updated_at = NOW() WHERE unsubscribe_token = $1"
+    ))
+    .bind(token)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Письмо, которое нужно отправить по событию, с учётом настроек пользователя.
+/// `None` — событию письмо не положено, пользователь отписан или email не подтверждён.
+pub async fn email_for_event(
+    state: &AppState,
+    event: &DomainEvent,
+) -> Result<Option<RenderedEmail>, String> {
+    let pool = &state.pool;
+    let Some((kind, vars)) = compose(pool, event).await.map_err(|e| e.to_string())? else {
+        return Ok(None);
+    };
+
+    let user_id = event.user_id();
+    let row = sqlx::query("SELECT email, email_verified, is_active FROM users WHERE id = $1")
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| e.to_string())?;
+    let Some(row) = row else {
+        return Ok(None);
+    };
+    let email_verified: bool = row.get("email_verified");
+    let is_active: bool = row.get("is_active");
+    if !email_verified || !is_active {
+        return Ok(None);
+    }
+
+    let preferences = get_preferences(pool, user_id)
+        .await
+        .map_err(|e| e.to_string())?;
+    if !preferences.allows(kind) {
+        return Ok(None);
+    }
+
+    let mut unsubscribe = None;
+    if let Some(category) = kind.category() {
+        let token = unsubscribe_token(pool, user_id)
+            .await
+            .map_err(|e| e.to_string())?;
+        let query = format!("token={token}&category={category}");
+        unsubscribe = Some((
+            format!("{}/unsubscribe?{query}", app_base_url()),
+            format!(
+                "{}/email/unsubscribe?{query}",
+                state.callback_base_url.trim_end_matches('/')
+            ),
+        ));
+    }
+
+    let email: String = row.get("email");
+    render(kind, &preferences.locale, &email, vars, unsubscribe).map(Some)
+}
+
+/// Ставит в `email_outbox` письмо по событию, если оно положено. `true` — письмо добавлено
+/// (повторная передача того же события его не продублирует).
+pub async fn enqueue_event(state: &AppState, event: &StoredEvent) -> Result<bool, String> {
+    let Some(email) = email_for_event(state, &event.event).await? else {
+        return Ok(false);
+    };
+    sender::enqueue(
+        &state.pool,
+        Some(event.event.user_id()),
+        Some(event.id),
+        &email,
+    )
+    .await
+    .map_err(|e| e.to_string())
+}
+
+/// Адрес фронтенда для ссылок в письмах: `APP_BASE_URL`, без него — `CALLBACK_BASE_URL`.
+pub fn app_base_url() -> String {
+    std::env::var("APP_BASE_URL")
+        .or_else(|_| std::env::var("CALLBACK_BASE_URL"))
+        .unwrap_or_default()
+        .trim_end_matches('/')
+        .to_string()
+}
+
+fn format_date(date: Option<DateTime<Utc>>) -> String {
+    date.map(|d| d.format("%Y-%m-%d").to_string())
+        .unwrap_or_else(|| "-".to_string())
+}
+
+/// Вид письма и переменные шаблона по событию.
+async fn compose(
+    pool: &PgPool,
+    event: &DomainEvent,
+) -> Result<Option<(EmailKind, Vars)>, sqlx::Error> {
+    let composed = match event {
+        DomainEvent::UploadCompleted { upload_id, .. } => {
+            let Some(row) = load_upload(pool, *upload_id).await? else {
+                return Ok(None);
+            };
+            let cleaned_url: Option<String> = row.get("cleaned_url");
+            (
+                EmailKind::UploadCompleted,
+                Vars::from([
+                    ("filename", row.get("original_filename")),
+                    ("download_url", cleaned_url.unwrap_or_default()),
+                ]),
+            )
+        }
+        DomainEvent::UploadFailed {
+            upload_id,
+            reason,
+            refunded,
+            ..
+        } => {
+            let Some(row) = load_upload(pool, *upload_id).await? else {
+                return Ok(None);
+            };
+            let mut vars = Vars::from([
+                ("filename", row.get("original_filename")),
+                ("reason", reason.clone().unwrap_or_default()),
+            ]);
+            if *refunded {
+                vars.insert("refunded", "true".to_string());
+            }
+            (EmailKind::UploadFailed, vars)
+        }
+        DomainEvent::PaymentSucceeded { transaction_id, .. } => {
+            let Some(row) = load_transaction(pool, *transaction_id).await? else {
+                return Ok(None);
+            };
+            let product: Option<String> = row.get("product_name");
+            let paid_at: Option<DateTime<Utc>> = row.get("paid_at");
+            (
+                EmailKind::Receipt,
+                Vars::from([
+                    ("receipt_number", transaction_id.to_string()),
+                    ("product", product.unwrap_or_else(|| "-".to_string())),
+                    ("amount", row.get("amount")),
+                    ("currency", row.get("currency")),
+                    ("date", format_date(paid_at)),
+                ]),
+            )
+        }
+        DomainEvent::PaymentFailed { transaction_id, .. } => {
+            let Some(row) = load_transaction(pool, *transaction_id).await? else {
+                return Ok(None);
+            };
+            let product_type: Option<String> = row.get("product_type");
+            if product_type.as_deref() != Some("subscription") {
+                return Ok(None);
+            }
+            let product: Option<String> = row.get("product_name");
+            (
+                EmailKind::SubscriptionFailing,
+                Vars::from([(
+                    "product",
+                    product.unwrap_or_else(|| "Sora Clean".to_string()),
+                )]),
+            )
+        }
+        DomainEvent::SubscriptionRenewed {
+            subscription_id, ..
+        }
+        | DomainEvent::SubscriptionCanceled {
+            subscription_id, ..
+        } => {
+            let Some(row) = load_subscription(pool, *subscription_id).await? else {
+                return Ok(None);
+            };
+            let period_end: Option<DateTime<Utc>> = row.get("current_period_end");
+            let kind = if matches!(event, DomainEvent::SubscriptionRenewed { .. }) {
+                EmailKind::SubscriptionRenewed
+            } else {
+                EmailKind::SubscriptionCanceled
+            };
+            (
+                kind,
+                Vars::from([
+                    ("product", row.get("product_name")),
+                    ("period_end", format_date(period_end)),
+                ]),
+            )
+        }
+        DomainEvent::CreditsLow {
+            credits,
+            monthly_quota,
+            ..
+        } => (
+            EmailKind::LowBalance,
+            Vars::from([("balance", (credits + monthly_quota).to_string())]),
+        ),
+        DomainEvent::CreditsGranted { .. } => return Ok(None),
+    };
+    Ok(Some(composed))
+}
+
+async fn load_upload(
+    pool: &PgPool,
+    upload_id: i32,
+) -> Result<Option<sqlx::postgres::PgRow>, sqlx::Error> {
+    sqlx::query("SELECT original_filename, cleaned_url FROM uploads WHERE id = $1")
+        .bind(upload_id)
+        .fetch_optional(pool)
+        .await
+}
+
+async fn load_transaction(
+    pool: &PgPool,
+    transaction_id: i32,
+) -> Result<Option<sqlx::postgres::PgRow>, sqlx::Error> {
+    sqlx::query(
+        r#"SELECT t.amount::text AS amount, t.currency, t.paid_at,
+                  p.name AS product_name, p.product_type
+           FROM transactions t
+           LEFT JOIN products p ON p.id = t.product_id
+           WHERE t.id = $1"#,
+    )
+    .bind(transaction_id)
+    .fetch_optional(pool)
+    .await
+}
+
+async fn load_subscription(
+    pool: &PgPool,
+    subscription_id: i32,
+) -> Result<Option<sqlx::postgres::PgRow>, sqlx::Error> {
+    sqlx::query(
+        r#"SELECT s.current_period_end, p.name AS product_name
+           FROM subscriptions s
+           JOIN products p ON p.id = s.product_id
+           WHERE s.id = $1"#,
+    )
+    .bind(subscription_id)
+    .fetch_optional(pool)
+    .await
+}
@@ -0,0 +1,258 @@
+// src/emails/sender.rs
+//
+// Очередь писем `email_outbox`. Письмо отрисовывается и записывается в очередь (по
+// возможности — в транзакции изменения, которое его вызвало), а отправляет его этот воркер:
+// с арендой (`FOR UPDATE SKIP LOCKED`) и повторами с растущей паузой. «Жёсткий» отказ
+// (адрес отклонён) повторять бессмысленно — такое письмо сразу `failed`.
+// В логи не попадают ни текст письма (в нём ссылки с токенами), ни адрес целиком.
+
+use futures_util::future::join_all;
+use sqlx::{PgExecutor, PgPool, Row};
+use std::time::Duration;
+
+use super::RenderedEmail;
+use super::mailer::{MailError, Mailer};
+use crate::AppState;
+use crate::worker::{RetryPolicy, Shutdown, run_claim_loop};
+
+/// Сколько символов ошибки хранить в `last_error`.
+const ERROR_LIMIT: usize = 500;
+
+#[derive(Debug, Clone)]
+pub struct EmailConfig {
+    /// `batch_size` — сколько писем отправляется одновременно
+    pub policy: RetryPolicy,
+}
+
+impl Default for EmailConfig {
+    fn default() -> Self {
+        Self {
+            policy: RetryPolicy {
+                poll_interval: Duration::from_secs(5),
+                batch_size: 20,
+                lease: Duration::from_secs(120),
+                max_attempts: 8,
+                retry_base_delay: Duration::from_secs(60),
+                retry_max_delay: Duration::from_secs(3600),
+            },
+        }
+    }
+}
+
+impl EmailConfig {
+    /// `EMAIL_*` из `RetryPolicy::from_env`.
+    pub fn from_env() -> Self {
+        Self {
+            policy: RetryPolicy::from_env("EMAIL", Self::default().policy),
+        }
+    }
+}
+
+/// Адрес для логов: `j***@example.com`.
+pub fn mask_email(email: &str) -> String {
+    match email.split_once('@') {
+        Some((local, domain)) => {
+            let first = local.chars().next().map(String::from).unwrap_or_default();
+            format!("{first}***@{domain}")
+        }
+        None => "***".to_string(),
+    }
+}
+
+/// Ставит письмо в очередь. `event_id` — доменное событие, из которого оно построено:
+/// повторная передача того же события письмо не продублирует. `false` — уже в очереди.
+/// После коммита — `state.email_wakeup.notify_one()`.
+pub async fn enqueue<'e>(
+    executor: impl PgExecutor<'e>,
+    user_id: Option<i32>,
+    event_id: Option<i64>,
+    email: &RenderedEmail,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        r#"INSERT INTO email_outbox
+                (user_id, event_id, kind, locale, recipient, subject, text_body, html_body,
+                 unsubscribe_url)
+           VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+           ON CONFLICT (event_id, kind) WHERE event_id IS NOT NULL DO NOTHING"#,
+    )
+    .bind(user_id)
+    .bind(event_id)
+    .bind(&email.kind)
+    .bind(&email.locale)
+    .bind(&email.to)
+    .bind(&email.subject)
+    .bind(&email.text)
+    .bind(&email.html)
+    .bind(&email.unsubscribe_url)
+    .execute(executor)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Фоновый воркер: отправляет письма сразу после `email_wakeup` или раз в `poll_interval`.
+pub async fn run_email_sender(
+    state: AppState,
+    mailer: std::sync::Arc<dyn Mailer>,
+    config: EmailConfig,
+    mut shutdown: Shutdown,
+) {
+    log::info!(
+        "email sender started mailer={} poll_interval={}s max_attempts={}",
+        mailer.name(),
+        config.policy.poll_interval.as_secs(),
+        config.policy.max_attempts
+    );
+
+    run_claim_loop(
+        "email sender",
+        &config.policy,
+        &state.email_wakeup,
+        &mut shutdown,
+        || send_pending(&state, mailer.as_ref(), &config),
+    )
+    .await;
+    log::info!("email sender stopped");
+}
+
+struct ClaimedEmail {
+    id: i64,
+    attempts: i32,
+    email: RenderedEmail,
+}
+
+/// Забирает пачку писем, которым пора, и отправляет их параллельно.
+/// Возвращает, сколько писем было забрано.
+pub async fn send_pending(
+    state: &AppState,
+    mailer: &dyn Mailer,
+    config: &EmailConfig,
+) -> Result<usize, String> {
+    let rows = sqlx::query(
+        r#"UPDATE email_outbox
+           SET next_attempt_at = NOW() + make_interval(secs => $2), attempts = attempts + 1
+           WHERE id IN (
+               SELECT id
+               FROM email_outbox
+               WHERE status = 'pending' AND next_attempt_at <= NOW()
+               ORDER BY next_attempt_at ASC, id ASC
+               LIMIT $1
+               FOR UPDATE SKIP LOCKED
+           )
+           RETURNING id, attempts, kind, locale, recipient, subject, text_body, html_body,
+                     unsubscribe_url"#,
+    )
+    .bind(config.policy.batch_size)
+    .bind(config.policy.lease.as_secs_f64())
+    .fetch_all(&state.pool)
+    .await
+    .map_err(|e| format!("email claim error: {e}"))?;
+
+    let emails: Vec<ClaimedEmail> = rows
+        .into_iter()
+        .map(|row| ClaimedEmail {
+            id: row.get("id"),
+            attempts: row.get("attempts"),
+            email: RenderedEmail {
+                kind: row.get("kind"),
+                locale: row.get("locale"),
+                to: row.get("recipient"),
+                subject: row.get("subject"),
+                text: row.get("text_body"),
+                html: row.get("html_body"),
+                unsubscribe_url: row.get("unsubscribe_url"),
+            },
+        })
+        .collect();
+    let claimed = emails.len();
+
+    join_all(
+        emails
+            .iter()
+            .map(|claimed| send_email(&state.pool, mailer, config, claimed)),
+    )
+    .await;
+
+    Ok(claimed)
+}
+
+async fn send_email(
+    pool: &PgPool,
+    mailer: &dyn Mailer,
+    config: &EmailConfig,
+    claimed: &ClaimedEmail,
+) {
+    let result = mailer.send(&claimed.email).await;
+    if let Err(e) = &result {
+        log::warn!(
+            "email send failed id={} kind={} to={} attempt={} permanent={} error={}",
+            claimed.id,
+            claimed.email.kind,
+            mask_email(&claimed.email.to),
+            claimed.attempts,
+            e.permanent,
+            e
+        );
+    }
+    if let Err(e) = finish_email(pool, config, claimed, result.err()).await {
+        log::error!("email finish error id={} error={}", claimed.id, e);
+    }
+}
+
+async fn finish_email(
+    pool: &PgPool,
+    config: &EmailConfig,
+    claimed: &ClaimedEmail,
+    error: Option<MailError>,
+) -> Result<(), sqlx::Error> {
+    let Some(error) = error else {
+        sqlx::query(
+            r#"UPDATE email_outbox
+               SET status = 'sent', sent_at = NOW(), last_error = NULL
+               WHERE id = $1"#,
+        )
+        .bind(claimed.id)
+        .execute(pool)
+        .await?;
+        return Ok(());
+    };
+
+    let gave_up = error.permanent || claimed.attempts >= config.policy.max_attempts;
+    if gave_up {
+        log::error!(
+            "email failed id={} kind={} to={} attempts={}",
+            claimed.id,
+            claimed.email.kind,
+            mask_email(&claimed.email.to),
+            claimed.attempts
+        );
+    }
+    let last_error: String = error.message.chars().take(ERROR_LIMIT).collect();
+    sqlx::query(
+        r#"UPDATE email_outbox
+           SET status = CASE WHEN $3 THEN 'failed' ELSE 'pending' END,
+               next_attempt_at = NOW() + make_interval(secs => $4),
+               last_error = $2
+           WHERE id = $1"#,
+    )
+    .bind(claimed.id)
+    .bind(last_error)
+    .bind(gave_up)
+    .bind(config.policy.retry_delay(claimed.attempts).as_secs_f64())
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Удаляет отправленные и окончательно неотправленные письма старше `retention`.
+pub async fn prune_email_outbox(pool: &PgPool, retention: Duration) -> Result<u64, String> {
+    let result = sqlx::query(
+        r#"DELETE FROM email_outbox
+           WHERE status <> 'pending'
+             AND created_at < NOW() - make_interval(secs => $1)"#,
+    )
+    .bind(retention.as_secs_f64())
+    .execute(pool)
+    .await
+    .map_err(|e| e.to_string())?;
+    Ok(result.rows_affected())
+}
@@ -0,0 +1,194 @@
+// src/emails/templates.rs
+//
+// Шаблоны писем: `templates/emails/<locale>/<name>.txt` и `.html`, встроены в бинарник.
+// Первая строка `.txt` — тема письма, дальше пустая строка и текст. Оба варианта
+// вставляются в `layout.txt`/`layout.html` своей локали (`{{{content}}}`).
+//
+// Синтаксис — небольшое подмножество Mustache:
+// - `{{name}}` — значение переменной, в HTML экранируется;
+// - `{{{name}}}` — значение без экранирования;
+// - `{{#name}}...{{/name}}` — блок, если значение не пустое;
+// - `{{^name}}...{{/name}}` — блок, если значение пустое.
+// Неизвестная переменная — пустая строка.
+
+use std::collections::BTreeMap;
+
+/// Переменные шаблона.
+pub type Vars = BTreeMap<&'static str, String>;
+
+/// Поддерживаемые локали; первая — запасная, если шаблона нет в нужной.
+pub const LOCALES: &[&str] = &["en", "ru"];
+
+struct Source {
+    text: &'static str,
+    html: &'static str,
+}
+
+/// `match` по именам шаблонов одной локали.
+macro_rules! locale_sources {
+    ($locale:literal, $name:expr) => {
+        locale_sources!(
+            $locale,
+            $name;
+            "layout",
+            "verify_email",
+            "upload_completed",
+            "upload_failed",
+            "receipt",
+            "subscription_renewed",
+            "subscription_canceled",
+            "subscription_failing",
+            "low_balance"
+        )
+    };
+    ($locale:literal, $name:expr; $($template:literal),*) => {
+        match $name {
+            $(
+                $template => Some(Source {
+                    text: include_str!(concat!(
+                        "../../templates/emails/", $locale, "/", $template, ".txt"
+                    )),
+                    html: include_str!(concat!(
+                        "../../templates/emails/", $locale, "/", $template, ".html"
+                    )),
+                }),
+            )*
+            _ => None,
+        }
+    };
+}
+
+fn source(locale: &str, name: &str) -> Option<Source> {
+    match locale {
+        "en" => locale_sources!("en", name),
+        "ru" => locale_sources!("ru", name),
+        _ => None,
+    }
+}
+
+/// Отрисованное письмо без адресата.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rendered {
+    pub subject: String,
+    pub text: String,
+    pub html: String,
+}
+
+/// Локаль из поддерживаемых: `locale` пользователя, иначе `EMAIL_DEFAULT_LOCALE`, иначе `en`.
+pub fn resolve_locale(locale: Option<&str>) -> &'static str {
+    let supported = |l: &str| {
+        let l = l.trim().to_ascii_lowercase();
+        // `ru-RU` -> `ru`
+        let l = l.split(['-', '_']).next().unwrap_or_default().to_string();
+        LOCALES.iter().copied().find(|s| *s == l)
+    };
+    locale
+        .and_then(supported)
+        .or_else(|| {
+            std::env::var("EMAIL_DEFAULT_LOCALE")
+                .ok()
+                .as_deref()
+                .and_then(supported)
+        })
+        .unwrap_or(LOCALES[0])
+}
+
+/// Отрисовывает шаблон `name` в локали `locale` (или в запасной, если его там нет)
+/// вместе с макетом.
+pub fn render(name: &str, locale: &str, vars: &Vars) -> Result<Rendered, String> {
+    let (locale, template) = source(locale, name)
+        .map(|t| (locale, t))
+        .or_else(|| source(LOCALES[0], name).map(|t| (LOCALES[0], t)))
+        .ok_or_else(|| format!("unknown email template {name}"))?;
+    let layout = source(locale, "layout").expect("every locale has a layout");
+
+    let (subject, text) = template
+        .text
+        .split_once('\n')
+        .ok_or_else(|| format!("email template {locale}/{name}.txt has no subject line"))?;
+    let subject = render_str(subject, vars, false);
+    let text = render_str(text.trim(), vars, false);
+    let html = render_str(template.html.trim(), vars, true);
+
+    let mut layout_vars = vars.clone();
+    layout_vars.insert("subject", subject.clone());
+    layout_vars.insert("content", text);
+    let text = render_str(layout.text, &layout_vars, false);
+    layout_vars.insert("content", html);
+    let html = render_str(layout.html, &layout_vars, true);
+
+    Ok(Rendered {
+        subject: subject.trim().to_string(),
+        text: text.trim().to_string(),
+        html: html.trim().to_string(),
+    })
+}
+
+/// Отрисовывает строку шаблона; `escape` — экранировать значения для HTML.
+pub fn render_str(template: &str, vars: &Vars, escape: bool) -> String {
+    let value = |name: &str| vars.get(name.trim()).map(String::as_str).unwrap_or("");
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find("{{") {
+        out.push_str(&rest[..start]);
+        let tag = &rest[start..];
+
+        if let Some(inner) = tag.strip_prefix("{{{") {
+            let Some(end) = inner.find("}}}") else {
+                out.push_str(tag);
+                return out;
+            };
+            out.push_str(value(&inner[..end]));
+            rest = &inner[end + 3..];
+            continue;
+        }
+
+        let inner = &tag[2..];
+        let Some(end) = inner.find("}}") else {
+            out.push_str(tag);
+            return out;
+        };
+        let name = &inner[..end];
+        let after = &inner[end + 2..];
+
+        if let Some(section) = name.strip_prefix('#').or_else(|| name.strip_prefix('^')) {
+            let section = section.trim();
+            let close = format!("{{{{/{section}}}}}");
+            let Some(close_at) = after.find(&close) else {
+                out.push_str(tag);
+                return out;
+            };
+            let shown = value(section).is_empty() != name.starts_with('#');
+            if shown {
+                out.push_str(&render_str(&after[..close_at], vars, escape));
+            }
+            rest = &after[close_at + close.len()..];
+        } else {
+            let value = value(name);
+            if escape {
+                out.push_str(&escape_html(value));
+            } else {
+                out.push_str(value);
+            }
+            rest = after;
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+pub fn escape_html(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
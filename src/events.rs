@@ -0,0 +1,200 @@
+// src/events.rs
+//
+// Типизированные события аккаунта для WebSocket (`/ws/uploads`) и SSE (`/api/events`):
+// баланс, платежи, подписки. `upload.updated` живёт в ws.rs. Все события приходят в одном
+// конверте `{"id", "event", "version", "data"}`, где `version` — `ws::EVENT_SCHEMA_VERSION`.
+// Схемы данных — в компонентах OpenAPI (`EventEnvelope`, `*Event`), список событий — в Readme.
+// Несовместимое изменение схемы данных = новая версия; новые поля добавляются без неё.
+
+use actix::Addr;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{PgPool, Row};
+use utoipa::ToSchema;
+
+use crate::ws::{WsHub, emit_event};
+
+/// Envelope of every realtime event (WebSocket `/ws/uploads` and SSE `/api/events`).
+#[derive(ToSchema)]
+pub struct EventEnvelope {
+    /// Monotonic event id; send it back as `last_event_id` / `Last-Event-ID` to resume
+    pub id: i64,
+    /// `upload.updated`, `snapshot`, `credits.updated`, `payment.succeeded`, `payment.failed`,
+    /// `subscription.updated` or `subscription.canceled`
+    pub event: String,
+    /// Schema version of `data`, currently 1
+    pub version: u16,
+    /// Event data, see the schema of the event
+    #[schema(value_type = Object)]
+    pub data: serde_json::Value,
+}
+
+/// `credits.updated`: the balance after a charge, refund or purchase.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct CreditsEvent {
+    /// One-time credits
+    pub credits: i32,
+    /// Credits left from the subscription for the current period
+    pub monthly_quota: i32,
+    pub free_generation_used: bool,
+    /// When the monthly quota is refilled
+    pub quota_reset_at: Option<DateTime<Utc>>,
+}
+
+/// `payment.succeeded` / `payment.failed`.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct PaymentEvent {
+    pub transaction_id: i32,
+    pub product_id: Option<i32>,
+    /// `one_time` or `subscription`
+    pub product_type: Option<String>,
+    /// Decimal amount as a string, e.g. `"9.99"`
+    pub amount: String,
+    pub currency: String,
+}
+
+/// `subscription.updated` / `subscription.canceled`.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct SubscriptionEvent {
+    pub subscription_id: i32,
+    pub product_id: i32,
+    /// `active` or `canceled` (a canceled subscription stays effective until the period end)
+    pub status: String,
+    pub current_period_start: Option<DateTime<Utc>>,
+    pub current_period_end: Option<DateTime<Utc>>,
+    pub canceled_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+pub enum AccountEvent {
+    CreditsUpdated(CreditsEvent),
+    PaymentSucceeded(PaymentEvent),
+    PaymentFailed(PaymentEvent),
+    SubscriptionUpdated(SubscriptionEvent),
+    SubscriptionCanceled(SubscriptionEvent),
+}
+
+impl AccountEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            AccountEvent::CreditsUpdated(_) => "credits.updated",
+            AccountEvent::PaymentSucceeded(_) => "payment.succeeded",
+            AccountEvent::PaymentFailed(_) => "payment.failed",
+            AccountEvent::SubscriptionUpdated(_) => "subscription.updated",
+            AccountEvent::SubscriptionCanceled(_) => "subscription.canceled",
+        }
+    }
+}
+
+pub async fn emit(pool: &PgPool, hub: &Addr<WsHub>, user_id: i32, event: &AccountEvent) {
+    emit_event(pool, hub, user_id, event.name(), event).await;
+}
+
+pub async fn load_credits(pool: &PgPool, user_id: i32) -> Result<CreditsEvent, sqlx::Error> {
+    let row = sqlx::query(
+        r#"SELECT credits, monthly_quota, free_generation_used, quota_reset_at
+           FROM users
+           WHERE id = $1"#,
+    )
+    .bind(user_id)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(CreditsEvent {
+        credits: row.get("credits"),
+        monthly_quota: row.get("monthly_quota"),
+        free_generation_used: row.get("free_generation_used"),
+        quota_reset_at: row.get("quota_reset_at"),
+    })
+}
+
+/// Событие по текущему статусу транзакции: `None`, пока она не завершилась.
+pub async fn load_payment(
+    pool: &PgPool,
+    transaction_id: i32,
+) -> Result<Option<(i32, AccountEvent)>, sqlx::Error> {
+    let row = sqlx::query(
+        r#"SELECT t.id, t.user_id, t.product_id, t.status, t.amount::text AS amount, t.currency,
+                  p.product_type
+           FROM transactions t
+           LEFT JOIN products p ON p.id = t.product_id
+           WHERE t.id = $1"#,
+    )
+    .bind(transaction_id)
+    .fetch_optional(pool)
+    .await?;
+    let Some(row) = row else {
+        return Ok(None);
+    };
+
+    let payment = PaymentEvent {
+        transaction_id: row.get("id"),
+        product_id: row.get("product_id"),
+        product_type: row.get("product_type"),
+        amount: row.get("amount"),
+        currency: row.get("currency"),
+    };
+    let status: String = row.get("status");
+    let event = match status.as_str() {
+        "succeeded" => AccountEvent::PaymentSucceeded(payment),
+        "failed" => AccountEvent::PaymentFailed(payment),
+        _ => return Ok(None),
+    };
+    Ok(Some((row.get("user_id"), event)))
+}
+
+pub async fn load_subscription(
+    pool: &PgPool,
+    subscription_id: i32,
+) -> Result<Option<(i32, AccountEvent)>, sqlx::Error> {
+    let row = sqlx::query(
+        r#"SELECT id, user_id, product_id, status, current_period_start, current_period_end,
+                  canceled_at
+           FROM subscriptions
+           WHERE id = $1"#,
+    )
+    .bind(subscription_id)
+    .fetch_optional(pool)
+    .await?;
+    let Some(row) = row else {
+        return Ok(None);
+    };
+
+    let subscription = SubscriptionEvent {
+        subscription_id: row.get("id"),
+        product_id: row.get("product_id"),
+        status: row.get("status"),
+        current_period_start: row.get("current_period_start"),
+        current_period_end: row.get("current_period_end"),
+        canceled_at: row.get("canceled_at"),
+    };
+    let event = if subscription.status == "canceled" {
+        AccountEvent::SubscriptionCanceled(subscription)
+    } else {
+        AccountEvent::SubscriptionUpdated(subscription)
+    };
+    Ok(Some((row.get("user_id"), event)))
+}
+
+/// Отправляет `payment.succeeded` или `payment.failed` по статусу транзакции.
+pub async fn notify_payment(pool: &PgPool, hub: &Addr<WsHub>, transaction_id: i32) {
+    match load_payment(pool, transaction_id).await {
+        Ok(Some((user_id, event))) => emit(pool, hub, user_id, &event).await,
+        Ok(None) => {}
+        Err(e) => log::error!("payment event error tx_id={} error={}", transaction_id, e),
+    }
+}
+
+/// Отправляет `subscription.updated` или `subscription.canceled` по статусу подписки.
+pub async fn notify_subscription(pool: &PgPool, hub: &Addr<WsHub>, subscription_id: i32) {
+    match load_subscription(pool, subscription_id).await {
+        Ok(Some((user_id, event))) => emit(pool, hub, user_id, &event).await,
+        Ok(None) => {}
+        Err(e) => log::error!(
+            "subscription event error subscription_id={} error={}",
+            subscription_id,
+            e
+        ),
+    }
+}
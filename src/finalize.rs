@@ -0,0 +1,162 @@
+// src/finalize.rs
+//
+// Завершение задачи бэкенда: одинаково для колбэка и для поллера статусов.
+// Результат копируется к нам в хранилище (`cleaned/{task_id}.mp4`), в БД пишутся
+// cleaned_s3_key и постоянная ссылка, в той же транзакции — одно событие `upload.completed`.
+// Локальный бэкенд (ffmpeg) сам кладёт результат под тот же ключ (`s3://...`), его не копируем.
+
+use crate::AppState;
+use crate::outbox::{self, DomainEvent};
+use crate::providers::ffmpeg;
+use crate::s3_utils::{StreamUploadOptions, UploadSource, build_public_url, stream_to_s3};
+use crate::safe_http::{self, FetchPolicy};
+use sqlx::Row;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum FinalizeOutcome {
+    /// Этот вызов сохранил результат и отправил событие
+    Finalized { cleaned_url: String },
+    /// Результат уже сохранён (колбэк и поллер пришли одновременно или повторно)
+    AlreadyFinalized,
+    /// Загрузка уже закрыта без результата (`failed`, кредиты возвращены): поздний
+    /// результат не принимаем, иначе пользователь получит его бесплатно
+    Closed,
+}
+
+pub fn cleaned_s3_key(task_id: &str) -> String {
+    format!("cleaned/{task_id}.mp4")
+}
+
+/// Итог для загрузки, которая уже не ждёт результата; `None` — загрузка в обработке.
+fn settled_outcome(row: &sqlx::postgres::PgRow) -> Option<FinalizeOutcome> {
+    let status: String = row.get("status");
+    let key: Option<String> = row.get("cleaned_s3_key");
+    match status.as_str() {
+        "processing" => None,
+        "ready" if key.is_some() => Some(FinalizeOutcome::AlreadyFinalized),
+        _ => Some(FinalizeOutcome::Closed),
+    }
+}
+
+async fn load_upload(state: &AppState, upload_id: i32) -> Result<sqlx::postgres::PgRow, String> {
+    sqlx::query("SELECT status, cleaned_s3_key, provider FROM uploads WHERE id = $1")
+        .bind(upload_id)
+        .fetch_optional(&state.pool)
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("upload {upload_id} not found"))
+}
+
+/// Сохраняет результат задачи для загрузки `upload_id`.
+///
+/// Повторный вызов безопасен: если результат уже в хранилище, ничего не качаем.
+/// При гонке оба участника могут скопировать файл (ключ один и тот же), но запись
+/// в БД и доменное событие делает только первый. Принимается результат только для
+/// загрузки в `processing`.
+pub async fn finalize_upload(
+    state: &AppState,
+    upload_id: i32,
+    task_id: &str,
+    result_url: &str,
+) -> Result<FinalizeOutcome, String> {
+    let row = load_upload(state, upload_id).await?;
+    if let Some(outcome) = settled_outcome(&row) {
+        return Ok(outcome);
+    }
+
+    let s3_key = cleaned_s3_key(task_id);
+
+    // Готовый результат в хранилище принимаем только от ffmpeg и только под ключом этой задачи
+    let stored = result_url.starts_with("s3:");
+    if stored {
+        let provider: Option<String> = row.get("provider");
+        if provider.as_deref() != Some(ffmpeg::PROVIDER_NAME) {
+            return Err(format!("stored result for provider {provider:?} rejected"));
+        }
+        if result_url != ffmpeg::stored_result_url(&state.s3_bucket, task_id) {
+            return Err(format!("stored result {result_url} does not belong to task {task_id}"));
+        }
+    }
+
+    let cleaned_url = if stored {
+        build_public_url(&state.s3_public_base_url, &state.s3_bucket, &s3_key)
+    } else if std::env::var("MOCK_S3").unwrap_or_default() == "true" {
+        result_url.to_string()
+    } else {
+        // Ссылка на результат приходит извне: качаем только с публичных (и разрешённых) хостов
+        let source: UploadSource = safe_http::get(result_url, &FetchPolicy::kie_results())
+            .await
+            .map_err(|e| format!("download failed url={result_url} error={e}"))?
+            .into();
+
+        // Переливаем частями, не держа весь ролик в памяти
+        let object = stream_to_s3(
+            &state.s3_client,
+            &state.s3_bucket,
+            &s3_key,
+            source,
+            &StreamUploadOptions::from_env(),
+        )
+        .await?;
+        log::info!(
+            "result stored key={} size={} parts={} sha256={}",
+            s3_key,
+            object.size,
+            object.parts,
+            object.sha256
+        );
+
+        build_public_url(&state.s3_public_base_url, &state.s3_bucket, &s3_key)
+    };
+
+    // Условный UPDATE: выигрывает только тот, кто первым записал ключ, и только пока загрузка
+    // в обработке (её мог закрыть таймаут или ошибка бэкенда, пока мы копировали файл)
+    let mut tx = state.pool.begin().await.map_err(|e| e.to_string())?;
+    let updated = sqlx::query(
+        r#"UPDATE uploads
+           SET cleaned_s3_key = $1, cleaned_url = $2, status = 'ready', task_id = COALESCE(task_id, $3)
+           WHERE id = $4 AND status = 'processing'
+           RETURNING user_id"#,
+    )
+    .bind(&s3_key)
+    .bind(&cleaned_url)
+    .bind(task_id)
+    .bind(upload_id)
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(|e| e.to_string())?;
+
+    let Some(updated) = updated else {
+        drop(tx);
+        let row = load_upload(state, upload_id).await?;
+        return Ok(settled_outcome(&row).unwrap_or(FinalizeOutcome::AlreadyFinalized));
+    };
+
+    let event = DomainEvent::UploadCompleted {
+        upload_id,
+        user_id: updated.get("user_id"),
+    };
+    outbox::record(&mut *tx, &event)
+        .await
+        .map_err(|e| e.to_string())?;
+    tx.commit().await.map_err(|e| e.to_string())?;
+    state.outbox_wakeup.notify_one();
+    Ok(FinalizeOutcome::Finalized { cleaned_url })
+}
+
+/// То же по `task_id` (для поллера, который знает только задачу).
+pub async fn finalize_task(
+    state: &AppState,
+    task_id: &str,
+    result_url: &str,
+) -> Result<FinalizeOutcome, String> {
+    let upload_id: i32 = sqlx::query("SELECT id FROM uploads WHERE task_id = $1")
+        .bind(task_id)
+        .fetch_optional(&state.pool)
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("upload for task {task_id} not found"))?
+        .get("id");
+
+    finalize_upload(state, upload_id, task_id, result_url).await
+}
@@ -0,0 +1,382 @@
+// src/kie_client.rs
+//
+// HTTP-клиент Kie.ai: один на процесс (лежит в AppState), соединения переиспользуются.
+// - типизированные запросы/ответы createTask и recordInfo
+// - таймауты на соединение и на весь запрос
+// - ограниченные повторы с джиттером на 5xx/429 (в т.ч. когда код приходит в теле ответа)
+
+use actix_web::rt::time::sleep;
+use rand::Rng;
+use reqwest::header::RETRY_AFTER;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fmt;
+use std::time::Duration;
+
+pub const DEFAULT_BASE_URL: &str = "https://api.kie.ai";
+
+#[derive(Debug, Clone)]
+pub struct KieConfig {
+    pub api_key: String,
+    pub base_url: String,
+    pub connect_timeout: Duration,
+    /// Таймаут одного запроса целиком (без учёта повторов)
+    pub request_timeout: Duration,
+    /// Сколько раз повторять запрос после первой попытки
+    pub max_retries: u32,
+    /// Базовая пауза перед повтором, дальше растёт вдвое
+    pub retry_base_delay: Duration,
+    pub retry_max_delay: Duration,
+}
+
+impl Default for KieConfig {
+    fn default() -> Self {
+        Self {
+            api_key: String::new(),
+            base_url: DEFAULT_BASE_URL.to_string(),
+            connect_timeout: Duration::from_secs(10),
+            request_timeout: Duration::from_secs(30),
+            max_retries: 2,
+            retry_base_delay: Duration::from_millis(500),
+            retry_max_delay: Duration::from_secs(10),
+        }
+    }
+}
+
+impl KieConfig {
+    /// `KIE_API_BASE_URL`, `KIE_CONNECT_TIMEOUT_SECS`, `KIE_REQUEST_TIMEOUT_SECS`,
+    /// `KIE_MAX_RETRIES`, `KIE_RETRY_BASE_DELAY_MS`.
+    pub fn from_env(api_key: &str) -> Self {
+        let defaults = Self::default();
+        let env_parse = |key: &str| std::env::var(key).ok().and_then(|v| v.trim().parse::<u64>().ok());
+        Self {
+            api_key: api_key.to_string(),
+            base_url: std::env::var("KIE_API_BASE_URL")
+                .ok()
+                .filter(|v| !v.trim().is_empty())
+                .map(|v| v.trim().trim_end_matches('/').to_string())
+                .unwrap_or(defaults.base_url),
+            connect_timeout: env_parse("KIE_CONNECT_TIMEOUT_SECS")
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.connect_timeout),
+            request_timeout: env_parse("KIE_REQUEST_TIMEOUT_SECS")
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.request_timeout),
+            max_retries: env_parse("KIE_MAX_RETRIES")
+                .map(|v| v.min(10) as u32)
+                .unwrap_or(defaults.max_retries),
+            retry_base_delay: env_parse("KIE_RETRY_BASE_DELAY_MS")
+                .map(Duration::from_millis)
+                .unwrap_or(defaults.retry_base_delay),
+            retry_max_delay: defaults.retry_max_delay,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum KieError {
+    /// Не удалось соединиться: запрос до KIE не дошёл
+    Connect(String),
+    /// Соединение оборвалось или не удалось прочитать ответ
+    Transport(String),
+    /// Запрос не уложился в `request_timeout`
+    Timeout,
+    /// Неуспешный HTTP-статус
+    Http { status: u16, body: String },
+    /// HTTP 200, но в теле `code` не 200
+    Api { code: i32, msg: String },
+    /// Ответ не соответствует ожидаемой схеме
+    Decode(String),
+}
+
+impl KieError {
+    /// 5xx, 429 и ошибки соединения — временные ошибки KIE, их имеет смысл повторить.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            KieError::Http { status, .. } => *status == 429 || *status >= 500,
+            KieError::Api { code, .. } => *code == 429 || (500..600).contains(code),
+            KieError::Connect(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Запрос точно не дошёл до KIE: повтор не создаст задачу второй раз.
+    pub fn is_unsent(&self) -> bool {
+        matches!(self, KieError::Connect(_))
+    }
+}
+
+impl fmt::Display for KieError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KieError::Connect(e) => write!(f, "kie connect error: {e}"),
+            KieError::Transport(e) => write!(f, "kie request error: {e}"),
+            KieError::Timeout => write!(f, "kie request timed out"),
+            KieError::Http { status, body } => write!(f, "kie http error status={status} body={body}"),
+            KieError::Api { code, msg } => write!(f, "kie api error code={code} msg={msg}"),
+            KieError::Decode(e) => write!(f, "kie invalid response: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for KieError {}
+
+impl From<reqwest::Error> for KieError {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_connect() {
+            KieError::Connect(e.to_string())
+        } else if e.is_timeout() {
+            KieError::Timeout
+        } else {
+            KieError::Transport(e.to_string())
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct CreateTaskRequest<'a> {
+    pub model: &'a str,
+    pub input: CreateTaskInput<'a>,
+    #[serde(rename = "callBackUrl")]
+    pub callback_url: &'a str,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CreateTaskInput<'a> {
+    pub video_url: &'a str,
+    /// Параметры из профиля обработки
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, Value>,
+}
+
+/// Общая обёртка ответов KIE: `{"code": 200, "msg": "success", "data": {...}}`.
+#[derive(Debug, Deserialize)]
+pub struct KieResponse<T> {
+    #[serde(default)]
+    pub code: Option<i32>,
+    #[serde(default)]
+    pub msg: Option<String>,
+    pub data: Option<T>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateTaskData {
+    #[serde(rename = "taskId")]
+    pub task_id: String,
+}
+
+/// Состояние задачи в recordInfo.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KieTaskState {
+    Waiting,
+    Queuing,
+    Generating,
+    Success,
+    Fail,
+    Unknown(String),
+}
+
+impl KieTaskState {
+    pub fn parse(raw: &str) -> Self {
+        match raw {
+            "waiting" => Self::Waiting,
+            "queuing" => Self::Queuing,
+            "generating" => Self::Generating,
+            "success" => Self::Success,
+            "fail" => Self::Fail,
+            other => Self::Unknown(other.to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RecordInfoData {
+    #[serde(rename = "taskId")]
+    pub task_id: String,
+    #[serde(default)]
+    pub state: Option<String>,
+    #[serde(default, rename = "resultJson")]
+    pub result_json: Option<String>,
+    #[serde(default, rename = "failCode")]
+    pub fail_code: Option<String>,
+    #[serde(default, rename = "failMsg")]
+    pub fail_msg: Option<String>,
+}
+
+impl RecordInfoData {
+    pub fn task_state(&self) -> KieTaskState {
+        KieTaskState::parse(self.state.as_deref().unwrap_or("waiting"))
+    }
+
+    pub fn result_url(&self) -> Option<String> {
+        self.result_json.as_deref().and_then(first_result_url)
+    }
+}
+
+/// Содержимое строки `resultJson`.
+#[derive(Debug, Deserialize)]
+pub struct ResultJson {
+    #[serde(default, rename = "resultUrls")]
+    pub result_urls: Vec<String>,
+}
+
+/// Первая ссылка из `resultJson` (`{"resultUrls": [...]}`).
+pub fn first_result_url(result_json: &str) -> Option<String> {
+    match serde_json::from_str::<ResultJson>(result_json) {
+        Ok(parsed) => parsed.result_urls.into_iter().next(),
+        Err(e) => {
+            log::warn!("kie resultJson parse error: {}", e);
+            None
+        }
+    }
+}
+
+/// Пауза перед повтором `attempt` (с нуля): экспонента с джиттером в [delay/2, delay].
+pub fn retry_delay(config: &KieConfig, attempt: u32) -> Duration {
+    let delay = config
+        .retry_base_delay
+        .saturating_mul(2u32.saturating_pow(attempt))
+        .min(config.retry_max_delay);
+    let half = delay / 2;
+    let jitter_ms = rand::thread_rng().gen_range(0..=half.as_millis() as u64);
+    half + Duration::from_millis(jitter_ms)
+}
+
+#[derive(Clone)]
+pub struct KieClient {
+    config: KieConfig,
+    http: reqwest::Client,
+}
+
+impl KieClient {
+    pub fn new(config: KieConfig) -> Self {
+        let http = reqwest::Client::builder()
+            .connect_timeout(config.connect_timeout)
+            .timeout(config.request_timeout)
+            .build()
+            .unwrap_or_else(|_| reqwest::Client::new());
+        Self { config, http }
+    }
+
+    pub fn from_env(api_key: &str) -> Self {
+        Self::new(KieConfig::from_env(api_key))
+    }
+
+    pub fn config(&self) -> &KieConfig {
+        &self.config
+    }
+
+    /// POST /api/v1/jobs/createTask, возвращает taskId.
+    /// createTask не идемпотентен: после 5xx задача могла создаться, поэтому повторяем только
+    /// запросы, которые не дошли до KIE.
+    pub async fn create_task(&self, request: &CreateTaskRequest<'_>) -> Result<String, KieError> {
+        let url = format!("{}/api/v1/jobs/createTask", self.config.base_url);
+        let data: CreateTaskData = self
+            .with_retries("createTask", KieError::is_unsent, || {
+                self.http.post(&url).json(request)
+            })
+            .await?;
+        Ok(data.task_id)
+    }
+
+    /// GET /api/v1/jobs/recordInfo?taskId=...
+    pub async fn record_info(&self, task_id: &str) -> Result<RecordInfoData, KieError> {
+        let url = format!("{}/api/v1/jobs/recordInfo", self.config.base_url);
+        self.with_retries("recordInfo", KieError::is_retryable, || {
+            self.http.get(&url).query(&[("taskId", task_id)])
+        })
+        .await
+    }
+
+    async fn with_retries<T, F>(
+        &self,
+        op: &str,
+        retryable: fn(&KieError) -> bool,
+        build: F,
+    ) -> Result<T, KieError>
+    where
+        T: serde::de::DeserializeOwned,
+        F: Fn() -> reqwest::RequestBuilder,
+    {
+        let mut attempt = 0;
+        loop {
+            let (result, retry_after) = self.send_once(build()).await;
+            match result {
+                Err(e) if retryable(&e) && attempt < self.config.max_retries => {
+                    let delay = retry_after
+                        .map(|d| d.min(self.config.retry_max_delay))
+                        .unwrap_or_else(|| retry_delay(&self.config, attempt));
+                    log::warn!(
+                        "kie {} failed, retrying in {}ms attempt={} error={}",
+                        op,
+                        delay.as_millis(),
+                        attempt + 1,
+                        e
+                    );
+                    sleep(delay).await;
+                    attempt += 1;
+                }
+                other => return other,
+            }
+        }
+    }
+
+    /// Одна попытка; вторым значением — `Retry-After` из ответа, если он был.
+    async fn send_once<T>(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> (Result<T, KieError>, Option<Duration>)
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        let resp = match request
+            .header("Authorization", format!("Bearer {}", self.config.api_key))
+            .send()
+            .await
+        {
+            Ok(resp) => resp,
+            Err(e) => return (Err(e.into()), None),
+        };
+
+        let status = resp.status();
+        let retry_after = resp
+            .headers()
+            .get(RETRY_AFTER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.trim().parse::<u64>().ok())
+            .map(Duration::from_secs);
+        let text = match resp.text().await {
+            Ok(text) => text,
+            Err(e) => return (Err(e.into()), retry_after),
+        };
+        if !status.is_success() {
+            return (
+                Err(KieError::Http {
+                    status: status.as_u16(),
+                    body: text,
+                }),
+                retry_after,
+            );
+        }
+
+        (parse_response(&text), retry_after)
+    }
+}
+
+/// Разбирает тело успешного HTTP-ответа: `code` != 200 — ошибка API, без `data` — ошибка схемы.
+pub fn parse_response<T>(text: &str) -> Result<T, KieError>
+where
+    T: serde::de::DeserializeOwned,
+{
+    let parsed: KieResponse<T> = serde_json::from_str(text)
+        .map_err(|e| KieError::Decode(format!("{e}; body={text}")))?;
+    match parsed.code {
+        Some(code) if code != 200 => Err(KieError::Api {
+            code,
+            msg: parsed.msg.unwrap_or_else(|| text.to_string()),
+        }),
+        _ => parsed
+            .data
+            .ok_or_else(|| KieError::Decode(format!("missing data; body={text}"))),
+    }
+}
@@ -1,14 +1,32 @@
 pub mod api;
 pub mod billing;
 pub mod db;
+pub mod dedup;
 pub mod docs;
+pub mod emails;
+pub mod events;
+pub mod finalize;
+pub mod kie_client;
 pub mod models;
+pub mod outbox;
+pub mod providers;
 pub mod queue;
 pub mod s3_utils;
+pub mod safe_http;
+pub mod source_resolver;
+pub mod submitter;
+pub mod user_webhooks;
+pub mod worker;
 pub mod ws;
+pub mod ws_bridge;
+pub mod ws_protocol;
 
+use actix::Actor;
+use aws_config::meta::region::RegionProviderChain;
 use aws_sdk_s3::Client as S3Client;
 use sqlx::PgPool;
+use std::env;
+use std::sync::Arc;
 
 #[derive(Clone)]
 pub struct AppState {
@@ -17,8 +35,80 @@ pub struct AppState {
     pub s3_bucket: String,
     pub s3_public_base_url: String,
     pub kie_api_key: String,
+    /// Общий HTTP-клиент KIE (пул соединений, таймауты, повторы)
+    pub kie: Arc<kie_client::KieClient>,
     pub callback_base_url: String,
     pub lava_api_key: String,
     pub lava_webhook_key: String,
     pub ws_hub: actix::Addr<ws::WsHub>,
+    pub source_resolvers: Arc<source_resolver::ResolverRegistry>,
+    pub removers: Arc<providers::RemoverRegistry>,
+    /// Будит воркер отправки, когда в очереди появилась загрузка
+    pub submit_wakeup: Arc<tokio::sync::Notify>,
+    /// Будит воркер outbox, когда записано доменное событие
+    pub outbox_wakeup: Arc<tokio::sync::Notify>,
+    /// Будит отправку вебхуков пользователей, когда появилась доставка
+    pub webhook_wakeup: Arc<tokio::sync::Notify>,
+    /// Будит отправку писем, когда в `email_outbox` появилось письмо
+    pub email_wakeup: Arc<tokio::sync::Notify>,
+}
+
+impl AppState {
+    /// Состояние из переменных окружения; общее для API и воркера.
+    /// Ключи Lava нужны только API, поэтому здесь они необязательны.
+    pub async fn from_env(pool: PgPool) -> Self {
+        let kie_api_key = env::var("KIE_API_KEY").expect("KIE_API_KEY required");
+        let s3_bucket = env::var("S3_BUCKET").expect("S3_BUCKET required");
+        let s3_endpoint = env::var("S3_ENDPOINT").ok();
+        let s3_public_base_url = env::var("S3_PUBLIC_BASE_URL")
+            .unwrap_or_else(|_| format!("https://{}.s3.amazonaws.com", s3_bucket));
+        let callback_base_url = env::var("CALLBACK_BASE_URL").expect("CALLBACK_BASE_URL required");
+
+        let region_provider = RegionProviderChain::default_provider().or_else("us-east-1");
+        let aws_config = aws_config::defaults(aws_config::BehaviorVersion::latest())
+            .region(region_provider)
+            .load()
+            .await;
+        let mut s3_config_builder = aws_sdk_s3::config::Builder::from(&aws_config);
+
+        // Allow custom S3-compatible endpoints (e.g., Beget, MinIO)
+        if let Some(endpoint) = s3_endpoint {
+            s3_config_builder = s3_config_builder
+                .endpoint_url(endpoint)
+                .force_path_style(true);
+        }
+
+        let s3_client = S3Client::from_conf(s3_config_builder.build());
+        let kie = Arc::new(kie_client::KieClient::from_env(&kie_api_key));
+        let removers = Arc::new(providers::RemoverRegistry::from_env(
+            kie.clone(),
+            providers::ResultStorage {
+                client: s3_client.clone(),
+                bucket: s3_bucket.clone(),
+            },
+        ));
+        let ws_hub = match ws_bridge::from_env(&pool) {
+            Some(bridge) => ws::WsHub::with_bridge(bridge),
+            None => ws::WsHub::new(),
+        };
+
+        Self {
+            pool,
+            s3_client,
+            s3_bucket,
+            s3_public_base_url,
+            kie_api_key,
+            kie,
+            callback_base_url,
+            lava_api_key: env::var("LAVA_API_KEY").unwrap_or_default(),
+            lava_webhook_key: env::var("LAVA_WEBHOOK_KEY").unwrap_or_default(),
+            ws_hub: ws_hub.start(),
+            source_resolvers: Arc::new(source_resolver::ResolverRegistry::default()),
+            removers,
+            submit_wakeup: Default::default(),
+            outbox_wakeup: Default::default(),
+            webhook_wakeup: Default::default(),
+            email_wakeup: Default::default(),
+        }
+    }
 }
@@ -1,17 +1,14 @@
 // src/main.rs
 use actix_cors::Cors;
 use actix_web::{App, HttpResponse, HttpServer, Responder, web};
-use actix::Actor;
 use actix_web::middleware::Logger;
-use aws_config::meta::region::RegionProviderChain;
-use aws_sdk_s3::Client as S3Client;
 use dotenvy::dotenv;
 use sqlx::PgPool;
 use std::env;
 use utoipa::OpenApi;
 use utoipa_swagger_ui::SwaggerUi;
 
-use sora_watermark_remov::{AppState, api, docs};
+use sora_watermark_remov::{AppState, api, docs, worker};
 
 async fn index() -> impl Responder {
     HttpResponse::Ok().body("Service ready!")
@@ -32,52 +29,23 @@ async fn main() -> std::io::Result<()> {
         .await
         .expect("Failed to run migrations");
 
-    let kie_api_key = env::var("KIE_API_KEY").expect("KIE_API_KEY required");
-    let s3_bucket = env::var("S3_BUCKET").expect("S3_BUCKET required");
-    let s3_endpoint = env::var("S3_ENDPOINT").ok();
-    let s3_public_base_url = env::var("S3_PUBLIC_BASE_URL")
-        .unwrap_or_else(|_| format!("https://{}.s3.amazonaws.com", s3_bucket));
-    let callback_base_url = env::var("CALLBACK_BASE_URL").expect("CALLBACK_BASE_URL required");
+    // Ключи Lava обязательны для API (платежи и вебхуки)
+    env::var("LAVA_API_KEY").expect("LAVA_API_KEY required");
+    env::var("LAVA_WEBHOOK_KEY").expect("LAVA_WEBHOOK_KEY required");
 
-    let lava_api_key = env::var("LAVA_API_KEY").expect("LAVA_API_KEY required");
-    let lava_webhook_key = env::var("LAVA_WEBHOOK_KEY").expect("LAVA_WEBHOOK_KEY required");
+    let state = web::Data::new(AppState::from_env(pool).await);
 
-    let region_provider = RegionProviderChain::default_provider().or_else("us-east-1");
-    let aws_config = aws_config::defaults(aws_config::BehaviorVersion::latest())
-        .region(region_provider)
-        .load()
-        .await;
-    let mut s3_config_builder = aws_sdk_s3::config::Builder::from(&aws_config);
-
-    // Allow custom S3-compatible endpoints (e.g., Beget, MinIO)
-    if let Some(endpoint) = s3_endpoint {
-        s3_config_builder = s3_config_builder
-            .endpoint_url(endpoint)
-            .force_path_style(true);
-    }
-
-    let s3_client = S3Client::from_conf(s3_config_builder.build());
-
-    let ws_hub = sora_watermark_remov::ws::WsHub::new().start();
-    let state = web::Data::new(AppState {
-        pool,
-        s3_client,
-        s3_bucket: s3_bucket.clone(),
-        s3_public_base_url: s3_public_base_url.clone(),
-        kie_api_key,
-        callback_base_url,
-        lava_api_key,
-        lava_webhook_key,
-        ws_hub,
-    });
-
-    // RabbitMQ status checker for KIE tasks
-    sora_watermark_remov::queue::start_kie_status_queue(
-        state.pool.clone(),
-        state.kie_api_key.clone(),
-        state.ws_hub.clone(),
-    )
-    .await;
+    // Воркеры (отправка загрузок, проверка статусов, обслуживание) можно вынести
+    // в отдельный процесс `worker`: тогда здесь DISABLE_WORKERS=true
+    let (shutdown_trigger, shutdown) = worker::shutdown_channel();
+    let worker_config = worker::WorkerConfig::from_env();
+    let workers = if env::var("DISABLE_WORKERS").unwrap_or_default() == "true" {
+        log::info!("workers disabled, run the worker binary separately");
+        Vec::new()
+    } else {
+        worker::spawn_workers(state.get_ref(), &worker_config, &shutdown)
+    };
+    let removers = state.removers.clone();
 
     HttpServer::new(move || {
         let cors = {
@@ -107,10 +75,14 @@ async fn main() -> std::io::Result<()> {
             .service(api::auth::login)
             .service(api::auth::verify_email)
             .service(api::auth::resend_verification)
+            // Отписка по ссылке из письма (без авторизации, по токену)
+            .service(api::email_preferences::unsubscribe)
             // Вебхуки (публичные)
             .service(api::webhooks::watermark_callback)
             .service(api::webhooks::watermark_callback_alias)
             .route("/ws/uploads", web::get().to(sora_watermark_remov::ws::uploads_ws))
+            // Метрики Prometheus (только с METRICS_TOKEN)
+            .service(api::metrics::metrics)
             // Защищённые роуты
             .service(
                 web::scope("/api")
@@ -118,14 +90,30 @@ async fn main() -> std::io::Result<()> {
                     .service(api::handlers::upload)
                     .service(api::handlers::credits_status)
                     .service(api::handlers::list_uploads)
+                    .service(api::events::events_sse)
                     .service(api::products::list_products)
+                    .service(api::products::list_processing_profiles)
                     .service(api::payments::create_payment)
                     .service(api::subscriptions::list_subscriptions)
-                    .service(api::subscriptions::cancel_subscription),
+                    .service(api::subscriptions::cancel_subscription)
+                    .service(api::user_webhooks::list_webhooks)
+                    .service(api::user_webhooks::create_webhook)
+                    .service(api::user_webhooks::update_webhook)
+                    .service(api::user_webhooks::delete_webhook)
+                    .service(api::user_webhooks::list_webhook_deliveries)
+                    .service(api::user_webhooks::redeliver_webhook)
+                    .service(api::email_preferences::get_email_preferences)
+                    .service(api::email_preferences::update_email_preferences),
             )
             .service(api::webhooks_lava::lava_webhook)
     })
     .bind(("0.0.0.0", 8065))?
     .run()
-    .await
+    .await?;
+
+    shutdown_trigger.trigger();
+    worker::join_workers(workers, worker_config.shutdown_timeout).await;
+    // Задачи ffmpeg выполняются в этом процессе: даём им доделаться
+    removers.shutdown(worker_config.shutdown_timeout).await;
+    Ok(())
 }
@@ -19,6 +19,22 @@ pub struct Product {
     pub created_at: Option<DateTime<Utc>>,
 }
 
+/// Профиль обработки: какой моделью KIE и за сколько кредитов обрабатывается загрузка.
+#[derive(Debug, Clone, Serialize)]
+pub struct ProcessingProfile {
+    pub id: i32,
+    pub slug: String,
+    pub name: String,
+    pub description: Option<String>,
+    #[serde(skip_serializing)]
+    pub kie_model: String,
+    /// Дополнительные поля `input` для createTask
+    #[serde(skip_serializing)]
+    pub kie_input: serde_json::Map<String, serde_json::Value>,
+    pub credit_cost: i32,
+    pub is_public: bool,
+}
+
 #[derive(Debug, Serialize)]
 pub struct Subscription {
     pub id: i32,
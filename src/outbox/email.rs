@@ -0,0 +1,26 @@
+// src/outbox/email.rs
+//
+// Доменные события -> транзакционные письма (`crate::emails`). Здесь письмо только
+// отрисовывается и ставится в `email_outbox`; отправляет его воркер писем.
+
+use async_trait::async_trait;
+
+use super::{StoredEvent, Subscriber};
+use crate::AppState;
+use crate::emails;
+
+pub struct EmailSubscriber;
+
+#[async_trait]
+impl Subscriber for EmailSubscriber {
+    fn name(&self) -> &'static str {
+        "email"
+    }
+
+    async fn handle(&self, state: &AppState, event: &StoredEvent) -> Result<(), String> {
+        if emails::enqueue_event(state, event).await? {
+            state.email_wakeup.notify_one();
+        }
+        Ok(())
+    }
+}
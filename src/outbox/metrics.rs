@@ -0,0 +1,69 @@
+// src/outbox/metrics.rs
+//
+// Счётчики доменных событий и доставок для `GET /metrics` (формат Prometheus).
+// Счётчики живут в памяти процесса, где работает воркер outbox: при отдельном воркере
+// их нужно снимать с него, а не с API.
+
+use async_trait::async_trait;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Mutex, OnceLock};
+
+use super::{StoredEvent, Subscriber};
+use crate::AppState;
+
+#[derive(Default)]
+struct Counters {
+    /// Событие -> сколько раз обработано
+    events: BTreeMap<&'static str, u64>,
+    /// (подписчик, успех) -> сколько доставок
+    deliveries: BTreeMap<(&'static str, bool), u64>,
+}
+
+static COUNTERS: OnceLock<Mutex<Counters>> = OnceLock::new();
+
+fn counters() -> std::sync::MutexGuard<'static, Counters> {
+    COUNTERS
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+}
+
+pub(crate) fn record_delivery(subscriber: &'static str, ok: bool) {
+    *counters().deliveries.entry((subscriber, ok)).or_default() += 1;
+}
+
+pub struct MetricsSubscriber;
+
+#[async_trait]
+impl Subscriber for MetricsSubscriber {
+    fn name(&self) -> &'static str {
+        "metrics"
+    }
+
+    async fn handle(&self, _: &AppState, event: &StoredEvent) -> Result<(), String> {
+        *counters().events.entry(event.event.name()).or_default() += 1;
+        Ok(())
+    }
+}
+
+/// Счётчики в текстовом формате Prometheus.
+pub fn render() -> String {
+    let counters = counters();
+    let mut out = String::new();
+    out.push_str("# HELP domain_events_total Domain events handled by the outbox worker.\n");
+    out.push_str("# TYPE domain_events_total counter\n");
+    for (event, count) in &counters.events {
+        let _ = writeln!(out, "domain_events_total{{event=\"{event}\"}} {count}");
+    }
+    out.push_str("# HELP domain_event_deliveries_total Deliveries to outbox subscribers.\n");
+    out.push_str("# TYPE domain_event_deliveries_total counter\n");
+    for ((subscriber, ok), count) in &counters.deliveries {
+        let result = if *ok { "ok" } else { "error" };
+        let _ = writeln!(
+            out,
+            "domain_event_deliveries_total{{subscriber=\"{subscriber}\",result=\"{result}\"}} {count}"
+        );
+    }
+    out
+}
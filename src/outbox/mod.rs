@@ -0,0 +1,412 @@
+// src/outbox/mod.rs
+//
+// Доменные события и транзакционный outbox. Код, который меняет состояние (завершение
+// загрузки, оплата, подписка), пишет событие в `domain_events` в той же транзакции —
+// событие появляется тогда и только тогда, когда изменение зафиксировано. Побочные эффекты
+// (WebSocket, вебхуки пользователей, метрики, письма) живут в подписчиках,
+// которых вызывает воркер outbox.
+// Доставка «хотя бы один раз»: событие забирается с арендой (`FOR UPDATE SKIP LOCKED`),
+// каждый подписчик отмечается в `domain_event_deliveries`, и повтор после ошибки получают
+// только упавшие. Если процесс упал между обработкой и отметкой, подписчик получит событие
+// ещё раз, поэтому подписчики должны переносить повторы.
+
+pub mod email;
+pub mod metrics;
+pub mod webhooks;
+pub mod ws;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{PgExecutor, Row};
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::AppState;
+use crate::worker::{RetryPolicy, Shutdown, run_claim_loop};
+
+/// Доменное событие. В `payload` хранится целиком, вместе с `type`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum DomainEvent {
+    /// Результат сохранён, загрузка `ready`
+    #[serde(rename = "upload.completed")]
+    UploadCompleted { upload_id: i32, user_id: i32 },
+    /// Загрузка `failed`; `refunded` — кредиты возвращены в той же транзакции
+    #[serde(rename = "upload.failed")]
+    UploadFailed {
+        upload_id: i32,
+        user_id: i32,
+        reason: Option<String>,
+        refunded: bool,
+    },
+    #[serde(rename = "payment.succeeded")]
+    PaymentSucceeded { transaction_id: i32, user_id: i32 },
+    #[serde(rename = "payment.failed")]
+    PaymentFailed { transaction_id: i32, user_id: i32 },
+    /// Начислены разовые кредиты (`one_time`) или выставлена месячная квота (`monthly`)
+    #[serde(rename = "credits.granted")]
+    CreditsGranted {
+        user_id: i32,
+        transaction_id: Option<i32>,
+        credit_type: String,
+        credits: i32,
+    },
+    /// Подписка активирована или продлена
+    #[serde(rename = "subscription.renewed")]
+    SubscriptionRenewed {
+        subscription_id: i32,
+        user_id: i32,
+        transaction_id: Option<i32>,
+    },
+    #[serde(rename = "subscription.canceled")]
+    SubscriptionCanceled { subscription_id: i32, user_id: i32 },
+    /// Списание опустило баланс (`credits` + `monthly_quota`) ниже `LOW_BALANCE_THRESHOLD`
+    #[serde(rename = "credits.low")]
+    CreditsLow {
+        user_id: i32,
+        credits: i32,
+        monthly_quota: i32,
+    },
+}
+
+impl DomainEvent {
+    /// Все виды событий, как их возвращает `name()`.
+    pub const NAMES: &'static [&'static str] = &[
+        "upload.completed",
+        "upload.failed",
+        "payment.succeeded",
+        "payment.failed",
+        "credits.granted",
+        "subscription.renewed",
+        "subscription.canceled",
+        "credits.low",
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            DomainEvent::UploadCompleted { .. } => "upload.completed",
+            DomainEvent::UploadFailed { .. } => "upload.failed",
+            DomainEvent::PaymentSucceeded { .. } => "payment.succeeded",
+            DomainEvent::PaymentFailed { .. } => "payment.failed",
+            DomainEvent::CreditsGranted { .. } => "credits.granted",
+            DomainEvent::SubscriptionRenewed { .. } => "subscription.renewed",
+            DomainEvent::SubscriptionCanceled { .. } => "subscription.canceled",
+            DomainEvent::CreditsLow { .. } => "credits.low",
+        }
+    }
+
+    pub fn user_id(&self) -> i32 {
+        match self {
+            DomainEvent::UploadCompleted { user_id, .. }
+            | DomainEvent::UploadFailed { user_id, .. }
+            | DomainEvent::PaymentSucceeded { user_id, .. }
+            | DomainEvent::PaymentFailed { user_id, .. }
+            | DomainEvent::CreditsGranted { user_id, .. }
+            | DomainEvent::SubscriptionRenewed { user_id, .. }
+            | DomainEvent::SubscriptionCanceled { user_id, .. }
+            | DomainEvent::CreditsLow { user_id, .. } => *user_id,
+        }
+    }
+}
+
+/// Событие из outbox.
+#[derive(Debug, Clone)]
+pub struct StoredEvent {
+    pub id: i64,
+    /// Сколько раз событие забирали, с этой попыткой
+    pub attempts: i32,
+    pub created_at: Option<DateTime<Utc>>,
+    pub event: DomainEvent,
+}
+
+/// Записывает событие в outbox. Вызывать в транзакции изменения, которое оно описывает,
+/// а после коммита — `state.outbox_wakeup.notify_one()`, чтобы не ждать опроса.
+pub async fn record<'e>(
+    executor: impl PgExecutor<'e>,
+    event: &DomainEvent,
+) -> Result<i64, sqlx::Error> {
+    let payload = serde_json::to_string(event).expect("domain event serializes");
+    let row = sqlx::query(
+        r#"INSERT INTO domain_events (event_type, user_id, payload)
+           VALUES ($1, $2, $3::jsonb)
+           RETURNING id"#,
+    )
+    .bind(event.name())
+    .bind(event.user_id())
+    .bind(payload)
+    .fetch_one(executor)
+    .await?;
+    Ok(row.get("id"))
+}
+
+/// Получатель доменных событий. Ошибка — событие придёт этому подписчику ещё раз.
+#[async_trait]
+pub trait Subscriber: Send + Sync {
+    /// Имя в `domain_event_deliveries`; не менять, иначе события придут повторно
+    fn name(&self) -> &'static str;
+
+    async fn handle(&self, state: &AppState, event: &StoredEvent) -> Result<(), String>;
+}
+
+/// Подписчики, которых вызывает воркер outbox, по порядку.
+#[derive(Clone, Default)]
+pub struct Subscribers {
+    subscribers: Vec<Arc<dyn Subscriber>>,
+}
+
+impl Subscribers {
+    pub fn new(subscribers: Vec<Arc<dyn Subscriber>>) -> Self {
+        Self { subscribers }
+    }
+
+    /// WebSocket/SSE, вебхуки пользователей, письма и метрики.
+    pub fn from_env() -> Self {
+        Self::new(vec![
+            Arc::new(ws::WsSubscriber),
+            Arc::new(webhooks::WebhookSubscriber),
+            Arc::new(email::EmailSubscriber),
+            Arc::new(metrics::MetricsSubscriber),
+        ])
+    }
+
+    pub fn names(&self) -> Vec<&'static str> {
+        self.subscribers.iter().map(|s| s.name()).collect()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct OutboxConfig {
+    pub policy: RetryPolicy,
+}
+
+impl Default for OutboxConfig {
+    fn default() -> Self {
+        Self {
+            policy: RetryPolicy {
+                poll_interval: Duration::from_secs(1),
+                batch_size: 100,
+                lease: Duration::from_secs(60),
+                max_attempts: 10,
+                retry_base_delay: Duration::from_secs(5),
+                retry_max_delay: Duration::from_secs(600),
+            },
+        }
+    }
+}
+
+impl OutboxConfig {
+    /// `OUTBOX_*` из `RetryPolicy::from_env`.
+    pub fn from_env() -> Self {
+        Self {
+            policy: RetryPolicy::from_env("OUTBOX", Self::default().policy),
+        }
+    }
+}
+
+/// Фоновый воркер: разбирает outbox сразу после `outbox_wakeup` или раз в `poll_interval`.
+pub async fn run_outbox_dispatcher(
+    state: AppState,
+    subscribers: Subscribers,
+    config: OutboxConfig,
+    mut shutdown: Shutdown,
+) {
+    log::info!(
+        "outbox dispatcher started subscribers={:?} poll_interval={}s",
+        subscribers.names(),
+        config.policy.poll_interval.as_secs()
+    );
+
+    run_claim_loop(
+        "outbox dispatcher",
+        &config.policy,
+        &state.outbox_wakeup,
+        &mut shutdown,
+        || dispatch_pending(&state, &subscribers, &config),
+    )
+    .await;
+    log::info!("outbox dispatcher stopped");
+}
+
+/// Забирает пачку событий, которым пора, и отдаёт их подписчикам.
+/// Возвращает, сколько событий было забрано.
+pub async fn dispatch_pending(
+    state: &AppState,
+    subscribers: &Subscribers,
+    config: &OutboxConfig,
+) -> Result<usize, String> {
+    let rows = sqlx::query(
+        r#"UPDATE domain_events
+           SET next_attempt_at = NOW() + make_interval(secs => $2), attempts = attempts + 1
+           WHERE id IN (
+               SELECT id
+               FROM domain_events
+               WHERE processed_at IS NULL
+                 AND dead_at IS NULL
+                 AND next_attempt_at <= NOW()
+               ORDER BY id ASC
+               LIMIT $1
+               FOR UPDATE SKIP LOCKED
+           )
+           RETURNING id, payload::text AS payload, attempts, created_at"#,
+    )
+    .bind(config.policy.batch_size)
+    .bind(config.policy.lease.as_secs_f64())
+    .fetch_all(&state.pool)
+    .await
+    .map_err(|e| format!("outbox claim error: {e}"))?;
+
+    let count = rows.len();
+    let mut events = Vec::with_capacity(count);
+    for row in rows {
+        let id: i64 = row.get("id");
+        let attempts: i32 = row.get("attempts");
+        let payload: String = row.get("payload");
+        match serde_json::from_str::<DomainEvent>(&payload) {
+            Ok(event) => events.push(StoredEvent {
+                id,
+                attempts,
+                created_at: row.get("created_at"),
+                event,
+            }),
+            Err(e) => {
+                // Повтор не поможет: событие неизвестного вида или сломанное
+                log::error!("outbox event is invalid id={} error={}", id, e);
+                let error = Err(format!("invalid payload: {e}"));
+                finish_event(&state.pool, id, attempts, error, true, config).await?;
+            }
+        }
+    }
+    // Порядок событий сохраняется для первой попытки; повторы идут позже следующих
+    events.sort_by_key(|event| event.id);
+
+    for event in &events {
+        let result = deliver(state, subscribers, event).await;
+        let dead = result.is_err() && event.attempts >= config.policy.max_attempts;
+        if let Err(e) = &result {
+            if dead {
+                log::error!(
+                    "outbox event dead id={} event={} attempts={} error={}",
+                    event.id,
+                    event.event.name(),
+                    event.attempts,
+                    e
+                );
+            } else {
+                log::warn!(
+                    "outbox event retry id={} event={} attempt={} error={}",
+                    event.id,
+                    event.event.name(),
+                    event.attempts,
+                    e
+                );
+            }
+        }
+        finish_event(&state.pool, event.id, event.attempts, result, dead, config)
+            .await
+            .map_err(|e| format!("outbox finish error id={}: {e}", event.id))?;
+    }
+
+    Ok(count)
+}
+
+/// Отдаёт событие подписчикам, которые его ещё не получили.
+async fn deliver(
+    state: &AppState,
+    subscribers: &Subscribers,
+    event: &StoredEvent,
+) -> Result<(), String> {
+    let delivered: HashSet<String> =
+        sqlx::query("SELECT subscriber FROM domain_event_deliveries WHERE event_id = $1")
+            .bind(event.id)
+            .fetch_all(&state.pool)
+            .await
+            .map_err(|e| e.to_string())?
+            .into_iter()
+            .map(|row| row.get("subscriber"))
+            .collect();
+
+    let mut errors = Vec::new();
+    for subscriber in &subscribers.subscribers {
+        let name = subscriber.name();
+        if delivered.contains(name) {
+            continue;
+        }
+        match subscriber.handle(state, event).await {
+            Ok(()) => {
+                metrics::record_delivery(name, true);
+                sqlx::query(
+                    r#"INSERT INTO domain_event_deliveries (event_id, subscriber)
+                       VALUES ($1, $2)
+                       ON CONFLICT DO NOTHING"#,
+                )
+                .bind(event.id)
+                .bind(name)
+                .execute(&state.pool)
+                .await
+                .map_err(|e| e.to_string())?;
+            }
+            Err(e) => {
+                metrics::record_delivery(name, false);
+                errors.push(format!("{name}: {e}"));
+            }
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors.join("; "))
+    }
+}
+
+async fn finish_event(
+    pool: &sqlx::PgPool,
+    id: i64,
+    attempts: i32,
+    result: Result<(), String>,
+    dead: bool,
+    config: &OutboxConfig,
+) -> Result<(), String> {
+    let query = match &result {
+        Ok(()) => sqlx::query(
+            "UPDATE domain_events SET processed_at = NOW(), last_error = NULL WHERE id = $1",
+        )
+        .bind(id),
+        Err(e) if dead => {
+            sqlx::query("UPDATE domain_events SET dead_at = NOW(), last_error = $2 WHERE id = $1")
+                .bind(id)
+                .bind(e)
+        }
+        Err(e) => sqlx::query(
+            r#"UPDATE domain_events
+               SET next_attempt_at = NOW() + make_interval(secs => $3), last_error = $2
+               WHERE id = $1"#,
+        )
+        .bind(id)
+        .bind(e)
+        .bind(config.policy.retry_delay(attempts).as_secs_f64()),
+    };
+    query.execute(pool).await.map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// Удаляет обработанные события старше `retention` и мёртвые старше `dead_retention`.
+pub async fn prune_domain_events(
+    pool: &sqlx::PgPool,
+    retention: Duration,
+    dead_retention: Duration,
+) -> Result<u64, String> {
+    let result = sqlx::query(
+        r#"DELETE FROM domain_events
+           WHERE processed_at < NOW() - make_interval(secs => $1)
+              OR dead_at < NOW() - make_interval(secs => $2)"#,
+    )
+    .bind(retention.as_secs_f64())
+    .bind(dead_retention.as_secs_f64())
+    .execute(pool)
+    .await
+    .map_err(|e| e.to_string())?;
+    Ok(result.rows_affected())
+}
@@ -0,0 +1,26 @@
+// src/outbox/webhooks.rs
+//
+// Доменные события -> доставки вебхуков пользователей. Сам запрос отправляет воркер
+// `user_webhooks`, здесь событие только ставится в очередь включённым адресам.
+
+use async_trait::async_trait;
+
+use super::{StoredEvent, Subscriber};
+use crate::AppState;
+use crate::user_webhooks;
+
+pub struct WebhookSubscriber;
+
+#[async_trait]
+impl Subscriber for WebhookSubscriber {
+    fn name(&self) -> &'static str {
+        "webhooks"
+    }
+
+    async fn handle(&self, state: &AppState, event: &StoredEvent) -> Result<(), String> {
+        if user_webhooks::enqueue_event(&state.pool, event).await? > 0 {
+            state.webhook_wakeup.notify_one();
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,82 @@
+// src/outbox/ws.rs
+//
+// Доменные события -> события WebSocket/SSE (`upload.updated`, `payment.*`, `subscription.*`,
+// `credits.updated`). Данные берутся из БД на момент доставки, так что повтор отправит
+// клиенту актуальное состояние, а не устаревшее.
+
+use async_trait::async_trait;
+
+use super::{DomainEvent, StoredEvent, Subscriber};
+use crate::AppState;
+use crate::events::{self, AccountEvent};
+use crate::ws::notify_upload;
+
+pub struct WsSubscriber;
+
+#[async_trait]
+impl Subscriber for WsSubscriber {
+    fn name(&self) -> &'static str {
+        "ws"
+    }
+
+    async fn handle(&self, state: &AppState, event: &StoredEvent) -> Result<(), String> {
+        let pool = &state.pool;
+        let hub = &state.ws_hub;
+        match &event.event {
+            DomainEvent::UploadCompleted { upload_id, .. } => {
+                notify_upload(pool, hub, *upload_id).await;
+            }
+            DomainEvent::UploadFailed {
+                upload_id,
+                user_id,
+                refunded,
+                ..
+            } => {
+                if *refunded {
+                    emit_credits(state, *user_id).await?;
+                }
+                notify_upload(pool, hub, *upload_id).await;
+            }
+            DomainEvent::PaymentSucceeded { transaction_id, .. }
+            | DomainEvent::PaymentFailed { transaction_id, .. } => {
+                if let Some((user_id, event)) = events::load_payment(pool, *transaction_id)
+                    .await
+                    .map_err(|e| e.to_string())?
+                {
+                    events::emit(pool, hub, user_id, &event).await;
+                }
+            }
+            DomainEvent::CreditsGranted { user_id, .. } => emit_credits(state, *user_id).await?,
+            // Баланс клиенту уже отправлен при списании
+            DomainEvent::CreditsLow { .. } => {}
+            DomainEvent::SubscriptionRenewed {
+                subscription_id, ..
+            }
+            | DomainEvent::SubscriptionCanceled {
+                subscription_id, ..
+            } => {
+                if let Some((user_id, event)) = events::load_subscription(pool, *subscription_id)
+                    .await
+                    .map_err(|e| e.to_string())?
+                {
+                    events::emit(pool, hub, user_id, &event).await;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+async fn emit_credits(state: &AppState, user_id: i32) -> Result<(), String> {
+    let credits = events::load_credits(&state.pool, user_id)
+        .await
+        .map_err(|e| e.to_string())?;
+    events::emit(
+        &state.pool,
+        &state.ws_hub,
+        user_id,
+        &AccountEvent::CreditsUpdated(credits),
+    )
+    .await;
+    Ok(())
+}
@@ -0,0 +1,614 @@
+// src/providers/ffmpeg.rs
+//
+// Локальная обработка системным ffmpeg (self-hosted, офлайн, CI без внешних API).
+// Видео скачивается в рабочий каталог, логотип замазывается фильтром delogo в известных
+// местах водяного знака Sora или в области, указанной пользователем.
+// О ходе и результате бэкенд сообщает так же, как KIE, — колбэком на callback_url.
+// API и воркеры могут работать на разных машинах, поэтому готовый ролик бэкенд сам кладёт
+// в общее хранилище (`s3://bucket/cleaned/{task_id}.mp4`), а finalize его только записывает.
+
+use super::{
+    CallbackResult, ProviderError, SubmitJob, TaskStatus, WatermarkRegion, WatermarkRemover,
+};
+use crate::finalize::cleaned_s3_key;
+use crate::s3_utils::{StreamUploadOptions, UploadSource, stream_to_s3};
+use crate::safe_http::{self, FetchPolicy};
+use async_trait::async_trait;
+use futures_util::future::join_all;
+use aws_sdk_s3::Client as S3Client;
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::process::Command;
+use tokio::sync::Semaphore;
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+pub const PROVIDER_NAME: &str = "ffmpeg";
+
+/// Места водяного знака Sora в долях кадра: (x, y, ширина, высота).
+/// Логотип перескакивает между ними по ходу ролика, поэтому замазываем все сразу.
+pub const SORA_WATERMARK_POSITIONS: [(f64, f64, f64, f64); 3] = [
+    (0.02, 0.03, 0.30, 0.08),
+    (0.68, 0.46, 0.30, 0.08),
+    (0.02, 0.89, 0.30, 0.08),
+];
+
+/// Шаг, с которым отправляем колбэки о прогрессе (в процентах).
+const PROGRESS_STEP: u8 = 10;
+/// Попыток отправить итоговый колбэк.
+const CALLBACK_ATTEMPTS: u32 = 3;
+const CALLBACK_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone)]
+pub struct FfmpegConfig {
+    pub ffmpeg: String,
+    pub ffprobe: String,
+    /// Каталог для исходников и результатов
+    pub work_dir: PathBuf,
+    /// Сколько роликов обрабатывается одновременно
+    pub max_jobs: usize,
+    /// Ограничение на один запуск ffmpeg
+    pub timeout: Duration,
+}
+
+impl Default for FfmpegConfig {
+    fn default() -> Self {
+        Self {
+            ffmpeg: "ffmpeg".to_string(),
+            ffprobe: "ffprobe".to_string(),
+            work_dir: std::env::temp_dir().join("sora-ffmpeg"),
+            max_jobs: 2,
+            timeout: Duration::from_secs(1800),
+        }
+    }
+}
+
+impl FfmpegConfig {
+    /// `FFMPEG_BIN`, `FFPROBE_BIN`, `FFMPEG_WORK_DIR`, `FFMPEG_MAX_JOBS`, `FFMPEG_TIMEOUT_SECS`.
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let env = |key: &str| std::env::var(key).ok().filter(|v| !v.trim().is_empty());
+        Self {
+            ffmpeg: env("FFMPEG_BIN").unwrap_or(defaults.ffmpeg),
+            ffprobe: env("FFPROBE_BIN").unwrap_or(defaults.ffprobe),
+            work_dir: env("FFMPEG_WORK_DIR")
+                .map(PathBuf::from)
+                .unwrap_or(defaults.work_dir),
+            max_jobs: env("FFMPEG_MAX_JOBS")
+                .and_then(|v| v.trim().parse::<usize>().ok())
+                .unwrap_or(defaults.max_jobs)
+                .max(1),
+            timeout: env("FFMPEG_TIMEOUT_SECS")
+                .and_then(|v| v.trim().parse::<u64>().ok())
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.timeout),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VideoInfo {
+    pub width: u32,
+    pub height: u32,
+    /// 0, если длительность неизвестна (тогда прогресс не считаем)
+    pub duration_us: u64,
+}
+
+/// Разбирает вывод `ffprobe -show_entries stream=width,height:format=duration -of json`.
+pub fn parse_probe(json: &str) -> Result<VideoInfo, String> {
+    let value: Value =
+        serde_json::from_str(json).map_err(|e| format!("ffprobe output parse error: {e}"))?;
+    let stream = value
+        .get("streams")
+        .and_then(|s| s.as_array())
+        .and_then(|s| s.first())
+        .ok_or_else(|| "no video stream".to_string())?;
+    let side = |key: &str| {
+        stream
+            .get(key)
+            .and_then(|v| v.as_u64())
+            .filter(|v| *v > 0)
+            .map(|v| v as u32)
+    };
+    let (Some(width), Some(height)) = (side("width"), side("height")) else {
+        return Err("video stream without frame size".to_string());
+    };
+    let duration_us = value
+        .pointer("/format/duration")
+        .and_then(|v| v.as_str())
+        .and_then(|v| v.parse::<f64>().ok())
+        .filter(|v| v.is_finite() && *v > 0.0)
+        .map(|secs| (secs * 1_000_000.0) as u64)
+        .unwrap_or(0);
+    Ok(VideoInfo {
+        width,
+        height,
+        duration_us,
+    })
+}
+
+/// Места водяного знака Sora в пикселях для данного кадра.
+pub fn sora_regions(info: &VideoInfo) -> Vec<WatermarkRegion> {
+    let (width, height) = (info.width as f64, info.height as f64);
+    SORA_WATERMARK_POSITIONS
+        .iter()
+        .map(|(x, y, w, h)| WatermarkRegion {
+            x: (x * width).round() as u32,
+            y: (y * height).round() as u32,
+            width: (w * width).round() as u32,
+            height: (h * height).round() as u32,
+        })
+        .collect()
+}
+
+/// delogo восстанавливает область по пикселям вокруг неё, поэтому область должна
+/// лежать внутри кадра с отступом в пиксель. `None`, если от области ничего не осталось.
+pub fn clamp_region(region: &WatermarkRegion, info: &VideoInfo) -> Option<WatermarkRegion> {
+    let x = region.x.max(1);
+    let y = region.y.max(1);
+    let max_width = info.width.checked_sub(x + 1)?;
+    let max_height = info.height.checked_sub(y + 1)?;
+    let width = region.width.min(max_width);
+    let height = region.height.min(max_height);
+    (width > 0 && height > 0).then_some(WatermarkRegion {
+        x,
+        y,
+        width,
+        height,
+    })
+}
+
+/// Цепочка `delogo` по всем областям, которые попадают в кадр.
+pub fn delogo_filter(regions: &[WatermarkRegion], info: &VideoInfo) -> Option<String> {
+    let filters: Vec<String> = regions
+        .iter()
+        .filter_map(|r| clamp_region(r, info))
+        .map(|r| format!("delogo=x={}:y={}:w={}:h={}", r.x, r.y, r.width, r.height))
+        .collect();
+    (!filters.is_empty()).then(|| filters.join(","))
+}
+
+/// Процент готовности по строке вывода `-progress` (`out_time_us=...`, `progress=end`).
+pub fn parse_progress(line: &str, duration_us: u64) -> Option<u8> {
+    let (key, value) = line.trim().split_once('=')?;
+    match key {
+        "progress" if value == "end" => Some(100),
+        // out_time_ms тоже в микросекундах (так исторически сложилось в ffmpeg)
+        "out_time_us" | "out_time_ms" if duration_us > 0 => {
+            let done = value.parse::<u64>().ok()?;
+            Some((done.saturating_mul(100) / duration_us).min(99) as u8)
+        }
+        _ => None,
+    }
+}
+
+/// Хранилище, куда бэкенд кладёт результаты.
+#[derive(Debug, Clone)]
+pub struct ResultStorage {
+    pub client: S3Client,
+    pub bucket: String,
+}
+
+/// Ссылка на результат задачи в хранилище: ключ тот же, что у результатов других бэкендов.
+pub fn stored_result_url(bucket: &str, task_id: &str) -> String {
+    format!("s3://{bucket}/{}", cleaned_s3_key(task_id))
+}
+
+pub struct FfmpegRemover {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    config: FfmpegConfig,
+    storage: OnceLock<ResultStorage>,
+    http: reqwest::Client,
+    slots: Semaphore,
+    /// Задачи, запущенные этим процессом. Итог хранится, пока его не заберут колбэком или `poll`
+    tasks: Mutex<HashMap<String, TaskStatus>>,
+    /// Выполняющиеся задачи (вместе с отправкой итогового колбэка): их ждёт `shutdown`
+    jobs: Mutex<HashMap<String, JoinHandle<()>>>,
+}
+
+impl FfmpegRemover {
+    pub fn new(config: FfmpegConfig) -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                http: reqwest::Client::builder()
+                    .timeout(CALLBACK_TIMEOUT)
+                    .build()
+                    .unwrap_or_default(),
+                storage: OnceLock::new(),
+                slots: Semaphore::new(config.max_jobs.max(1)),
+                tasks: Mutex::new(HashMap::new()),
+                jobs: Mutex::new(HashMap::new()),
+                config,
+            }),
+        }
+    }
+
+    /// Без хранилища задачи завершаются ошибкой: локальный файл другим процессам недоступен.
+    pub fn with_storage(self, storage: ResultStorage) -> Self {
+        let _ = self.shared.storage.set(storage);
+        self
+    }
+}
+
+async fn run_job(shared: Arc<Shared>, task_id: String, job: SubmitJob) {
+    let result = match shared.slots.acquire().await {
+        Ok(_permit) => shared.process(&task_id, &job).await,
+        Err(_) => Err("ffmpeg backend is shut down".to_string()),
+    };
+
+    let (status, callback) = match result {
+        Ok(result_url) => {
+            log::info!("ffmpeg task finished task_id={}", task_id);
+            (
+                TaskStatus::Succeeded {
+                    result_url: result_url.clone(),
+                },
+                json!({ "taskId": task_id, "status": "success", "resultUrl": result_url }),
+            )
+        }
+        Err(e) => {
+            log::error!("ffmpeg task failed task_id={} error={}", task_id, e);
+            (
+                TaskStatus::Failed {
+                    reason: Some(e.clone()),
+                },
+                json!({ "taskId": task_id, "status": "fail", "error": e }),
+            )
+        }
+    };
+
+    shared.tasks.lock().unwrap().insert(task_id.clone(), status);
+    // Не доставили — итог остаётся для поллера статусов
+    if shared
+        .send_callback(&job.callback_url, &callback, CALLBACK_ATTEMPTS)
+        .await
+    {
+        shared.tasks.lock().unwrap().remove(&task_id);
+    }
+    shared.jobs.lock().unwrap().remove(&task_id);
+}
+
+impl Shared {
+    /// Скачивает, обрабатывает, кладёт результат в хранилище и возвращает ссылку на него.
+    async fn process(&self, task_id: &str, job: &SubmitJob) -> Result<String, String> {
+        let storage = self
+            .storage
+            .get()
+            .ok_or_else(|| "result storage is not configured".to_string())?;
+        tokio::fs::create_dir_all(&self.config.work_dir)
+            .await
+            .map_err(|e| format!("create {} error: {e}", self.config.work_dir.display()))?;
+        let work_dir = tokio::fs::canonicalize(&self.config.work_dir)
+            .await
+            .map_err(|e| format!("resolve {} error: {e}", self.config.work_dir.display()))?;
+        let input = work_dir.join(format!("{task_id}.source"));
+        let output = work_dir.join(format!("{task_id}.mp4"));
+
+        let result = self.transcode(task_id, job, &input, &output).await;
+        let _ = tokio::fs::remove_file(&input).await;
+        let result = match result {
+            Ok(()) => store_result(storage, task_id, &output).await,
+            Err(e) => Err(e),
+        };
+        let _ = tokio::fs::remove_file(&output).await;
+        result
+    }
+
+    async fn transcode(
+        &self,
+        task_id: &str,
+        job: &SubmitJob,
+        input: &Path,
+        output: &Path,
+    ) -> Result<(), String> {
+        download(&job.video_url, input).await?;
+        let info = probe(&self.config.ffprobe, input).await?;
+
+        let regions = match job.region {
+            Some(region) => vec![region],
+            None => sora_regions(&info),
+        };
+        let filter = delogo_filter(&regions, &info)
+            .ok_or_else(|| "watermark region is outside of the frame".to_string())?;
+        log::info!(
+            "ffmpeg task started task_id={} size={}x{} filter={}",
+            task_id,
+            info.width,
+            info.height,
+            filter
+        );
+
+        let run = self.run_ffmpeg(
+            task_id,
+            &job.callback_url,
+            input,
+            output,
+            &filter,
+            info.duration_us,
+        );
+        // При таймауте future отбрасывается, а вместе с ним убивается и процесс (kill_on_drop)
+        match actix_web::rt::time::timeout(self.config.timeout, run).await {
+            Ok(result) => result,
+            Err(_) => Err(format!(
+                "ffmpeg timed out after {}s",
+                self.config.timeout.as_secs()
+            )),
+        }
+    }
+
+    async fn run_ffmpeg(
+        &self,
+        task_id: &str,
+        callback_url: &str,
+        input: &Path,
+        output: &Path,
+        filter: &str,
+        duration_us: u64,
+    ) -> Result<(), String> {
+        let mut child = Command::new(&self.config.ffmpeg)
+            .args(["-hide_banner", "-nostdin", "-y", "-loglevel", "error"])
+            .args(["-nostats", "-progress", "pipe:1", "-i"])
+            .arg(input)
+            .args(["-vf", filter, "-c:a", "copy", "-movflags", "+faststart"])
+            .arg(output)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| format!("ffmpeg spawn error: {e}"))?;
+        let stdout = child
+            .stdout
+            .take()
+            .ok_or_else(|| "ffmpeg stdout unavailable".to_string())?;
+        let mut stderr = child
+            .stderr
+            .take()
+            .ok_or_else(|| "ffmpeg stderr unavailable".to_string())?;
+
+        let report = async {
+            let mut lines = BufReader::new(stdout).lines();
+            let mut reported = 0u8;
+            while let Ok(Some(line)) = lines.next_line().await {
+                let Some(progress) = parse_progress(&line, duration_us) else {
+                    continue;
+                };
+                if progress > reported && progress >= reported.saturating_add(PROGRESS_STEP).min(100)
+                {
+                    reported = progress;
+                    let body =
+                        json!({ "taskId": task_id, "status": "running", "progress": progress });
+                    let _ = self.send_callback(callback_url, &body, 1).await;
+                }
+            }
+        };
+        let errors = async {
+            let mut errors = String::new();
+            let _ = stderr.read_to_string(&mut errors).await;
+            errors
+        };
+        let ((), errors) = futures_util::join!(report, errors);
+
+        let status = child
+            .wait()
+            .await
+            .map_err(|e| format!("ffmpeg wait error: {e}"))?;
+        if !status.success() {
+            return Err(format!("ffmpeg exited with {status}: {}", errors.trim()));
+        }
+        Ok(())
+    }
+
+    /// Колбэк на наш же `/api/watermark-callback`; отказ (4xx) не повторяем.
+    /// `false`, если сервис так и не ответил.
+    async fn send_callback(&self, url: &str, body: &Value, attempts: u32) -> bool {
+        for attempt in 1..=attempts {
+            match self.http.post(url).json(body).send().await {
+                Ok(resp) if resp.status().is_success() => return true,
+                Ok(resp) if resp.status().is_client_error() => {
+                    log::warn!("ffmpeg callback rejected status={}", resp.status());
+                    return true;
+                }
+                Ok(resp) => log::warn!(
+                    "ffmpeg callback failed status={} attempt={}/{}",
+                    resp.status(),
+                    attempt,
+                    attempts
+                ),
+                Err(e) => log::warn!(
+                    "ffmpeg callback error={} attempt={}/{}",
+                    e,
+                    attempt,
+                    attempts
+                ),
+            }
+            if attempt < attempts {
+                actix_web::rt::time::sleep(Duration::from_secs(2u64.pow(attempt - 1))).await;
+            }
+        }
+        false
+    }
+}
+
+async fn download(url: &str, path: &Path) -> Result<(), String> {
+    let mut resp = safe_http::get(url, &FetchPolicy::from_env())
+        .await
+        .map_err(|e| format!("download failed: {e}"))?;
+    let mut file = tokio::fs::File::create(path)
+        .await
+        .map_err(|e| format!("create {} error: {e}", path.display()))?;
+    while let Some(chunk) = resp
+        .chunk()
+        .await
+        .map_err(|e| format!("download failed: {e}"))?
+    {
+        file.write_all(&chunk)
+            .await
+            .map_err(|e| format!("write {} error: {e}", path.display()))?;
+    }
+    file.flush()
+        .await
+        .map_err(|e| format!("write {} error: {e}", path.display()))
+}
+
+/// Переливает готовый ролик в хранилище под ключом результата задачи.
+async fn store_result(storage: &ResultStorage, task_id: &str, output: &Path) -> Result<String, String> {
+    let key = cleaned_s3_key(task_id);
+    let object = stream_to_s3(
+        &storage.client,
+        &storage.bucket,
+        &key,
+        UploadSource::file(output).await?,
+        &StreamUploadOptions::from_env(),
+    )
+    .await?;
+    log::info!(
+        "ffmpeg result stored task_id={} key={} size={}",
+        task_id,
+        key,
+        object.size
+    );
+    Ok(stored_result_url(&storage.bucket, task_id))
+}
+
+async fn probe(ffprobe: &str, input: &Path) -> Result<VideoInfo, String> {
+    let output = Command::new(ffprobe)
+        .args(["-v", "error", "-select_streams", "v:0"])
+        .args(["-show_entries", "stream=width,height:format=duration", "-of", "json"])
+        .arg(input)
+        .stdin(Stdio::null())
+        .kill_on_drop(true)
+        .output()
+        .await
+        .map_err(|e| format!("ffprobe spawn error: {e}"))?;
+    if !output.status.success() {
+        return Err(format!(
+            "ffprobe exited with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    parse_probe(&String::from_utf8_lossy(&output.stdout))
+}
+
+#[async_trait]
+impl WatermarkRemover for FfmpegRemover {
+    fn name(&self) -> &'static str {
+        PROVIDER_NAME
+    }
+
+    fn model(&self, _job: &SubmitJob) -> Option<String> {
+        Some("delogo".to_string())
+    }
+
+    /// Задача выполняется в фоне в этом процессе; id возвращается сразу.
+    async fn submit(&self, job: &SubmitJob) -> Result<String, ProviderError> {
+        let task_id = format!("ffmpeg-{}", Uuid::new_v4().simple());
+        self.shared
+            .tasks
+            .lock()
+            .unwrap()
+            .insert(task_id.clone(), TaskStatus::Pending);
+
+        // Под замком: быстро завершившаяся задача не уберёт себя раньше, чем её запишут
+        let mut jobs = self.shared.jobs.lock().unwrap();
+        let handle = actix_web::rt::spawn(run_job(self.shared.clone(), task_id.clone(), job.clone()));
+        jobs.insert(task_id.clone(), handle);
+        Ok(task_id)
+    }
+
+    /// Состояние знает только процесс, который запустил задачу. Поллер статусов работает
+    /// и в API, и в каждом воркере, поэтому чужая (или потерянная при перезапуске) задача —
+    /// `Pending`: итог придёт колбэком от владельца, а зависшую закроет срок `KIE_STATUS_MAX_AGE_SECS`.
+    /// Итог отдаётся один раз: дальше задача для процесса тоже чужая.
+    async fn poll(&self, task_id: &str) -> Result<TaskStatus, ProviderError> {
+        let mut tasks = self.shared.tasks.lock().unwrap();
+        Ok(match tasks.get(task_id) {
+            Some(TaskStatus::Pending) => TaskStatus::Pending,
+            Some(_) => tasks.remove(task_id).unwrap_or(TaskStatus::Pending),
+            None => TaskStatus::Pending,
+        })
+    }
+
+    /// `{"taskId": "...", "status": "running" | "success" | "fail", "progress": 40,
+    /// "resultUrl": "s3://...", "error": "..."}`
+    fn parse_callback(&self, body: &Value) -> Result<CallbackResult, ProviderError> {
+        let task_id = body
+            .get("taskId")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| ProviderError::InvalidResponse("missing taskId".to_string()))?
+            .to_string();
+        let progress = body
+            .get("progress")
+            .and_then(|v| v.as_u64())
+            .map(|p| p.min(100) as u8);
+
+        let status = match body.get("status").and_then(|v| v.as_str()) {
+            Some("running") => TaskStatus::Pending,
+            Some("success") => match body.get("resultUrl").and_then(|v| v.as_str()) {
+                Some(url) => TaskStatus::Succeeded {
+                    result_url: url.to_string(),
+                },
+                None => {
+                    return Err(ProviderError::InvalidResponse("missing resultUrl".to_string()));
+                }
+            },
+            Some("fail") => TaskStatus::Failed {
+                reason: body.get("error").and_then(|v| v.as_str()).map(|s| s.to_string()),
+            },
+            other => {
+                return Err(ProviderError::InvalidResponse(format!("unknown status {other:?}")));
+            }
+        };
+
+        Ok(CallbackResult {
+            task_id,
+            status,
+            progress,
+        })
+    }
+
+    async fn cancel(&self, task_id: &str) -> Result<(), ProviderError> {
+        let Some(handle) = self.shared.jobs.lock().unwrap().remove(task_id) else {
+            return Err(ProviderError::Api {
+                status: 404,
+                message: format!("task {task_id} is not running"),
+            });
+        };
+        handle.abort();
+        // Отменяют закрытую загрузку: итог уже никто не ждёт
+        self.shared.tasks.lock().unwrap().remove(task_id);
+
+        let work_dir = &self.shared.config.work_dir;
+        for ext in ["source", "mp4"] {
+            let _ = tokio::fs::remove_file(work_dir.join(format!("{task_id}.{ext}"))).await;
+        }
+        Ok(())
+    }
+
+    /// Дожидается запущенных задач вместе с итоговыми колбэками. Не успевшие за `timeout`
+    /// прерываются: их загрузки закроет срок `KIE_STATUS_MAX_AGE_SECS` с возвратом кредитов.
+    async fn shutdown(&self, timeout: Duration) {
+        let jobs: Vec<_> = self.shared.jobs.lock().unwrap().drain().collect();
+        if jobs.is_empty() {
+            return;
+        }
+        log::info!("ffmpeg waiting for {} running tasks", jobs.len());
+        let aborts: Vec<_> = jobs.iter().map(|(_, handle)| handle.abort_handle()).collect();
+        let handles = jobs.into_iter().map(|(_, handle)| handle);
+        if actix_web::rt::time::timeout(timeout, join_all(handles)).await.is_err() {
+            log::warn!("ffmpeg tasks did not finish within {}s, aborting", timeout.as_secs());
+            for abort in aborts {
+                abort.abort();
+            }
+        }
+    }
+}
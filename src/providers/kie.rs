@@ -0,0 +1,169 @@
+// src/providers/kie.rs
+//
+// Kie.ai: createTask + recordInfo, результат приходит колбэком на callBackUrl.
+
+use super::{CallbackResult, ProviderError, SubmitJob, TaskStatus, WatermarkRemover};
+use crate::kie_client::{
+    CreateTaskInput, CreateTaskRequest, KieClient, KieError, KieTaskState, first_result_url,
+};
+use async_trait::async_trait;
+use serde::Deserialize;
+use serde_json::Value;
+use std::sync::Arc;
+use utoipa::ToSchema;
+
+pub const PROVIDER_NAME: &str = "kie";
+pub const DEFAULT_MODEL: &str = "sora-watermark-remover";
+
+#[derive(Deserialize, Debug, ToSchema)]
+pub struct CallbackPayload {
+    pub code: i32,
+    pub msg: Option<String>,
+    pub data: CallbackData,
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+pub struct CallbackData {
+    #[serde(rename = "taskId")]
+    pub task_id: String,
+    #[serde(default)]
+    pub status: Option<String>,
+    #[serde(default)]
+    pub state: Option<String>,
+    #[serde(default, rename = "outputUrl")]
+    pub output_url: Option<String>,
+    #[serde(default, rename = "resultJson")]
+    pub result_json: Option<String>,
+    #[serde(default, rename = "failMsg")]
+    pub fail_msg: Option<String>,
+}
+
+pub struct KieRemover {
+    client: Arc<KieClient>,
+}
+
+impl KieRemover {
+    /// Отдельный клиент с настройками из окружения (тесты, утилиты).
+    pub fn new(api_key: &str) -> Self {
+        Self::with_client(Arc::new(KieClient::from_env(api_key)))
+    }
+
+    /// Общий клиент из `AppState`.
+    pub fn with_client(client: Arc<KieClient>) -> Self {
+        Self { client }
+    }
+}
+
+impl From<KieError> for ProviderError {
+    fn from(e: KieError) -> Self {
+        match e {
+            KieError::Connect(e) | KieError::Transport(e) => ProviderError::Request(e),
+            KieError::Timeout => ProviderError::Request("kie request timed out".to_string()),
+            KieError::Http { status, body } => ProviderError::Api {
+                status,
+                message: body,
+            },
+            KieError::Api { code, msg } => ProviderError::Api {
+                status: u16::try_from(code).unwrap_or(500),
+                message: msg,
+            },
+            KieError::Decode(e) => ProviderError::InvalidResponse(e),
+        }
+    }
+}
+
+#[async_trait]
+impl WatermarkRemover for KieRemover {
+    fn name(&self) -> &'static str {
+        PROVIDER_NAME
+    }
+
+    fn model(&self, job: &SubmitJob) -> Option<String> {
+        Some(job.model.clone().unwrap_or_else(|| DEFAULT_MODEL.to_string()))
+    }
+
+    async fn submit(&self, job: &SubmitJob) -> Result<String, ProviderError> {
+        let model = self.model(job).unwrap_or_else(|| DEFAULT_MODEL.to_string());
+        // video_url всегда наш, профиль не может его подменить
+        let extra = job
+            .extra_input
+            .iter()
+            .filter(|(key, _)| key.as_str() != "video_url")
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+        let body = CreateTaskRequest {
+            model: &model,
+            input: CreateTaskInput {
+                video_url: &job.video_url,
+                extra,
+            },
+            callback_url: &job.callback_url,
+        };
+
+        Ok(self.client.create_task(&body).await?)
+    }
+
+    /// `KIE_CALLBACK_VERIFY_RECORD=true`: результат подтверждается через `recordInfo`.
+    fn verify_callbacks(&self) -> bool {
+        std::env::var("KIE_CALLBACK_VERIFY_RECORD").unwrap_or_default() == "true"
+    }
+
+    async fn poll(&self, task_id: &str) -> Result<TaskStatus, ProviderError> {
+        let data = self.client.record_info(task_id).await?;
+        Ok(match data.task_state() {
+            KieTaskState::Success => match data.result_url() {
+                Some(result_url) => TaskStatus::Succeeded { result_url },
+                None => {
+                    return Err(ProviderError::InvalidResponse(format!(
+                        "task {} succeeded without result url",
+                        data.task_id
+                    )));
+                }
+            },
+            KieTaskState::Fail => TaskStatus::Failed {
+                reason: data.fail_msg,
+            },
+            _ => TaskStatus::Pending,
+        })
+    }
+
+    fn parse_callback(&self, body: &Value) -> Result<CallbackResult, ProviderError> {
+        let payload: CallbackPayload = serde_json::from_value(body.clone())
+            .map_err(|e| ProviderError::InvalidResponse(e.to_string()))?;
+        let data = payload.data;
+
+        let failed = |reason: Option<String>| CallbackResult {
+            task_id: data.task_id.clone(),
+            status: TaskStatus::Failed { reason },
+            progress: None,
+        };
+
+        if payload.code != 200 {
+            return Ok(failed(payload.msg.clone().or(data.fail_msg.clone())));
+        }
+
+        // В разных версиях API состояние приходит в `status` или в `state`
+        for state in [data.status.as_deref(), data.state.as_deref()].into_iter().flatten() {
+            if state != "success" {
+                return Ok(failed(data.fail_msg.clone().or(Some(state.to_string()))));
+            }
+        }
+
+        let result_url = data
+            .output_url
+            .clone()
+            .or_else(|| data.result_json.as_deref().and_then(first_result_url))
+            .ok_or_else(|| ProviderError::InvalidResponse("no result url in callback".to_string()))?;
+
+        Ok(CallbackResult {
+            task_id: data.task_id,
+            status: TaskStatus::Succeeded { result_url },
+            progress: None,
+        })
+    }
+
+    async fn cancel(&self, _task_id: &str) -> Result<(), ProviderError> {
+        // У KIE нет API отмены задач
+        Err(ProviderError::Unsupported("cancel"))
+    }
+}
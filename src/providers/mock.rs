@@ -0,0 +1,136 @@
+// src/providers/mock.rs
+//
+// Детерминированный бэкенд для тестов и локальной разработки: ничего не обрабатывает,
+// «результатом» считается исходное видео (или `MOCK_PROVIDER_RESULT_URL`).
+
+use super::{CallbackResult, ProviderError, SubmitJob, TaskStatus, WatermarkRemover};
+use crate::dedup::content_hash;
+use async_trait::async_trait;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+pub const PROVIDER_NAME: &str = "mock";
+
+pub struct MockRemover {
+    result_url: Option<String>,
+    fail_submit: bool,
+    tasks: Mutex<HashMap<String, TaskStatus>>,
+}
+
+impl MockRemover {
+    pub fn new() -> Self {
+        Self {
+            result_url: std::env::var("MOCK_PROVIDER_RESULT_URL")
+                .ok()
+                .filter(|v| !v.trim().is_empty()),
+            fail_submit: false,
+            tasks: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Все задачи завершаются этой ссылкой.
+    pub fn with_result_url(mut self, url: &str) -> Self {
+        self.result_url = Some(url.to_string());
+        self
+    }
+
+    /// `submit` всегда отвечает ошибкой (для проверки обработки сбоев).
+    pub fn failing() -> Self {
+        Self {
+            fail_submit: true,
+            ..Self::new()
+        }
+    }
+
+    /// id задачи зависит только от входных данных
+    pub fn task_id_for(job: &SubmitJob) -> String {
+        let hash = content_hash(format!("{}\n{}", job.video_url, job.callback_url).as_bytes());
+        format!("mock-{}", &hash[..16])
+    }
+}
+
+impl Default for MockRemover {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl WatermarkRemover for MockRemover {
+    fn name(&self) -> &'static str {
+        PROVIDER_NAME
+    }
+
+    async fn submit(&self, job: &SubmitJob) -> Result<String, ProviderError> {
+        if self.fail_submit {
+            return Err(ProviderError::Api {
+                status: 503,
+                message: "mock provider is configured to fail".to_string(),
+            });
+        }
+
+        let task_id = Self::task_id_for(job);
+        let result_url = self
+            .result_url
+            .clone()
+            .unwrap_or_else(|| job.video_url.clone());
+        self.tasks
+            .lock()
+            .unwrap()
+            .insert(task_id.clone(), TaskStatus::Succeeded { result_url });
+        Ok(task_id)
+    }
+
+    async fn poll(&self, task_id: &str) -> Result<TaskStatus, ProviderError> {
+        Ok(self
+            .tasks
+            .lock()
+            .unwrap()
+            .get(task_id)
+            .cloned()
+            .unwrap_or(TaskStatus::Failed {
+                reason: Some("unknown task".to_string()),
+            }))
+    }
+
+    /// `{"taskId": "...", "status": "success", "resultUrl": "..."}`
+    fn parse_callback(&self, body: &Value) -> Result<CallbackResult, ProviderError> {
+        let task_id = body
+            .get("taskId")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| ProviderError::InvalidResponse("missing taskId".to_string()))?
+            .to_string();
+
+        let status = match body.get("status").and_then(|v| v.as_str()) {
+            Some("success") => match body.get("resultUrl").and_then(|v| v.as_str()) {
+                Some(url) => TaskStatus::Succeeded {
+                    result_url: url.to_string(),
+                },
+                None => {
+                    return Err(ProviderError::InvalidResponse("missing resultUrl".to_string()));
+                }
+            },
+            Some("fail") => TaskStatus::Failed {
+                reason: body.get("error").and_then(|v| v.as_str()).map(|s| s.to_string()),
+            },
+            _ => TaskStatus::Pending,
+        };
+
+        Ok(CallbackResult {
+            task_id,
+            status,
+            progress: None,
+        })
+    }
+
+    async fn cancel(&self, task_id: &str) -> Result<(), ProviderError> {
+        self.tasks.lock().unwrap().insert(
+            task_id.to_string(),
+            TaskStatus::Failed {
+                reason: Some("canceled".to_string()),
+            },
+        );
+        Ok(())
+    }
+}
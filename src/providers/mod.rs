@@ -0,0 +1,338 @@
+// src/providers/mod.rs
+//
+// Бэкенды удаления водяного знака. Хендлеры, колбэк и поллер работают только через
+// трейт `WatermarkRemover`; какой бэкенд обработал загрузку, записано в uploads.provider.
+
+pub mod ffmpeg;
+pub mod kie;
+pub mod mock;
+pub mod router;
+
+use crate::kie_client::KieClient;
+use async_trait::async_trait;
+use futures_util::future::join_all;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+pub use ffmpeg::{FfmpegConfig, FfmpegRemover, ResultStorage};
+pub use kie::KieRemover;
+pub use mock::MockRemover;
+pub use router::{HealthTracker, RouteContext, RoutingConfig};
+
+/// Задача на обработку одного видео.
+#[derive(Debug, Clone)]
+pub struct SubmitJob {
+    /// Публичная ссылка на исходное видео
+    pub video_url: String,
+    /// Куда бэкенд пришлёт колбэк (если умеет)
+    pub callback_url: String,
+    /// Область водяного знака, указанная пользователем (учитывают только локальные бэкенды)
+    pub region: Option<WatermarkRegion>,
+    /// Модель из профиля обработки; `None` — модель бэкенда по умолчанию
+    pub model: Option<String>,
+    /// Дополнительные параметры задачи из профиля обработки
+    pub extra_input: serde_json::Map<String, Value>,
+}
+
+/// Прямоугольник в пикселях кадра.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatermarkRegion {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Больше любой разумной стороны кадра (8K — 7680).
+const MAX_REGION_SIDE: u32 = 10_000;
+
+impl WatermarkRegion {
+    /// `x,y,width,height`, например `12,20,220,64`.
+    pub fn parse(raw: &str) -> Result<Self, String> {
+        let values: Vec<u32> = raw
+            .split(',')
+            .map(|v| v.trim().parse::<u32>())
+            .collect::<Result<_, _>>()
+            .map_err(|_| format!("invalid watermark region {raw:?}, expected x,y,width,height"))?;
+        let [x, y, width, height] = values[..] else {
+            return Err(format!("invalid watermark region {raw:?}, expected x,y,width,height"));
+        };
+        if width == 0 || height == 0 {
+            return Err("watermark region must not be empty".to_string());
+        }
+        if [x, y, width, height].iter().any(|v| *v > MAX_REGION_SIDE) {
+            return Err("watermark region is too large".to_string());
+        }
+        Ok(Self {
+            x,
+            y,
+            width,
+            height,
+        })
+    }
+}
+
+impl fmt::Display for WatermarkRegion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{},{},{},{}", self.x, self.y, self.width, self.height)
+    }
+}
+
+/// Состояние задачи у бэкенда.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TaskStatus {
+    /// В очереди или в работе
+    Pending,
+    /// Готово, результат можно забирать по ссылке
+    Succeeded { result_url: String },
+    /// Завершилась ошибкой или отменена
+    Failed { reason: Option<String> },
+}
+
+/// Разобранный колбэк бэкенда.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CallbackResult {
+    pub task_id: String,
+    pub status: TaskStatus,
+    /// Процент готовности для промежуточных колбэков (0..=100)
+    pub progress: Option<u8>,
+}
+
+#[derive(Debug)]
+pub enum ProviderError {
+    /// Сетевая ошибка или таймаут
+    Request(String),
+    /// Бэкенд ответил ошибкой
+    Api { status: u16, message: String },
+    /// Ответ или колбэк не удалось разобрать
+    InvalidResponse(String),
+    /// Операция не поддерживается бэкендом
+    Unsupported(&'static str),
+}
+
+impl fmt::Display for ProviderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProviderError::Request(e) => write!(f, "request error: {e}"),
+            ProviderError::Api { status, message } => {
+                write!(f, "provider error status={status} message={message}")
+            }
+            ProviderError::InvalidResponse(e) => write!(f, "invalid response: {e}"),
+            ProviderError::Unsupported(op) => write!(f, "operation not supported: {op}"),
+        }
+    }
+}
+
+#[async_trait]
+pub trait WatermarkRemover: Send + Sync {
+    /// Имя, которое сохраняется в uploads.provider
+    fn name(&self) -> &'static str;
+
+    /// Модель, которой бэкенд обработает задачу (сохраняется в uploads.model).
+    fn model(&self, _job: &SubmitJob) -> Option<String> {
+        None
+    }
+
+    /// Создаёт задачу и возвращает её id у бэкенда.
+    async fn submit(&self, job: &SubmitJob) -> Result<String, ProviderError>;
+
+    /// Текущее состояние задачи.
+    async fn poll(&self, task_id: &str) -> Result<TaskStatus, ProviderError>;
+
+    /// Разбирает тело колбэка.
+    fn parse_callback(&self, body: &Value) -> Result<CallbackResult, ProviderError>;
+
+    /// Сверять ли успешный колбэк с `poll` и брать ссылку на результат оттуда.
+    fn verify_callbacks(&self) -> bool {
+        false
+    }
+
+    /// Отменяет задачу.
+    async fn cancel(&self, task_id: &str) -> Result<(), ProviderError>;
+
+    /// Дожидается задач, которые бэкенд выполняет в этом процессе, не дольше `timeout`.
+    async fn shutdown(&self, _timeout: Duration) {}
+}
+
+/// Набор доступных бэкендов с выбором бэкенда для новой задачи (см. `router`).
+pub struct RemoverRegistry {
+    removers: HashMap<&'static str, Arc<dyn WatermarkRemover>>,
+    default: Option<&'static str>,
+    routing: RoutingConfig,
+    health: HealthTracker,
+}
+
+impl RemoverRegistry {
+    pub fn new() -> Self {
+        Self {
+            removers: HashMap::new(),
+            default: None,
+            routing: RoutingConfig::default(),
+            health: HealthTracker::default(),
+        }
+    }
+
+    /// Первый зарегистрированный бэкенд становится бэкендом по умолчанию.
+    pub fn register(mut self, remover: impl WatermarkRemover + 'static) -> Self {
+        let name = remover.name();
+        self.removers.insert(name, Arc::new(remover));
+        self.default.get_or_insert(name);
+        self
+    }
+
+    pub fn with_default(mut self, name: &str) -> Self {
+        if let Some((key, _)) = self.removers.get_key_value(name) {
+            self.default = Some(*key);
+        } else {
+            log::warn!("unknown watermark provider {}, keeping {:?}", name, self.default);
+        }
+        self
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<dyn WatermarkRemover>> {
+        self.removers.get(name).cloned()
+    }
+
+    pub fn default_remover(&self) -> Arc<dyn WatermarkRemover> {
+        self.default
+            .and_then(|name| self.get(name))
+            .expect("no watermark providers registered")
+    }
+
+    /// Бэкенд загрузки; для старых записей без provider — KIE.
+    pub fn for_upload(&self, provider: Option<&str>) -> Option<Arc<dyn WatermarkRemover>> {
+        self.get(provider.unwrap_or(kie::PROVIDER_NAME))
+    }
+
+    /// Останавливает бэкенды при завершении процесса (см. `WatermarkRemover::shutdown`).
+    pub async fn shutdown(&self, timeout: Duration) {
+        join_all(self.removers.values().map(|remover| remover.shutdown(timeout))).await;
+    }
+
+    pub fn with_routing(mut self, routing: RoutingConfig) -> Self {
+        self.routing = routing;
+        self
+    }
+
+    pub fn is_healthy(&self, name: &str) -> bool {
+        self.health.is_healthy(name)
+    }
+
+    /// Бэкенды в порядке попыток: здоровые по правилам маршрутизации, затем нездоровые
+    /// (если все лежат, всё равно пробуем).
+    pub fn candidates(&self, ctx: &RouteContext<'_>) -> Vec<Arc<dyn WatermarkRemover>> {
+        let default = self.default.unwrap_or(kie::PROVIDER_NAME);
+        let (healthy, unhealthy): (Vec<_>, Vec<_>) = self
+            .routing
+            .order(ctx, default)
+            .into_iter()
+            .filter_map(|name| {
+                let remover = self.get(&name);
+                if remover.is_none() {
+                    log::warn!("routing refers to unknown provider {}", name);
+                }
+                remover
+            })
+            .partition(|r| self.health.is_healthy(r.name()));
+        healthy.into_iter().chain(unhealthy).collect()
+    }
+
+    /// Создаёт задачу у первого бэкенда, который ответил успешно.
+    /// Ошибки и таймауты учитываются в здоровье бэкенда; возвращается последняя ошибка.
+    pub async fn submit(
+        &self,
+        ctx: &RouteContext<'_>,
+        job: &SubmitJob,
+    ) -> Result<(Arc<dyn WatermarkRemover>, String), ProviderError> {
+        let mut last_error = ProviderError::Unsupported("no providers configured");
+
+        for remover in self.candidates(ctx) {
+            let name = remover.name();
+            let result =
+                match actix_web::rt::time::timeout(self.routing.submit_timeout, remover.submit(job))
+                    .await
+                {
+                    Ok(result) => result,
+                    Err(_) => Err(ProviderError::Request("submit timed out".to_string())),
+                };
+
+            match result {
+                Ok(task_id) => {
+                    self.health.record_success(name);
+                    return Ok((remover, task_id));
+                }
+                Err(e) => {
+                    log::warn!("provider submit failed provider={} error={}", name, e);
+                    if self.health.record_failure(
+                        name,
+                        self.routing.failure_threshold,
+                        self.routing.cooldown,
+                    ) {
+                        log::error!("provider marked unhealthy provider={}", name);
+                    }
+                    last_error = e;
+                }
+            }
+        }
+
+        Err(last_error)
+    }
+
+    /// Бэкенды из `REMOVER_BACKENDS` (`parse_backends`): KIE (через общий клиент), ffmpeg
+    /// (результаты — в `storage`), mock; по умолчанию `WATERMARK_PROVIDER` (kie, если не задан),
+    /// маршрутизация из `RoutingConfig::from_env`.
+    pub fn from_env(kie: Arc<KieClient>, storage: ResultStorage) -> Self {
+        let mut registry = Self::new();
+        for name in parse_backends(&std::env::var("REMOVER_BACKENDS").unwrap_or_default()) {
+            registry = match name {
+                mock::PROVIDER_NAME => {
+                    log::warn!("mock watermark provider is enabled, results are not processed");
+                    registry.register(MockRemover::new())
+                }
+                ffmpeg::PROVIDER_NAME => registry.register(
+                    FfmpegRemover::new(FfmpegConfig::from_env()).with_storage(storage.clone()),
+                ),
+                kie::PROVIDER_NAME => registry.register(KieRemover::with_client(kie.clone())),
+                _ => registry,
+            };
+        }
+        let registry = registry.with_routing(RoutingConfig::from_env());
+        match std::env::var("WATERMARK_PROVIDER") {
+            Ok(name) if !name.trim().is_empty() => registry.with_default(name.trim()),
+            _ => registry,
+        }
+    }
+}
+
+/// Бэкенды, которые регистрируются без `REMOVER_BACKENDS`. `ffmpeg` (нагружает машину) и
+/// `mock` (отдаёт исходное видео как результат) включаются только явно.
+pub const DEFAULT_BACKENDS: &[&str] = &[kie::PROVIDER_NAME];
+
+/// Известные бэкенды из списка через запятую (`kie,ffmpeg,mock`); пустой список — `DEFAULT_BACKENDS`.
+pub fn parse_backends(raw: &str) -> Vec<&'static str> {
+    let mut backends = Vec::new();
+    for name in raw.split(',').map(|v| v.trim().to_lowercase()).filter(|v| !v.is_empty()) {
+        let known = [kie::PROVIDER_NAME, ffmpeg::PROVIDER_NAME, mock::PROVIDER_NAME]
+            .into_iter()
+            .find(|known| *known == name);
+        match known {
+            Some(known) if !backends.contains(&known) => backends.push(known),
+            Some(_) => {}
+            None => log::warn!("unknown watermark provider in REMOVER_BACKENDS: {}", name),
+        }
+    }
+    if backends.is_empty() {
+        DEFAULT_BACKENDS.to_vec()
+    } else {
+        backends
+    }
+}
+
+impl Default for RemoverRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,184 @@
+// src/providers/router.rs
+//
+// Выбор бэкенда для новой задачи и переключение на следующий при сбоях.
+// Порядок кандидатов:
+// 1. бэкенд, закреплённый за тарифом пользователя (`PROVIDER_PLAN_ROUTES`)
+// 2. бэкенд, закреплённый за типом источника (`PROVIDER_SOURCE_ROUTES`)
+// 3. случайный по весам (`PROVIDER_WEIGHTS`), затем остальные по убыванию веса
+// Бэкенды, помеченные нездоровыми, уходят в конец списка до истечения паузы.
+
+use rand::Rng;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Что известно о задаче на момент выбора бэкенда.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RouteContext<'a> {
+    /// slug продукта активной подписки
+    pub plan: Option<&'a str>,
+    /// `SourceKind::as_str()` ссылки
+    pub source_kind: Option<&'a str>,
+}
+
+#[derive(Debug, Clone)]
+pub struct RoutingConfig {
+    /// Бэкенды, между которыми распределяются задачи; пусто — только бэкенд по умолчанию
+    pub weights: Vec<(String, u32)>,
+    pub plan_routes: HashMap<String, String>,
+    pub source_routes: HashMap<String, String>,
+    /// Сколько ошибок подряд делает бэкенд нездоровым
+    pub failure_threshold: u32,
+    /// На сколько бэкенд исключается из выбора
+    pub cooldown: Duration,
+    /// Таймаут на создание задачи у одного бэкенда
+    pub submit_timeout: Duration,
+}
+
+impl Default for RoutingConfig {
+    fn default() -> Self {
+        Self {
+            weights: Vec::new(),
+            plan_routes: HashMap::new(),
+            source_routes: HashMap::new(),
+            failure_threshold: 3,
+            cooldown: Duration::from_secs(60),
+            submit_timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+/// `a:1,b:2` -> [(a, 1), (b, 2)]
+pub fn parse_weights(raw: &str) -> Vec<(String, u32)> {
+    raw.split(',')
+        .filter_map(|pair| {
+            let (name, weight) = pair.split_once(':')?;
+            let weight = weight.trim().parse::<u32>().ok()?;
+            let name = name.trim().to_lowercase();
+            (!name.is_empty()).then_some((name, weight))
+        })
+        .collect()
+}
+
+/// `pro:kie,basic:mock` -> {pro: kie, basic: mock}
+pub fn parse_routes(raw: &str) -> HashMap<String, String> {
+    raw.split(',')
+        .filter_map(|pair| {
+            let (key, provider) = pair.split_once(':')?;
+            let (key, provider) = (key.trim().to_lowercase(), provider.trim().to_lowercase());
+            (!key.is_empty() && !provider.is_empty()).then_some((key, provider))
+        })
+        .collect()
+}
+
+impl RoutingConfig {
+    /// `PROVIDER_WEIGHTS`, `PROVIDER_PLAN_ROUTES`, `PROVIDER_SOURCE_ROUTES`,
+    /// `PROVIDER_FAILURE_THRESHOLD`, `PROVIDER_COOLDOWN_SECS`, `PROVIDER_SUBMIT_TIMEOUT_SECS`.
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let env = |key: &str| std::env::var(key).unwrap_or_default();
+        let secs = |key: &str, default: Duration| {
+            std::env::var(key)
+                .ok()
+                .and_then(|v| v.trim().parse::<u64>().ok())
+                .map(Duration::from_secs)
+                .unwrap_or(default)
+        };
+        Self {
+            weights: parse_weights(&env("PROVIDER_WEIGHTS")),
+            plan_routes: parse_routes(&env("PROVIDER_PLAN_ROUTES")),
+            source_routes: parse_routes(&env("PROVIDER_SOURCE_ROUTES")),
+            failure_threshold: std::env::var("PROVIDER_FAILURE_THRESHOLD")
+                .ok()
+                .and_then(|v| v.trim().parse::<u32>().ok())
+                .unwrap_or(defaults.failure_threshold)
+                .max(1),
+            cooldown: secs("PROVIDER_COOLDOWN_SECS", defaults.cooldown),
+            submit_timeout: secs("PROVIDER_SUBMIT_TIMEOUT_SECS", defaults.submit_timeout),
+        }
+    }
+
+    /// Имена бэкендов в порядке попыток (без учёта здоровья).
+    pub fn order(&self, ctx: &RouteContext<'_>, default: &str) -> Vec<String> {
+        let mut order: Vec<String> = Vec::new();
+        let mut push = |name: &str| {
+            if !order.iter().any(|n| n == name) {
+                order.push(name.to_string());
+            }
+        };
+
+        if let Some(provider) = ctx.plan.and_then(|p| self.plan_routes.get(&p.to_lowercase())) {
+            push(provider);
+        }
+        if let Some(provider) = ctx
+            .source_kind
+            .and_then(|k| self.source_routes.get(&k.to_lowercase()))
+        {
+            push(provider);
+        }
+
+        let weighted: Vec<&(String, u32)> = self.weights.iter().filter(|(_, w)| *w > 0).collect();
+        if weighted.is_empty() {
+            push(default);
+            return order;
+        }
+
+        let total: u32 = weighted.iter().map(|(_, w)| *w).sum();
+        let mut pick = rand::thread_rng().gen_range(0..total);
+        for (name, weight) in &weighted {
+            if pick < *weight {
+                push(name);
+                break;
+            }
+            pick -= weight;
+        }
+
+        let mut rest = weighted.clone();
+        rest.sort_by_key(|(_, weight)| std::cmp::Reverse(*weight));
+        for (name, _) in rest {
+            push(name);
+        }
+        order
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+struct ProviderHealth {
+    consecutive_failures: u32,
+    unhealthy_until: Option<Instant>,
+}
+
+/// Счётчики ошибок по бэкендам (в памяти процесса).
+#[derive(Debug, Default)]
+pub struct HealthTracker {
+    state: Mutex<HashMap<String, ProviderHealth>>,
+}
+
+impl HealthTracker {
+    pub fn is_healthy(&self, name: &str) -> bool {
+        let state = self.state.lock().unwrap();
+        match state.get(name).and_then(|h| h.unhealthy_until) {
+            Some(until) => Instant::now() >= until,
+            None => true,
+        }
+    }
+
+    pub fn record_success(&self, name: &str) {
+        self.state.lock().unwrap().remove(name);
+    }
+
+    /// Возвращает true, если бэкенд только что стал нездоровым.
+    pub fn record_failure(&self, name: &str, threshold: u32, cooldown: Duration) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let health = state.entry(name.to_string()).or_default();
+        health.consecutive_failures += 1;
+        if health.consecutive_failures >= threshold {
+            let was_healthy = health
+                .unhealthy_until
+                .is_none_or(|until| Instant::now() >= until);
+            health.unhealthy_until = Some(Instant::now() + cooldown);
+            return was_healthy;
+        }
+        false
+    }
+}
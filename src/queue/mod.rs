@@ -0,0 +1,429 @@
+// src/queue/mod.rs
+//
+// Очередь проверки статусов задач (на случай потерянных колбэков).
+// У каждой загрузки в `processing` своё расписание проверок (`next_check_at`, пауза растёт
+// с каждой проверкой); продюсер ставит в очередь задачи, которым пора, и закрывает зависшие.
+// Консьюмер опрашивает бэкенд и завершает загрузку тем же путём, что и колбэк.
+// Очередь — за трейтом `JobQueue`: RabbitMQ, если он настроен, иначе таблица в Postgres.
+
+pub mod postgres;
+pub mod rabbitmq;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::{PgPool, Row};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::AppState;
+use crate::billing::fail_upload_with_refund;
+use crate::finalize::{FinalizeOutcome, finalize_task};
+use crate::providers::TaskStatus;
+use crate::worker::Shutdown;
+
+pub use postgres::PgJobQueue;
+pub use rabbitmq::{RabbitConfig, RabbitJobQueue};
+
+pub const STATUS_QUEUE: &str = "kie.status.check";
+/// Задания, которые не удалось выполнить за `max_attempts` попыток (для разбора вручную)
+pub const STATUS_DEAD_LETTER_QUEUE: &str = "kie.status.check.dead";
+
+/// Пауза консьюмера, когда очередь пуста.
+const IDLE_DELAY: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TaskMessage {
+    pub task_id: String,
+    /// Бэкенд задачи; в старых сообщениях отсутствует (значит KIE)
+    #[serde(default)]
+    pub provider: Option<String>,
+}
+
+/// Новое задание.
+#[derive(Debug, Clone)]
+pub struct NewJob {
+    pub queue: String,
+    pub payload: serde_json::Value,
+    /// Не ставить повторно, пока задание с тем же ключом не выполнено (если бэкенд умеет)
+    pub dedup_key: Option<String>,
+    /// Запустить не раньше чем через
+    pub delay: Duration,
+}
+
+impl NewJob {
+    pub fn new(queue: &str, payload: serde_json::Value) -> Self {
+        Self {
+            queue: queue.to_string(),
+            payload,
+            dedup_key: None,
+            delay: Duration::ZERO,
+        }
+    }
+
+    pub fn with_dedup_key(mut self, key: impl Into<String>) -> Self {
+        self.dedup_key = Some(key.into());
+        self
+    }
+
+    pub fn with_delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+}
+
+/// Забранное задание; его нужно подтвердить (`ack`) или вернуть (`retry`).
+#[derive(Debug, Clone)]
+pub struct Job {
+    /// id строки в Postgres или delivery tag в RabbitMQ
+    pub id: u64,
+    pub queue: String,
+    pub payload: serde_json::Value,
+    /// Сколько раз задание забирали, включая текущий
+    pub attempts: u32,
+}
+
+#[async_trait]
+pub trait JobQueue: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    async fn enqueue(&self, job: NewJob) -> Result<(), String>;
+
+    /// До `limit` готовых заданий; остальным воркерам они не видны `visibility`.
+    async fn reserve(&self, queue: &str, limit: usize, visibility: Duration) -> Result<Vec<Job>, String>;
+
+    /// Задание выполнено, удалить.
+    async fn ack(&self, job: &Job) -> Result<(), String>;
+
+    /// Вернуть задание в очередь через `delay`.
+    async fn retry(&self, job: &Job, delay: Duration, error: &str) -> Result<(), String>;
+}
+
+/// `JOB_QUEUE_BACKEND=postgres|rabbitmq`; по умолчанию RabbitMQ, если задан `RABBITMQ_URL`.
+pub fn from_env(pool: &PgPool) -> Arc<dyn JobQueue> {
+    let backend = std::env::var("JOB_QUEUE_BACKEND")
+        .unwrap_or_default()
+        .trim()
+        .to_lowercase();
+    let rabbit_url = rabbitmq::resolve_rabbit_url();
+
+    match (backend.as_str(), rabbit_url) {
+        ("postgres", _) => Arc::new(PgJobQueue::new(pool.clone())),
+        ("rabbitmq" | "", Some(url)) => Arc::new(RabbitJobQueue::new(RabbitConfig::from_env(url))),
+        ("rabbitmq", None) => {
+            log::warn!("JOB_QUEUE_BACKEND=rabbitmq but RABBITMQ_URL is not set, using postgres");
+            Arc::new(PgJobQueue::new(pool.clone()))
+        }
+        (other, url) => {
+            if !other.is_empty() {
+                log::warn!("unknown JOB_QUEUE_BACKEND={other}");
+            }
+            match url {
+                Some(url) => Arc::new(RabbitJobQueue::new(RabbitConfig::from_env(url))),
+                None => Arc::new(PgJobQueue::new(pool.clone())),
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct StatusQueueConfig {
+    /// Пауза перед первой проверкой задачи; дальше она удваивается с каждой проверкой
+    pub poll_interval: Duration,
+    pub max_poll_interval: Duration,
+    /// Задача в `processing` дольше этого считается зависшей: `failed` с возвратом кредитов
+    pub max_age: Duration,
+    /// Как часто продюсер ищет задачи, которым пора на проверку
+    pub scheduler_interval: Duration,
+    pub batch_size: i64,
+    /// Сколько заданий консьюмер забирает за раз
+    pub prefetch: usize,
+    pub visibility_timeout: Duration,
+    /// После стольких неудачных попыток задание уходит в `STATUS_DEAD_LETTER_QUEUE`
+    pub max_attempts: u32,
+    pub retry_delay: Duration,
+}
+
+impl Default for StatusQueueConfig {
+    fn default() -> Self {
+        Self {
+            poll_interval: Duration::from_secs(60),
+            max_poll_interval: Duration::from_secs(1800),
+            max_age: Duration::from_secs(6 * 3600),
+            scheduler_interval: Duration::from_secs(10),
+            batch_size: 50,
+            prefetch: 10,
+            visibility_timeout: Duration::from_secs(300),
+            max_attempts: 5,
+            retry_delay: Duration::from_secs(30),
+        }
+    }
+}
+
+impl StatusQueueConfig {
+    /// `KIE_STATUS_POLL_INTERVAL_SECS`, `KIE_STATUS_MAX_POLL_INTERVAL_SECS`, `KIE_STATUS_MAX_AGE_SECS`,
+    /// `KIE_STATUS_SCHEDULER_INTERVAL_SECS`, `KIE_STATUS_BATCH_SIZE`, `JOB_VISIBILITY_TIMEOUT_SECS`,
+    /// `JOB_MAX_ATTEMPTS`, `JOB_RETRY_DELAY_SECS`.
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let env_parse = |key: &str| std::env::var(key).ok().and_then(|v| v.trim().parse::<u64>().ok());
+        let secs = |key: &str, default: Duration| env_parse(key).map(Duration::from_secs).unwrap_or(default);
+        Self {
+            poll_interval: secs("KIE_STATUS_POLL_INTERVAL_SECS", defaults.poll_interval),
+            max_poll_interval: secs("KIE_STATUS_MAX_POLL_INTERVAL_SECS", defaults.max_poll_interval),
+            max_age: secs("KIE_STATUS_MAX_AGE_SECS", defaults.max_age),
+            scheduler_interval: secs("KIE_STATUS_SCHEDULER_INTERVAL_SECS", defaults.scheduler_interval),
+            batch_size: env_parse("KIE_STATUS_BATCH_SIZE")
+                .map(|v| v.max(1) as i64)
+                .unwrap_or(defaults.batch_size),
+            visibility_timeout: secs("JOB_VISIBILITY_TIMEOUT_SECS", defaults.visibility_timeout),
+            max_attempts: env_parse("JOB_MAX_ATTEMPTS")
+                .map(|v| v.max(1) as u32)
+                .unwrap_or(defaults.max_attempts),
+            retry_delay: secs("JOB_RETRY_DELAY_SECS", defaults.retry_delay),
+            ..defaults
+        }
+    }
+
+    /// Пауза перед повтором задания после `attempts`-й неудачи (с единицы): удваивается до потолка.
+    pub fn job_retry_delay(&self, attempts: u32) -> Duration {
+        let exp = attempts.saturating_sub(1).min(20);
+        self.retry_delay
+            .saturating_mul(2u32.saturating_pow(exp))
+            .min(self.max_poll_interval)
+    }
+}
+
+/// Продюсер: раз в `scheduler_interval` ставит в очередь проверки, которым пора.
+pub async fn run_status_scheduler(
+    state: AppState,
+    queue: Arc<dyn JobQueue>,
+    config: StatusQueueConfig,
+    mut shutdown: Shutdown,
+) {
+    log::info!(
+        "status scheduler started backend={} queue={} poll_interval={}s",
+        queue.name(),
+        STATUS_QUEUE,
+        config.poll_interval.as_secs()
+    );
+    while !shutdown.is_triggered() {
+        if let Err(e) = enqueue_pending_tasks(&state.pool, queue.as_ref(), &config).await {
+            log::error!("queue enqueue error: {e}");
+        }
+        if shutdown.sleep(config.scheduler_interval).await {
+            break;
+        }
+    }
+    log::info!("status scheduler stopped");
+}
+
+/// Консьюмер: забирает проверки пачками; начатая пачка при остановке доделывается.
+pub async fn run_status_consumer(
+    state: AppState,
+    queue: Arc<dyn JobQueue>,
+    config: StatusQueueConfig,
+    mut shutdown: Shutdown,
+) {
+    log::info!("status consumer started backend={} queue={}", queue.name(), STATUS_QUEUE);
+    while !shutdown.is_triggered() {
+        let pause = match consume_once(&state, queue.as_ref(), &config).await {
+            Ok(0) => IDLE_DELAY,
+            Ok(_) => Duration::ZERO,
+            Err(e) => {
+                log::error!("queue consume error: {e}");
+                Duration::from_secs(5)
+            }
+        };
+        if !pause.is_zero() && shutdown.sleep(pause).await {
+            break;
+        }
+    }
+    log::info!("status consumer stopped");
+}
+
+/// Ставит в очередь проверку статуса для загрузок, которым она положена по расписанию.
+/// Следующая проверка сразу переносится на `poll_interval * 2^checks` (не дальше
+/// `max_poll_interval`), поэтому пока KIE медлит, одна задача не попадает в очередь повторно.
+pub async fn enqueue_pending_tasks(
+    pool: &PgPool,
+    queue: &dyn JobQueue,
+    config: &StatusQueueConfig,
+) -> Result<usize, String> {
+    let rows = sqlx::query(
+        r#"UPDATE uploads
+           SET status_checks = status_checks + 1,
+               next_check_at = NOW() + make_interval(
+                   secs => LEAST($2 * power(2, LEAST(status_checks + 1, 20)), $3)
+               )
+           WHERE id IN (
+               SELECT id
+               FROM uploads
+               WHERE status = 'processing'
+                 AND task_id IS NOT NULL
+                 AND COALESCE(
+                         next_check_at,
+                         COALESCE(processing_started_at, created_at) + make_interval(secs => $2)
+                     ) <= NOW()
+               ORDER BY next_check_at ASC NULLS FIRST, id ASC
+               LIMIT $1
+               FOR UPDATE SKIP LOCKED
+           )
+           RETURNING task_id, provider, status_checks"#,
+    )
+    .bind(config.batch_size)
+    .bind(config.poll_interval.as_secs_f64())
+    .bind(config.max_poll_interval.as_secs_f64())
+    .fetch_all(pool)
+    .await
+    .map_err(|e| e.to_string())?;
+
+    let count = rows.len();
+    for row in rows {
+        let message = TaskMessage {
+            task_id: row.get("task_id"),
+            provider: row.get("provider"),
+        };
+        let checks: i32 = row.get("status_checks");
+        log::debug!("status check scheduled task_id={} check={}", message.task_id, checks);
+        let payload = serde_json::to_value(&message).map_err(|e| e.to_string())?;
+        queue
+            .enqueue(NewJob::new(STATUS_QUEUE, payload).with_dedup_key(message.task_id))
+            .await?;
+    }
+
+    Ok(count)
+}
+
+/// Закрывает задачи, которые дольше `max_age` в обработке: `failed`, возврат кредитов,
+/// отмена у бэкенда (если он умеет). Возвращает число закрытых загрузок.
+pub async fn expire_stale_tasks(state: &AppState, config: &StatusQueueConfig) -> Result<usize, String> {
+    let mut tx = state.pool.begin().await.map_err(|e| e.to_string())?;
+    let rows = sqlx::query(
+        r#"SELECT id, task_id, provider FROM uploads
+           WHERE status = 'processing'
+             AND COALESCE(processing_started_at, created_at) <= NOW() - make_interval(secs => $1)
+           FOR UPDATE SKIP LOCKED"#,
+    )
+    .bind(config.max_age.as_secs_f64())
+    .fetch_all(&mut *tx)
+    .await
+    .map_err(|e| e.to_string())?;
+
+    for row in &rows {
+        let upload_id: i32 = row.get("id");
+        fail_upload_with_refund(&mut tx, upload_id, "processing", "status check timed out")
+            .await
+            .map_err(|e| format!("timeout refund error upload_id={upload_id}: {e}"))?;
+    }
+    tx.commit().await.map_err(|e| e.to_string())?;
+    if !rows.is_empty() {
+        state.outbox_wakeup.notify_one();
+    }
+
+    for row in &rows {
+        let upload_id: i32 = row.get("id");
+        let task_id: Option<String> = row.get("task_id");
+        let provider: Option<String> = row.get("provider");
+        log::warn!("status check timed out upload_id={} task_id={:?}", upload_id, task_id);
+        if let (Some(task_id), Some(remover)) =
+            (task_id.as_deref(), state.removers.for_upload(provider.as_deref()))
+        {
+            let _ = remover.cancel(task_id).await;
+        }
+    }
+
+    Ok(rows.len())
+}
+
+/// Забирает и обрабатывает одну пачку; возвращает размер пачки.
+pub async fn consume_once(
+    state: &AppState,
+    queue: &dyn JobQueue,
+    config: &StatusQueueConfig,
+) -> Result<usize, String> {
+    let jobs = queue
+        .reserve(STATUS_QUEUE, config.prefetch, config.visibility_timeout)
+        .await?;
+    let count = jobs.len();
+
+    for job in jobs {
+        match handle_task_message(state, &job.payload).await {
+            Ok(()) => queue.ack(&job).await?,
+            Err(e) if job.attempts >= config.max_attempts => {
+                log::error!(
+                    "status check dead-lettered attempts={} error={}",
+                    job.attempts,
+                    e
+                );
+                let dead = json!({
+                    "queue": job.queue,
+                    "payload": job.payload,
+                    "attempts": job.attempts,
+                    "error": e,
+                });
+                queue.enqueue(NewJob::new(STATUS_DEAD_LETTER_QUEUE, dead)).await?;
+                queue.ack(&job).await?;
+            }
+            Err(e) => {
+                let delay = config.job_retry_delay(job.attempts);
+                log::warn!(
+                    "status check failed attempts={} retry_in={}s error={}",
+                    job.attempts,
+                    delay.as_secs(),
+                    e
+                );
+                queue.retry(&job, delay, &e).await?;
+            }
+        }
+    }
+
+    Ok(count)
+}
+
+async fn handle_task_message(state: &AppState, payload: &serde_json::Value) -> Result<(), String> {
+    let msg: TaskMessage = serde_json::from_value(payload.clone()).map_err(|e| e.to_string())?;
+    let remover = state
+        .removers
+        .for_upload(msg.provider.as_deref())
+        .ok_or_else(|| format!("unknown provider {:?}", msg.provider))?;
+    let status = remover
+        .poll(&msg.task_id)
+        .await
+        .map_err(|e| e.to_string())?;
+    let pool = &state.pool;
+
+    match status {
+        TaskStatus::Succeeded { result_url } => {
+            // Тот же путь, что и у колбэка: результат копируется к нам в хранилище
+            if let FinalizeOutcome::Finalized { .. } =
+                finalize_task(state, &msg.task_id, &result_url).await?
+            {
+                log::info!("queue finalized task_id={}", msg.task_id);
+            }
+        }
+        TaskStatus::Failed { reason } => {
+            log::warn!("queue task failed task_id={} reason={:?}", msg.task_id, reason);
+            // Результата нет — кредиты возвращаем, как при ошибке отправки и таймауте
+            let reason = reason.as_deref().unwrap_or("provider task failed");
+            let mut tx = pool.begin().await.map_err(|e| e.to_string())?;
+            let upload_id: Option<i32> =
+                sqlx::query_scalar("SELECT id FROM uploads WHERE task_id = $1 FOR UPDATE")
+                    .bind(&msg.task_id)
+                    .fetch_optional(&mut *tx)
+                    .await
+                    .map_err(|e| e.to_string())?;
+            if let Some(upload_id) = upload_id
+                && fail_upload_with_refund(&mut tx, upload_id, "processing", reason)
+                    .await
+                    .map_err(|e| format!("failed task refund error upload_id={upload_id}: {e}"))?
+            {
+                tx.commit().await.map_err(|e| e.to_string())?;
+                state.outbox_wakeup.notify_one();
+            }
+        }
+        TaskStatus::Pending => {}
+    }
+
+    Ok(())
+}
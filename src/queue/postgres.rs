@@ -0,0 +1,113 @@
+// src/queue/postgres.rs
+//
+// Очередь заданий в таблице `job_queue`: для небольших установок хватает одного Postgres.
+// - задания забираются `FOR UPDATE SKIP LOCKED`, несколько воркеров не мешают друг другу
+// - забранное задание невидимо до `locked_until` (таймаут видимости); если воркер упал,
+//   задание вернётся в работу само
+// - `run_at` — не раньше какого момента запускать, `attempts` — сколько раз забирали
+// - `dedup_key` не даёт поставить одно и то же задание дважды, пока первое не выполнено
+
+use async_trait::async_trait;
+use sqlx::{PgPool, Row};
+use std::time::Duration;
+
+use super::{Job, JobQueue, NewJob};
+
+pub struct PgJobQueue {
+    pool: PgPool,
+}
+
+impl PgJobQueue {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl JobQueue for PgJobQueue {
+    fn name(&self) -> &'static str {
+        "postgres"
+    }
+
+    async fn enqueue(&self, job: NewJob) -> Result<(), String> {
+        sqlx::query(
+            r#"INSERT INTO job_queue (queue, payload, dedup_key, run_at)
+               VALUES ($1, $2::jsonb, $3, NOW() + make_interval(secs => $4))
+               ON CONFLICT (queue, dedup_key) WHERE dedup_key IS NOT NULL DO NOTHING"#,
+        )
+        .bind(&job.queue)
+        .bind(job.payload.to_string())
+        .bind(job.dedup_key.as_deref())
+        .bind(job.delay.as_secs_f64())
+        .execute(&self.pool)
+        .await
+        .map_err(|e| format!("job enqueue error: {e}"))?;
+        Ok(())
+    }
+
+    async fn reserve(&self, queue: &str, limit: usize, visibility: Duration) -> Result<Vec<Job>, String> {
+        let rows = sqlx::query(
+            r#"UPDATE job_queue
+               SET locked_until = NOW() + make_interval(secs => $3), attempts = attempts + 1
+               WHERE id IN (
+                   SELECT id
+                   FROM job_queue
+                   WHERE queue = $1
+                     AND run_at <= NOW()
+                     AND (locked_until IS NULL OR locked_until <= NOW())
+                   ORDER BY run_at ASC, id ASC
+                   LIMIT $2
+                   FOR UPDATE SKIP LOCKED
+               )
+               RETURNING id, queue, payload::text AS payload, attempts"#,
+        )
+        .bind(queue)
+        .bind(limit as i64)
+        .bind(visibility.as_secs_f64())
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| format!("job reserve error: {e}"))?;
+
+        let mut jobs = Vec::with_capacity(rows.len());
+        for row in rows {
+            let id: i64 = row.get("id");
+            let raw: String = row.get("payload");
+            let attempts: i32 = row.get("attempts");
+            match serde_json::from_str(&raw) {
+                Ok(payload) => jobs.push(Job {
+                    id: id as u64,
+                    queue: row.get("queue"),
+                    payload,
+                    attempts: attempts.max(0) as u32,
+                }),
+                // payload — JSONB, сюда попасть не должны
+                Err(e) => log::error!("job invalid payload id={} error={}", id, e),
+            }
+        }
+        Ok(jobs)
+    }
+
+    async fn ack(&self, job: &Job) -> Result<(), String> {
+        sqlx::query("DELETE FROM job_queue WHERE id = $1")
+            .bind(job.id as i64)
+            .execute(&self.pool)
+            .await
+            .map_err(|e| format!("job ack error: {e}"))?;
+        Ok(())
+    }
+
+    async fn retry(&self, job: &Job, delay: Duration, error: &str) -> Result<(), String> {
+        sqlx::query(
+            r#"UPDATE job_queue
+               SET locked_until = NULL, run_at = NOW() + make_interval(secs => $2), last_error = $3
+               WHERE id = $1"#,
+        )
+        .bind(job.id as i64)
+        .bind(delay.as_secs_f64())
+        .bind(error)
+        .execute(&self.pool)
+        .await
+        .map_err(|e| format!("job retry error: {e}"))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,531 @@
+// src/queue/rabbitmq.rs
+//
+// Очередь заданий в RabbitMQ (если задан `RABBITMQ_URL`).
+// Топология долговечная (durable) и строится от одного direct-обменника `RABBITMQ_EXCHANGE`:
+// - `<queue>`           — рабочая очередь; отклонённые сообщения уходят через
+//                         `RABBITMQ_DEAD_LETTER_EXCHANGE` в `<queue>.dead`
+// - `<queue>.retry.<N>s` — отложенные повторы, по очереди на каждую паузу: `x-message-ttl`
+//                         общий для всей очереди, поэтому сообщения истекают по порядку и
+//                         короткая пауза не ждёт за длинной; по истечении — обратно в `<queue>`
+// - `<queue>.dead`      — мёртвые письма, разбираются вручную
+// Имена очередей и routing key начинаются с `RABBITMQ_ROUTING_KEY_PREFIX`, поэтому несколько
+// окружений могут делить один vhost.
+// Сообщения публикуются persistent и с подтверждением брокера (publisher confirms), консьюмер
+// получает не больше `RABBITMQ_PREFETCH` неподтверждённых сообщений. Номер попытки и последняя
+// ошибка едут в заголовках `x-attempts` / `x-last-error`.
+
+use async_trait::async_trait;
+use futures_util::{FutureExt, StreamExt};
+use lapin::{
+    BasicProperties, Channel, Connection, ConnectionProperties, Consumer, ExchangeKind,
+    options::{
+        BasicAckOptions, BasicConsumeOptions, BasicNackOptions, BasicPublishOptions, BasicQosOptions,
+        ConfirmSelectOptions, ExchangeDeclareOptions, QueueBindOptions, QueueDeclareOptions,
+    },
+    publisher_confirm::Confirmation,
+    types::{AMQPValue, FieldTable, LongString},
+};
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+use super::{Job, JobQueue, NewJob};
+
+pub const ATTEMPTS_HEADER: &str = "x-attempts";
+pub const LAST_ERROR_HEADER: &str = "x-last-error";
+pub const RETRY_SUFFIX: &str = ".retry";
+pub const DEAD_SUFFIX: &str = ".dead";
+
+/// Persistent delivery mode
+const PERSISTENT: u8 = 2;
+/// Сколько ждать первое сообщение в `reserve`, прежде чем сказать «очередь пуста»
+const RESERVE_WAIT: Duration = Duration::from_secs(1);
+/// Длинные ошибки в заголовке не нужны
+const MAX_ERROR_HEADER_LEN: usize = 512;
+
+#[derive(Debug, Clone)]
+pub struct RabbitConfig {
+    pub url: String,
+    pub exchange: String,
+    pub dead_letter_exchange: String,
+    /// Префикс имён очередей и routing key, чтобы несколько окружений могли делить один vhost
+    pub routing_key_prefix: String,
+    pub prefetch: u16,
+}
+
+impl RabbitConfig {
+    pub fn new(url: String) -> Self {
+        Self {
+            url,
+            exchange: "sora.jobs".to_string(),
+            dead_letter_exchange: "sora.jobs.dlx".to_string(),
+            routing_key_prefix: String::new(),
+            prefetch: 10,
+        }
+    }
+
+    /// `RABBITMQ_EXCHANGE`, `RABBITMQ_DEAD_LETTER_EXCHANGE`, `RABBITMQ_ROUTING_KEY_PREFIX`,
+    /// `RABBITMQ_PREFETCH`.
+    pub fn from_env(url: String) -> Self {
+        let defaults = Self::new(url);
+        let env_value = |key: &str| std::env::var(key).ok().filter(|v| !v.trim().is_empty());
+        Self {
+            exchange: env_value("RABBITMQ_EXCHANGE").unwrap_or(defaults.exchange),
+            dead_letter_exchange: env_value("RABBITMQ_DEAD_LETTER_EXCHANGE")
+                .unwrap_or(defaults.dead_letter_exchange),
+            routing_key_prefix: env_value("RABBITMQ_ROUTING_KEY_PREFIX")
+                .unwrap_or(defaults.routing_key_prefix),
+            prefetch: env_value("RABBITMQ_PREFETCH")
+                .and_then(|v| v.trim().parse::<u16>().ok())
+                .map(|v| v.max(1))
+                .unwrap_or(defaults.prefetch),
+            ..defaults
+        }
+    }
+
+    pub fn routing_key(&self, queue: &str) -> String {
+        format!("{}{}", self.routing_key_prefix, queue)
+    }
+
+    /// Имя очереди у брокера; совпадает с её routing key.
+    pub fn queue_name(&self, queue: &str) -> String {
+        self.routing_key(queue)
+    }
+
+    /// Рабочая очередь и очередь мёртвых писем для `queue`. Для `<name>.dead` — только сама
+    /// очередь мёртвых писем. Очереди повторов объявляются по мере надобности (`retry_spec`).
+    pub fn topology(&self, queue: &str) -> Vec<QueueSpec> {
+        if let Some(base) = queue.strip_suffix(DEAD_SUFFIX) {
+            return vec![self.dead_spec(base)];
+        }
+        let routing_key = self.routing_key(queue);
+
+        let mut main_args = FieldTable::default();
+        main_args.insert(
+            "x-dead-letter-exchange".into(),
+            long_string(&self.dead_letter_exchange),
+        );
+        main_args.insert("x-dead-letter-routing-key".into(), long_string(&routing_key));
+
+        vec![
+            QueueSpec {
+                name: self.queue_name(queue),
+                arguments: main_args,
+                bindings: vec![(self.exchange.clone(), routing_key)],
+            },
+            self.dead_spec(queue),
+        ]
+    }
+
+    /// Очередь повторов `queue` с паузой `retry_step(delay)`: сообщения лежат там
+    /// `x-message-ttl` и возвращаются в рабочую очередь.
+    pub fn retry_spec(&self, queue: &str, delay: Duration) -> QueueSpec {
+        let step = retry_step(delay);
+        let routing_key = self.routing_key(queue);
+        let retry_key = format!("{routing_key}{RETRY_SUFFIX}.{}s", step.as_secs());
+
+        let mut arguments = FieldTable::default();
+        arguments.insert(
+            "x-message-ttl".into(),
+            AMQPValue::LongLongInt(step.as_millis() as i64),
+        );
+        arguments.insert("x-dead-letter-exchange".into(), long_string(&self.exchange));
+        arguments.insert("x-dead-letter-routing-key".into(), long_string(&routing_key));
+
+        QueueSpec {
+            name: retry_key.clone(),
+            arguments,
+            bindings: vec![(self.exchange.clone(), retry_key)],
+        }
+    }
+
+    fn dead_spec(&self, queue: &str) -> QueueSpec {
+        let routing_key = self.routing_key(queue);
+        QueueSpec {
+            name: self.queue_name(&format!("{queue}{DEAD_SUFFIX}")),
+            arguments: FieldTable::default(),
+            bindings: vec![
+                // Отклонённые брокером (nack без requeue)
+                (self.dead_letter_exchange.clone(), routing_key.clone()),
+                // Перенесённые воркером явно, с ошибкой в теле
+                (self.exchange.clone(), format!("{routing_key}{DEAD_SUFFIX}")),
+            ],
+        }
+    }
+}
+
+/// Durable-очередь с аргументами и привязками `(exchange, routing_key)`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueueSpec {
+    pub name: String,
+    pub arguments: FieldTable,
+    pub bindings: Vec<(String, String)>,
+}
+
+fn long_string(value: &str) -> AMQPValue {
+    AMQPValue::LongString(LongString::from(value))
+}
+
+/// Пауза повтора, округлённая вверх до секунды: у каждой паузы своя очередь, так их
+/// остаётся немного.
+pub fn retry_step(delay: Duration) -> Duration {
+    let secs = delay.as_secs() + u64::from(delay.subsec_nanos() > 0);
+    Duration::from_secs(secs.max(1))
+}
+
+/// Свойства публикуемого сообщения: persistent, JSON, попытки и ошибка в заголовках.
+pub fn message_properties(attempts: u32, last_error: Option<&str>) -> BasicProperties {
+    let mut headers = FieldTable::default();
+    headers.insert(ATTEMPTS_HEADER.into(), AMQPValue::LongUInt(attempts));
+    if let Some(error) = last_error {
+        let error: String = error.chars().take(MAX_ERROR_HEADER_LEN).collect();
+        headers.insert(LAST_ERROR_HEADER.into(), long_string(&error));
+    }
+
+    BasicProperties::default()
+        .with_delivery_mode(PERSISTENT)
+        .with_content_type("application/json".into())
+        .with_headers(headers)
+}
+
+/// Сколько раз сообщение уже обрабатывали (заголовок `x-attempts`).
+pub fn attempts_header(properties: &BasicProperties) -> u32 {
+    properties
+        .headers()
+        .as_ref()
+        .and_then(|headers| headers.inner().get(ATTEMPTS_HEADER))
+        .and_then(|value| match value {
+            AMQPValue::LongUInt(v) => Some(*v),
+            AMQPValue::LongLongInt(v) => u32::try_from(*v).ok(),
+            AMQPValue::LongInt(v) => u32::try_from(*v).ok(),
+            _ => None,
+        })
+        .unwrap_or(0)
+}
+
+struct Session {
+    channel: Channel,
+    /// Очереди (и очереди повторов), топология которых уже объявлена на этом соединении
+    declared: Vec<String>,
+    consumers: HashMap<String, Consumer>,
+}
+
+pub struct RabbitJobQueue {
+    config: RabbitConfig,
+    /// Соединение переподключается лениво после ошибки
+    session: Mutex<Option<Session>>,
+}
+
+impl RabbitJobQueue {
+    pub fn new(config: RabbitConfig) -> Self {
+        Self {
+            config,
+            session: Mutex::new(None),
+        }
+    }
+
+    pub fn config(&self) -> &RabbitConfig {
+        &self.config
+    }
+
+    async fn connect(&self) -> Result<Session, String> {
+        let url = &self.config.url;
+        log::info!("rabbitmq connecting to {}", redact_amqp_url(url));
+        let conn = Connection::connect(url, ConnectionProperties::default())
+            .await
+            .map_err(|e| format!("rabbitmq connect error: {e} url={}", redact_amqp_url(url)))?;
+        let channel = conn
+            .create_channel()
+            .await
+            .map_err(|e| format!("rabbitmq channel error: {e}"))?;
+        channel
+            .confirm_select(ConfirmSelectOptions::default())
+            .await
+            .map_err(|e| format!("rabbitmq confirm select error: {e}"))?;
+        channel
+            .basic_qos(self.config.prefetch, BasicQosOptions::default())
+            .await
+            .map_err(|e| format!("rabbitmq qos error: {e}"))?;
+
+        let durable = ExchangeDeclareOptions {
+            durable: true,
+            ..ExchangeDeclareOptions::default()
+        };
+        for exchange in [&self.config.exchange, &self.config.dead_letter_exchange] {
+            channel
+                .exchange_declare(exchange, ExchangeKind::Direct, durable, FieldTable::default())
+                .await
+                .map_err(|e| format!("rabbitmq declare exchange {exchange} error: {e}"))?;
+        }
+
+        Ok(Session {
+            channel,
+            declared: Vec::new(),
+            consumers: HashMap::new(),
+        })
+    }
+
+    /// Канал с объявленной топологией `queue` и, если нужна, очередью повторов с паузой `delay`.
+    async fn channel_with_retry(&self, queue: &str, delay: Option<Duration>) -> Result<Channel, String> {
+        let mut guard = self.session.lock().await;
+        if guard
+            .as_ref()
+            .is_none_or(|session| !session.channel.status().connected())
+        {
+            *guard = Some(self.connect().await?);
+        }
+        let session = guard.as_mut().expect("rabbitmq session");
+
+        if !session.declared.iter().any(|name| name == queue) {
+            for spec in self.config.topology(queue) {
+                declare_queue(&session.channel, &spec).await?;
+            }
+            log::info!("rabbitmq queue ready: {}", queue);
+            session.declared.push(queue.to_string());
+        }
+        if let Some(delay) = delay {
+            let spec = self.config.retry_spec(queue, delay);
+            if !session.declared.contains(&spec.name) {
+                declare_queue(&session.channel, &spec).await?;
+                session.declared.push(spec.name);
+            }
+        }
+        Ok(session.channel.clone())
+    }
+
+    async fn channel(&self, queue: &str) -> Result<Channel, String> {
+        self.channel_with_retry(queue, None).await
+    }
+
+    async fn consumer(&self, queue: &str) -> Result<Consumer, String> {
+        let channel = self.channel(queue).await?;
+        let mut guard = self.session.lock().await;
+        let session = guard.as_mut().ok_or_else(|| "rabbitmq session lost".to_string())?;
+        if let Some(consumer) = session.consumers.get(queue) {
+            return Ok(consumer.clone());
+        }
+        let consumer = channel
+            .basic_consume(
+                &self.config.queue_name(queue),
+                "",
+                BasicConsumeOptions::default(),
+                FieldTable::default(),
+            )
+            .await
+            .map_err(|e| format!("rabbitmq consume error: {e}"))?;
+        session.consumers.insert(queue.to_string(), consumer.clone());
+        Ok(consumer)
+    }
+
+    /// Публикует в рабочую очередь `queue` или, если задан `delay`, в её очередь повторов.
+    async fn publish(
+        &self,
+        queue: &str,
+        delay: Option<Duration>,
+        payload: &serde_json::Value,
+        properties: BasicProperties,
+    ) -> Result<(), String> {
+        let channel = self.channel_with_retry(queue, delay).await?;
+        let routing_key = match delay {
+            Some(delay) => self.config.retry_spec(queue, delay).name,
+            None => self.config.routing_key(queue),
+        };
+        let routing_key = routing_key.as_str();
+        let body = serde_json::to_vec(payload).map_err(|e| e.to_string())?;
+        let confirmation = channel
+            .basic_publish(
+                &self.config.exchange,
+                routing_key,
+                BasicPublishOptions {
+                    mandatory: true,
+                    ..BasicPublishOptions::default()
+                },
+                &body,
+                properties,
+            )
+            .await
+            .map_err(|e| format!("rabbitmq publish error: {e}"))?
+            .await
+            .map_err(|e| format!("rabbitmq publish confirm error: {e}"))?;
+
+        match confirmation {
+            Confirmation::Ack(None) | Confirmation::NotRequested => Ok(()),
+            // mandatory: брокер вернул сообщение, очереди под routing key нет
+            Confirmation::Ack(Some(_)) => Err(format!("rabbitmq message unroutable key={routing_key}")),
+            Confirmation::Nack(_) => Err(format!("rabbitmq publish nacked key={routing_key}")),
+        }
+    }
+
+    async fn nack(&self, job: &Job, requeue: bool) -> Result<(), String> {
+        let channel = self.channel(&job.queue).await?;
+        channel
+            .basic_nack(
+                job.id,
+                BasicNackOptions {
+                    requeue,
+                    ..BasicNackOptions::default()
+                },
+            )
+            .await
+            .map_err(|e| format!("rabbitmq nack error: {e}"))
+    }
+}
+
+async fn declare_queue(channel: &Channel, spec: &QueueSpec) -> Result<(), String> {
+    channel
+        .queue_declare(
+            &spec.name,
+            QueueDeclareOptions {
+                durable: true,
+                ..QueueDeclareOptions::default()
+            },
+            spec.arguments.clone(),
+        )
+        .await
+        .map_err(|e| format!("rabbitmq declare queue {} error: {e}", spec.name))?;
+    for (exchange, routing_key) in &spec.bindings {
+        channel
+            .queue_bind(
+                &spec.name,
+                exchange,
+                routing_key,
+                QueueBindOptions::default(),
+                FieldTable::default(),
+            )
+            .await
+            .map_err(|e| format!("rabbitmq bind {} error: {e}", spec.name))?;
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl JobQueue for RabbitJobQueue {
+    fn name(&self) -> &'static str {
+        "rabbitmq"
+    }
+
+    async fn enqueue(&self, job: NewJob) -> Result<(), String> {
+        // Дедупликации нет: статус просто проверится лишний раз
+        let delay = Some(job.delay).filter(|delay| !delay.is_zero());
+        let properties = message_properties(0, None);
+        self.publish(&job.queue, delay, &job.payload, properties).await
+    }
+
+    async fn reserve(&self, queue: &str, limit: usize, _visibility: Duration) -> Result<Vec<Job>, String> {
+        // Таймаут видимости держит сам брокер: неподтверждённое сообщение вернётся
+        // в очередь при обрыве канала (или по `consumer_timeout`)
+        let mut consumer = self.consumer(queue).await?;
+
+        let mut jobs = Vec::new();
+        while jobs.len() < limit {
+            let next = if jobs.is_empty() {
+                actix_web::rt::time::timeout(RESERVE_WAIT, consumer.next())
+                    .await
+                    .ok()
+            } else {
+                consumer.next().now_or_never()
+            };
+            let delivery = match next {
+                Some(Some(delivery)) => delivery.map_err(|e| format!("rabbitmq delivery error: {e}"))?,
+                // Консьюмер закрыт: пересоздадим при следующем вызове
+                Some(None) => {
+                    if let Some(session) = self.session.lock().await.as_mut() {
+                        session.consumers.remove(queue);
+                    }
+                    break;
+                }
+                None => break,
+            };
+
+            let attempts = attempts_header(&delivery.properties) + 1;
+            match serde_json::from_slice(&delivery.data) {
+                Ok(payload) => jobs.push(Job {
+                    id: delivery.delivery_tag,
+                    queue: queue.to_string(),
+                    payload,
+                    attempts,
+                }),
+                Err(e) => {
+                    // Повтор не поможет: брокер переложит сообщение в `<queue>.dead`
+                    log::error!("rabbitmq invalid job payload queue={} error={}", queue, e);
+                    let _ = delivery
+                        .nack(BasicNackOptions {
+                            requeue: false,
+                            ..BasicNackOptions::default()
+                        })
+                        .await;
+                }
+            }
+        }
+        Ok(jobs)
+    }
+
+    async fn ack(&self, job: &Job) -> Result<(), String> {
+        let channel = self.channel(&job.queue).await?;
+        channel
+            .basic_ack(job.id, BasicAckOptions::default())
+            .await
+            .map_err(|e| format!("rabbitmq ack error: {e}"))
+    }
+
+    async fn retry(&self, job: &Job, delay: Duration, error: &str) -> Result<(), String> {
+        // Копия с увеличенным счётчиком попыток уходит в очередь повторов и вернётся через `delay`
+        let properties = message_properties(job.attempts, Some(error));
+        match self.publish(&job.queue, Some(delay), &job.payload, properties).await {
+            Ok(()) => self.ack(job).await,
+            Err(e) => {
+                // Копию опубликовать не удалось: возвращаем оригинал, чтобы не потерять
+                log::warn!("rabbitmq retry publish failed, requeueing queue={} error={}", job.queue, e);
+                self.nack(job, true).await
+            }
+        }
+    }
+}
+
+pub fn resolve_rabbit_url() -> Option<String> {
+    let env_url = std::env::var("RABBITMQ_URL").ok().filter(|v| !v.trim().is_empty());
+    let file_url = read_dotenv_value("RABBITMQ_URL").filter(|v| !v.trim().is_empty());
+
+    match (env_url, file_url) {
+        (Some(env), Some(file)) if looks_local_rabbit(&env) && env != file => {
+            log::warn!(
+                "RABBITMQ_URL from environment is local, overriding with .env value"
+            );
+            Some(file)
+        }
+        (Some(env), _) => Some(env),
+        (None, Some(file)) => Some(file),
+        _ => None,
+    }
+}
+
+fn read_dotenv_value(key: &str) -> Option<String> {
+    let iter = dotenvy::from_filename_iter(".env").ok()?;
+    for item in iter {
+        if let Ok((k, v)) = item
+            && k == key
+        {
+            return Some(v);
+        }
+    }
+    None
+}
+
+fn looks_local_rabbit(url: &str) -> bool {
+    url.contains("@localhost")
+        || url.contains("://localhost")
+        || url.contains("127.0.0.1")
+}
+
+pub fn redact_amqp_url(url: &str) -> String {
+    let at_pos = match url.rfind('@') {
+        Some(pos) => pos,
+        None => return url.to_string(),
+    };
+    let (creds, rest) = url.split_at(at_pos);
+    let colon_pos = match creds.rfind(':') {
+        Some(pos) => pos,
+        None => return format!("{creds}{rest}"),
+    };
+    format!("{}:***{}", &creds[..colon_pos], rest)
+}
@@ -1,3 +1,15 @@
+use crate::safe_http::SafeResponse;
+use actix_web::web::Bytes;
+use aws_sdk_s3::Client as S3Client;
+use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_s3::types::{ChecksumAlgorithm, CompletedMultipartUpload, CompletedPart};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use sha2::{Digest, Sha256};
+use std::path::Path;
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+
 // Helpers for working with public S3-compatible URLs.
 pub fn build_public_url(base: &str, bucket: &str, key: &str) -> String {
     let trimmed = base.trim_end_matches('/');
@@ -14,3 +26,338 @@ pub fn build_public_url(base: &str, bucket: &str, key: &str) -> String {
         format!("{}/{}/{}", trimmed, bucket, key)
     }
 }
+
+// --- Streaming upload ---
+//
+// Результат обработки переливаем в S3 частями: в памяти держим не больше одной части,
+// каждая часть отправляется с SHA256 и повторяется при ошибке.
+
+/// Минимальный размер части multipart upload в S3 (кроме последней).
+const MIN_PART_SIZE: usize = 5 * 1024 * 1024;
+
+#[derive(Debug, Clone)]
+pub struct StreamUploadOptions {
+    pub part_size: usize,
+    pub part_retries: u32,
+    /// Тип по умолчанию, если источник его не прислал
+    pub default_content_type: String,
+}
+
+impl Default for StreamUploadOptions {
+    fn default() -> Self {
+        Self {
+            part_size: 8 * 1024 * 1024,
+            part_retries: 3,
+            default_content_type: "video/mp4".to_string(),
+        }
+    }
+}
+
+impl StreamUploadOptions {
+    /// `S3_MULTIPART_PART_SIZE_MB` (не меньше 5), `S3_PART_RETRIES`.
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let part_size = std::env::var("S3_MULTIPART_PART_SIZE_MB")
+            .ok()
+            .and_then(|v| v.trim().parse::<usize>().ok())
+            .map(|mb| (mb * 1024 * 1024).max(MIN_PART_SIZE))
+            .unwrap_or(defaults.part_size);
+        let part_retries = std::env::var("S3_PART_RETRIES")
+            .ok()
+            .and_then(|v| v.trim().parse::<u32>().ok())
+            .unwrap_or(defaults.part_retries);
+        Self {
+            part_size,
+            part_retries,
+            ..defaults
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct StreamedObject {
+    pub size: u64,
+    /// SHA256 всего объекта (hex)
+    pub sha256: String,
+    pub content_type: String,
+    pub parts: usize,
+}
+
+/// Откуда `stream_to_s3` читает данные.
+pub enum UploadSource {
+    /// Ответ внешнего сервера (результат KIE)
+    Http(SafeResponse),
+    /// Локальный файл (результат локального бэкенда)
+    File { file: tokio::fs::File, len: u64 },
+}
+
+/// Сколько читаем из файла за раз.
+const FILE_CHUNK_SIZE: usize = 256 * 1024;
+
+impl UploadSource {
+    pub async fn file(path: &Path) -> Result<Self, String> {
+        let file = tokio::fs::File::open(path)
+            .await
+            .map_err(|e| format!("open {} error: {e}", path.display()))?;
+        let len = file
+            .metadata()
+            .await
+            .map_err(|e| format!("stat {} error: {e}", path.display()))?
+            .len();
+        Ok(Self::File { file, len })
+    }
+
+    fn content_type(&self) -> Option<String> {
+        match self {
+            Self::Http(resp) => resp.content_type(),
+            Self::File { .. } => None,
+        }
+    }
+
+    fn content_length(&self) -> Option<u64> {
+        match self {
+            Self::Http(resp) => resp.content_length(),
+            Self::File { len, .. } => Some(*len),
+        }
+    }
+
+    async fn chunk(&mut self) -> Result<Option<Bytes>, String> {
+        match self {
+            Self::Http(resp) => resp.chunk().await.map_err(|e| e.to_string()),
+            Self::File { file, .. } => {
+                let mut buf = vec![0u8; FILE_CHUNK_SIZE];
+                let read = file.read(&mut buf).await.map_err(|e| e.to_string())?;
+                if read == 0 {
+                    return Ok(None);
+                }
+                buf.truncate(read);
+                Ok(Some(Bytes::from(buf)))
+            }
+        }
+    }
+}
+
+impl From<SafeResponse> for UploadSource {
+    fn from(resp: SafeResponse) -> Self {
+        Self::Http(resp)
+    }
+}
+
+fn sha256_base64(data: &[u8]) -> String {
+    BASE64.encode(Sha256::digest(data))
+}
+
+/// Переливает тело ответа (или локальный файл) в `bucket/key`.
+/// Маленькие файлы (до одной части) уходят одним `PutObject`, остальные — multipart upload.
+/// Если источник прислал `Content-Length`, итоговый размер обязан с ним совпасть.
+pub async fn stream_to_s3(
+    client: &S3Client,
+    bucket: &str,
+    key: &str,
+    source: impl Into<UploadSource>,
+    options: &StreamUploadOptions,
+) -> Result<StreamedObject, String> {
+    let mut source = source.into();
+    let content_type = source
+        .content_type()
+        .unwrap_or_else(|| options.default_content_type.clone());
+    let expected_len = source.content_length();
+    let part_size = options.part_size.max(MIN_PART_SIZE);
+
+    let mut whole = Sha256::new();
+    let mut total: u64 = 0;
+    let mut buffer: Vec<u8> = Vec::with_capacity(part_size);
+    let mut upload_id: Option<String> = None;
+    let mut parts: Vec<CompletedPart> = Vec::new();
+
+    let result: Result<(), String> = async {
+        loop {
+            let chunk = source.chunk().await?;
+            let finished = chunk.is_none();
+            if let Some(chunk) = chunk {
+                whole.update(&chunk);
+                total += chunk.len() as u64;
+                buffer.extend_from_slice(&chunk);
+            }
+
+            // Весь файл поместился в одну часть — обычный PutObject
+            if finished && upload_id.is_none() {
+                break;
+            }
+
+            while buffer.len() >= part_size || (finished && !buffer.is_empty()) {
+                let take = buffer.len().min(part_size);
+                let part: Vec<u8> = buffer.drain(..take).collect();
+
+                let id = match upload_id.as_deref() {
+                    Some(id) => id.to_string(),
+                    None => {
+                        let created = client
+                            .create_multipart_upload()
+                            .bucket(bucket)
+                            .key(key)
+                            .content_type(&content_type)
+                            .checksum_algorithm(ChecksumAlgorithm::Sha256)
+                            .send()
+                            .await
+                            .map_err(|e| format!("s3 create multipart error: {e}"))?;
+                        let id = created
+                            .upload_id()
+                            .ok_or_else(|| "s3 create multipart: missing upload id".to_string())?
+                            .to_string();
+                        upload_id = Some(id.clone());
+                        id
+                    }
+                };
+
+                let part_number = parts.len() as i32 + 1;
+                parts.push(
+                    upload_part_with_retries(client, bucket, key, &id, part_number, part, options)
+                        .await?,
+                );
+            }
+
+            if finished {
+                break;
+            }
+        }
+
+        if let Some(expected) = expected_len
+            && expected != total
+        {
+            return Err(format!(
+                "source size mismatch: content-length={expected} received={total}"
+            ));
+        }
+        Ok(())
+    }
+    .await;
+
+    if let Err(e) = result {
+        if let Some(id) = upload_id.as_deref() {
+            abort_multipart(client, bucket, key, id).await;
+        }
+        return Err(e);
+    }
+
+    let parts_count = match upload_id {
+        None => {
+            let checksum = sha256_base64(&buffer);
+            client
+                .put_object()
+                .bucket(bucket)
+                .key(key)
+                .content_type(&content_type)
+                .content_length(total as i64)
+                .checksum_algorithm(ChecksumAlgorithm::Sha256)
+                .checksum_sha256(checksum)
+                .body(ByteStream::from(buffer))
+                .send()
+                .await
+                .map_err(|e| format!("s3 put object error: {e}"))?;
+            1
+        }
+        Some(id) => {
+            let parts_count = parts.len();
+            let completed = client
+                .complete_multipart_upload()
+                .bucket(bucket)
+                .key(key)
+                .upload_id(&id)
+                .multipart_upload(
+                    CompletedMultipartUpload::builder()
+                        .set_parts(Some(parts))
+                        .build(),
+                )
+                .send()
+                .await;
+            if let Err(e) = completed {
+                abort_multipart(client, bucket, key, &id).await;
+                return Err(format!("s3 complete multipart error: {e}"));
+            }
+            parts_count
+        }
+    };
+
+    Ok(StreamedObject {
+        size: total,
+        sha256: hex::encode(whole.finalize()),
+        content_type,
+        parts: parts_count,
+    })
+}
+
+async fn upload_part_with_retries(
+    client: &S3Client,
+    bucket: &str,
+    key: &str,
+    upload_id: &str,
+    part_number: i32,
+    data: Vec<u8>,
+    options: &StreamUploadOptions,
+) -> Result<CompletedPart, String> {
+    let checksum = sha256_base64(&data);
+    let data = actix_web::web::Bytes::from(data);
+    let attempts = options.part_retries.max(1);
+    let mut last_error = String::new();
+
+    for attempt in 1..=attempts {
+        let result = client
+            .upload_part()
+            .bucket(bucket)
+            .key(key)
+            .upload_id(upload_id)
+            .part_number(part_number)
+            .content_length(data.len() as i64)
+            .checksum_algorithm(ChecksumAlgorithm::Sha256)
+            .checksum_sha256(&checksum)
+            .body(ByteStream::from(data.clone()))
+            .send()
+            .await;
+
+        match result {
+            Ok(out) => {
+                // S3 возвращает контрольную сумму, которую сам посчитал по полученным байтам
+                if let Some(returned) = out.checksum_sha256()
+                    && returned != checksum
+                {
+                    last_error = format!("checksum mismatch part={part_number}");
+                } else {
+                    return Ok(CompletedPart::builder()
+                        .part_number(part_number)
+                        .set_e_tag(out.e_tag().map(|s| s.to_string()))
+                        .checksum_sha256(&checksum)
+                        .build());
+                }
+            }
+            Err(e) => last_error = e.to_string(),
+        }
+
+        log::warn!(
+            "s3 upload part failed key={} part={} attempt={}/{} error={}",
+            key,
+            part_number,
+            attempt,
+            attempts,
+            last_error
+        );
+        if attempt < attempts {
+            actix_web::rt::time::sleep(Duration::from_millis(500 * 2u64.pow(attempt - 1))).await;
+        }
+    }
+
+    Err(format!("s3 upload part {part_number} failed: {last_error}"))
+}
+
+async fn abort_multipart(client: &S3Client, bucket: &str, key: &str, upload_id: &str) {
+    if let Err(e) = client
+        .abort_multipart_upload()
+        .bucket(bucket)
+        .key(key)
+        .upload_id(upload_id)
+        .send()
+        .await
+    {
+        log::error!("s3 abort multipart failed key={} error={}", key, e);
+    }
+}
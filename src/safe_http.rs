@@ -0,0 +1,429 @@
+// src/safe_http.rs
+//
+// Исходящие запросы по ссылкам, которые пришли извне (ссылки пользователей, outputUrl из
+// колбэков). Защита от SSRF:
+// - только http(s)
+// - адреса проверяются после DNS-резолва, запрос идёт ровно на проверенный IP
+// - приватные, loopback, link-local и прочие служебные диапазоны запрещены
+// - редиректы обрабатываем сами и проверяем каждый шаг
+// - ограничения по размеру ответа и времени: соединение, простой между кусками тела
+//   и (только для небольших ответов) общий срок запроса
+
+use actix_web::rt;
+use actix_web::web::Bytes;
+use reqwest::Url;
+use reqwest::header::{HeaderMap, LOCATION};
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+#[derive(Debug, Clone)]
+pub struct FetchPolicy {
+    pub max_redirects: usize,
+    pub max_bytes: u64,
+    /// Сколько ждать установки соединения
+    pub connect_timeout: Duration,
+    /// Сколько ждать заголовков ответа и каждого следующего куска тела
+    pub read_timeout: Duration,
+    /// Общий срок запроса вместе с телом. Только для небольших ответов (страницы, вебхуки):
+    /// файл в пару гигабайт качается долго, и для него важен только простой (`read_timeout`).
+    pub timeout: Option<Duration>,
+    /// Если задан — разрешены только эти хосты (и их поддомены)
+    pub allowed_hosts: Option<Vec<String>>,
+    /// Разрешить приватные адреса (локальная разработка, MinIO, тесты)
+    pub allow_private: bool,
+}
+
+impl Default for FetchPolicy {
+    fn default() -> Self {
+        Self {
+            max_redirects: 5,
+            max_bytes: 2 * 1024 * 1024 * 1024,
+            connect_timeout: Duration::from_secs(10),
+            read_timeout: Duration::from_secs(60),
+            timeout: None,
+            allowed_hosts: None,
+            allow_private: false,
+        }
+    }
+}
+
+impl FetchPolicy {
+    /// `OUTBOUND_MAX_REDIRECTS`, `OUTBOUND_MAX_BYTES`, `OUTBOUND_CONNECT_TIMEOUT_SECS`,
+    /// `OUTBOUND_READ_TIMEOUT_SECS`, `OUTBOUND_ALLOW_PRIVATE`. Общего срока нет: его задаёт
+    /// `with_timeout` там, где ответ заведомо небольшой.
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            max_redirects: env_parse("OUTBOUND_MAX_REDIRECTS").unwrap_or(defaults.max_redirects),
+            max_bytes: env_parse("OUTBOUND_MAX_BYTES").unwrap_or(defaults.max_bytes),
+            connect_timeout: env_parse("OUTBOUND_CONNECT_TIMEOUT_SECS")
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.connect_timeout),
+            read_timeout: env_parse("OUTBOUND_READ_TIMEOUT_SECS")
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.read_timeout),
+            timeout: None,
+            allowed_hosts: None,
+            allow_private: std::env::var("OUTBOUND_ALLOW_PRIVATE").unwrap_or_default() == "true",
+        }
+    }
+
+    /// Политика для скачивания результатов KIE: дополнительно `KIE_RESULT_ALLOWED_HOSTS`
+    /// (список через запятую; пусто — любой публичный хост).
+    pub fn kie_results() -> Self {
+        Self::from_env().with_allowed_hosts(parse_host_list(
+            &std::env::var("KIE_RESULT_ALLOWED_HOSTS").unwrap_or_default(),
+        ))
+    }
+
+    pub fn with_allowed_hosts(mut self, hosts: Vec<String>) -> Self {
+        self.allowed_hosts = if hosts.is_empty() { None } else { Some(hosts) };
+        self
+    }
+
+    pub fn with_max_bytes(mut self, max_bytes: u64) -> Self {
+        self.max_bytes = max_bytes;
+        self
+    }
+
+    /// Общий срок на весь запрос; не для скачивания файлов.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    fn client(&self, host: &str, addrs: &[SocketAddr]) -> Result<reqwest::Client, FetchError> {
+        let mut builder = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .connect_timeout(self.connect_timeout)
+            .resolve_to_addrs(host, addrs);
+        if let Some(timeout) = self.timeout {
+            builder = builder.timeout(timeout);
+        }
+        Ok(builder.build()?)
+    }
+}
+
+fn env_parse<T: std::str::FromStr>(key: &str) -> Option<T> {
+    std::env::var(key).ok().and_then(|v| v.trim().parse::<T>().ok())
+}
+
+pub fn parse_host_list(raw: &str) -> Vec<String> {
+    raw.split(',')
+        .map(|s| s.trim().trim_start_matches('.').to_lowercase())
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+#[derive(Debug)]
+pub enum FetchError {
+    InvalidUrl(String),
+    SchemeNotAllowed(String),
+    HostNotAllowed(String),
+    BlockedAddress { host: String, ip: IpAddr },
+    Dns(String),
+    TooManyRedirects,
+    TooLarge { limit: u64 },
+    /// Ответ или очередной кусок тела не пришёл за `read_timeout`
+    Timeout,
+    Status(u16),
+    Http(reqwest::Error),
+}
+
+impl fmt::Display for FetchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FetchError::InvalidUrl(e) => write!(f, "invalid url: {e}"),
+            FetchError::SchemeNotAllowed(s) => write!(f, "scheme not allowed: {s}"),
+            FetchError::HostNotAllowed(h) => write!(f, "host not allowed: {h}"),
+            FetchError::BlockedAddress { host, ip } => {
+                write!(f, "host {host} resolves to a non-public address {ip}")
+            }
+            FetchError::Dns(e) => write!(f, "host cannot be resolved: {e}"),
+            FetchError::TooManyRedirects => write!(f, "too many redirects"),
+            FetchError::TooLarge { limit } => write!(f, "response exceeds {limit} bytes"),
+            FetchError::Timeout => write!(f, "response timed out"),
+            FetchError::Status(s) => write!(f, "unexpected status {s}"),
+            FetchError::Http(e) => write!(f, "http error: {e}"),
+        }
+    }
+}
+
+impl From<reqwest::Error> for FetchError {
+    fn from(value: reqwest::Error) -> Self {
+        Self::Http(value)
+    }
+}
+
+/// Адрес из публичного интернета (не приватный, не loopback, не link-local, не служебный).
+pub fn is_public_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => is_public_ipv4(v4),
+        IpAddr::V6(v6) => is_public_ipv6(v6),
+    }
+}
+
+fn is_public_ipv4(ip: Ipv4Addr) -> bool {
+    let [a, b, c, _] = ip.octets();
+    !(ip.is_private()
+        || ip.is_loopback()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_multicast()
+        || a == 0
+        // 100.64.0.0/10 — CGNAT
+        || (a == 100 && (64..=127).contains(&b))
+        // 192.0.0.0/24 — IETF protocol assignments
+        || (a == 192 && b == 0 && c == 0)
+        // 198.18.0.0/15 — benchmarking
+        || (a == 198 && (b == 18 || b == 19))
+        // 240.0.0.0/4 — reserved
+        || a >= 240)
+}
+
+fn is_public_ipv6(ip: Ipv6Addr) -> bool {
+    if let Some(v4) = ip.to_ipv4_mapped() {
+        return is_public_ipv4(v4);
+    }
+    let segments = ip.segments();
+    let embedded = |hi: u16, lo: u16| {
+        let [a, b] = hi.to_be_bytes();
+        let [c, d] = lo.to_be_bytes();
+        Ipv4Addr::new(a, b, c, d)
+    };
+    // 64:ff9b::/96 — NAT64, проверяем вложенный IPv4
+    if segments[..6] == [0x64, 0xff9b, 0, 0, 0, 0] {
+        return is_public_ipv4(embedded(segments[6], segments[7]));
+    }
+    // ::/96 — устаревшие IPv4-совместимые адреса `::a.b.c.d` (сюда же попадают `::` и `::1`)
+    if segments[..6] == [0; 6] {
+        return is_public_ipv4(embedded(segments[6], segments[7]));
+    }
+    // 2002::/16 — 6to4, IPv4 шлюза во втором и третьем сегментах
+    if segments[0] == 0x2002 {
+        return is_public_ipv4(embedded(segments[1], segments[2]));
+    }
+    !(ip.is_loopback()
+        || ip.is_unspecified()
+        || ip.is_multicast()
+        // 2001::/32 — Teredo: туннель к произвольному IPv4 (в том числе внутреннему)
+        || (segments[0] == 0x2001 && segments[1] == 0)
+        // fc00::/7 — unique local
+        || (segments[0] & 0xfe00) == 0xfc00
+        // fe80::/10 — link-local
+        || (segments[0] & 0xffc0) == 0xfe80
+        // 2001:db8::/32 — documentation
+        || (segments[0] == 0x2001 && segments[1] == 0x0db8))
+}
+
+fn host_allowed(host: &str, allowed: &[String]) -> bool {
+    let host = host.to_lowercase();
+    allowed
+        .iter()
+        .any(|a| host == *a || host.ends_with(&format!(".{a}")))
+}
+
+/// Проверяет ссылку по политике и возвращает адреса, на которые можно идти.
+pub async fn check_url(url: &Url, policy: &FetchPolicy) -> Result<Vec<SocketAddr>, FetchError> {
+    if url.scheme() != "http" && url.scheme() != "https" {
+        return Err(FetchError::SchemeNotAllowed(url.scheme().to_string()));
+    }
+    let host = url
+        .host_str()
+        .ok_or_else(|| FetchError::InvalidUrl("missing host".to_string()))?;
+    let host_plain = host.trim_start_matches('[').trim_end_matches(']');
+
+    if let Some(allowed) = policy.allowed_hosts.as_deref()
+        && !host_allowed(host_plain, allowed)
+    {
+        return Err(FetchError::HostNotAllowed(host_plain.to_string()));
+    }
+
+    let port = url
+        .port_or_known_default()
+        .ok_or_else(|| FetchError::InvalidUrl("missing port".to_string()))?;
+
+    let addrs: Vec<SocketAddr> = match host_plain.parse::<IpAddr>() {
+        Ok(ip) => vec![SocketAddr::new(ip, port)],
+        Err(_) => tokio::net::lookup_host((host_plain, port))
+            .await
+            .map_err(|e| FetchError::Dns(format!("{host_plain}: {e}")))?
+            .collect(),
+    };
+
+    if addrs.is_empty() {
+        return Err(FetchError::Dns(format!("{host_plain}: no addresses")));
+    }
+
+    if !policy.allow_private
+        && let Some(blocked) = addrs.iter().find(|a| !is_public_ip(a.ip()))
+    {
+        return Err(FetchError::BlockedAddress {
+            host: host_plain.to_string(),
+            ip: blocked.ip(),
+        });
+    }
+
+    Ok(addrs)
+}
+
+/// То же, что `check_url`, но для строки (например, ссылки от пользователя до отправки в KIE).
+pub async fn validate_url(raw: &str, policy: &FetchPolicy) -> Result<(), FetchError> {
+    let url = Url::parse(raw.trim()).map_err(|e| FetchError::InvalidUrl(e.to_string()))?;
+    check_url(&url, policy).await.map(|_| ())
+}
+
+/// Ответ с ограничением на размер тела.
+pub struct SafeResponse {
+    inner: reqwest::Response,
+    url: Url,
+    max_bytes: u64,
+    read_timeout: Duration,
+    received: u64,
+}
+
+impl SafeResponse {
+    pub fn status(&self) -> reqwest::StatusCode {
+        self.inner.status()
+    }
+
+    pub fn headers(&self) -> &HeaderMap {
+        self.inner.headers()
+    }
+
+    pub fn content_length(&self) -> Option<u64> {
+        self.inner.content_length()
+    }
+
+    pub fn content_type(&self) -> Option<String> {
+        self.inner
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .map(|s| s.to_string())
+    }
+
+    /// Итоговый адрес после редиректов.
+    pub fn url(&self) -> &Url {
+        &self.url
+    }
+
+    pub async fn chunk(&mut self) -> Result<Option<Bytes>, FetchError> {
+        let chunk = rt::time::timeout(self.read_timeout, self.inner.chunk())
+            .await
+            .map_err(|_| FetchError::Timeout)?;
+        let Some(chunk) = chunk? else {
+            return Ok(None);
+        };
+        self.received += chunk.len() as u64;
+        if self.received > self.max_bytes {
+            return Err(FetchError::TooLarge {
+                limit: self.max_bytes,
+            });
+        }
+        Ok(Some(chunk))
+    }
+
+    pub async fn bytes(mut self) -> Result<Vec<u8>, FetchError> {
+        let mut body = Vec::with_capacity(self.content_length().unwrap_or(0) as usize);
+        while let Some(chunk) = self.chunk().await? {
+            body.extend_from_slice(&chunk);
+        }
+        Ok(body)
+    }
+
+    pub async fn text(self) -> Result<String, FetchError> {
+        let body = self.bytes().await?;
+        Ok(String::from_utf8_lossy(&body).into_owned())
+    }
+}
+
+/// GET по внешней ссылке с проверкой каждого шага редиректа.
+/// Неуспешный статус возвращается как `FetchError::Status`.
+pub async fn get(url: &str, policy: &FetchPolicy) -> Result<SafeResponse, FetchError> {
+    let mut current = Url::parse(url).map_err(|e| FetchError::InvalidUrl(e.to_string()))?;
+
+    for _ in 0..=policy.max_redirects {
+        let addrs = check_url(&current, policy).await?;
+        let host = current.host_str().unwrap_or_default().to_string();
+
+        // Фиксируем проверенные адреса, чтобы повторный DNS-резолв не увёл запрос в сторону
+        let client = policy.client(&host, &addrs)?;
+
+        let resp = send(policy, client.get(current.clone())).await?;
+        let status = resp.status();
+
+        if status.is_redirection() {
+            let location = resp
+                .headers()
+                .get(LOCATION)
+                .and_then(|v| v.to_str().ok())
+                .ok_or_else(|| FetchError::InvalidUrl("redirect without location".to_string()))?;
+            current = current
+                .join(location)
+                .map_err(|e| FetchError::InvalidUrl(e.to_string()))?;
+            continue;
+        }
+
+        if !status.is_success() {
+            return Err(FetchError::Status(status.as_u16()));
+        }
+
+        if let Some(len) = resp.content_length()
+            && len > policy.max_bytes
+        {
+            return Err(FetchError::TooLarge {
+                limit: policy.max_bytes,
+            });
+        }
+
+        return Ok(SafeResponse {
+            inner: resp,
+            url: current,
+            max_bytes: policy.max_bytes,
+            read_timeout: policy.read_timeout,
+            received: 0,
+        });
+    }
+
+    Err(FetchError::TooManyRedirects)
+}
+
+/// POST на внешний адрес (вебхуки пользователей). Редиректы не выполняются, а любой
+/// статус возвращается как есть: решение об успехе остаётся за вызывающим.
+pub async fn post(
+    url: &str,
+    headers: HeaderMap,
+    body: String,
+    policy: &FetchPolicy,
+) -> Result<SafeResponse, FetchError> {
+    let url = Url::parse(url).map_err(|e| FetchError::InvalidUrl(e.to_string()))?;
+    let addrs = check_url(&url, policy).await?;
+    let host = url.host_str().unwrap_or_default().to_string();
+
+    let client = policy.client(&host, &addrs)?;
+
+    let resp = send(policy, client.post(url.clone()).headers(headers).body(body)).await?;
+
+    Ok(SafeResponse {
+        inner: resp,
+        url,
+        max_bytes: policy.max_bytes,
+        read_timeout: policy.read_timeout,
+        received: 0,
+    })
+}
+
+/// Отправляет запрос и ждёт заголовков ответа не дольше соединения плюс `read_timeout`.
+async fn send(
+    policy: &FetchPolicy,
+    request: reqwest::RequestBuilder,
+) -> Result<reqwest::Response, FetchError> {
+    rt::time::timeout(policy.connect_timeout + policy.read_timeout, request.send())
+        .await
+        .map_err(|_| FetchError::Timeout)?
+        .map_err(FetchError::from)
+}
@@ -0,0 +1,528 @@
+// src/source_resolver.rs
+//
+// Разбор пользовательских ссылок на видео до того, как мы спишем кредит и отправим задачу.
+// Каждый резолвер отвечает за свой хостинг; реестр перебирает их по порядку.
+
+use crate::safe_http::{self, FetchPolicy};
+use async_trait::async_trait;
+use regex::Regex;
+use reqwest::Url;
+use std::fmt;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+const VIDEO_EXTENSIONS: &[&str] = &["mp4", "mov", "m4v", "webm", "mkv"];
+const PAGE_FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+const PAGE_MAX_BYTES: u64 = 2 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SourceKind {
+    Sora,
+    GoogleDrive,
+    Dropbox,
+    Direct,
+}
+
+impl SourceKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SourceKind::Sora => "sora",
+            SourceKind::GoogleDrive => "google_drive",
+            SourceKind::Dropbox => "dropbox",
+            SourceKind::Direct => "direct",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "sora" => Some(SourceKind::Sora),
+            "google_drive" => Some(SourceKind::GoogleDrive),
+            "dropbox" => Some(SourceKind::Dropbox),
+            "direct" => Some(SourceKind::Direct),
+            _ => None,
+        }
+    }
+
+    /// Ссылка на видео подписана и быстро протухает (CDN Sora, подтверждение Drive):
+    /// её нужно получать заново перед отправкой в бэкенд, а не брать сохранённую.
+    pub fn is_short_lived(&self) -> bool {
+        matches!(self, SourceKind::Sora | SourceKind::GoogleDrive)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ResolvedSource {
+    pub kind: SourceKind,
+    /// Ссылка, которую отдаём на обработку
+    pub video_url: String,
+    /// Каноничная ссылка на источник (для дедупликации и истории)
+    pub canonical_url: String,
+    pub original_filename: String,
+}
+
+#[derive(Debug)]
+pub enum ResolveError {
+    InvalidUrl(String),
+    UnsupportedSource(String),
+    MalformedLink(String),
+    /// Ссылка правильная, но видео по ней получить не удалось (нет доступа, страница без видео)
+    Unavailable(String),
+}
+
+impl fmt::Display for ResolveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResolveError::InvalidUrl(e) => write!(f, "invalid video URL: {e}"),
+            ResolveError::UnsupportedSource(host) => write!(
+                f,
+                "unsupported video link ({host}): expected a Sora share link, Google Drive, Dropbox or a direct video file URL"
+            ),
+            ResolveError::MalformedLink(e) => write!(f, "malformed video link: {e}"),
+            ResolveError::Unavailable(e) => write!(f, "video is not available: {e}"),
+        }
+    }
+}
+
+#[async_trait]
+pub trait SourceResolver: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Берётся ли резолвер за эту ссылку (проверяется по порядку регистрации).
+    fn matches(&self, url: &Url) -> bool;
+
+    async fn resolve(&self, url: &Url) -> Result<ResolvedSource, ResolveError>;
+}
+
+pub struct ResolverRegistry {
+    resolvers: Vec<Box<dyn SourceResolver>>,
+}
+
+impl Default for ResolverRegistry {
+    fn default() -> Self {
+        Self::new()
+            .register(SoraResolver)
+            .register(GoogleDriveResolver)
+            .register(DropboxResolver)
+            .register(DirectVideoResolver)
+    }
+}
+
+impl ResolverRegistry {
+    /// Пустой реестр; стандартный набор — `ResolverRegistry::default()`.
+    pub fn new() -> Self {
+        Self {
+            resolvers: Vec::new(),
+        }
+    }
+
+    pub fn register(mut self, resolver: impl SourceResolver + 'static) -> Self {
+        self.resolvers.push(Box::new(resolver));
+        self
+    }
+
+    pub async fn resolve(&self, raw: &str) -> Result<ResolvedSource, ResolveError> {
+        let url = Url::parse(raw.trim()).map_err(|e| ResolveError::InvalidUrl(e.to_string()))?;
+        if url.scheme() != "http" && url.scheme() != "https" {
+            return Err(ResolveError::InvalidUrl(format!(
+                "unsupported scheme {}",
+                url.scheme()
+            )));
+        }
+        let host = url
+            .host_str()
+            .ok_or_else(|| ResolveError::InvalidUrl("missing host".to_string()))?
+            .to_string();
+
+        for resolver in &self.resolvers {
+            if resolver.matches(&url) {
+                log::debug!("source resolver={} url={}", resolver.name(), url);
+                return resolver.resolve(&url).await;
+            }
+        }
+
+        Err(ResolveError::UnsupportedSource(host))
+    }
+}
+
+/// Оставляет в имени файла только безопасные символы.
+pub fn sanitize_filename(filename: &str) -> String {
+    filename
+        .chars()
+        .filter(|c| c.is_alphanumeric() || *c == '.' || *c == '_' || *c == '-')
+        .collect()
+}
+
+fn host_is(url: &Url, domains: &[&str]) -> bool {
+    let Some(host) = url.host_str() else {
+        return false;
+    };
+    let host = host.to_lowercase();
+    domains
+        .iter()
+        .any(|d| host == *d || host.ends_with(&format!(".{d}")))
+}
+
+fn last_segment(url: &Url) -> Option<String> {
+    url.path_segments()?
+        .rev()
+        .find(|s| !s.is_empty())
+        .map(|s| urlencoding::decode(s).map(|d| d.into_owned()).unwrap_or_else(|_| s.to_string()))
+}
+
+fn has_video_extension(name: &str) -> bool {
+    name.rsplit_once('.')
+        .map(|(_, ext)| VIDEO_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+        .unwrap_or(false)
+}
+
+fn filename_with_extension(name: &str, fallback: &str) -> String {
+    let name = sanitize_filename(name);
+    if name.is_empty() {
+        fallback.to_string()
+    } else if has_video_extension(&name) {
+        name
+    } else {
+        format!("{name}.mp4")
+    }
+}
+
+fn is_valid_id(id: &str) -> bool {
+    !id.is_empty() && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+fn decode_html_entities(value: &str) -> String {
+    value
+        .replace("&amp;", "&")
+        .replace("&#38;", "&")
+        .replace("&quot;", "\"")
+        .replace("&#x2F;", "/")
+        .replace("&#47;", "/")
+        .replace("\\u0026", "&")
+        .replace("\\/", "/")
+}
+
+/// Загружает HTML страницы (с ограничением по размеру и времени, через SSRF-safe клиент).
+/// `None` — по ссылке не страница, а сам файл: тело тогда не читаем.
+async fn fetch_page(url: &str) -> Result<Option<String>, String> {
+    let policy = FetchPolicy::from_env()
+        .with_max_bytes(PAGE_MAX_BYTES)
+        .with_timeout(PAGE_FETCH_TIMEOUT);
+    let resp = safe_http::get(url, &policy)
+        .await
+        .map_err(|e| e.to_string())?;
+    if !resp.status().is_success() {
+        return Err(format!("status {}", resp.status()));
+    }
+    if !is_html(resp.content_type().as_deref()) {
+        return Ok(None);
+    }
+    resp.text().await.map(Some).map_err(|e| e.to_string())
+}
+
+fn is_html(content_type: Option<&str>) -> bool {
+    content_type
+        .map(|ct| {
+            let ct = ct.to_ascii_lowercase();
+            ct.starts_with("text/html") || ct.starts_with("application/xhtml")
+        })
+        .unwrap_or(false)
+}
+
+// --- Sora ---
+
+/// Ссылки вида `https://sora.chatgpt.com/p/s_...` (а также `/g/gen_...`, домен sora.com).
+pub struct SoraResolver;
+
+impl SoraResolver {
+    fn share_id(url: &Url) -> Result<String, ResolveError> {
+        let segments: Vec<&str> = url
+            .path_segments()
+            .map(|s| s.filter(|p| !p.is_empty()).collect())
+            .unwrap_or_default();
+        match segments.as_slice() {
+            [kind, id, ..] if (*kind == "p" || *kind == "g") && is_valid_id(id) => {
+                Ok(id.to_string())
+            }
+            _ => Err(ResolveError::MalformedLink(
+                "expected a Sora share link like https://sora.chatgpt.com/p/s_...".to_string(),
+            )),
+        }
+    }
+}
+
+/// Достаёт прямую ссылку на видео со страницы Sora:
+/// `og:video` / `og:video:secure_url` / `og:video:url`, иначе первый `<video src>` или `<source src>`.
+pub fn extract_sora_video_url(html: &str) -> Option<String> {
+    static META_RE: OnceLock<Regex> = OnceLock::new();
+    static TAG_RE: OnceLock<Regex> = OnceLock::new();
+    static CONTENT_RE: OnceLock<Regex> = OnceLock::new();
+    static PROP_RE: OnceLock<Regex> = OnceLock::new();
+    static SRC_RE: OnceLock<Regex> = OnceLock::new();
+
+    let meta_re = META_RE.get_or_init(|| Regex::new(r"(?is)<meta\b[^>]*>").expect("meta regex"));
+    let prop_re = PROP_RE.get_or_init(|| {
+        Regex::new(r#"(?i)(?:property|name)\s*=\s*["']og:video(?::secure_url|:url)?["']"#)
+            .expect("prop regex")
+    });
+    let content_re = CONTENT_RE
+        .get_or_init(|| Regex::new(r#"(?i)content\s*=\s*["']([^"']+)["']"#).expect("content regex"));
+
+    for tag in meta_re.find_iter(html) {
+        let tag = tag.as_str();
+        if !prop_re.is_match(tag) {
+            continue;
+        }
+        if let Some(content) = content_re.captures(tag).and_then(|c| c.get(1)) {
+            let value = decode_html_entities(content.as_str().trim());
+            if value.starts_with("https://") || value.starts_with("http://") {
+                return Some(value);
+            }
+        }
+    }
+
+    let tag_re =
+        TAG_RE.get_or_init(|| Regex::new(r"(?is)<(?:video|source)\b[^>]*>").expect("tag regex"));
+    let src_re = SRC_RE
+        .get_or_init(|| Regex::new(r#"(?i)\bsrc\s*=\s*["']([^"']+)["']"#).expect("src regex"));
+    for tag in tag_re.find_iter(html) {
+        if let Some(src) = src_re.captures(tag.as_str()).and_then(|c| c.get(1)) {
+            let value = decode_html_entities(src.as_str().trim());
+            if value.starts_with("https://") || value.starts_with("http://") {
+                return Some(value);
+            }
+        }
+    }
+
+    None
+}
+
+#[async_trait]
+impl SourceResolver for SoraResolver {
+    fn name(&self) -> &'static str {
+        "sora"
+    }
+
+    fn matches(&self, url: &Url) -> bool {
+        host_is(url, &["sora.chatgpt.com", "sora.com"])
+    }
+
+    async fn resolve(&self, url: &Url) -> Result<ResolvedSource, ResolveError> {
+        let id = Self::share_id(url)?;
+        let canonical_url = format!("https://sora.chatgpt.com/p/{id}");
+
+        // Без прямой ссылки на видео задачу не создаём: кредит за нерабочую ссылку не списываем
+        let video_url = match fetch_page(&canonical_url).await {
+            Ok(Some(html)) => extract_sora_video_url(&html).ok_or_else(|| {
+                log::warn!("sora page has no video url id={}", id);
+                ResolveError::Unavailable(
+                    "the Sora page has no video (is the link public?)".to_string(),
+                )
+            })?,
+            // Вместо страницы сразу отдали файл
+            Ok(None) => canonical_url.clone(),
+            Err(e) => {
+                log::warn!("sora page fetch failed id={} error={}", id, e);
+                return Err(ResolveError::Unavailable(format!(
+                    "could not load the Sora page: {e}"
+                )));
+            }
+        };
+
+        Ok(ResolvedSource {
+            kind: SourceKind::Sora,
+            video_url,
+            canonical_url,
+            original_filename: format!("sora-{}.mp4", sanitize_filename(&id)),
+        })
+    }
+}
+
+// --- Google Drive ---
+
+/// `drive.google.com/file/d/{id}/view`, `drive.google.com/open?id=`, `drive.google.com/uc?id=`.
+pub struct GoogleDriveResolver;
+
+impl GoogleDriveResolver {
+    fn file_id(url: &Url) -> Option<String> {
+        let segments: Vec<&str> = url
+            .path_segments()
+            .map(|s| s.filter(|p| !p.is_empty()).collect())
+            .unwrap_or_default();
+        if let ["file", "d", id, ..] = segments.as_slice() {
+            return Some(id.to_string());
+        }
+        url.query_pairs()
+            .find(|(k, _)| k == "id")
+            .map(|(_, v)| v.into_owned())
+    }
+}
+
+/// Для больших файлов Drive вместо видео отдаёт страницу «не удалось проверить на вирусы»
+/// с формой подтверждения. Собираем из неё ссылку на скачивание.
+pub fn extract_drive_confirm_url(html: &str) -> Option<String> {
+    static FORM_RE: OnceLock<Regex> = OnceLock::new();
+    static INPUT_RE: OnceLock<Regex> = OnceLock::new();
+    static NAME_RE: OnceLock<Regex> = OnceLock::new();
+    static VALUE_RE: OnceLock<Regex> = OnceLock::new();
+
+    let form_re = FORM_RE.get_or_init(|| {
+        Regex::new(r#"(?is)<form\b[^>]*\baction\s*=\s*["']([^"']+)["'][^>]*>(.*?)</form>"#)
+            .expect("form regex")
+    });
+    let input_re =
+        INPUT_RE.get_or_init(|| Regex::new(r"(?is)<input\b[^>]*>").expect("input regex"));
+    let name_re = NAME_RE
+        .get_or_init(|| Regex::new(r#"(?i)\bname\s*=\s*["']([^"']+)["']"#).expect("name regex"));
+    let value_re = VALUE_RE
+        .get_or_init(|| Regex::new(r#"(?i)\bvalue\s*=\s*["']([^"']*)["']"#).expect("value regex"));
+
+    for form in form_re.captures_iter(html) {
+        let action = decode_html_entities(form.get(1)?.as_str());
+        if !action.contains("drive.usercontent.google.com") && !action.contains("/uc") {
+            continue;
+        }
+        let mut url = Url::parse(&action).ok()?;
+        {
+            let mut query = url.query_pairs_mut();
+            for input in input_re.find_iter(form.get(2)?.as_str()) {
+                let input = input.as_str();
+                let (Some(name), Some(value)) = (
+                    name_re.captures(input).and_then(|c| c.get(1)),
+                    value_re.captures(input).and_then(|c| c.get(1)),
+                ) else {
+                    continue;
+                };
+                query.append_pair(name.as_str(), &decode_html_entities(value.as_str()));
+            }
+        }
+        return Some(url.to_string());
+    }
+
+    None
+}
+
+#[async_trait]
+impl SourceResolver for GoogleDriveResolver {
+    fn name(&self) -> &'static str {
+        "google_drive"
+    }
+
+    fn matches(&self, url: &Url) -> bool {
+        host_is(url, &["drive.google.com", "docs.google.com"])
+    }
+
+    async fn resolve(&self, url: &Url) -> Result<ResolvedSource, ResolveError> {
+        let id = Self::file_id(url).filter(|id| is_valid_id(id)).ok_or_else(|| {
+            ResolveError::MalformedLink(
+                "expected a Google Drive file link like https://drive.google.com/file/d/<id>/view"
+                    .to_string(),
+            )
+        })?;
+
+        // Небольшие файлы Drive отдаёт сразу (не HTML) — тогда ссылка на скачивание и есть видео.
+        // Другая HTML-страница (вход, нет доступа) или ошибка — видео не получить.
+        let download_url = format!("https://drive.google.com/uc?export=download&id={id}");
+        let video_url = match fetch_page(&download_url).await {
+            Ok(Some(html)) => extract_drive_confirm_url(&html).ok_or_else(|| {
+                log::warn!("drive page has no download form id={}", id);
+                ResolveError::Unavailable(
+                    "the Google Drive file is not shared publicly".to_string(),
+                )
+            })?,
+            Ok(None) => download_url,
+            Err(e) => {
+                log::warn!("drive download failed id={} error={}", id, e);
+                return Err(ResolveError::Unavailable(format!(
+                    "could not load the Google Drive file: {e}"
+                )));
+            }
+        };
+
+        Ok(ResolvedSource {
+            kind: SourceKind::GoogleDrive,
+            video_url,
+            canonical_url: format!("https://drive.google.com/file/d/{id}"),
+            original_filename: format!("gdrive-{}.mp4", sanitize_filename(&id)),
+        })
+    }
+}
+
+// --- Dropbox ---
+
+/// Ссылки `dropbox.com/s/...` и `dropbox.com/scl/fi/...`: переключаем на прямое скачивание (`dl=1`).
+pub struct DropboxResolver;
+
+#[async_trait]
+impl SourceResolver for DropboxResolver {
+    fn name(&self) -> &'static str {
+        "dropbox"
+    }
+
+    fn matches(&self, url: &Url) -> bool {
+        host_is(url, &["dropbox.com"])
+    }
+
+    async fn resolve(&self, url: &Url) -> Result<ResolvedSource, ResolveError> {
+        let path = url.path();
+        if !(path.starts_with("/s/") || path.starts_with("/scl/fi/")) {
+            return Err(ResolveError::MalformedLink(
+                "expected a Dropbox shared file link".to_string(),
+            ));
+        }
+
+        let params: Vec<(String, String)> = url
+            .query_pairs()
+            .filter(|(k, _)| k != "dl" && k != "raw")
+            .map(|(k, v)| (k.into_owned(), v.into_owned()))
+            .collect();
+
+        let mut canonical = url.clone();
+        canonical.set_fragment(None);
+        canonical.set_query(None);
+        if !params.is_empty() {
+            canonical.query_pairs_mut().extend_pairs(&params);
+        }
+
+        let mut video_url = canonical.clone();
+        video_url.query_pairs_mut().append_pair("dl", "1");
+
+        let name = last_segment(url).unwrap_or_default();
+        Ok(ResolvedSource {
+            kind: SourceKind::Dropbox,
+            video_url: video_url.to_string(),
+            canonical_url: canonical.to_string(),
+            original_filename: filename_with_extension(&name, "dropbox-video.mp4"),
+        })
+    }
+}
+
+// --- Прямые ссылки на файл ---
+
+/// Любой хост, если путь заканчивается на расширение видеофайла.
+pub struct DirectVideoResolver;
+
+#[async_trait]
+impl SourceResolver for DirectVideoResolver {
+    fn name(&self) -> &'static str {
+        "direct"
+    }
+
+    fn matches(&self, url: &Url) -> bool {
+        last_segment(url)
+            .map(|name| has_video_extension(&name))
+            .unwrap_or(false)
+    }
+
+    async fn resolve(&self, url: &Url) -> Result<ResolvedSource, ResolveError> {
+        let mut canonical = url.clone();
+        canonical.set_fragment(None);
+        let name = last_segment(url).unwrap_or_default();
+
+        Ok(ResolvedSource {
+            kind: SourceKind::Direct,
+            video_url: canonical.to_string(),
+            canonical_url: canonical.to_string(),
+            original_filename: filename_with_extension(&name, "video.mp4"),
+        })
+    }
+}
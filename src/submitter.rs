@@ -0,0 +1,334 @@
+// src/submitter.rs
+//
+// Создание задач у бэкендов вне HTTP-запроса.
+// `POST /api/upload` только проверяет ссылку, списывает кредиты и вставляет загрузку
+// со статусом `queued`. Воркер забирает такие строки (`FOR UPDATE SKIP LOCKED`, с арендой на
+// случай падения процесса), создаёт задачу через `RemoverRegistry` и переводит загрузку
+// в `processing`. Недоступность бэкендов не роняет загрузку: повторяем с растущей паузой,
+// и только после `SUBMIT_MAX_ATTEMPTS` попыток — `failed` с возвратом кредитов.
+// Подписанные ссылки Sora и Drive живут недолго, поэтому перед отправкой такой источник
+// разбирается заново (`source_url`), а не берётся сохранённая при загрузке ссылка.
+
+use futures_util::future::join_all;
+use sqlx::Row;
+use std::time::Duration;
+
+use crate::AppState;
+use crate::api::webhooks::watermark_callback_url;
+use crate::billing::fail_upload_with_refund;
+use crate::db;
+use crate::providers::{RouteContext, SubmitJob, WatermarkRegion};
+use crate::safe_http::{self, FetchPolicy};
+use crate::source_resolver::SourceKind;
+use crate::worker::{RetryPolicy, Shutdown, run_claim_loop};
+use crate::ws::notify_upload;
+
+/// Попытки записать созданную задачу в загрузку, прежде чем отменить её у бэкенда.
+const RECORD_TASK_ATTEMPTS: u32 = 3;
+const RECORD_TASK_RETRY_DELAY: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone)]
+pub struct SubmitConfig {
+    pub policy: RetryPolicy,
+}
+
+impl Default for SubmitConfig {
+    fn default() -> Self {
+        Self {
+            policy: RetryPolicy {
+                poll_interval: Duration::from_secs(5),
+                batch_size: 10,
+                lease: Duration::from_secs(120),
+                max_attempts: 20,
+                retry_base_delay: Duration::from_secs(10),
+                retry_max_delay: Duration::from_secs(600),
+            },
+        }
+    }
+}
+
+impl SubmitConfig {
+    /// `SUBMIT_*` из `RetryPolicy::from_env`.
+    pub fn from_env() -> Self {
+        Self {
+            policy: RetryPolicy::from_env("SUBMIT", Self::default().policy),
+        }
+    }
+}
+
+/// Фоновый воркер: обрабатывает очередь сразу после `submit_wakeup` или раз в `poll_interval`.
+/// Начатая пачка при остановке доделывается.
+pub async fn run_submit_worker(state: AppState, config: SubmitConfig, mut shutdown: Shutdown) {
+    log::info!(
+        "submit worker started poll_interval={}s max_attempts={}",
+        config.policy.poll_interval.as_secs(),
+        config.policy.max_attempts
+    );
+
+    run_claim_loop(
+        "submit worker",
+        &config.policy,
+        &state.submit_wakeup,
+        &mut shutdown,
+        || process_due_submissions(&state, &config),
+    )
+    .await;
+    log::info!("submit worker stopped");
+}
+
+struct QueuedUpload {
+    id: i32,
+    user_id: i32,
+    video_url: String,
+    callback_token: Option<String>,
+    source_kind: Option<String>,
+    /// Ссылка пользователя в каноничном виде
+    source_url: Option<String>,
+    watermark_region: Option<String>,
+    processing_profile: Option<String>,
+    submit_attempts: i32,
+}
+
+/// Забирает загрузки, которым пора уйти в бэкенд, и пытается создать задачи.
+/// Возвращает, сколько загрузок было забрано.
+pub async fn process_due_submissions(state: &AppState, config: &SubmitConfig) -> Result<usize, String> {
+    let rows = sqlx::query(
+        r#"UPDATE uploads
+           SET next_submit_at = NOW() + make_interval(secs => $2)
+           WHERE id IN (
+               SELECT id
+               FROM uploads
+               WHERE status = 'queued'
+                 AND next_submit_at <= NOW()
+               ORDER BY next_submit_at ASC
+               LIMIT $1
+               FOR UPDATE SKIP LOCKED
+           )
+           RETURNING id, user_id, original_s3_key, callback_token, source_kind, source_url,
+                     watermark_region, processing_profile, submit_attempts"#,
+    )
+    .bind(config.policy.batch_size)
+    .bind(config.policy.lease.as_secs_f64())
+    .fetch_all(&state.pool)
+    .await
+    .map_err(|e| e.to_string())?;
+
+    let uploads: Vec<QueuedUpload> = rows
+        .into_iter()
+        .map(|row| QueuedUpload {
+            id: row.get("id"),
+            user_id: row.get("user_id"),
+            video_url: row.get("original_s3_key"),
+            callback_token: row.get("callback_token"),
+            source_kind: row.get("source_kind"),
+            source_url: row.get("source_url"),
+            watermark_region: row.get("watermark_region"),
+            processing_profile: row.get("processing_profile"),
+            submit_attempts: row.get("submit_attempts"),
+        })
+        .collect();
+    let claimed = uploads.len();
+
+    join_all(uploads.into_iter().map(|upload| submit_upload(state, config, upload))).await;
+    Ok(claimed)
+}
+
+async fn submit_upload(state: &AppState, config: &SubmitConfig, upload: QueuedUpload) {
+    let job = match build_job(state, &upload).await {
+        Ok(job) => job,
+        Err(e) => return record_failure(state, config, &upload, &e).await,
+    };
+
+    let plan = match db::get_user_plan_slug(&state.pool, upload.user_id).await {
+        Ok(plan) => plan,
+        Err(e) => {
+            log::warn!("submit plan lookup error upload_id={} error={}", upload.id, e);
+            None
+        }
+    };
+    let route = RouteContext {
+        plan: plan.as_deref(),
+        source_kind: upload.source_kind.as_deref(),
+    };
+
+    let (remover, task_id) = match state.removers.submit(&route, &job).await {
+        Ok(submitted) => submitted,
+        Err(e) => return record_failure(state, config, &upload, &e.to_string()).await,
+    };
+
+    // Задача уже создана (и оплачена у бэкенда): запись не бросаем после первой ошибки БД
+    let mut attempt = 1;
+    let updated = loop {
+        let result = sqlx::query(
+            r#"UPDATE uploads
+               SET status = 'processing', task_id = $1, provider = $2, model = $3,
+                   submit_attempts = submit_attempts + 1, next_submit_at = NULL, last_error = NULL,
+                   processing_started_at = NOW(), status_checks = 0, next_check_at = NULL,
+                   original_s3_key = $5
+               WHERE id = $4 AND status = 'queued'"#,
+        )
+        .bind(&task_id)
+        .bind(remover.name())
+        .bind(remover.model(&job))
+        .bind(upload.id)
+        .bind(&job.video_url)
+        .execute(&state.pool)
+        .await;
+        match result {
+            Err(e) if attempt < RECORD_TASK_ATTEMPTS => {
+                log::warn!(
+                    "submit db update error upload_id={} task_id={} attempt={}/{} error={}",
+                    upload.id,
+                    task_id,
+                    attempt,
+                    RECORD_TASK_ATTEMPTS,
+                    e
+                );
+                actix_web::rt::time::sleep(RECORD_TASK_RETRY_DELAY * attempt).await;
+                attempt += 1;
+            }
+            result => break result,
+        }
+    };
+
+    match updated {
+        Ok(result) if result.rows_affected() > 0 => {
+            log::info!(
+                "upload started task upload_id={} provider={} task_id={}",
+                upload.id,
+                remover.name(),
+                task_id
+            );
+            notify_upload(&state.pool, &state.ws_hub, upload.id).await;
+        }
+        Ok(_) => {
+            // Пока задача создавалась, загрузку закрыли: задача больше не нужна
+            log::warn!("submit upload no longer queued upload_id={} task_id={}", upload.id, task_id);
+            let _ = remover.cancel(&task_id).await;
+        }
+        Err(e) => {
+            // Задачу так и не записали: аренда истечёт и загрузку отправят заново, а эту
+            // задачу отменяем, чтобы бэкенд не обрабатывал (и не брал денег) дважды
+            log::error!("submit db update error upload_id={} task_id={} error={}", upload.id, task_id, e);
+            if let Err(e) = remover.cancel(&task_id).await {
+                log::warn!("submit cancel error upload_id={} task_id={} error={}", upload.id, task_id, e);
+            }
+        }
+    }
+}
+
+async fn build_job(state: &AppState, upload: &QueuedUpload) -> Result<SubmitJob, String> {
+    let token = upload
+        .callback_token
+        .as_deref()
+        .ok_or_else(|| "upload has no callback token".to_string())?;
+    let profile = match upload.processing_profile.as_deref() {
+        Some(slug) => db::get_processing_profile(&state.pool, slug)
+            .await
+            .map_err(|e| format!("profile lookup error: {e}"))?,
+        None => None,
+    };
+    let region = upload
+        .watermark_region
+        .as_deref()
+        .map(WatermarkRegion::parse)
+        .transpose()?;
+
+    let video_url = fresh_video_url(state, upload).await?;
+
+    Ok(SubmitJob {
+        video_url,
+        callback_url: watermark_callback_url(&state.callback_base_url, upload.id, token),
+        region,
+        model: profile.as_ref().map(|p| p.kie_model.clone()),
+        extra_input: profile.map(|p| p.kie_input).unwrap_or_default(),
+    })
+}
+
+/// Ссылка на видео для бэкенда. Короткоживущие источники разбираются заново: сохранённая
+/// при загрузке подписанная ссылка к этому моменту могла протухнуть.
+async fn fresh_video_url(state: &AppState, upload: &QueuedUpload) -> Result<String, String> {
+    let short_lived = upload
+        .source_kind
+        .as_deref()
+        .and_then(SourceKind::parse)
+        .is_some_and(|kind| kind.is_short_lived());
+    let Some(source_url) = upload.source_url.as_deref().filter(|_| short_lived) else {
+        return Ok(upload.video_url.clone());
+    };
+
+    let source = state
+        .source_resolvers
+        .resolve(source_url)
+        .await
+        .map_err(|e| format!("source resolve error: {e}"))?;
+    safe_http::validate_url(&source.video_url, &FetchPolicy::from_env())
+        .await
+        .map_err(|e| format!("source blocked: {e}"))?;
+    Ok(source.video_url)
+}
+
+/// Неудачная попытка: откладываем загрузку или, если попытки кончились, закрываем с возвратом.
+async fn record_failure(state: &AppState, config: &SubmitConfig, upload: &QueuedUpload, error: &str) {
+    let attempts = upload.submit_attempts + 1;
+
+    if attempts < config.policy.max_attempts {
+        let delay = config.policy.retry_delay(attempts);
+        log::warn!(
+            "submit failed, retrying upload_id={} attempt={} in={}s error={}",
+            upload.id,
+            attempts,
+            delay.as_secs(),
+            error
+        );
+        if let Err(e) = sqlx::query(
+            r#"UPDATE uploads
+               SET submit_attempts = $1, last_error = $2,
+                   next_submit_at = NOW() + make_interval(secs => $3)
+               WHERE id = $4 AND status = 'queued'"#,
+        )
+        .bind(attempts)
+        .bind(error)
+        .bind(delay.as_secs_f64())
+        .bind(upload.id)
+        .execute(&state.pool)
+        .await
+        {
+            log::error!("submit reschedule db error upload_id={} error={}", upload.id, e);
+        }
+        return;
+    }
+
+    log::error!(
+        "submit gave up upload_id={} attempts={} error={}",
+        upload.id,
+        attempts,
+        error
+    );
+    match close_failed(state, upload, attempts, error).await {
+        Ok(true) => state.outbox_wakeup.notify_one(),
+        Ok(false) => {}
+        Err(e) => log::error!("submit close db error upload_id={} error={}", upload.id, e),
+    }
+}
+
+/// `failed`, возврат кредитов и `upload.failed` одной транзакцией; `false`, если загрузка
+/// уже не в очереди. При ошибке загрузка остаётся `queued` и закроется на следующем проходе.
+async fn close_failed(
+    state: &AppState,
+    upload: &QueuedUpload,
+    attempts: i32,
+    error: &str,
+) -> Result<bool, sqlx::Error> {
+    let mut tx = state.pool.begin().await?;
+    if !fail_upload_with_refund(&mut tx, upload.id, "queued", error).await? {
+        return Ok(false);
+    }
+    sqlx::query("UPDATE uploads SET submit_attempts = $1 WHERE id = $2")
+        .bind(attempts)
+        .bind(upload.id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    Ok(true)
+}
@@ -0,0 +1,721 @@
+// src/user_webhooks.rs
+//
+// Исходящие вебхуки пользователей: пользователь регистрирует адрес, секрет выдаём мы,
+// и доменные события его аккаунта (`upload.completed` и т.д.) уходят туда POST-запросом.
+// Событие из outbox превращается в строку `webhook_deliveries` (подписчик `webhooks`),
+// её отправляет отдельный воркер с повторами и растущей паузой. Строки доставок — это же
+// журнал, который видит пользователь, и из них же делается ручная повторная отправка.
+// Подпись: `X-Webhook-Signature: v1=<hex>`, HMAC-SHA256 секрета от `"{timestamp}.{body}"`,
+// где timestamp — `X-Webhook-Timestamp`; так старый запрос нельзя переиграть с новым временем.
+// После `WEBHOOK_DISABLE_AFTER_FAILURES` неудач подряд адрес выключается; его доставки
+// остаются в очереди и уходят, когда пользователь включит адрес снова.
+
+use chrono::{DateTime, Utc};
+use futures_util::future::join_all;
+use reqwest::header::{CONTENT_TYPE, HeaderMap, HeaderValue, USER_AGENT};
+use serde::Serialize;
+use sqlx::{PgPool, Row};
+use std::time::{Duration, Instant};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::AppState;
+use crate::api::lava::sign_hmac_sha256_hex;
+use crate::outbox::{DomainEvent, StoredEvent};
+use crate::safe_http::{self, FetchError, FetchPolicy};
+use crate::worker::{RetryPolicy, Shutdown, run_claim_loop};
+use crate::ws;
+
+/// Сколько байт ответа получателя сохраняем в журнале
+const RESPONSE_BODY_LIMIT: usize = 1024;
+
+const USER_AGENT_VALUE: &str = "sora-watermark-remov-webhooks/1";
+
+/// Registered webhook endpoint. The secret is returned only when the endpoint is created.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct WebhookEndpoint {
+    pub id: i32,
+    pub url: String,
+    /// Event types to deliver, empty for all
+    pub event_types: Vec<String>,
+    /// Disabled endpoints get no new events; queued deliveries wait until it is enabled
+    pub is_active: bool,
+    /// Failed attempts in a row
+    pub consecutive_failures: i32,
+    pub disabled_at: Option<DateTime<Utc>>,
+    pub disabled_reason: Option<String>,
+    pub created_at: Option<DateTime<Utc>>,
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+/// One delivery of an event to an endpoint, with the result of the last attempt.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct WebhookDelivery {
+    pub id: i64,
+    pub endpoint_id: i32,
+    /// Domain event id, same as `id` in the payload
+    pub event_id: Option<i64>,
+    pub event_type: String,
+    /// `pending`, `succeeded` or `failed`
+    pub status: String,
+    pub attempts: i32,
+    /// Next attempt of a pending delivery
+    pub next_attempt_at: DateTime<Utc>,
+    pub response_status: Option<i32>,
+    /// First 1 KiB of the response body
+    pub response_body: Option<String>,
+    pub last_error: Option<String>,
+    pub duration_ms: Option<i32>,
+    /// Delivery this one repeats (manual redelivery)
+    pub redelivery_of: Option<i64>,
+    pub delivered_at: Option<DateTime<Utc>>,
+    pub created_at: Option<DateTime<Utc>>,
+    /// JSON body that was sent
+    #[schema(value_type = Object)]
+    pub payload: serde_json::Value,
+}
+
+#[derive(Debug, Clone)]
+pub struct WebhookConfig {
+    /// `batch_size` — сколько доставок отправляется одновременно; `lease` должна быть больше `timeout`
+    pub policy: RetryPolicy,
+    pub timeout: Duration,
+    /// После стольких неудачных попыток подряд адрес выключается
+    pub disable_after_failures: i32,
+    /// Сколько адресов может зарегистрировать пользователь
+    pub max_endpoints: i64,
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        Self {
+            policy: RetryPolicy {
+                poll_interval: Duration::from_secs(5),
+                batch_size: 20,
+                lease: Duration::from_secs(60),
+                max_attempts: 10,
+                retry_base_delay: Duration::from_secs(60),
+                retry_max_delay: Duration::from_secs(6 * 3600),
+            },
+            timeout: Duration::from_secs(10),
+            disable_after_failures: 20,
+            max_endpoints: 10,
+        }
+    }
+}
+
+impl WebhookConfig {
+    /// `WEBHOOK_*` из `RetryPolicy::from_env`, а также `WEBHOOK_TIMEOUT_SECS`,
+    /// `WEBHOOK_DISABLE_AFTER_FAILURES`, `WEBHOOK_MAX_ENDPOINTS`.
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let env_parse = |key: &str| {
+            std::env::var(key)
+                .ok()
+                .and_then(|v| v.trim().parse::<u64>().ok())
+        };
+        let secs = |key: &str, default: Duration| {
+            env_parse(key)
+                .map(|v| Duration::from_secs(v.max(1)))
+                .unwrap_or(default)
+        };
+        let count =
+            |key: &str, default: i64| env_parse(key).map(|v| v.max(1) as i64).unwrap_or(default);
+        Self {
+            policy: RetryPolicy::from_env("WEBHOOK", defaults.policy),
+            timeout: secs("WEBHOOK_TIMEOUT_SECS", defaults.timeout),
+            disable_after_failures: count(
+                "WEBHOOK_DISABLE_AFTER_FAILURES",
+                defaults.disable_after_failures as i64,
+            ) as i32,
+            max_endpoints: count("WEBHOOK_MAX_ENDPOINTS", defaults.max_endpoints),
+        }
+    }
+}
+
+/// Случайный секрет адреса (256 бит).
+pub fn new_secret() -> String {
+    format!(
+        "whsec_{}{}",
+        Uuid::new_v4().simple(),
+        Uuid::new_v4().simple()
+    )
+}
+
+/// Значение `X-Webhook-Signature` для тела `body`, отправленного в `timestamp` (unix, секунды).
+pub fn sign(secret: &str, timestamp: i64, body: &str) -> String {
+    format!(
+        "v1={}",
+        sign_hmac_sha256_hex(secret, &format!("{timestamp}.{body}"))
+    )
+}
+
+/// Проверяет список типов событий; пустой список — все события. Дубли убираются.
+pub fn validate_event_types(event_types: &[String]) -> Result<Vec<String>, String> {
+    let mut result: Vec<String> = Vec::with_capacity(event_types.len());
+    for event_type in event_types {
+        let event_type = event_type.trim();
+        if !DomainEvent::NAMES.contains(&event_type) {
+            return Err(format!(
+                "unknown event type `{event_type}`, expected one of: {}",
+                DomainEvent::NAMES.join(", ")
+            ));
+        }
+        if !result.iter().any(|t| t == event_type) {
+            result.push(event_type.to_string());
+        }
+    }
+    Ok(result)
+}
+
+/// Адрес должен быть публичным http(s), как и остальные исходящие запросы (`OUTBOUND_*`).
+pub async fn validate_endpoint_url(url: &str) -> Result<String, String> {
+    let url = url.trim();
+    safe_http::validate_url(url, &FetchPolicy::from_env())
+        .await
+        .map_err(|e| format!("invalid webhook url: {e}"))?;
+    Ok(url.to_string())
+}
+
+const ENDPOINT_COLUMNS: &str = r#"id, url, event_types, is_active, consecutive_failures,
+                  disabled_at, disabled_reason, created_at, updated_at"#;
+
+fn endpoint_from_row(r: &sqlx::postgres::PgRow) -> WebhookEndpoint {
+    WebhookEndpoint {
+        id: r.get("id"),
+        url: r.get("url"),
+        event_types: r.get("event_types"),
+        is_active: r.get("is_active"),
+        consecutive_failures: r.get("consecutive_failures"),
+        disabled_at: r.get("disabled_at"),
+        disabled_reason: r.get("disabled_reason"),
+        created_at: r.get("created_at"),
+        updated_at: r.get("updated_at"),
+    }
+}
+
+const DELIVERY_COLUMNS: &str = r#"id, endpoint_id, event_id, event_type, status, attempts,
+                  next_attempt_at, response_status, response_body, last_error, duration_ms,
+                  redelivery_of, delivered_at, created_at, payload::text AS payload"#;
+
+fn delivery_from_row(r: &sqlx::postgres::PgRow) -> WebhookDelivery {
+    let payload: String = r.get("payload");
+    WebhookDelivery {
+        id: r.get("id"),
+        endpoint_id: r.get("endpoint_id"),
+        event_id: r.get("event_id"),
+        event_type: r.get("event_type"),
+        status: r.get("status"),
+        attempts: r.get("attempts"),
+        next_attempt_at: r.get("next_attempt_at"),
+        response_status: r.get("response_status"),
+        response_body: r.get("response_body"),
+        last_error: r.get("last_error"),
+        duration_ms: r.get("duration_ms"),
+        redelivery_of: r.get("redelivery_of"),
+        delivered_at: r.get("delivered_at"),
+        created_at: r.get("created_at"),
+        payload: serde_json::from_str(&payload).unwrap_or_default(),
+    }
+}
+
+pub async fn list_endpoints(
+    pool: &PgPool,
+    user_id: i32,
+) -> Result<Vec<WebhookEndpoint>, sqlx::Error> {
+    let rows = sqlx::query(&format!(
+        r#"SELECT {ENDPOINT_COLUMNS}
+           FROM webhook_endpoints
+           WHERE user_id = $1
+           ORDER BY id ASC"#
+    ))
+    .bind(user_id)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows.iter().map(endpoint_from_row).collect())
+}
+
+pub async fn get_endpoint(
+    pool: &PgPool,
+    user_id: i32,
+    endpoint_id: i32,
+) -> Result<Option<WebhookEndpoint>, sqlx::Error> {
+    let row = sqlx::query(&format!(
+        r#"SELECT {ENDPOINT_COLUMNS}
+           FROM webhook_endpoints
+           WHERE id = $1 AND user_id = $2"#
+    ))
+    .bind(endpoint_id)
+    .bind(user_id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row.as_ref().map(endpoint_from_row))
+}
+
+pub async fn count_endpoints(pool: &PgPool, user_id: i32) -> Result<i64, sqlx::Error> {
+    let row = sqlx::query("SELECT COUNT(*) AS count FROM webhook_endpoints WHERE user_id = $1")
+        .bind(user_id)
+        .fetch_one(pool)
+        .await?;
+    Ok(row.get("count"))
+}
+
+/// Регистрирует адрес; возвращает его вместе с новым секретом.
+pub async fn create_endpoint(
+    pool: &PgPool,
+    user_id: i32,
+    url: &str,
+    event_types: &[String],
+) -> Result<(WebhookEndpoint, String), sqlx::Error> {
+    let secret = new_secret();
+    let row = sqlx::query(&format!(
+        r#"INSERT INTO webhook_endpoints (user_id, url, secret, event_types)
+           VALUES ($1, $2, $3, $4)
+           RETURNING {ENDPOINT_COLUMNS}"#
+    ))
+    .bind(user_id)
+    .bind(url)
+    .bind(&secret)
+    .bind(event_types)
+    .fetch_one(pool)
+    .await?;
+
+    Ok((endpoint_from_row(&row), secret))
+}
+
+/// Изменения адреса; `None` — оставить как есть.
+#[derive(Debug, Default)]
+pub struct EndpointUpdate {
+    pub url: Option<String>,
+    pub event_types: Option<Vec<String>>,
+    pub is_active: Option<bool>,
+}
+
+/// Включение сбрасывает счётчик неудач, выключение пользователем помечается причиной.
+pub async fn update_endpoint(
+    pool: &PgPool,
+    user_id: i32,
+    endpoint_id: i32,
+    update: &EndpointUpdate,
+) -> Result<Option<WebhookEndpoint>, sqlx::Error> {
+    let row = sqlx::query(&format!(
+        r#"UPDATE webhook_endpoints
+           SET url = COALESCE($3, url),
+               event_types = COALESCE($4, event_types),
+               is_active = COALESCE($5, is_active),
+               consecutive_failures = CASE WHEN $5 THEN 0 ELSE consecutive_failures END,
+               disabled_at = CASE
+                   WHEN $5 THEN NULL
+                   WHEN NOT $5 THEN COALESCE(disabled_at, NOW())
+                   ELSE disabled_at
+               END,
+               disabled_reason = CASE
+                   WHEN $5 THEN NULL
+                   WHEN NOT $5 THEN COALESCE(disabled_reason, 'disabled by user')
+                   ELSE disabled_reason
+               END,
+               updated_at = NOW()
+           WHERE id = $1 AND user_id = $2
+           RETURNING {ENDPOINT_COLUMNS}"#
+    ))
+    .bind(endpoint_id)
+    .bind(user_id)
+    .bind(update.url.as_deref())
+    .bind(update.event_types.as_deref())
+    .bind(update.is_active)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row.as_ref().map(endpoint_from_row))
+}
+
+/// Удаляет адрес вместе с журналом доставок; `false`, если адреса нет или он чужой.
+pub async fn delete_endpoint(
+    pool: &PgPool,
+    user_id: i32,
+    endpoint_id: i32,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM webhook_endpoints WHERE id = $1 AND user_id = $2")
+        .bind(endpoint_id)
+        .bind(user_id)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Журнал доставок адреса, новые первыми; `before` — курсор по `id` для следующей страницы.
+pub async fn list_deliveries(
+    pool: &PgPool,
+    endpoint_id: i32,
+    limit: i64,
+    before: Option<i64>,
+) -> Result<Vec<WebhookDelivery>, sqlx::Error> {
+    let rows = sqlx::query(&format!(
+        r#"SELECT {DELIVERY_COLUMNS}
+           FROM webhook_deliveries
+           WHERE endpoint_id = $1 AND ($3::bigint IS NULL OR id < $3)
+           ORDER BY id DESC
+           LIMIT $2"#
+    ))
+    .bind(endpoint_id)
+    .bind(limit)
+    .bind(before)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows.iter().map(delivery_from_row).collect())
+}
+
+/// Ставит в очередь копию доставки с тем же телом; `None`, если доставки у адреса нет.
+pub async fn redeliver(
+    pool: &PgPool,
+    endpoint_id: i32,
+    delivery_id: i64,
+) -> Result<Option<WebhookDelivery>, sqlx::Error> {
+    let row = sqlx::query(&format!(
+        r#"INSERT INTO webhook_deliveries (endpoint_id, event_id, event_type, payload, redelivery_of)
+           SELECT endpoint_id, event_id, event_type, payload, id
+           FROM webhook_deliveries
+           WHERE id = $1 AND endpoint_id = $2
+           RETURNING {DELIVERY_COLUMNS}"#
+    ))
+    .bind(delivery_id)
+    .bind(endpoint_id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row.as_ref().map(delivery_from_row))
+}
+
+/// Тело вебхука: `{"id", "type", "created_at", "data"}`. В `data` — поля события,
+/// для событий загрузки ещё и сама загрузка (`upload`, с `cleaned_url`).
+pub async fn event_payload(
+    pool: &PgPool,
+    event: &StoredEvent,
+) -> Result<serde_json::Value, String> {
+    let mut data = serde_json::to_value(&event.event).map_err(|e| e.to_string())?;
+    if let Some(data) = data.as_object_mut() {
+        data.remove("type");
+        if let DomainEvent::UploadCompleted { upload_id, .. }
+        | DomainEvent::UploadFailed { upload_id, .. } = &event.event
+            && let Some(upload) = ws::load_upload(pool, *upload_id)
+                .await
+                .map_err(|e| e.to_string())?
+        {
+            let upload = serde_json::to_value(upload).map_err(|e| e.to_string())?;
+            data.insert("upload".to_string(), upload);
+        }
+    }
+    Ok(serde_json::json!({
+        "id": event.id,
+        "type": event.event.name(),
+        "created_at": event.created_at,
+        "data": data,
+    }))
+}
+
+/// Ставит событие в очередь каждому включённому адресу пользователя, который на него подписан.
+/// Повторная передача того же события ничего не добавит. Возвращает число новых доставок.
+pub async fn enqueue_event(pool: &PgPool, event: &StoredEvent) -> Result<u64, String> {
+    let event_type = event.event.name();
+    let endpoint_ids: Vec<i32> = sqlx::query(
+        r#"SELECT id
+           FROM webhook_endpoints
+           WHERE user_id = $1
+             AND is_active
+             AND (cardinality(event_types) = 0 OR $2 = ANY(event_types))"#,
+    )
+    .bind(event.event.user_id())
+    .bind(event_type)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| e.to_string())?
+    .into_iter()
+    .map(|row| row.get("id"))
+    .collect();
+    if endpoint_ids.is_empty() {
+        return Ok(0);
+    }
+
+    let payload = event_payload(pool, event).await?;
+    let result = sqlx::query(
+        r#"INSERT INTO webhook_deliveries (endpoint_id, event_id, event_type, payload)
+           SELECT endpoint_id, $2, $3, $4::jsonb
+           FROM unnest($1::int[]) AS endpoint_id
+           ON CONFLICT DO NOTHING"#,
+    )
+    .bind(&endpoint_ids)
+    .bind(event.id)
+    .bind(event_type)
+    .bind(payload.to_string())
+    .execute(pool)
+    .await
+    .map_err(|e| e.to_string())?;
+    Ok(result.rows_affected())
+}
+
+/// Фоновый воркер: отправляет доставки сразу после `webhook_wakeup` или раз в `poll_interval`.
+pub async fn run_webhook_sender(state: AppState, config: WebhookConfig, mut shutdown: Shutdown) {
+    log::info!(
+        "webhook sender started poll_interval={}s max_attempts={}",
+        config.policy.poll_interval.as_secs(),
+        config.policy.max_attempts
+    );
+
+    run_claim_loop(
+        "webhook sender",
+        &config.policy,
+        &state.webhook_wakeup,
+        &mut shutdown,
+        || send_pending(&state, &config),
+    )
+    .await;
+    log::info!("webhook sender stopped");
+}
+
+struct ClaimedDelivery {
+    id: i64,
+    endpoint_id: i32,
+    event_type: String,
+    payload: String,
+    attempts: i32,
+    url: String,
+    secret: String,
+}
+
+/// Результат одной попытки.
+struct Attempt {
+    response_status: Option<u16>,
+    response_body: Option<String>,
+    error: Option<String>,
+    duration_ms: i32,
+}
+
+impl Attempt {
+    fn succeeded(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// Забирает пачку доставок, которым пора, и отправляет их параллельно.
+/// Возвращает, сколько доставок было забрано.
+pub async fn send_pending(state: &AppState, config: &WebhookConfig) -> Result<usize, String> {
+    let rows = sqlx::query(
+        r#"UPDATE webhook_deliveries d
+           SET next_attempt_at = NOW() + make_interval(secs => $2), attempts = d.attempts + 1
+           FROM webhook_endpoints e
+           WHERE e.id = d.endpoint_id
+             AND d.id IN (
+                 SELECT wd.id
+                 FROM webhook_deliveries wd
+                 JOIN webhook_endpoints we ON we.id = wd.endpoint_id
+                 WHERE wd.status = 'pending'
+                   AND wd.next_attempt_at <= NOW()
+                   AND we.is_active
+                 ORDER BY wd.next_attempt_at ASC, wd.id ASC
+                 LIMIT $1
+                 FOR UPDATE OF wd SKIP LOCKED
+             )
+           RETURNING d.id, d.endpoint_id, d.event_type, d.payload::text AS payload, d.attempts,
+                     e.url, e.secret"#,
+    )
+    .bind(config.policy.batch_size)
+    .bind(config.policy.lease.as_secs_f64())
+    .fetch_all(&state.pool)
+    .await
+    .map_err(|e| format!("webhook claim error: {e}"))?;
+
+    let deliveries: Vec<ClaimedDelivery> = rows
+        .into_iter()
+        .map(|row| ClaimedDelivery {
+            id: row.get("id"),
+            endpoint_id: row.get("endpoint_id"),
+            event_type: row.get("event_type"),
+            payload: row.get("payload"),
+            attempts: row.get("attempts"),
+            url: row.get("url"),
+            secret: row.get("secret"),
+        })
+        .collect();
+    let claimed = deliveries.len();
+
+    join_all(
+        deliveries
+            .iter()
+            .map(|delivery| send_delivery(&state.pool, config, delivery)),
+    )
+    .await;
+
+    Ok(claimed)
+}
+
+async fn send_delivery(pool: &PgPool, config: &WebhookConfig, delivery: &ClaimedDelivery) {
+    let attempt = attempt_delivery(config, delivery).await;
+    if let Some(error) = &attempt.error {
+        log::warn!(
+            "webhook delivery failed id={} endpoint_id={} attempt={} error={}",
+            delivery.id,
+            delivery.endpoint_id,
+            delivery.attempts,
+            error
+        );
+    }
+    if let Err(e) = finish_delivery(pool, config, delivery, &attempt).await {
+        log::error!("webhook finish error id={} error={}", delivery.id, e);
+    }
+}
+
+async fn attempt_delivery(config: &WebhookConfig, delivery: &ClaimedDelivery) -> Attempt {
+    let timestamp = Utc::now().timestamp();
+    let signature = sign(&delivery.secret, timestamp, &delivery.payload);
+    let mut headers = HeaderMap::new();
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    headers.insert(USER_AGENT, HeaderValue::from_static(USER_AGENT_VALUE));
+    headers.insert("x-webhook-id", HeaderValue::from(delivery.id));
+    headers.insert("x-webhook-timestamp", HeaderValue::from(timestamp));
+    if let Ok(event_type) = HeaderValue::from_str(&delivery.event_type) {
+        headers.insert("x-webhook-event", event_type);
+    }
+    if let Ok(signature) = HeaderValue::from_str(&signature) {
+        headers.insert("x-webhook-signature", signature);
+    }
+
+    let policy = FetchPolicy::from_env().with_timeout(config.timeout);
+    let started = Instant::now();
+    let result = safe_http::post(&delivery.url, headers, delivery.payload.clone(), &policy).await;
+    let mut attempt = Attempt {
+        response_status: None,
+        response_body: None,
+        error: None,
+        duration_ms: 0,
+    };
+    match result {
+        Ok(mut resp) => {
+            let status = resp.status();
+            let mut body = Vec::new();
+            while body.len() < RESPONSE_BODY_LIMIT {
+                match resp.chunk().await {
+                    Ok(Some(chunk)) => body.extend_from_slice(&chunk),
+                    _ => break,
+                }
+            }
+            body.truncate(RESPONSE_BODY_LIMIT);
+            attempt.response_status = Some(status.as_u16());
+            attempt.response_body = Some(String::from_utf8_lossy(&body).into_owned());
+            if !status.is_success() {
+                attempt.error = Some(FetchError::Status(status.as_u16()).to_string());
+            }
+        }
+        Err(e) => attempt.error = Some(e.to_string()),
+    }
+    attempt.duration_ms = started.elapsed().as_millis().min(i32::MAX as u128) as i32;
+    attempt
+}
+
+async fn finish_delivery(
+    pool: &PgPool,
+    config: &WebhookConfig,
+    delivery: &ClaimedDelivery,
+    attempt: &Attempt,
+) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let response_status = attempt.response_status.map(i32::from);
+
+    if attempt.succeeded() {
+        sqlx::query(
+            r#"UPDATE webhook_deliveries
+               SET status = 'succeeded', delivered_at = NOW(), response_status = $2,
+                   response_body = $3, last_error = NULL, duration_ms = $4
+               WHERE id = $1"#,
+        )
+        .bind(delivery.id)
+        .bind(response_status)
+        .bind(&attempt.response_body)
+        .bind(attempt.duration_ms)
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query(
+            r#"UPDATE webhook_endpoints
+               SET consecutive_failures = 0
+               WHERE id = $1 AND consecutive_failures > 0"#,
+        )
+        .bind(delivery.endpoint_id)
+        .execute(&mut *tx)
+        .await?;
+        return tx.commit().await;
+    }
+
+    let gave_up = delivery.attempts >= config.policy.max_attempts;
+    sqlx::query(
+        r#"UPDATE webhook_deliveries
+           SET status = CASE WHEN $5 THEN 'failed' ELSE 'pending' END,
+               next_attempt_at = NOW() + make_interval(secs => $6),
+               response_status = $2, response_body = $3, last_error = $4, duration_ms = $7
+           WHERE id = $1"#,
+    )
+    .bind(delivery.id)
+    .bind(response_status)
+    .bind(&attempt.response_body)
+    .bind(&attempt.error)
+    .bind(gave_up)
+    .bind(config.policy.retry_delay(delivery.attempts).as_secs_f64())
+    .bind(attempt.duration_ms)
+    .execute(&mut *tx)
+    .await?;
+
+    let reason = format!(
+        "disabled after {} failed delivery attempts in a row",
+        config.disable_after_failures
+    );
+    let row = sqlx::query(
+        r#"UPDATE webhook_endpoints
+           SET consecutive_failures = consecutive_failures + 1,
+               is_active = is_active AND consecutive_failures + 1 < $2,
+               disabled_at = CASE
+                   WHEN is_active AND consecutive_failures + 1 >= $2 THEN NOW()
+                   ELSE disabled_at
+               END,
+               disabled_reason = CASE
+                   WHEN is_active AND consecutive_failures + 1 >= $2 THEN $3
+                   ELSE disabled_reason
+               END,
+               updated_at = NOW()
+           WHERE id = $1
+           RETURNING is_active, consecutive_failures"#,
+    )
+    .bind(delivery.endpoint_id)
+    .bind(config.disable_after_failures)
+    .bind(&reason)
+    .fetch_optional(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    if let Some(row) = row {
+        let is_active: bool = row.get("is_active");
+        let failures: i32 = row.get("consecutive_failures");
+        if !is_active && failures == config.disable_after_failures {
+            log::warn!(
+                "webhook endpoint disabled endpoint_id={} failures={}",
+                delivery.endpoint_id,
+                failures
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Удаляет завершённые доставки старше `retention`.
+pub async fn prune_webhook_deliveries(pool: &PgPool, retention: Duration) -> Result<u64, String> {
+    let result = sqlx::query(
+        r#"DELETE FROM webhook_deliveries
+           WHERE status <> 'pending'
+             AND created_at < NOW() - make_interval(secs => $1)"#,
+    )
+    .bind(retention.as_secs_f64())
+    .execute(pool)
+    .await
+    .map_err(|e| e.to_string())?;
+    Ok(result.rows_affected())
+}
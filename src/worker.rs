@@ -0,0 +1,409 @@
+// src/worker.rs
+//
+// Фоновые воркеры: отправка загрузок бэкендам, очередь проверки статусов, outbox доменных
+// событий, вебхуки пользователей, обслуживание.
+// Запускаются отдельным бинарником (`src/bin/worker.rs`), чтобы масштабировать их отдельно
+// от API, или внутри API-процесса, если не задан `DISABLE_WORKERS=true`.
+// Остановка мягкая: воркер доделывает текущую пачку и выходит, ожидание прерывается сразу.
+
+use actix_web::rt;
+use futures_util::future::{Either, join_all, select};
+use sqlx::PgPool;
+use std::future::Future;
+use std::pin::pin;
+use std::time::Duration;
+use tokio::sync::{Notify, watch};
+
+use crate::AppState;
+use crate::emails::{self, sender::EmailConfig};
+use crate::outbox::{self, OutboxConfig, Subscribers};
+use crate::queue::{self, StatusQueueConfig};
+use crate::submitter::{self, SubmitConfig};
+use crate::user_webhooks::{self, WebhookConfig};
+use crate::ws;
+
+/// Сигнал остановки для воркеров; клонируется в каждый.
+#[derive(Clone)]
+pub struct Shutdown {
+    rx: watch::Receiver<bool>,
+}
+
+/// Владелец сигнала; если его уронить, воркеры тоже остановятся.
+pub struct ShutdownTrigger {
+    tx: watch::Sender<bool>,
+}
+
+pub fn shutdown_channel() -> (ShutdownTrigger, Shutdown) {
+    let (tx, rx) = watch::channel(false);
+    (ShutdownTrigger { tx }, Shutdown { rx })
+}
+
+impl ShutdownTrigger {
+    pub fn trigger(&self) {
+        self.tx.send_replace(true);
+    }
+}
+
+impl Shutdown {
+    pub fn is_triggered(&self) -> bool {
+        *self.rx.borrow()
+    }
+
+    pub async fn triggered(&mut self) {
+        loop {
+            if *self.rx.borrow_and_update() {
+                return;
+            }
+            if self.rx.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+
+    /// Ждёт `fut`; `None`, если раньше пришёл сигнал остановки.
+    pub async fn wait_for<F: Future>(&mut self, fut: F) -> Option<F::Output> {
+        let fut = pin!(fut);
+        let stop = pin!(self.triggered());
+        match select(fut, stop).await {
+            Either::Left((output, _)) => Some(output),
+            Either::Right(_) => None,
+        }
+    }
+
+    /// Пауза; `true`, если её прервал сигнал остановки.
+    pub async fn sleep(&mut self, duration: Duration) -> bool {
+        self.wait_for(rt::time::sleep(duration)).await.is_none()
+    }
+}
+
+/// Очередь в Postgres, которую воркеры забирают пачками с арендой (`FOR UPDATE SKIP LOCKED`)
+/// и повторяют с растущей паузой: загрузки, outbox, вебхуки, письма.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Как часто проверять очередь, если никто не разбудил
+    pub poll_interval: Duration,
+    /// Сколько записей забирается за раз
+    pub batch_size: i64,
+    /// На сколько воркер забирает запись; после истечения её возьмёт другой
+    pub lease: Duration,
+    pub max_attempts: i32,
+    pub retry_base_delay: Duration,
+    pub retry_max_delay: Duration,
+}
+
+impl RetryPolicy {
+    /// `<prefix>_POLL_INTERVAL_SECS`, `<prefix>_BATCH_SIZE`, `<prefix>_LEASE_SECS`,
+    /// `<prefix>_MAX_ATTEMPTS`, `<prefix>_RETRY_BASE_SECS`, `<prefix>_RETRY_MAX_SECS`;
+    /// не заданные берутся из `defaults`.
+    pub fn from_env(prefix: &str, defaults: Self) -> Self {
+        let env_parse = |name: &str| {
+            std::env::var(format!("{prefix}_{name}"))
+                .ok()
+                .and_then(|v| v.trim().parse::<u64>().ok())
+                .map(|v| v.max(1))
+        };
+        let secs = |name: &str, default: Duration| {
+            env_parse(name).map(Duration::from_secs).unwrap_or(default)
+        };
+        Self {
+            poll_interval: secs("POLL_INTERVAL_SECS", defaults.poll_interval),
+            batch_size: env_parse("BATCH_SIZE")
+                .map(|v| v as i64)
+                .unwrap_or(defaults.batch_size),
+            lease: secs("LEASE_SECS", defaults.lease),
+            max_attempts: env_parse("MAX_ATTEMPTS")
+                .map(|v| v.min(i32::MAX as u64) as i32)
+                .unwrap_or(defaults.max_attempts),
+            retry_base_delay: secs("RETRY_BASE_SECS", defaults.retry_base_delay),
+            retry_max_delay: secs("RETRY_MAX_SECS", defaults.retry_max_delay),
+        }
+    }
+
+    /// Пауза после `attempts`-й неудачной попытки (с единицы): удваивается до потолка.
+    pub fn retry_delay(&self, attempts: i32) -> Duration {
+        let exp = attempts.saturating_sub(1).clamp(0, 20) as u32;
+        self.retry_base_delay
+            .saturating_mul(2u32.saturating_pow(exp))
+            .min(self.retry_max_delay)
+    }
+}
+
+/// Цикл воркера очереди: `claim` забирает и обрабатывает пачку (возвращает её размер); после
+/// неполной пачки воркер ждёт `wakeup` или `poll_interval`. Начатая пачка при остановке
+/// доделывается.
+pub async fn run_claim_loop<F, Fut>(
+    name: &str,
+    policy: &RetryPolicy,
+    wakeup: &Notify,
+    shutdown: &mut Shutdown,
+    mut claim: F,
+) where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<usize, String>>,
+{
+    while !shutdown.is_triggered() {
+        match claim().await {
+            // Забрали полную пачку — возможно, в очереди есть ещё
+            Ok(n) if n as i64 >= policy.batch_size => continue,
+            Ok(_) => {}
+            Err(e) => log::error!("{name} error: {e}"),
+        }
+        let wakeup = rt::time::timeout(policy.poll_interval, wakeup.notified());
+        if shutdown.wait_for(wakeup).await.is_none() {
+            break;
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct WorkerConfig {
+    pub submit: bool,
+    pub status_queue: bool,
+    pub outbox: bool,
+    pub webhooks: bool,
+    pub emails: bool,
+    pub maintenance: bool,
+    pub maintenance_interval: Duration,
+    /// Сколько хранить мёртвые письма в `job_queue`
+    pub dead_letter_retention: Duration,
+    /// Сколько хранить журнал WebSocket-событий для переподключений
+    pub ws_event_retention: Duration,
+    /// Сколько хранить обработанные доменные события
+    pub outbox_retention: Duration,
+    /// Сколько хранить журнал доставок вебхуков
+    pub webhook_delivery_retention: Duration,
+    /// Сколько хранить отправленные и неотправленные письма в `email_outbox`
+    pub email_outbox_retention: Duration,
+    /// Сколько ждать воркеры при остановке
+    pub shutdown_timeout: Duration,
+}
+
+impl Default for WorkerConfig {
+    fn default() -> Self {
+        Self {
+            submit: true,
+            status_queue: true,
+            outbox: true,
+            webhooks: true,
+            emails: true,
+            maintenance: true,
+            maintenance_interval: Duration::from_secs(300),
+            dead_letter_retention: Duration::from_secs(14 * 24 * 3600),
+            ws_event_retention: Duration::from_secs(24 * 3600),
+            outbox_retention: Duration::from_secs(7 * 24 * 3600),
+            webhook_delivery_retention: Duration::from_secs(30 * 24 * 3600),
+            email_outbox_retention: Duration::from_secs(30 * 24 * 3600),
+            shutdown_timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+impl WorkerConfig {
+    /// `WORKER_SUBMIT`, `WORKER_STATUS_QUEUE`, `WORKER_OUTBOX`, `WORKER_WEBHOOKS`,
+    /// `WORKER_EMAILS`, `WORKER_MAINTENANCE` (`false` выключает), `MAINTENANCE_INTERVAL_SECS`,
+    /// `DEAD_LETTER_RETENTION_DAYS`, `WS_EVENT_RETENTION_HOURS`, `OUTBOX_RETENTION_DAYS`,
+    /// `WEBHOOK_DELIVERY_RETENTION_DAYS`, `EMAIL_OUTBOX_RETENTION_DAYS`,
+    /// `WORKER_SHUTDOWN_TIMEOUT_SECS`.
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let enabled = |key: &str| std::env::var(key).unwrap_or_default().trim() != "false";
+        let env_parse = |key: &str| std::env::var(key).ok().and_then(|v| v.trim().parse::<u64>().ok());
+        Self {
+            submit: enabled("WORKER_SUBMIT"),
+            status_queue: enabled("WORKER_STATUS_QUEUE"),
+            outbox: enabled("WORKER_OUTBOX"),
+            webhooks: enabled("WORKER_WEBHOOKS"),
+            emails: enabled("WORKER_EMAILS"),
+            maintenance: enabled("WORKER_MAINTENANCE"),
+            maintenance_interval: env_parse("MAINTENANCE_INTERVAL_SECS")
+                .map(|v| Duration::from_secs(v.max(1)))
+                .unwrap_or(defaults.maintenance_interval),
+            dead_letter_retention: env_parse("DEAD_LETTER_RETENTION_DAYS")
+                .map(|v| Duration::from_secs(v * 24 * 3600))
+                .unwrap_or(defaults.dead_letter_retention),
+            ws_event_retention: env_parse("WS_EVENT_RETENTION_HOURS")
+                .map(|v| Duration::from_secs(v * 3600))
+                .unwrap_or(defaults.ws_event_retention),
+            outbox_retention: env_parse("OUTBOX_RETENTION_DAYS")
+                .map(|v| Duration::from_secs(v * 24 * 3600))
+                .unwrap_or(defaults.outbox_retention),
+            webhook_delivery_retention: env_parse("WEBHOOK_DELIVERY_RETENTION_DAYS")
+                .map(|v| Duration::from_secs(v * 24 * 3600))
+                .unwrap_or(defaults.webhook_delivery_retention),
+            email_outbox_retention: env_parse("EMAIL_OUTBOX_RETENTION_DAYS")
+                .map(|v| Duration::from_secs(v * 24 * 3600))
+                .unwrap_or(defaults.email_outbox_retention),
+            shutdown_timeout: env_parse("WORKER_SHUTDOWN_TIMEOUT_SECS")
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.shutdown_timeout),
+        }
+    }
+}
+
+/// Запускает включённые воркеры; их нужно дождаться через `join_workers`.
+pub fn spawn_workers(
+    state: &AppState,
+    config: &WorkerConfig,
+    shutdown: &Shutdown,
+) -> Vec<rt::task::JoinHandle<()>> {
+    let mut handles = Vec::new();
+
+    if config.submit {
+        handles.push(rt::spawn(submitter::run_submit_worker(
+            state.clone(),
+            SubmitConfig::from_env(),
+            shutdown.clone(),
+        )));
+    }
+
+    let status_config = StatusQueueConfig::from_env();
+    if config.status_queue {
+        let job_queue = queue::from_env(&state.pool);
+        handles.push(rt::spawn(queue::run_status_scheduler(
+            state.clone(),
+            job_queue.clone(),
+            status_config.clone(),
+            shutdown.clone(),
+        )));
+        handles.push(rt::spawn(queue::run_status_consumer(
+            state.clone(),
+            job_queue,
+            status_config.clone(),
+            shutdown.clone(),
+        )));
+    }
+
+    if config.outbox {
+        handles.push(rt::spawn(outbox::run_outbox_dispatcher(
+            state.clone(),
+            Subscribers::from_env(),
+            OutboxConfig::from_env(),
+            shutdown.clone(),
+        )));
+    }
+
+    if config.webhooks {
+        handles.push(rt::spawn(user_webhooks::run_webhook_sender(
+            state.clone(),
+            WebhookConfig::from_env(),
+            shutdown.clone(),
+        )));
+    }
+
+    if config.emails {
+        handles.push(rt::spawn(emails::sender::run_email_sender(
+            state.clone(),
+            emails::mailer::from_env(),
+            EmailConfig::from_env(),
+            shutdown.clone(),
+        )));
+    }
+
+    if config.maintenance {
+        handles.push(rt::spawn(run_maintenance(
+            state.clone(),
+            config.clone(),
+            status_config,
+            shutdown.clone(),
+        )));
+    }
+
+    log::info!(
+        "workers started submit={} status_queue={} outbox={} webhooks={} emails={} maintenance={}",
+        config.submit,
+        config.status_queue,
+        config.outbox,
+        config.webhooks,
+        config.emails,
+        config.maintenance
+    );
+    handles
+}
+
+/// Ждёт остановки воркеров не дольше `timeout`; `false`, если не дождались.
+pub async fn join_workers(handles: Vec<rt::task::JoinHandle<()>>, timeout: Duration) -> bool {
+    match rt::time::timeout(timeout, join_all(handles)).await {
+        Ok(_) => true,
+        Err(_) => {
+            log::warn!("workers did not stop within {}s", timeout.as_secs());
+            false
+        }
+    }
+}
+
+async fn run_maintenance(
+    state: AppState,
+    config: WorkerConfig,
+    status_config: StatusQueueConfig,
+    mut shutdown: Shutdown,
+) {
+    log::info!(
+        "maintenance started interval={}s",
+        config.maintenance_interval.as_secs()
+    );
+    while !shutdown.is_triggered() {
+        if let Err(e) = run_maintenance_once(&state, &config, &status_config).await {
+            log::error!("maintenance error: {e}");
+        }
+        if shutdown.sleep(config.maintenance_interval).await {
+            break;
+        }
+    }
+    log::info!("maintenance stopped");
+}
+
+/// Один проход обслуживания: закрыть зависшие задачи, удалить старые мёртвые письма,
+/// старые события из журнала WebSocket, обработанные доменные события, журнал вебхуков
+/// и старые письма из `email_outbox`.
+pub async fn run_maintenance_once(
+    state: &AppState,
+    config: &WorkerConfig,
+    status_config: &StatusQueueConfig,
+) -> Result<(), String> {
+    let expired = queue::expire_stale_tasks(state, status_config).await?;
+    let pruned = prune_dead_letters(&state.pool, config.dead_letter_retention).await?;
+    let pruned_events = ws::prune_ws_events(&state.pool, config.ws_event_retention).await?;
+    let pruned_domain_events = outbox::prune_domain_events(
+        &state.pool,
+        config.outbox_retention,
+        config.dead_letter_retention,
+    )
+    .await?;
+    let pruned_deliveries =
+        user_webhooks::prune_webhook_deliveries(&state.pool, config.webhook_delivery_retention)
+            .await?;
+    let pruned_emails =
+        emails::sender::prune_email_outbox(&state.pool, config.email_outbox_retention).await?;
+    if expired > 0
+        || pruned > 0
+        || pruned_events > 0
+        || pruned_domain_events > 0
+        || pruned_deliveries > 0
+        || pruned_emails > 0
+    {
+        log::info!(
+            "maintenance expired_tasks={} pruned_dead_letters={} pruned_ws_events={} pruned_domain_events={} pruned_webhook_deliveries={} pruned_emails={}",
+            expired,
+            pruned,
+            pruned_events,
+            pruned_domain_events,
+            pruned_deliveries,
+            pruned_emails
+        );
+    }
+    Ok(())
+}
+
+/// Удаляет мёртвые письма (очереди `*.dead`) из `job_queue` старше `retention`.
+pub async fn prune_dead_letters(pool: &PgPool, retention: Duration) -> Result<u64, String> {
+    let result = sqlx::query(
+        r#"DELETE FROM job_queue
+           WHERE queue LIKE '%.dead'
+             AND created_at < NOW() - make_interval(secs => $1)"#,
+    )
+    .bind(retention.as_secs_f64())
+    .execute(pool)
+    .await
+    .map_err(|e| e.to_string())?;
+    Ok(result.rows_affected())
+}
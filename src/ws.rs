@@ -1,74 +1,238 @@
-use actix::{Actor, ActorContext, AsyncContext, Handler, Message, Recipient};
+use actix::{
+    Actor, ActorContext, ActorFutureExt, AsyncContext, ContextFutureSpawner, Handler, Message,
+    Recipient, WrapFuture,
+};
 use actix_web::{Error, HttpRequest, HttpResponse, web};
 use actix_web_actors::ws;
 use chrono::{DateTime, Utc};
 use jsonwebtoken::{DecodingKey, Validation, decode};
 use serde::{Deserialize, Serialize};
+use serde_json::value::RawValue;
 use serde_urlencoded;
-use sqlx::Row;
-use std::collections::HashMap;
+use sqlx::{PgPool, Row};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
 use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use utoipa::ToSchema;
 
 use crate::AppState;
+use crate::ws_bridge::{BridgeEnvelope, EventBridge, deliver_raw};
+use crate::ws_protocol::{
+    ClientMessage, ClientRequest, ErrorCode, ProtocolError, Subscriptions, parse_client_message,
+    parse_topics, reply,
+};
 
 static NEXT_SESSION_ID: AtomicUsize = AtomicUsize::new(1);
 
+/// Пауза перед переподключением к мосту
+const BRIDGE_RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Событие для одной сессии (WebSocket или SSE).
 #[derive(Message)]
 #[rtype(result = "()")]
-struct WsMessage(pub String);
+pub(crate) struct WsMessage {
+    /// Id из `ws_events`; `None`, если событие не удалось записать в журнал
+    pub(crate) id: Option<i64>,
+    pub(crate) payload: String,
+}
+
+/// Настройки WebSocket-сессий.
+#[derive(Debug, Clone)]
+pub struct WsConfig {
+    /// Как часто сервер шлёт ping
+    pub heartbeat_interval: Duration,
+    /// Сколько ждать любого сообщения от клиента, прежде чем закрыть соединение
+    pub client_timeout: Duration,
+    /// Сколько пропущенных событий можно доиграть; если больше — клиент получает снимок
+    pub replay_limit: i64,
+    /// Сколько последних загрузок попадает в снимок
+    pub snapshot_limit: i64,
+    /// Сколько ждать сообщения `auth`, если токена нет в URL
+    pub auth_timeout: Duration,
+}
+
+impl Default for WsConfig {
+    fn default() -> Self {
+        Self {
+            heartbeat_interval: Duration::from_secs(15),
+            client_timeout: Duration::from_secs(45),
+            replay_limit: 500,
+            snapshot_limit: 100,
+            auth_timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+impl WsConfig {
+    /// `WS_HEARTBEAT_INTERVAL_SECS`, `WS_CLIENT_TIMEOUT_SECS`, `WS_REPLAY_LIMIT`, `WS_SNAPSHOT_LIMIT`,
+    /// `WS_AUTH_TIMEOUT_SECS`.
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let env_parse = |key: &str| std::env::var(key).ok().and_then(|v| v.trim().parse::<u64>().ok());
+        let secs = |key: &str, default: Duration| {
+            env_parse(key)
+                .map(|v| Duration::from_secs(v.max(1)))
+                .unwrap_or(default)
+        };
+        Self {
+            heartbeat_interval: secs("WS_HEARTBEAT_INTERVAL_SECS", defaults.heartbeat_interval),
+            client_timeout: secs("WS_CLIENT_TIMEOUT_SECS", defaults.client_timeout),
+            replay_limit: env_parse("WS_REPLAY_LIMIT")
+                .map(|v| v as i64)
+                .unwrap_or(defaults.replay_limit),
+            snapshot_limit: env_parse("WS_SNAPSHOT_LIMIT")
+                .map(|v| v.max(1) as i64)
+                .unwrap_or(defaults.snapshot_limit),
+            auth_timeout: secs("WS_AUTH_TIMEOUT_SECS", defaults.auth_timeout),
+        }
+    }
+}
 
 #[derive(Message)]
 #[rtype(result = "()")]
-struct Connect {
-    user_id: i32,
-    session_id: usize,
-    addr: Recipient<WsMessage>,
+pub(crate) struct Connect {
+    pub(crate) user_id: i32,
+    pub(crate) session_id: usize,
+    pub(crate) addr: Recipient<WsMessage>,
 }
 
 #[derive(Message)]
 #[rtype(result = "()")]
-struct Disconnect {
-    user_id: i32,
-    session_id: usize,
+pub(crate) struct Disconnect {
+    pub(crate) user_id: i32,
+    pub(crate) session_id: usize,
+}
+
+pub(crate) fn next_session_id() -> usize {
+    NEXT_SESSION_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Готовое событие для всех сессий пользователя (через мост, если он есть).
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct NotifyUser {
+    pub user_id: i32,
+    /// Монотонный id из журнала `ws_events`; с ним клиент переподключается (`last_event_id`)
+    pub id: Option<i64>,
+    /// JSON события в конверте `{"id", "event", "version", "data"}`
+    pub payload: String,
 }
 
+/// Событие, пришедшее из моста (`ws_bridge`): доставить сессиям пользователя на этом инстансе.
 #[derive(Message)]
 #[rtype(result = "()")]
-pub struct NotifyUpload {
+pub struct DeliverEvent {
     pub user_id: i32,
-    pub event: UploadEvent,
+    pub event_id: Option<i64>,
+    /// Готовый JSON события
+    pub payload: String,
 }
 
-#[derive(Clone, Debug, Serialize)]
-pub struct UploadEvent {
-    pub event: &'static str,
-    pub data: UploadEventData,
+/// Версия схемы событий (поле `version`); меняется только при несовместимых изменениях.
+/// Описание схемы — в `crate::events` и компонентах OpenAPI.
+pub const EVENT_SCHEMA_VERSION: u16 = 1;
+
+/// Конверт любого события WebSocket/SSE.
+#[derive(Serialize)]
+struct Envelope<'a, T: Serialize + ?Sized> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<i64>,
+    event: &'a str,
+    version: u16,
+    data: &'a T,
 }
 
-#[derive(Clone, Debug, Serialize)]
+/// Данные `upload.updated`.
+#[derive(Clone, Debug, Serialize, ToSchema)]
 pub struct UploadEventData {
     pub id: i32,
     pub task_id: Option<String>,
     pub status: String,
     pub cleaned_url: Option<String>,
+    pub progress: Option<i16>,
     pub original_filename: String,
     pub created_at: Option<DateTime<Utc>>,
 }
 
 pub struct WsHub {
     sessions: HashMap<i32, HashMap<usize, Recipient<WsMessage>>>,
+    bridge: Option<Arc<dyn EventBridge>>,
+    /// Очередь на публикацию в мост; один публикатор сохраняет порядок событий
+    publisher: Option<mpsc::UnboundedSender<String>>,
+}
+
+impl Default for WsHub {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl WsHub {
+    /// Хаб без моста: события доставляются только сессиям этого процесса.
     pub fn new() -> Self {
         Self {
             sessions: HashMap::new(),
+            bridge: None,
+            publisher: None,
+        }
+    }
+
+    /// Хаб, который рассылает события через мост всем инстансам.
+    pub fn with_bridge(bridge: Arc<dyn EventBridge>) -> Self {
+        Self {
+            bridge: Some(bridge),
+            ..Self::new()
+        }
+    }
+
+    fn deliver(&self, user_id: i32, id: Option<i64>, payload: &str) {
+        if let Some(user_sessions) = self.sessions.get(&user_id) {
+            for addr in user_sessions.values() {
+                addr.do_send(WsMessage {
+                    id,
+                    payload: payload.to_string(),
+                });
+            }
         }
     }
 }
 
 impl Actor for WsHub {
     type Context = actix::Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        let Some(bridge) = self.bridge.clone() else {
+            return;
+        };
+        let sink = ctx.address().recipient::<DeliverEvent>();
+        let (tx, mut rx) = mpsc::unbounded_channel::<String>();
+        self.publisher = Some(tx);
+
+        let publish_bridge = bridge.clone();
+        let fallback = sink.clone();
+        actix::spawn(async move {
+            while let Some(payload) = rx.recv().await {
+                if let Err(e) = publish_bridge.publish(&payload).await {
+                    // Мост недоступен: хотя бы свои сессии получат событие
+                    log::warn!("ws bridge publish failed, delivering locally: {e}");
+                    deliver_raw(&fallback, &payload);
+                }
+            }
+        });
+
+        // Слушатель живёт прямо в рантайме tokio, а не в LocalSet actix: соединение
+        // PgListener при остановке закрывается через рантайм и падает без его контекста
+        tokio::spawn(async move {
+            loop {
+                if let Err(e) = bridge.listen(sink.clone()).await {
+                    log::error!("ws bridge {} error: {e}", bridge.name());
+                }
+                actix_web::rt::time::sleep(BRIDGE_RECONNECT_DELAY).await;
+            }
+        });
+    }
 }
 
 impl Handler<Connect> for WsHub {
@@ -95,52 +259,247 @@ impl Handler<Disconnect> for WsHub {
     }
 }
 
-impl Handler<NotifyUpload> for WsHub {
+impl Handler<NotifyUser> for WsHub {
     type Result = ();
 
-    fn handle(&mut self, msg: NotifyUpload, _: &mut Self::Context) -> Self::Result {
-        if let Some(user_sessions) = self.sessions.get(&msg.user_id) {
-            if let Ok(payload) = serde_json::to_string(&msg.event) {
-                for addr in user_sessions.values() {
-                    let _ = addr.do_send(WsMessage(payload.clone()));
-                }
+    fn handle(&mut self, msg: NotifyUser, _: &mut Self::Context) -> Self::Result {
+        let Some(publisher) = &self.publisher else {
+            self.deliver(msg.user_id, msg.id, &msg.payload);
+            return;
+        };
+
+        // Своим сессиям событие придёт обратно из моста, вместе со всеми инстансами
+        let envelope = RawValue::from_string(msg.payload).map(|event| BridgeEnvelope {
+            user_id: msg.user_id,
+            event_id: msg.id,
+            event,
+        });
+        match envelope.and_then(|envelope| serde_json::to_string(&envelope)) {
+            Ok(payload) => {
+                let _ = publisher.send(payload);
             }
+            Err(e) => log::error!("ws event serialize error: {e}"),
         }
     }
 }
 
+impl Handler<DeliverEvent> for WsHub {
+    type Result = ();
+
+    fn handle(&mut self, msg: DeliverEvent, _: &mut Self::Context) -> Self::Result {
+        self.deliver(msg.user_id, msg.event_id, &msg.payload);
+    }
+}
+
 struct WsSession {
-    user_id: i32,
+    /// `None`, пока клиент не прислал `auth` (если токена не было в URL)
+    user_id: Option<i32>,
     session_id: usize,
     hub: actix::Addr<WsHub>,
+    pool: PgPool,
+    config: WsConfig,
+    /// Последнее событие, полученное клиентом до переподключения
+    resume_from: Option<i64>,
+    replayed: ReplayFilter,
+    subscriptions: Subscriptions,
+    last_heartbeat: Instant,
 }
 
 impl WsSession {
-    fn new(user_id: i32, hub: actix::Addr<WsHub>) -> Self {
+    fn new(
+        user_id: Option<i32>,
+        hub: actix::Addr<WsHub>,
+        pool: PgPool,
+        config: WsConfig,
+        resume_from: Option<i64>,
+    ) -> Self {
         Self {
             user_id,
-            session_id: NEXT_SESSION_ID.fetch_add(1, Ordering::Relaxed),
+            session_id: next_session_id(),
             hub,
+            pool,
+            config,
+            resume_from,
+            replayed: ReplayFilter::default(),
+            subscriptions: Subscriptions::default(),
+            last_heartbeat: Instant::now(),
         }
     }
-}
 
-impl Actor for WsSession {
-    type Context = ws::WebsocketContext<Self>;
+    fn start_heartbeat(&self, ctx: &mut ws::WebsocketContext<Self>) {
+        ctx.run_interval(self.config.heartbeat_interval, |act, ctx| {
+            if act.last_heartbeat.elapsed() > act.config.client_timeout {
+                log::info!(
+                    "ws client timed out user_id={:?} session_id={}",
+                    act.user_id,
+                    act.session_id
+                );
+                ctx.stop();
+                return;
+            }
+            ctx.ping(b"");
+        });
+    }
 
-    fn started(&mut self, ctx: &mut Self::Context) {
+    /// Подключает сессию к хабу и доигрывает пропущенное.
+    fn authenticated(&mut self, user_id: i32, ctx: &mut ws::WebsocketContext<Self>) {
+        self.user_id = Some(user_id);
         self.hub.do_send(Connect {
-            user_id: self.user_id,
+            user_id,
             session_id: self.session_id,
             addr: ctx.address().recipient(),
         });
+        if let Some(after) = self.resume_from {
+            self.replay(user_id, after, ctx);
+        }
+    }
+
+    /// Отправляет пропущенные события или снимок. Пока он не ушёл, сессия не разбирает
+    /// свою очередь, так что живые события приходят клиенту после доигранных.
+    fn replay(&self, user_id: i32, after: i64, ctx: &mut ws::WebsocketContext<Self>) {
+        let pool = self.pool.clone();
+        let config = self.config.clone();
+        async move { load_replay(&pool, user_id, after, &config).await }
+            .into_actor(self)
+            .map(move |result, act, ctx| match result {
+                Ok(replay) => {
+                    for (_, payload) in act.replayed.record(replay) {
+                        if act.subscriptions.matches(&payload) {
+                            ctx.text(payload);
+                        }
+                    }
+                }
+                Err(e) => {
+                    // Клиент переподключится и попробует ещё раз
+                    log::error!("ws replay error user_id={} error={}", user_id, e);
+                    ctx.close(Some(ws::CloseCode::Error.into()));
+                    ctx.stop();
+                }
+            })
+            .wait(ctx);
+    }
+
+    fn handle_request(&mut self, request: ClientRequest, ctx: &mut ws::WebsocketContext<Self>) {
+        let ClientRequest {
+            request_id,
+            message,
+        } = request;
+
+        match (message, self.user_id) {
+            (ClientMessage::Auth { .. }, Some(_)) => {
+                let error = ProtocolError::new(
+                    request_id,
+                    ErrorCode::AlreadyAuthenticated,
+                    "session is already authenticated",
+                );
+                ctx.text(error.to_json());
+            }
+            (
+                ClientMessage::Auth {
+                    token,
+                    last_event_id,
+                },
+                None,
+            ) => {
+                let Ok(user_id) = decode_user_id(&token) else {
+                    let error =
+                        ProtocolError::new(request_id, ErrorCode::Unauthorized, "invalid token");
+                    ctx.text(error.to_json());
+                    ctx.close(Some(ws::CloseCode::Policy.into()));
+                    ctx.stop();
+                    return;
+                };
+                ctx.text(reply(
+                    &request_id,
+                    serde_json::json!({ "user_id": user_id }),
+                ));
+                if last_event_id.is_some() {
+                    self.resume_from = last_event_id;
+                }
+                self.authenticated(user_id, ctx);
+            }
+            (ClientMessage::Ping, _) => {
+                ctx.text(reply(&request_id, serde_json::json!({ "pong": true })))
+            }
+            (_, None) => {
+                let error =
+                    ProtocolError::new(request_id, ErrorCode::Unauthorized, "send auth first");
+                ctx.text(error.to_json());
+            }
+            (ClientMessage::Subscribe { topics }, Some(_)) => {
+                match parse_topics(&request_id, &topics) {
+                    Ok(topics) => {
+                        self.subscriptions.subscribe(topics);
+                        let topics = self.subscriptions.topics();
+                        ctx.text(reply(&request_id, serde_json::json!({ "topics": topics })));
+                    }
+                    Err(error) => ctx.text(error.to_json()),
+                }
+            }
+            (ClientMessage::Unsubscribe { topics }, Some(_)) => {
+                match parse_topics(&request_id, &topics) {
+                    Ok(topics) => {
+                        self.subscriptions.unsubscribe(&topics);
+                        let topics = self.subscriptions.topics();
+                        ctx.text(reply(&request_id, serde_json::json!({ "topics": topics })));
+                    }
+                    Err(error) => ctx.text(error.to_json()),
+                }
+            }
+            (ClientMessage::Snapshot { limit }, Some(user_id)) => {
+                let pool = self.pool.clone();
+                let limit = limit
+                    .unwrap_or(self.config.snapshot_limit)
+                    .clamp(1, self.config.snapshot_limit);
+                async move { load_recent_uploads(&pool, user_id, limit).await }
+                    .into_actor(self)
+                    .map(move |result, _, ctx| match result {
+                        Ok(uploads) => ctx.text(reply(
+                            &request_id,
+                            serde_json::json!({ "uploads": uploads }),
+                        )),
+                        Err(e) => {
+                            log::error!("ws snapshot error user_id={} error={}", user_id, e);
+                            let error = ProtocolError::new(
+                                request_id,
+                                ErrorCode::Internal,
+                                "failed to load uploads",
+                            );
+                            ctx.text(error.to_json());
+                        }
+                    })
+                    .spawn(ctx);
+            }
+        }
+    }
+}
+
+impl Actor for WsSession {
+    type Context = ws::WebsocketContext<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.start_heartbeat(ctx);
+        match self.user_id {
+            Some(user_id) => self.authenticated(user_id, ctx),
+            None => {
+                ctx.run_later(self.config.auth_timeout, |act, ctx| {
+                    if act.user_id.is_none() {
+                        log::info!("ws auth timed out session_id={}", act.session_id);
+                        ctx.close(Some(ws::CloseCode::Policy.into()));
+                        ctx.stop();
+                    }
+                });
+            }
+        }
     }
 
     fn stopped(&mut self, _: &mut Self::Context) {
-        self.hub.do_send(Disconnect {
-            user_id: self.user_id,
-            session_id: self.session_id,
-        });
+        if let Some(user_id) = self.user_id {
+            self.hub.do_send(Disconnect {
+                user_id,
+                session_id: self.session_id,
+            });
+        }
     }
 }
 
@@ -148,12 +507,17 @@ impl Handler<WsMessage> for WsSession {
     type Result = ();
 
     fn handle(&mut self, msg: WsMessage, ctx: &mut Self::Context) -> Self::Result {
-        ctx.text(msg.0);
+        if !self.replayed.is_duplicate(msg.id) && self.subscriptions.matches(&msg.payload) {
+            ctx.text(msg.payload);
+        }
     }
 }
 
 impl actix::StreamHandler<Result<ws::Message, ws::ProtocolError>> for WsSession {
     fn handle(&mut self, item: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
+        if item.is_ok() {
+            self.last_heartbeat = Instant::now();
+        }
         match item {
             Ok(ws::Message::Ping(msg)) => ctx.pong(&msg),
             Ok(ws::Message::Pong(_)) => {}
@@ -161,7 +525,10 @@ impl actix::StreamHandler<Result<ws::Message, ws::ProtocolError>> for WsSession
                 ctx.close(reason);
                 ctx.stop();
             }
-            Ok(ws::Message::Text(_)) => {}
+            Ok(ws::Message::Text(text)) => match parse_client_message(&text) {
+                Ok(request) => self.handle_request(request, ctx),
+                Err(error) => ctx.text(error.to_json()),
+            },
             Ok(ws::Message::Binary(_)) => {}
             Ok(ws::Message::Continuation(_)) => {}
             Ok(ws::Message::Nop) => {}
@@ -172,12 +539,18 @@ impl actix::StreamHandler<Result<ws::Message, ws::ProtocolError>> for WsSession
 
 #[derive(Deserialize)]
 struct WsQuery {
-    token: String,
+    /// Токен можно не передавать в URL, а прислать первым сообщением `auth`
+    #[serde(default)]
+    token: Option<String>,
+    /// Id последнего полученного события, чтобы доиграть пропущенные
+    #[serde(default)]
+    last_event_id: Option<i64>,
 }
 
 #[derive(Deserialize)]
 struct Claims {
     sub: i32,
+    #[allow(dead_code)]
     exp: usize,
 }
 
@@ -186,17 +559,21 @@ pub async fn uploads_ws(
     stream: web::Payload,
     state: web::Data<AppState>,
 ) -> Result<HttpResponse, Error> {
-    let token = serde_urlencoded::from_str::<WsQuery>(req.query_string())
-        .ok()
-        .map(|q| q.token)
-        .filter(|t| !t.is_empty());
+    let query = serde_urlencoded::from_str::<WsQuery>(req.query_string())
+        .map_err(|_| actix_web::error::ErrorBadRequest("Invalid query"))?;
 
-    let Some(token) = token else {
-        return Err(actix_web::error::ErrorUnauthorized("Missing token"));
+    let user_id = match query.token.as_deref().filter(|token| !token.is_empty()) {
+        Some(token) => Some(decode_user_id(token)?),
+        None => None,
     };
-
-    let user_id = decode_user_id(&token)?;
-    ws::start(WsSession::new(user_id, state.ws_hub.clone()), &req, stream)
+    let session = WsSession::new(
+        user_id,
+        state.ws_hub.clone(),
+        state.pool.clone(),
+        WsConfig::from_env(),
+        query.last_event_id,
+    );
+    ws::start(session, &req, stream)
 }
 
 fn decode_user_id(token: &str) -> Result<i32, Error> {
@@ -217,7 +594,7 @@ pub async fn notify_upload_by_task(
     task_id: &str,
 ) {
     let row = sqlx::query(
-        r#"SELECT id, user_id, status, cleaned_url, original_filename, created_at, task_id
+        r#"SELECT id, user_id, status, cleaned_url, progress, original_filename, created_at, task_id
            FROM uploads
            WHERE task_id = $1"#,
     )
@@ -225,22 +602,272 @@ pub async fn notify_upload_by_task(
     .fetch_optional(pool)
     .await;
 
-    let Ok(Some(row)) = row else {
-        return;
-    };
+    if let Ok(Some(row)) = row {
+        send_upload_row(pool, hub, row).await;
+    }
+}
 
+pub async fn notify_upload(pool: &sqlx::PgPool, hub: &actix::Addr<WsHub>, upload_id: i32) {
+    let row = sqlx::query(
+        r#"SELECT id, user_id, status, cleaned_url, progress, original_filename, created_at, task_id
+           FROM uploads
+           WHERE id = $1"#,
+    )
+    .bind(upload_id)
+    .fetch_optional(pool)
+    .await;
+
+    if let Ok(Some(row)) = row {
+        send_upload_row(pool, hub, row).await;
+    }
+}
+
+/// Загрузка в том виде, в каком она уходит в `upload.updated`.
+pub async fn load_upload(
+    pool: &sqlx::PgPool,
+    upload_id: i32,
+) -> Result<Option<UploadEventData>, sqlx::Error> {
+    let row = sqlx::query(
+        r#"SELECT id, status, cleaned_url, progress, original_filename, created_at, task_id
+           FROM uploads
+           WHERE id = $1"#,
+    )
+    .bind(upload_id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row.as_ref().map(upload_event_data))
+}
+
+fn upload_event_data(row: &sqlx::postgres::PgRow) -> UploadEventData {
+    UploadEventData {
+        id: row.get("id"),
+        task_id: row.get("task_id"),
+        status: row.get("status"),
+        cleaned_url: row.get("cleaned_url"),
+        progress: row.get("progress"),
+        original_filename: row.get("original_filename"),
+        created_at: row.get("created_at"),
+    }
+}
+
+async fn send_upload_row(
+    pool: &sqlx::PgPool,
+    hub: &actix::Addr<WsHub>,
+    row: sqlx::postgres::PgRow,
+) {
     let user_id: i32 = row.get("user_id");
-    let event = UploadEvent {
-        event: "upload.updated",
-        data: UploadEventData {
-            id: row.get("id"),
-            task_id: row.get("task_id"),
-            status: row.get("status"),
-            cleaned_url: row.get("cleaned_url"),
-            original_filename: row.get("original_filename"),
-            created_at: row.get("created_at"),
-        },
+    emit_event(pool, hub, user_id, "upload.updated", &upload_event_data(&row)).await;
+}
+
+/// Пишет событие в журнал и отправляет его всем сессиям пользователя (WebSocket и SSE).
+pub async fn emit_event<T: Serialize + ?Sized>(
+    pool: &sqlx::PgPool,
+    hub: &actix::Addr<WsHub>,
+    user_id: i32,
+    event: &str,
+    data: &T,
+) {
+    let data = match serde_json::value::to_raw_value(data) {
+        Ok(data) => data,
+        Err(e) => {
+            log::error!("ws event serialize error event={} error={}", event, e);
+            return;
+        }
+    };
+    let id = record_event(pool, user_id, event, data.get()).await;
+    let envelope = Envelope {
+        id,
+        event,
+        version: EVENT_SCHEMA_VERSION,
+        data: &*data,
     };
+    match serde_json::to_string(&envelope) {
+        Ok(payload) => hub.do_send(NotifyUser {
+            user_id,
+            id,
+            payload,
+        }),
+        Err(e) => log::error!("ws event serialize error event={} error={}", event, e),
+    }
+}
+
+/// Пишет событие в журнал `ws_events`. Без записи событие всё равно уходит, но без id,
+/// и после переподключения клиент его не доиграет.
+async fn record_event(pool: &sqlx::PgPool, user_id: i32, event: &str, data: &str) -> Option<i64> {
+    let row = sqlx::query(
+        r#"INSERT INTO ws_events (user_id, event, version, payload)
+           VALUES ($1, $2, $3, $4::jsonb)
+           RETURNING id"#,
+    )
+    .bind(user_id)
+    .bind(event)
+    .bind(EVENT_SCHEMA_VERSION as i16)
+    .bind(data)
+    .fetch_one(pool)
+    .await;
+
+    match row {
+        Ok(row) => Some(row.get("id")),
+        Err(e) => {
+            log::error!("ws event log error user_id={} error={}", user_id, e);
+            None
+        }
+    }
+}
 
-    hub.do_send(NotifyUpload { user_id, event });
+/// Что отправить клиенту, переподключившемуся с `last_event_id`.
+#[derive(Debug)]
+pub enum Replay {
+    /// Пропущенные события по порядку: id и готовый JSON
+    Events(Vec<(i64, String)>),
+    /// Клиент отстал больше, чем можно доиграть: текущее состояние его загрузок
+    Snapshot {
+        /// С этим id клиент переподключается в следующий раз
+        id: i64,
+        uploads: Vec<UploadEventData>,
+    },
+}
+
+impl Replay {
+    /// Сообщения для клиента: id и JSON (снимок — одно событие `snapshot`).
+    pub fn into_messages(self) -> Vec<(i64, String)> {
+        match self {
+            Replay::Events(events) => events,
+            Replay::Snapshot { id, uploads } => {
+                let snapshot = Envelope {
+                    id: Some(id),
+                    event: "snapshot",
+                    version: EVENT_SCHEMA_VERSION,
+                    data: &serde_json::json!({ "uploads": uploads }),
+                };
+                let message = serde_json::to_string(&snapshot).expect("snapshot serializes");
+                vec![(id, message)]
+            }
+        }
+    }
+}
+
+/// Отсекает живые события, которые клиент уже получил при доигрывании.
+#[derive(Debug, Default)]
+pub(crate) struct ReplayFilter {
+    replayed: HashSet<i64>,
+    /// Событиями не новее снимка клиент уже располагает
+    snapshot_id: Option<i64>,
+}
+
+impl ReplayFilter {
+    /// Запоминает отправленное и возвращает сообщения для клиента.
+    pub(crate) fn record(&mut self, replay: Replay) -> Vec<(i64, String)> {
+        match &replay {
+            Replay::Events(events) => self.replayed.extend(events.iter().map(|(id, _)| *id)),
+            Replay::Snapshot { id, .. } => self.snapshot_id = Some(*id),
+        }
+        replay.into_messages()
+    }
+
+    pub(crate) fn is_duplicate(&mut self, id: Option<i64>) -> bool {
+        id.is_some_and(|id| {
+            self.replayed.remove(&id) || self.snapshot_id.is_some_and(|s| id <= s)
+        })
+    }
+}
+
+pub async fn load_replay(
+    pool: &sqlx::PgPool,
+    user_id: i32,
+    after: i64,
+    config: &WsConfig,
+) -> Result<Replay, String> {
+    let bounds =
+        sqlx::query("SELECT MIN(id) AS oldest, COALESCE(MAX(id), 0) AS newest FROM ws_events")
+            .fetch_one(pool)
+            .await
+            .map_err(|e| e.to_string())?;
+    let oldest: Option<i64> = bounds.get("oldest");
+    let newest: i64 = bounds.get("newest");
+
+    // Ids общие для всех пользователей, поэтому о дыре судим по всему журналу: если
+    // события после `after` уже удалены, пропущенное восстановит только снимок
+    let pruned = match oldest {
+        Some(oldest) => after + 1 < oldest,
+        None => after > 0,
+    };
+    if !pruned {
+        let rows = sqlx::query(
+            r#"SELECT id, event, version, payload::text AS payload
+               FROM ws_events
+               WHERE user_id = $1 AND id > $2
+               ORDER BY id
+               LIMIT $3"#,
+        )
+        .bind(user_id)
+        .bind(after)
+        .bind(config.replay_limit + 1)
+        .fetch_all(pool)
+        .await
+        .map_err(|e| e.to_string())?;
+
+        if rows.len() as i64 <= config.replay_limit {
+            let mut events = Vec::with_capacity(rows.len());
+            for row in rows {
+                // Собираем заново через serde: JSONB выводит текст с пробелами
+                let id: i64 = row.get("id");
+                let event: String = row.get("event");
+                let version: i16 = row.get("version");
+                let payload: String = row.get("payload");
+                let data: serde_json::Value =
+                    serde_json::from_str(&payload).map_err(|e| e.to_string())?;
+                let message = serde_json::to_string(&Envelope {
+                    id: Some(id),
+                    event: &event,
+                    version: version as u16,
+                    data: &data,
+                })
+                .map_err(|e| e.to_string())?;
+                events.push((id, message));
+            }
+            return Ok(Replay::Events(events));
+        }
+    }
+
+    Ok(Replay::Snapshot {
+        id: newest,
+        uploads: load_recent_uploads(pool, user_id, config.snapshot_limit).await?,
+    })
+}
+
+/// Последние `limit` загрузок пользователя, новые первыми.
+pub async fn load_recent_uploads(
+    pool: &sqlx::PgPool,
+    user_id: i32,
+    limit: i64,
+) -> Result<Vec<UploadEventData>, String> {
+    let rows = sqlx::query(
+        r#"SELECT id, status, cleaned_url, progress, original_filename, created_at, task_id
+           FROM uploads
+           WHERE user_id = $1
+           ORDER BY created_at DESC, id DESC
+           LIMIT $2"#,
+    )
+    .bind(user_id)
+    .bind(limit)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| e.to_string())?;
+
+    Ok(rows.iter().map(upload_event_data).collect())
+}
+
+/// Удаляет из журнала `ws_events` события старше `retention`.
+pub async fn prune_ws_events(pool: &sqlx::PgPool, retention: Duration) -> Result<u64, String> {
+    let result = sqlx::query(
+        r#"DELETE FROM ws_events
+           WHERE created_at < NOW() - make_interval(secs => $1)"#,
+    )
+    .bind(retention.as_secs_f64())
+    .execute(pool)
+    .await
+    .map_err(|e| e.to_string())?;
+    Ok(result.rows_affected())
 }
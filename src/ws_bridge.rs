@@ -0,0 +1,267 @@
+// src/ws_bridge.rs
+//
+// Мост событий WebSocket между инстансами. `WsHub` каждого процесса публикует событие в мост
+// и получает из него все события (включая свои), а доставляет только своим сессиям.
+// Так колбэк, пришедший на реплику A, доходит до пользователя, подключённого к реплике B,
+// а события отдельного воркера — до клиентов API.
+// По умолчанию мост — Postgres LISTEN/NOTIFY (`WS_BRIDGE=postgres`), альтернатива —
+// fanout-обменник RabbitMQ (`WS_BRIDGE=rabbitmq`); `WS_BRIDGE=off` — только локальная доставка.
+
+use actix::Recipient;
+use async_trait::async_trait;
+use futures_util::StreamExt;
+use lapin::{
+    BasicProperties, Channel, Connection, ConnectionProperties, ExchangeKind,
+    options::{
+        BasicConsumeOptions, BasicPublishOptions, ExchangeDeclareOptions, QueueBindOptions,
+        QueueDeclareOptions,
+    },
+    types::FieldTable,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::value::RawValue;
+use sqlx::PgPool;
+use sqlx::postgres::PgListener;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+use crate::queue::rabbitmq::{redact_amqp_url, resolve_rabbit_url};
+use crate::ws::DeliverEvent;
+
+pub const DEFAULT_PG_CHANNEL: &str = "ws_events";
+pub const DEFAULT_FANOUT_EXCHANGE: &str = "sora.ws.events";
+
+/// Сообщение в мосте: кому и что отправить.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BridgeEnvelope {
+    pub user_id: i32,
+    /// Id события в журнале `ws_events`
+    #[serde(default)]
+    pub event_id: Option<i64>,
+    /// Событие как есть, без пересборки JSON
+    pub event: Box<RawValue>,
+}
+
+#[async_trait]
+pub trait EventBridge: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Публикует сериализованный `BridgeEnvelope` для всех инстансов.
+    async fn publish(&self, payload: &str) -> Result<(), String>;
+
+    /// Передаёт в `sink` всё, что приходит в мост; возвращается только при ошибке.
+    async fn listen(&self, sink: Recipient<DeliverEvent>) -> Result<(), String>;
+}
+
+/// `WS_BRIDGE=postgres|rabbitmq|off`, по умолчанию Postgres.
+pub fn from_env(pool: &PgPool) -> Option<Arc<dyn EventBridge>> {
+    let mode = std::env::var("WS_BRIDGE")
+        .unwrap_or_default()
+        .trim()
+        .to_lowercase();
+    let pg_channel =
+        std::env::var("WS_BRIDGE_CHANNEL").unwrap_or_else(|_| DEFAULT_PG_CHANNEL.to_string());
+
+    match mode.as_str() {
+        "off" | "local" => None,
+        "rabbitmq" => match resolve_rabbit_url() {
+            Some(url) => {
+                let exchange = std::env::var("WS_FANOUT_EXCHANGE")
+                    .unwrap_or_else(|_| DEFAULT_FANOUT_EXCHANGE.to_string());
+                Some(Arc::new(RabbitBridge::new(url, exchange)))
+            }
+            None => {
+                log::warn!("WS_BRIDGE=rabbitmq but RABBITMQ_URL is not set, using postgres");
+                Some(Arc::new(PgBridge::new(pool.clone(), pg_channel)))
+            }
+        },
+        other => {
+            if !other.is_empty() && other != "postgres" {
+                log::warn!("unknown WS_BRIDGE={other}, using postgres");
+            }
+            Some(Arc::new(PgBridge::new(pool.clone(), pg_channel)))
+        }
+    }
+}
+
+/// Разбирает сообщение из моста и отдаёт его хабу.
+pub fn deliver_raw(sink: &Recipient<DeliverEvent>, raw: &str) {
+    match serde_json::from_str::<BridgeEnvelope>(raw) {
+        Ok(envelope) => sink.do_send(DeliverEvent {
+            user_id: envelope.user_id,
+            event_id: envelope.event_id,
+            payload: envelope.event.get().to_string(),
+        }),
+        Err(e) => log::warn!("ws bridge invalid message error={}", e),
+    }
+}
+
+pub struct PgBridge {
+    pool: PgPool,
+    channel: String,
+}
+
+impl PgBridge {
+    pub fn new(pool: PgPool, channel: String) -> Self {
+        Self { pool, channel }
+    }
+}
+
+#[async_trait]
+impl EventBridge for PgBridge {
+    fn name(&self) -> &'static str {
+        "postgres"
+    }
+
+    async fn publish(&self, payload: &str) -> Result<(), String> {
+        sqlx::query("SELECT pg_notify($1, $2)")
+            .bind(&self.channel)
+            .bind(payload)
+            .execute(&self.pool)
+            .await
+            .map_err(|e| format!("ws bridge notify error: {e}"))?;
+        Ok(())
+    }
+
+    async fn listen(&self, sink: Recipient<DeliverEvent>) -> Result<(), String> {
+        let mut listener = PgListener::connect_with(&self.pool)
+            .await
+            .map_err(|e| format!("ws bridge listen connect error: {e}"))?;
+        listener
+            .listen(&self.channel)
+            .await
+            .map_err(|e| format!("ws bridge listen error: {e}"))?;
+        log::info!("ws bridge listening on postgres channel {}", self.channel);
+
+        loop {
+            // После обрыва соединения `recv` переподключается сам; пропущенные события
+            // клиент доберёт из снимка при переподключении
+            let notification = listener
+                .recv()
+                .await
+                .map_err(|e| format!("ws bridge recv error: {e}"))?;
+            deliver_raw(&sink, notification.payload());
+        }
+    }
+}
+
+pub struct RabbitBridge {
+    url: String,
+    exchange: String,
+    publish_channel: Mutex<Option<Channel>>,
+}
+
+impl RabbitBridge {
+    pub fn new(url: String, exchange: String) -> Self {
+        Self {
+            url,
+            exchange,
+            publish_channel: Mutex::new(None),
+        }
+    }
+
+    async fn open_channel(&self) -> Result<Channel, String> {
+        let conn = Connection::connect(&self.url, ConnectionProperties::default())
+            .await
+            .map_err(|e| {
+                format!(
+                    "ws bridge rabbitmq connect error: {e} url={}",
+                    redact_amqp_url(&self.url)
+                )
+            })?;
+        let channel = conn
+            .create_channel()
+            .await
+            .map_err(|e| format!("ws bridge rabbitmq channel error: {e}"))?;
+        channel
+            .exchange_declare(
+                &self.exchange,
+                ExchangeKind::Fanout,
+                ExchangeDeclareOptions {
+                    durable: true,
+                    ..ExchangeDeclareOptions::default()
+                },
+                FieldTable::default(),
+            )
+            .await
+            .map_err(|e| format!("ws bridge declare exchange error: {e}"))?;
+        Ok(channel)
+    }
+}
+
+#[async_trait]
+impl EventBridge for RabbitBridge {
+    fn name(&self) -> &'static str {
+        "rabbitmq"
+    }
+
+    async fn publish(&self, payload: &str) -> Result<(), String> {
+        let mut guard = self.publish_channel.lock().await;
+        let channel = match guard.as_ref() {
+            Some(channel) if channel.status().connected() => channel.clone(),
+            _ => {
+                let channel = self.open_channel().await?;
+                *guard = Some(channel.clone());
+                channel
+            }
+        };
+        // События живут секунды: persistent и подтверждения тут не нужны
+        channel
+            .basic_publish(
+                &self.exchange,
+                "",
+                BasicPublishOptions::default(),
+                payload.as_bytes(),
+                BasicProperties::default().with_content_type("application/json".into()),
+            )
+            .await
+            .map_err(|e| format!("ws bridge publish error: {e}"))?;
+        Ok(())
+    }
+
+    async fn listen(&self, sink: Recipient<DeliverEvent>) -> Result<(), String> {
+        let channel = self.open_channel().await?;
+        // Своя временная очередь у каждого инстанса, удаляется вместе с соединением
+        let queue = channel
+            .queue_declare(
+                "",
+                QueueDeclareOptions {
+                    exclusive: true,
+                    auto_delete: true,
+                    ..QueueDeclareOptions::default()
+                },
+                FieldTable::default(),
+            )
+            .await
+            .map_err(|e| format!("ws bridge declare queue error: {e}"))?;
+        channel
+            .queue_bind(
+                queue.name().as_str(),
+                &self.exchange,
+                "",
+                QueueBindOptions::default(),
+                FieldTable::default(),
+            )
+            .await
+            .map_err(|e| format!("ws bridge bind error: {e}"))?;
+        let mut consumer = channel
+            .basic_consume(
+                queue.name().as_str(),
+                "",
+                BasicConsumeOptions {
+                    no_ack: true,
+                    ..BasicConsumeOptions::default()
+                },
+                FieldTable::default(),
+            )
+            .await
+            .map_err(|e| format!("ws bridge consume error: {e}"))?;
+        log::info!("ws bridge listening on rabbitmq exchange {}", self.exchange);
+
+        while let Some(delivery) = consumer.next().await {
+            let delivery = delivery.map_err(|e| format!("ws bridge delivery error: {e}"))?;
+            deliver_raw(&sink, &String::from_utf8_lossy(&delivery.data));
+        }
+        Err("ws bridge consumer closed".to_string())
+    }
+}
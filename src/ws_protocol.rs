@@ -0,0 +1,272 @@
+// src/ws_protocol.rs
+//
+// JSON-протокол входящих сообщений `/ws/uploads`. Каждое сообщение клиента — объект с `type`
+// и необязательным `request_id`, который сервер возвращает в ответе:
+//   {"type": "auth", "request_id": 1, "token": "<jwt>", "last_event_id": 1042}
+//   {"type": "subscribe", "request_id": 2, "topics": ["upload:7", "account"]}
+//   {"type": "unsubscribe", "request_id": 3, "topics": ["account"]}
+//   {"type": "snapshot", "request_id": 4, "limit": 20}
+//   {"type": "ping", "request_id": 5}
+// Ответ: {"type": "reply", "request_id": 2, "data": {...}},
+// ошибка: {"type": "error", "request_id": 2, "error": {"code": "...", "message": "..."}}.
+// События идут как раньше, в конверте `{"id", "event", "version", "data"}` без `type`.
+
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::collections::BTreeSet;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientMessage {
+    /// Авторизация первым сообщением, чтобы токен не попадал в URL и логи прокси
+    Auth {
+        token: String,
+        #[serde(default)]
+        last_event_id: Option<i64>,
+    },
+    Subscribe {
+        topics: Vec<String>,
+    },
+    Unsubscribe {
+        topics: Vec<String>,
+    },
+    /// Последние загрузки пользователя
+    Snapshot {
+        #[serde(default)]
+        limit: Option<i64>,
+    },
+    Ping,
+}
+
+/// Сообщение клиента вместе с его `request_id`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClientRequest {
+    pub request_id: Option<Value>,
+    pub message: ClientMessage,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    InvalidMessage,
+    UnknownType,
+    Unauthorized,
+    AlreadyAuthenticated,
+    InvalidTopic,
+    Internal,
+}
+
+impl ErrorCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::InvalidMessage => "invalid_message",
+            ErrorCode::UnknownType => "unknown_type",
+            ErrorCode::Unauthorized => "unauthorized",
+            ErrorCode::AlreadyAuthenticated => "already_authenticated",
+            ErrorCode::InvalidTopic => "invalid_topic",
+            ErrorCode::Internal => "internal",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProtocolError {
+    pub request_id: Option<Value>,
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+impl ProtocolError {
+    pub fn new(request_id: Option<Value>, code: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            request_id,
+            code,
+            message: message.into(),
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        json!({
+            "type": "error",
+            "request_id": self.request_id,
+            "error": { "code": self.code.as_str(), "message": self.message },
+        })
+        .to_string()
+    }
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.code.as_str(), self.message)
+    }
+}
+
+const MESSAGE_TYPES: [&str; 5] = ["auth", "subscribe", "unsubscribe", "snapshot", "ping"];
+
+pub fn parse_client_message(text: &str) -> Result<ClientRequest, ProtocolError> {
+    let value: Value = serde_json::from_str(text)
+        .map_err(|e| ProtocolError::new(None, ErrorCode::InvalidMessage, e.to_string()))?;
+    let Some(object) = value.as_object() else {
+        return Err(ProtocolError::new(
+            None,
+            ErrorCode::InvalidMessage,
+            "message must be a JSON object",
+        ));
+    };
+    let request_id = object.get("request_id").cloned().filter(|id| !id.is_null());
+
+    match object.get("type").and_then(Value::as_str) {
+        Some(kind) if MESSAGE_TYPES.contains(&kind) => {}
+        Some(kind) => {
+            return Err(ProtocolError::new(
+                request_id,
+                ErrorCode::UnknownType,
+                format!("unknown message type {kind:?}"),
+            ));
+        }
+        None => {
+            return Err(ProtocolError::new(
+                request_id,
+                ErrorCode::InvalidMessage,
+                "missing \"type\"",
+            ));
+        }
+    }
+
+    let message = serde_json::from_value(value.clone()).map_err(|e| {
+        ProtocolError::new(request_id.clone(), ErrorCode::InvalidMessage, e.to_string())
+    })?;
+    Ok(ClientRequest {
+        request_id,
+        message,
+    })
+}
+
+pub fn reply(request_id: &Option<Value>, data: Value) -> String {
+    json!({ "type": "reply", "request_id": request_id, "data": data }).to_string()
+}
+
+/// Тема подписки.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Topic {
+    /// Все `upload.updated`
+    Uploads,
+    /// `upload.updated` одной загрузки
+    Upload(i32),
+    /// `credits.*`, `payment.*`, `subscription.*`
+    Account,
+}
+
+impl Topic {
+    pub fn parse(raw: &str) -> Option<Self> {
+        match raw.trim() {
+            "uploads" => Some(Topic::Uploads),
+            "account" => Some(Topic::Account),
+            other => other
+                .strip_prefix("upload:")
+                .and_then(|id| id.parse().ok())
+                .map(Topic::Upload),
+        }
+    }
+}
+
+impl fmt::Display for Topic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Topic::Uploads => write!(f, "uploads"),
+            Topic::Upload(id) => write!(f, "upload:{id}"),
+            Topic::Account => write!(f, "account"),
+        }
+    }
+}
+
+pub fn parse_topics(
+    request_id: &Option<Value>,
+    raw: &[String],
+) -> Result<Vec<Topic>, ProtocolError> {
+    if raw.is_empty() {
+        return Err(ProtocolError::new(
+            request_id.clone(),
+            ErrorCode::InvalidTopic,
+            "topics must not be empty",
+        ));
+    }
+    raw.iter()
+        .map(|topic| {
+            Topic::parse(topic).ok_or_else(|| {
+                ProtocolError::new(
+                    request_id.clone(),
+                    ErrorCode::InvalidTopic,
+                    format!("unknown topic {topic:?}, expected uploads, upload:<id> or account"),
+                )
+            })
+        })
+        .collect()
+}
+
+/// Подписки сессии. Пока клиент ни на что не подписался, он получает все свои события.
+#[derive(Debug, Default)]
+pub struct Subscriptions {
+    topics: Option<BTreeSet<Topic>>,
+}
+
+#[derive(Deserialize)]
+struct EventHead {
+    event: String,
+    #[serde(default)]
+    data: Option<EventDataHead>,
+}
+
+#[derive(Deserialize)]
+struct EventDataHead {
+    #[serde(default)]
+    id: Option<i32>,
+}
+
+impl Subscriptions {
+    pub fn subscribe(&mut self, topics: Vec<Topic>) {
+        self.topics.get_or_insert_with(BTreeSet::new).extend(topics);
+    }
+
+    pub fn unsubscribe(&mut self, topics: &[Topic]) {
+        let current = self.topics.get_or_insert_with(BTreeSet::new);
+        for topic in topics {
+            current.remove(topic);
+        }
+    }
+
+    /// Текущие темы; `None` — подписки не заданы, приходит всё.
+    pub fn topics(&self) -> Option<Vec<String>> {
+        self.topics
+            .as_ref()
+            .map(|topics| topics.iter().map(Topic::to_string).collect())
+    }
+
+    /// Нужно ли отправлять событие (JSON в конверте) этой сессии.
+    pub fn matches(&self, payload: &str) -> bool {
+        let Some(topics) = &self.topics else {
+            return true;
+        };
+        let Ok(head) = serde_json::from_str::<EventHead>(payload) else {
+            return true;
+        };
+        match head.event.as_str() {
+            "upload.updated" => {
+                topics.contains(&Topic::Uploads)
+                    || head
+                        .data
+                        .and_then(|data| data.id)
+                        .is_some_and(|id| topics.contains(&Topic::Upload(id)))
+            }
+            "snapshot" => true,
+            event
+                if ["credits.", "payment.", "subscription."]
+                    .iter()
+                    .any(|prefix| event.starts_with(prefix)) =>
+            {
+                topics.contains(&Topic::Account)
+            }
+            _ => true,
+        }
+    }
+}
@@ -0,0 +1,214 @@
+use actix_web::test::TestRequest;
+use actix_web::{App, test, web};
+use serde_json::{Value, json};
+use sqlx::{PgPool, Row};
+use uuid::Uuid;
+
+use sora_watermark_remov::api::webhooks_lava::lava_webhook;
+use sora_watermark_remov::outbox::{self, OutboxConfig, Subscribers};
+
+mod support;
+
+async fn create_user(pool: &PgPool, email: &str) -> i32 {
+    sqlx::query(
+        r#"INSERT INTO users (username, email, password_hash, credits, monthly_quota)
+           VALUES ($1, $2, 'test-hash', 0, 0)
+           RETURNING id"#,
+    )
+    .bind(format!("user_{}", Uuid::new_v4().simple()))
+    .bind(email)
+    .fetch_one(pool)
+    .await
+    .expect("insert user")
+    .get("id")
+}
+
+async fn create_product(pool: &PgPool, product_type: &str, offer: Uuid) -> i32 {
+    let (credits_granted, monthly_credits) = match product_type {
+        "one_time" => (Some(3), None),
+        _ => (None, Some(12)),
+    };
+    sqlx::query(
+        r#"INSERT INTO products
+           (slug, name, description, price, currency, product_type, credits_granted, monthly_credits, is_active, lava_offer_id)
+           VALUES ($1, 'Test', 'Test product', 9.99, 'RUB', $2, $3, $4, true, $5)
+           RETURNING id"#,
+    )
+    .bind(format!("test_{}", Uuid::new_v4().simple()))
+    .bind(product_type)
+    .bind(credits_granted)
+    .bind(monthly_credits)
+    .bind(offer)
+    .fetch_one(pool)
+    .await
+    .expect("insert product")
+    .get("id")
+}
+
+/// События пользователя из журнала: имя, версия и данные.
+async fn user_events(pool: &PgPool, user_id: i32) -> Vec<(String, i16, Value)> {
+    sqlx::query(
+        r#"SELECT event, version, payload::text AS payload
+           FROM ws_events
+           WHERE user_id = $1
+           ORDER BY id"#,
+    )
+    .bind(user_id)
+    .fetch_all(pool)
+    .await
+    .expect("events")
+    .into_iter()
+    .map(|row| {
+        let payload: String = row.get("payload");
+        (
+            row.get("event"),
+            row.get("version"),
+            serde_json::from_str(&payload).expect("json"),
+        )
+    })
+    .collect()
+}
+
+async fn send_webhook(state: &web::Data<sora_watermark_remov::AppState>, payload: Value) {
+    let app = test::init_service(App::new().app_data(state.clone()).service(lava_webhook)).await;
+    let req = TestRequest::post()
+        .uri("/webhook/lava")
+        .insert_header(("X-Api-Key", "test-key"))
+        .set_json(payload)
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert!(resp.status().is_success());
+    // События аккаунта уходят через outbox, поэтому раздаём их сразу после вебхука.
+    outbox::dispatch_pending(state, &Subscribers::from_env(), &OutboxConfig::default())
+        .await
+        .expect("dispatch outbox");
+}
+
+#[actix_web::test]
+async fn one_time_payment_emits_payment_and_credits_events() {
+    let test_db = support::init_test_db().await;
+    let pool = &test_db.pool;
+    let email = format!("events_{}@lava.top", Uuid::new_v4());
+    let offer = Uuid::new_v4();
+    let user_id = create_user(pool, &email).await;
+    let product_id = create_product(pool, "one_time", offer).await;
+    let state = web::Data::new(support::build_state(pool.clone(), "test-key").await);
+
+    send_webhook(
+        &state,
+        json!({
+            "eventType": "payment.success",
+            "product": { "id": offer.to_string() },
+            "buyer": { "email": email },
+            "contractId": Uuid::new_v4().to_string(),
+            "amount": 9.99,
+            "currency": "RUB",
+            "status": "completed"
+        }),
+    )
+    .await;
+
+    let events = user_events(pool, user_id).await;
+    let names: Vec<&str> = events.iter().map(|(name, _, _)| name.as_str()).collect();
+    assert_eq!(names, ["payment.succeeded", "credits.updated"]);
+    assert!(events.iter().all(|(_, version, _)| *version == 1));
+
+    let payment = &events[0].2;
+    assert_eq!(payment["product_id"], product_id);
+    assert_eq!(payment["product_type"], "one_time");
+    assert_eq!(payment["amount"], "9.99");
+    assert_eq!(payment["currency"], "RUB");
+    assert_eq!(events[1].2["credits"], 3);
+}
+
+#[actix_web::test]
+async fn failed_payment_emits_payment_failed() {
+    let test_db = support::init_test_db().await;
+    let pool = &test_db.pool;
+    let email = format!("events_{}@lava.top", Uuid::new_v4());
+    let offer = Uuid::new_v4();
+    let user_id = create_user(pool, &email).await;
+    let product_id = create_product(pool, "one_time", offer).await;
+    let contract_id = Uuid::new_v4().to_string();
+    let tx_id: i32 = sqlx::query(
+        r#"INSERT INTO transactions
+           (user_id, product_id, provider, provider_order_id, amount, currency, status, type)
+           VALUES ($1, $2, 'lava', $3, 9.99, 'RUB', 'pending', 'payment')
+           RETURNING id"#,
+    )
+    .bind(user_id)
+    .bind(product_id)
+    .bind(&contract_id)
+    .fetch_one(pool)
+    .await
+    .expect("insert tx")
+    .get("id");
+    let state = web::Data::new(support::build_state(pool.clone(), "test-key").await);
+
+    send_webhook(
+        &state,
+        json!({
+            "eventType": "payment.failed",
+            "contractId": contract_id,
+            "status": "failed"
+        }),
+    )
+    .await;
+
+    let events = user_events(pool, user_id).await;
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].0, "payment.failed");
+    assert_eq!(events[0].2["transaction_id"], tx_id);
+}
+
+#[actix_web::test]
+async fn subscription_payment_and_cancel_emit_subscription_events() {
+    let test_db = support::init_test_db().await;
+    let pool = &test_db.pool;
+    let email = format!("events_{}@lava.top", Uuid::new_v4());
+    let offer = Uuid::new_v4();
+    let user_id = create_user(pool, &email).await;
+    let product_id = create_product(pool, "subscription", offer).await;
+    let contract_id = Uuid::new_v4().to_string();
+    let state = web::Data::new(support::build_state(pool.clone(), "test-key").await);
+
+    send_webhook(
+        &state,
+        json!({
+            "eventType": "payment.success",
+            "product": { "id": offer.to_string() },
+            "buyer": { "email": email },
+            "contractId": contract_id,
+            "amount": 9.99,
+            "currency": "RUB",
+            "status": "completed"
+        }),
+    )
+    .await;
+    send_webhook(
+        &state,
+        json!({
+            "eventType": "subscription.cancelled",
+            "contractId": contract_id
+        }),
+    )
+    .await;
+
+    let events = user_events(pool, user_id).await;
+    let names: Vec<&str> = events.iter().map(|(name, _, _)| name.as_str()).collect();
+    assert_eq!(
+        names,
+        [
+            "payment.succeeded",
+            "subscription.updated",
+            "credits.updated",
+            "subscription.canceled"
+        ]
+    );
+    assert_eq!(events[1].2["product_id"], product_id);
+    assert_eq!(events[1].2["status"], "active");
+    assert_eq!(events[2].2["monthly_quota"], 12);
+    assert_eq!(events[3].2["status"], "canceled");
+    assert!(events[3].2["canceled_at"].is_string());
+    assert_eq!(events[3].2["subscription_id"], events[1].2["subscription_id"]);
+}
@@ -0,0 +1,36 @@
+use sora_watermark_remov::dedup::{content_hash, normalize_source_url, url_fingerprint};
+
+#[test]
+fn normalize_strips_tracking_and_fragment() {
+    let a = normalize_source_url("https://sora.chatgpt.com/p/s_123?utm_source=x&fbclid=1#top")
+        .expect("normalize");
+    let b = normalize_source_url("http://WWW.Sora.ChatGPT.com/p/s_123/").expect("normalize");
+
+    assert_eq!(a, "https://sora.chatgpt.com/p/s_123");
+    assert_eq!(a, b);
+    assert_eq!(url_fingerprint(&a), url_fingerprint(&b));
+}
+
+#[test]
+fn normalize_keeps_meaningful_query_sorted() {
+    let a = normalize_source_url("https://cdn.example.com/v.mp4?b=2&a=1").expect("normalize");
+    let b = normalize_source_url("https://cdn.example.com/v.mp4?a=1&b=2").expect("normalize");
+    let c = normalize_source_url("https://cdn.example.com/v.mp4?a=1&b=3").expect("normalize");
+
+    assert_eq!(a, b);
+    assert_ne!(a, c);
+}
+
+#[test]
+fn normalize_rejects_non_http() {
+    assert!(normalize_source_url("ftp://example.com/v.mp4").is_none());
+    assert!(normalize_source_url("not a url").is_none());
+}
+
+#[test]
+fn content_hash_is_sha256_hex() {
+    assert_eq!(
+        content_hash(b"abc"),
+        "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+    );
+}
@@ -0,0 +1,37 @@
+use sora_watermark_remov::emails::templates::{self, Vars};
+
+#[test]
+fn templates_escape_html_and_fall_back_to_default_locale() {
+    let vars = Vars::from([
+        ("filename", "<b>clip</b> & co.mp4".to_string()),
+        ("reason", String::new()),
+        ("refunded", "yes".to_string()),
+    ]);
+    let html = templates::render_str(
+        "{{filename}}|{{{filename}}}|{{#reason}}R{{/reason}}{{^reason}}no reason{{/reason}}|{{#refunded}}refunded{{/refunded}}|{{missing}}",
+        &vars,
+        true,
+    );
+    assert_eq!(
+        html,
+        "&lt;b&gt;clip&lt;/b&gt; &amp; co.mp4|<b>clip</b> & co.mp4|no reason|refunded|"
+    );
+    assert_eq!(
+        templates::render_str("{{filename}}", &vars, false),
+        "<b>clip</b> & co.mp4"
+    );
+
+    assert_eq!(templates::resolve_locale(Some("ru-RU")), "ru");
+    assert_eq!(templates::resolve_locale(Some("de")), "en");
+
+    let rendered = templates::render("upload_failed", "en", &vars).expect("render");
+    assert_eq!(rendered.subject, "We couldn't process your video");
+    assert!(
+        rendered
+            .html
+            .contains("<strong>&lt;b&gt;clip&lt;/b&gt; &amp; co.mp4</strong>")
+    );
+    assert!(!rendered.html.contains("Reason:"));
+    assert!(rendered.html.starts_with("<!DOCTYPE html>"));
+    assert!(templates::render("no_such_template", "en", &vars).is_err());
+}
@@ -0,0 +1,463 @@
+use actix_web::dev::Service;
+use actix_web::http::StatusCode;
+use actix_web::test::TestRequest;
+use actix_web::{App, HttpMessage, test, web};
+use serde_json::{Value, json};
+use sqlx::{PgPool, Row};
+use uuid::Uuid;
+
+use sora_watermark_remov::api::auth::resend_verification;
+use sora_watermark_remov::api::email_preferences::{
+    get_email_preferences, unsubscribe, update_email_preferences,
+};
+use sora_watermark_remov::billing::consume_credit;
+use sora_watermark_remov::emails::mailer::{MailError, MemoryMailer};
+use sora_watermark_remov::emails::sender::{self, EmailConfig};
+use sora_watermark_remov::emails::templates::Vars;
+use sora_watermark_remov::emails::{self, EmailKind};
+use sora_watermark_remov::outbox::{self, DomainEvent, OutboxConfig, Subscribers};
+
+mod support;
+
+fn set_env(key: &str, value: &str) {
+    unsafe {
+        std::env::set_var(key, value);
+    }
+}
+
+async fn create_user(pool: &PgPool, credits: i32, email_verified: bool) -> (i32, String) {
+    let email = format!("mail_{}@example.com", Uuid::new_v4());
+    let row = sqlx::query(
+        r#"INSERT INTO users (username, email, password_hash, credits, monthly_quota, email_verified)
+           VALUES ($1, $2, 'test-hash', $3, 0, $4)
+           RETURNING id"#,
+    )
+    .bind(format!("user_{}", Uuid::new_v4().simple()))
+    .bind(&email)
+    .bind(credits)
+    .bind(email_verified)
+    .fetch_one(pool)
+    .await
+    .expect("insert user");
+    (row.get("id"), email)
+}
+
+fn token_from(url: &str) -> String {
+    let start = url.find("token=").expect("token in url") + "token=".len();
+    url[start..].split('&').next().unwrap().to_string()
+}
+
+#[actix_web::test]
+async fn emails_follow_preferences_and_unsubscribe() {
+    let test_db = support::init_test_db().await;
+    set_env("APP_BASE_URL", "https://app.example.com");
+    let pool = &test_db.pool;
+    let (user_id, email) = create_user(pool, 0, true).await;
+    let state = web::Data::new(support::build_state(pool.clone(), "test-key").await);
+
+    let upload_id: i32 = sqlx::query(
+        r#"INSERT INTO uploads (user_id, original_filename, original_s3_key, status, cleaned_url)
+           VALUES ($1, 'clip.mp4', 'https://cdn.example.com/clip.mp4', 'ready',
+                   'https://cdn.example.com/clean.mp4')
+           RETURNING id"#,
+    )
+    .bind(user_id)
+    .fetch_one(pool)
+    .await
+    .expect("insert upload")
+    .get("id");
+    let transaction_id: i32 = sqlx::query(
+        r#"INSERT INTO transactions
+                (user_id, product_id, provider, provider_order_id, amount, currency, status, type, paid_at)
+           SELECT $1, id, 'lava', $2, price, currency, 'succeeded', 'payment', NOW()
+           FROM products WHERE slug = 'pack_5'
+           RETURNING id"#,
+    )
+    .bind(user_id)
+    .bind(Uuid::new_v4().to_string())
+    .fetch_one(pool)
+    .await
+    .expect("insert transaction")
+    .get("id");
+    let completed = DomainEvent::UploadCompleted { upload_id, user_id };
+    let receipt = DomainEvent::PaymentSucceeded {
+        transaction_id,
+        user_id,
+    };
+
+    let message = emails::email_for_event(&state, &completed)
+        .await
+        .expect("compose")
+        .expect("upload email");
+    assert_eq!(message.to, email);
+    assert!(message.text.contains("https://cdn.example.com/clean.mp4"));
+    assert!(message.html.contains("<strong>clip.mp4</strong>"));
+    assert_eq!(message.locale, "en");
+    let unsubscribe_url = message.unsubscribe_url.expect("unsubscribe url");
+    assert!(unsubscribe_url.starts_with("http://localhost/email/unsubscribe?token="));
+    assert!(unsubscribe_url.ends_with("&category=upload_completed"));
+    assert!(
+        message
+            .text
+            .contains("https://app.example.com/unsubscribe?token=")
+    );
+
+    let message = emails::email_for_event(&state, &receipt)
+        .await
+        .expect("compose")
+        .expect("receipt");
+    assert!(message.subject.contains(&format!("#{transaction_id}")));
+    assert!(message.text.contains("5 Removals Pack"));
+    assert!(message.text.contains("4.99 USD"));
+    assert!(message.unsubscribe_url.is_none());
+
+    let app = test::init_service(
+        App::new()
+            .app_data(state.clone())
+            .service(unsubscribe)
+            .service(
+                web::scope("/api")
+                    .wrap_fn(move |req, srv| {
+                        req.extensions_mut().insert(user_id);
+                        srv.call(req)
+                    })
+                    .service(get_email_preferences)
+                    .service(update_email_preferences),
+            ),
+    )
+    .await;
+
+    let token = token_from(&unsubscribe_url);
+    let req = TestRequest::post()
+        .uri(&format!("/email/unsubscribe?token={token}&category=spam"))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    // Категория попадает в SQL только как известная колонка
+    let token_id = Uuid::parse_str(&token).expect("token uuid");
+    assert!(
+        emails::unsubscribe(&state.pool, token_id, Some("upload_failed = FALSE, low_balance"))
+            .await
+            .is_err()
+    );
+
+    let req = TestRequest::post()
+        .uri(&format!("/email/unsubscribe?token={}", Uuid::new_v4()))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+    // Так отписывается почтовый клиент по List-Unsubscribe-Post
+    let req = TestRequest::post()
+        .uri(&format!(
+            "/email/unsubscribe?token={token}&category=upload_completed"
+        ))
+        .insert_header(("content-type", "application/x-www-form-urlencoded"))
+        .set_payload("List-Unsubscribe=One-Click")
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let req = TestRequest::get()
+        .uri("/api/email-preferences")
+        .to_request();
+    let preferences: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(
+        preferences,
+        json!({
+            "upload_completed": false,
+            "upload_failed": true,
+            "subscription_renewed": true,
+            "low_balance": true,
+            "locale": "en",
+        })
+    );
+    assert_eq!(emails::email_for_event(&state, &completed).await, Ok(None));
+
+    // Отписка от всего не касается обязательных писем
+    let req = TestRequest::post()
+        .uri(&format!("/email/unsubscribe?token={token}"))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let low = DomainEvent::CreditsLow {
+        user_id,
+        credits: 1,
+        monthly_quota: 0,
+    };
+    assert_eq!(emails::email_for_event(&state, &low).await, Ok(None));
+    assert!(
+        emails::email_for_event(&state, &receipt)
+            .await
+            .expect("compose")
+            .is_some()
+    );
+
+    let req = TestRequest::put()
+        .uri("/api/email-preferences")
+        .set_json(json!({ "low_balance": true }))
+        .to_request();
+    let preferences: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(preferences["low_balance"], true);
+    assert_eq!(preferences["upload_completed"], false);
+    let message = emails::email_for_event(&state, &low)
+        .await
+        .expect("compose")
+        .expect("low balance email");
+    assert!(message.text.contains("1 credit(s) left"));
+
+    let req = TestRequest::put()
+        .uri("/api/email-preferences")
+        .set_json(json!({ "locale": "de" }))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+    let req = TestRequest::put()
+        .uri("/api/email-preferences")
+        .set_json(json!({ "locale": "ru" }))
+        .to_request();
+    let preferences: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(preferences["locale"], "ru");
+    let message = emails::email_for_event(&state, &low)
+        .await
+        .expect("compose")
+        .expect("low balance email");
+    assert_eq!(message.locale, "ru");
+    assert_eq!(message.subject, "Кредиты заканчиваются");
+
+    // На неподтверждённый адрес ничего не уходит
+    let (unverified_id, _) = create_user(pool, 0, false).await;
+    let receipt = DomainEvent::PaymentSucceeded {
+        transaction_id,
+        user_id: unverified_id,
+    };
+    assert_eq!(emails::email_for_event(&state, &receipt).await, Ok(None));
+}
+
+async fn consume(pool: &PgPool, user_id: i32, credit_type: &str) -> bool {
+    let mut tx = pool.begin().await.expect("begin");
+    let crossed = consume_credit(&mut tx, user_id, credit_type, 1)
+        .await
+        .expect("consume credit")
+        .expect("enough credits");
+    tx.commit().await.expect("commit");
+    crossed
+}
+
+#[actix_web::test]
+async fn low_balance_is_recorded_once_when_crossing_threshold() {
+    let test_db = support::init_test_db().await;
+    let pool = &test_db.pool;
+    let (user_id, _) = create_user(pool, 4, true).await;
+    let low_events = || async {
+        sqlx::query(
+            r#"SELECT payload::text AS payload
+               FROM domain_events
+               WHERE user_id = $1 AND event_type = 'credits.low'"#,
+        )
+        .bind(user_id)
+        .fetch_all(pool)
+        .await
+        .expect("events")
+        .into_iter()
+        .map(|r| {
+            let payload: String = r.get("payload");
+            serde_json::from_str::<DomainEvent>(&payload).expect("domain event")
+        })
+        .collect::<Vec<_>>()
+    };
+
+    // Порог по умолчанию — 3: событие только при переходе 3 -> 2
+    assert!(!consume(pool, user_id, "one_time").await);
+    assert!(low_events().await.is_empty());
+    assert!(consume(pool, user_id, "one_time").await);
+    assert!(!consume(pool, user_id, "one_time").await);
+    assert!(!consume(pool, user_id, "free").await);
+    assert_eq!(
+        low_events().await,
+        vec![DomainEvent::CreditsLow {
+            user_id,
+            credits: 2,
+            monthly_quota: 0,
+        }]
+    );
+}
+
+async fn outbox_rows(pool: &PgPool, user_id: i32) -> Vec<(String, String, i32, Option<String>)> {
+    sqlx::query(
+        r#"SELECT kind, status, attempts, last_error
+           FROM email_outbox
+           WHERE user_id = $1
+           ORDER BY id"#,
+    )
+    .bind(user_id)
+    .fetch_all(pool)
+    .await
+    .expect("email outbox")
+    .into_iter()
+    .map(|r| {
+        (
+            r.get("kind"),
+            r.get("status"),
+            r.get("attempts"),
+            r.get("last_error"),
+        )
+    })
+    .collect()
+}
+
+async fn make_due(pool: &PgPool, user_id: i32) {
+    sqlx::query("UPDATE email_outbox SET next_attempt_at = NOW() WHERE user_id = $1")
+        .bind(user_id)
+        .execute(pool)
+        .await
+        .expect("make due");
+}
+
+#[actix_web::test]
+async fn verification_email_is_queued_and_retried_until_sent() {
+    let test_db = support::init_test_db().await;
+    set_env("APP_BASE_URL", "https://app.example.com");
+    let pool = &test_db.pool;
+    let (user_id, email) = create_user(pool, 0, false).await;
+    sqlx::query("UPDATE users SET locale = 'ru' WHERE id = $1")
+        .bind(user_id)
+        .execute(pool)
+        .await
+        .expect("set locale");
+    let state = web::Data::new(support::build_state(pool.clone(), "test-key").await);
+    let app = test::init_service(
+        App::new()
+            .app_data(state.clone())
+            .service(resend_verification),
+    )
+    .await;
+
+    let req = TestRequest::post()
+        .uri("/auth/resend-verification")
+        .set_json(json!({ "email": email }))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(
+        outbox_rows(pool, user_id).await,
+        vec![("verify_email".to_string(), "pending".to_string(), 0, None)]
+    );
+
+    let mailer = MemoryMailer::new();
+    let config = EmailConfig::default();
+    mailer.fail_next(MailError::temporary("451 try again later"));
+    assert_eq!(sender::send_pending(&state, &mailer, &config).await, Ok(1));
+    assert_eq!(
+        outbox_rows(pool, user_id).await,
+        vec![(
+            "verify_email".to_string(),
+            "pending".to_string(),
+            1,
+            Some("451 try again later".to_string())
+        )]
+    );
+    // Следующая попытка — только после паузы
+    assert_eq!(sender::send_pending(&state, &mailer, &config).await, Ok(0));
+    assert!(mailer.sent().is_empty());
+
+    make_due(pool, user_id).await;
+    assert_eq!(sender::send_pending(&state, &mailer, &config).await, Ok(1));
+    assert_eq!(
+        outbox_rows(pool, user_id).await,
+        vec![("verify_email".to_string(), "sent".to_string(), 2, None)]
+    );
+    let sent = mailer.sent();
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].to, email);
+    assert_eq!(sent[0].subject, "Подтвердите email для Sora Clean");
+    assert!(sent[0].unsubscribe_url.is_none());
+    let token: Uuid = sqlx::query("SELECT token FROM email_verification_tokens WHERE user_id = $1")
+        .bind(user_id)
+        .fetch_one(pool)
+        .await
+        .expect("token")
+        .get("token");
+    let verify_url = format!("https://app.example.com/verify-email?token={token}");
+    assert!(sent[0].text.contains(&verify_url));
+    assert!(sent[0].html.contains(&verify_url));
+
+    // «Жёсткий» отказ не повторяется
+    let (bounced_id, bounced) = create_user(pool, 0, false).await;
+    let email = emails::render(
+        EmailKind::VerifyEmail,
+        "en",
+        &bounced,
+        Vars::from([("verify_url", "https://app.example.com/verify".to_string())]),
+        None,
+    )
+    .expect("render");
+    assert!(
+        sender::enqueue(pool, Some(bounced_id), None, &email)
+            .await
+            .expect("enqueue")
+    );
+    mailer.fail_next(MailError::permanent("550 mailbox unavailable"));
+    assert_eq!(sender::send_pending(&state, &mailer, &config).await, Ok(1));
+    assert_eq!(
+        outbox_rows(pool, bounced_id).await,
+        vec![(
+            "verify_email".to_string(),
+            "failed".to_string(),
+            1,
+            Some("550 mailbox unavailable".to_string())
+        )]
+    );
+    make_due(pool, bounced_id).await;
+    assert_eq!(sender::send_pending(&state, &mailer, &config).await, Ok(0));
+    assert_eq!(mailer.sent().len(), 1);
+}
+
+#[actix_web::test]
+async fn domain_event_enqueues_its_email_once() {
+    let test_db = support::init_test_db().await;
+    set_env("APP_BASE_URL", "https://app.example.com");
+    let pool = &test_db.pool;
+    let (user_id, _) = create_user(pool, 0, true).await;
+    let state = support::build_state(pool.clone(), "test-key").await;
+
+    let low = DomainEvent::CreditsLow {
+        user_id,
+        credits: 2,
+        monthly_quota: 0,
+    };
+    let event_id = outbox::record(pool, &low).await.expect("record");
+    outbox::dispatch_pending(&state, &Subscribers::from_env(), &OutboxConfig::default())
+        .await
+        .expect("dispatch");
+    assert_eq!(
+        outbox_rows(pool, user_id).await,
+        vec![("low_balance".to_string(), "pending".to_string(), 0, None)]
+    );
+
+    // Повторная передача того же события (например, после сбоя другого подписчика)
+    let event = outbox::StoredEvent {
+        id: event_id,
+        attempts: 2,
+        created_at: None,
+        event: low,
+    };
+    assert_eq!(emails::enqueue_event(&state, &event).await, Ok(false));
+    assert_eq!(outbox_rows(pool, user_id).await.len(), 1);
+
+    let mailer = MemoryMailer::new();
+    assert_eq!(
+        sender::send_pending(&state, &mailer, &EmailConfig::default()).await,
+        Ok(1)
+    );
+    let sent = mailer.sent();
+    assert_eq!(sent.len(), 1);
+    assert!(sent[0].text.contains("2 credit(s) left"));
+    assert!(
+        sent[0]
+            .unsubscribe_url
+            .as_deref()
+            .is_some_and(|url| url.ends_with("&category=low_balance"))
+    );
+}
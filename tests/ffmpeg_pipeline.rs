@@ -0,0 +1,446 @@
+use actix_web::dev::Service;
+use actix_web::test::TestRequest;
+use actix_web::{App, HttpMessage, HttpServer, test, web};
+use aws_sdk_s3::Client as S3Client;
+use aws_sdk_s3::config::{BehaviorVersion, Credentials, Region};
+use httpmock::Method::{GET, PUT};
+use httpmock::MockServer;
+use sqlx::Row;
+use std::net::TcpListener;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use uuid::Uuid;
+
+use sora_watermark_remov::api::handlers::upload;
+use sora_watermark_remov::api::webhooks::watermark_callback;
+use sora_watermark_remov::AppState;
+use sora_watermark_remov::providers::{
+    FfmpegConfig, FfmpegRemover, RemoverRegistry, ResultStorage, SubmitJob, TaskStatus,
+    WatermarkRemover,
+};
+use sora_watermark_remov::submitter::{SubmitConfig, process_due_submissions};
+
+mod support;
+
+fn set_env(key: &str, value: &str) {
+    unsafe {
+        std::env::set_var(key, value);
+    }
+}
+
+fn s3_client(endpoint: &str) -> S3Client {
+    let config = aws_sdk_s3::Config::builder()
+        .behavior_version(BehaviorVersion::latest())
+        .region(Region::new("us-east-1"))
+        .credentials_provider(Credentials::new("test", "test", None, None, "tests"))
+        .endpoint_url(endpoint)
+        .force_path_style(true)
+        .build();
+    S3Client::from_conf(config)
+}
+
+fn build_form(boundary: &str, fields: &[(&str, &str)]) -> Vec<u8> {
+    let mut body = Vec::new();
+    for (name, value) in fields {
+        body.extend_from_slice(format!("--{boundary}\r\n").as_bytes());
+        body.extend_from_slice(
+            format!("Content-Disposition: form-data; name=\"{name}\"\r\n\r\n").as_bytes(),
+        );
+        body.extend_from_slice(value.as_bytes());
+        body.extend_from_slice(b"\r\n");
+    }
+    body.extend_from_slice(format!("--{boundary}--\r\n").as_bytes());
+    body
+}
+
+fn write_script(path: &Path, body: &str) {
+    std::fs::write(path, format!("#!/bin/sh\n{body}")).expect("write script");
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755)).expect("chmod script");
+}
+
+/// Заглушки ffmpeg/ffprobe: копируют вход в выход, печатают прогресс и сохраняют аргументы
+/// рядом с результатом (`<output>.args`).
+fn fake_binaries(dir: &Path, fail: bool) -> (String, String) {
+    let ffprobe = dir.join("ffprobe");
+    write_script(
+        &ffprobe,
+        r#"echo '{"streams": [{"width": 640, "height": 360}], "format": {"duration": "2.000000"}}'"#,
+    );
+
+    let ffmpeg = dir.join("ffmpeg");
+    let finish = if fail {
+        "echo 'boom: invalid data' >&2\nexit 1"
+    } else {
+        "echo 'out_time_us=2000000'\necho 'progress=end'\ncp \"$input\" \"$output\""
+    };
+    write_script(
+        &ffmpeg,
+        &format!(
+            r#"prev=""
+for arg in "$@"; do
+  if [ "$prev" = "-i" ]; then input="$arg"; fi
+  prev="$arg"
+  output="$arg"
+done
+printf '%s\n' "$@" > "$output.args"
+echo 'out_time_us=1000000'
+echo 'progress=continue'
+{finish}
+"#
+        ),
+    );
+    (
+        ffmpeg.to_string_lossy().into_owned(),
+        ffprobe.to_string_lossy().into_owned(),
+    )
+}
+
+fn system_ffmpeg_available() -> bool {
+    ["ffmpeg", "ffprobe"].iter().all(|bin| {
+        std::process::Command::new(bin)
+            .arg("-version")
+            .output()
+            .is_ok_and(|out| out.status.success())
+    })
+}
+
+/// Двухсекундный тестовый ролик, если в системе есть ffmpeg.
+fn render_test_clip(dir: &Path) -> Vec<u8> {
+    let path = dir.join("clip.mp4");
+    let status = std::process::Command::new("ffmpeg")
+        .args(["-hide_banner", "-loglevel", "error", "-y", "-f", "lavfi"])
+        .args(["-i", "testsrc=size=640x360:rate=10", "-t", "2", "-pix_fmt", "yuv420p"])
+        .arg(&path)
+        .status()
+        .expect("run ffmpeg");
+    assert!(status.success(), "render test clip");
+    std::fs::read(&path).expect("read test clip")
+}
+
+fn work_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("{name}-{}", Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).expect("work dir");
+    dir
+}
+
+async fn insert_user(pool: &sqlx::PgPool, name: &str) -> i32 {
+    sqlx::query(
+        r#"INSERT INTO users (username, email, password_hash, credits, monthly_quota, free_generation_used)
+           VALUES ($1, $2, $3, 1, 0, true)
+           RETURNING id"#,
+    )
+    .bind(name)
+    .bind(format!("{name}_{}@example.com", Uuid::new_v4()))
+    .bind("hash")
+    .fetch_one(pool)
+    .await
+    .expect("insert user")
+    .get("id")
+}
+
+/// Отправляет загрузку из ответа `POST /upload` воркером и возвращает id задачи.
+async fn submit_queued(state: &AppState, body: &serde_json::Value) -> String {
+    let upload_id = body["upload_id"].as_i64().expect("upload id") as i32;
+    process_due_submissions(state, &SubmitConfig::default())
+        .await
+        .expect("submit");
+    sqlx::query("SELECT task_id FROM uploads WHERE id = $1")
+        .bind(upload_id)
+        .fetch_one(&state.pool)
+        .await
+        .expect("select upload")
+        .get::<Option<String>, _>("task_id")
+        .expect("task id")
+}
+
+#[actix_web::test]
+async fn ffmpeg_backend_processes_upload_end_to_end() {
+    let bin_dir = work_dir("ffmpeg-bin");
+    let work = work_dir("ffmpeg-e2e");
+    // На машинах с ffmpeg гоняем настоящий, иначе — заглушку с тем же интерфейсом
+    let real = system_ffmpeg_available();
+    let (ffmpeg, ffprobe, clip) = if real {
+        ("ffmpeg".to_string(), "ffprobe".to_string(), render_test_clip(&bin_dir))
+    } else {
+        let (ffmpeg, ffprobe) = fake_binaries(&bin_dir, false);
+        (ffmpeg, ffprobe, vec![5u8; 64 * 1024])
+    };
+
+    let cdn = MockServer::start_async().await;
+    cdn.mock(|when, then| {
+        when.method(GET).path("/videos/clip.mp4");
+        then.status(200).header("Content-Type", "video/mp4").body(clip.clone());
+    });
+    let s3 = MockServer::start_async().await;
+    let put = s3.mock(|when, then| {
+        when.method(PUT).path_contains("/test-bucket/cleaned/ffmpeg-");
+        then.status(200).header("ETag", "\"e2e\"");
+    });
+
+    let test_db = support::init_test_db().await;
+    set_env("MOCK_S3", "false");
+    set_env("OUTBOUND_ALLOW_PRIVATE", "true");
+    set_env("FFMPEG_WORK_DIR", &work.to_string_lossy());
+    let pool = &test_db.pool;
+    let user_id = insert_user(pool, "ffmpeg_user").await;
+
+    // Колбэки бэкенд отправляет по HTTP, как и KIE: поднимаем настоящий сервер
+    let listener = TcpListener::bind("127.0.0.1:0").expect("bind callback server");
+    let callback_addr = listener.local_addr().expect("callback addr");
+
+    let mut state = support::build_state(test_db.pool.clone(), "test-key").await;
+    state.s3_client = s3_client(&s3.base_url());
+    state.callback_base_url = format!("http://{callback_addr}");
+    let remover = FfmpegRemover::new(FfmpegConfig {
+        ffmpeg,
+        ffprobe,
+        work_dir: work.clone(),
+        ..FfmpegConfig::default()
+    })
+    .with_storage(ResultStorage {
+        client: state.s3_client.clone(),
+        bucket: state.s3_bucket.clone(),
+    });
+    state.removers = Arc::new(RemoverRegistry::new().register(remover));
+    let state = web::Data::new(state);
+
+    let server = HttpServer::new({
+        let state = state.clone();
+        move || App::new().app_data(state.clone()).service(watermark_callback)
+    })
+    .workers(1)
+    .listen(listener)
+    .expect("listen")
+    .run();
+    let server_handle = server.handle();
+    actix_web::rt::spawn(server);
+
+    let app = test::init_service(
+        App::new()
+            .app_data(state.clone())
+            .wrap_fn(move |req, srv| {
+                req.extensions_mut().insert(user_id);
+                srv.call(req)
+            })
+            .service(upload),
+    )
+    .await;
+
+    let boundary = "BOUNDARY";
+    let req = TestRequest::post()
+        .uri("/upload")
+        .insert_header(("content-type", format!("multipart/form-data; boundary={boundary}")))
+        .set_payload(build_form(boundary, &[("url", &cdn.url("/videos/clip.mp4"))]))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert!(resp.status().is_success());
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    let task_id = submit_queued(&state, &body).await;
+    assert!(task_id.starts_with("ffmpeg-"), "{task_id}");
+
+    let mut row = None;
+    for _ in 0..300 {
+        let current = sqlx::query(
+            "SELECT status, provider, progress, cleaned_s3_key, cleaned_url FROM uploads WHERE task_id = $1",
+        )
+        .bind(&task_id)
+        .fetch_one(pool)
+        .await
+        .expect("select upload");
+        if current.get::<String, _>("status") != "processing" {
+            row = Some(current);
+            break;
+        }
+        actix_web::rt::time::sleep(Duration::from_millis(100)).await;
+    }
+    let row = row.expect("upload finished");
+
+    assert_eq!(row.get::<String, _>("status"), "ready");
+    assert_eq!(row.get::<Option<String>, _>("provider").as_deref(), Some("ffmpeg"));
+    assert!(row.get::<Option<i16>, _>("progress").is_some_and(|p| p >= 50));
+    let expected_key = format!("cleaned/{task_id}.mp4");
+    assert_eq!(row.get::<Option<String>, _>("cleaned_s3_key").as_deref(), Some(expected_key.as_str()));
+    assert_eq!(
+        row.get::<Option<String>, _>("cleaned_url"),
+        Some(format!("http://localhost/test-bucket/{expected_key}"))
+    );
+    put.assert_hits(1);
+
+    // Результат залил в хранилище сам бэкенд, локальные файлы удалены
+    assert!(!work.join(format!("{task_id}.source")).exists());
+    assert!(!work.join(format!("{task_id}.mp4")).exists());
+    if !real {
+        let args = std::fs::read_to_string(work.join(format!("{task_id}.mp4.args"))).expect("args");
+        assert!(args.contains("delogo=x=13:y=11:w=192:h=29,"), "{args}");
+        assert_eq!(args.matches("delogo=").count(), 3, "{args}");
+    }
+
+    server_handle.stop(true).await;
+    let _ = std::fs::remove_dir_all(&work);
+    let _ = std::fs::remove_dir_all(&bin_dir);
+}
+
+#[actix_web::test]
+async fn ffmpeg_failure_keeps_upload_processing_and_reports_reason() {
+    let bin_dir = work_dir("ffmpeg-bin");
+    let work = work_dir("ffmpeg-fail");
+    let (ffmpeg, ffprobe) = fake_binaries(&bin_dir, true);
+
+    let cdn = MockServer::start_async().await;
+    cdn.mock(|when, then| {
+        when.method(GET).path("/videos/broken.mp4");
+        then.status(200).body(vec![1u8; 1024]);
+    });
+
+    let test_db = support::init_test_db().await;
+    set_env("MOCK_S3", "false");
+    set_env("OUTBOUND_ALLOW_PRIVATE", "true");
+    set_env("FFMPEG_WORK_DIR", &work.to_string_lossy());
+    let pool = &test_db.pool;
+    let user_id = insert_user(pool, "ffmpeg_fail_user").await;
+
+    let mut state = support::build_state(test_db.pool.clone(), "test-key").await;
+    // Колбэк уходить некуда: итог забираем через poll, как это делает поллер статусов
+    state.callback_base_url = "http://127.0.0.1:9".to_string();
+    let remover = FfmpegRemover::new(FfmpegConfig {
+        ffmpeg,
+        ffprobe,
+        work_dir: work.clone(),
+        ..FfmpegConfig::default()
+    })
+    .with_storage(ResultStorage {
+        client: state.s3_client.clone(),
+        bucket: state.s3_bucket.clone(),
+    });
+    state.removers = Arc::new(RemoverRegistry::new().register(remover));
+    let state = web::Data::new(state);
+
+    let app = test::init_service(
+        App::new()
+            .app_data(state.clone())
+            .wrap_fn(move |req, srv| {
+                req.extensions_mut().insert(user_id);
+                srv.call(req)
+            })
+            .service(upload),
+    )
+    .await;
+
+    // Своя область водяного знака: у края кадра она прижимается внутрь
+    let boundary = "BOUNDARY";
+    let req = TestRequest::post()
+        .uri("/upload")
+        .insert_header(("content-type", format!("multipart/form-data; boundary={boundary}")))
+        .set_payload(build_form(
+            boundary,
+            &[("url", &cdn.url("/videos/broken.mp4")), ("watermark_region", "0,0,50,20")],
+        ))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert!(resp.status().is_success());
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    let task_id = submit_queued(&state, &body).await;
+
+    let remover = state.removers.get("ffmpeg").expect("ffmpeg registered");
+    let mut status = TaskStatus::Pending;
+    for _ in 0..100 {
+        status = remover.poll(&task_id).await.expect("poll");
+        if status != TaskStatus::Pending {
+            break;
+        }
+        actix_web::rt::time::sleep(Duration::from_millis(100)).await;
+    }
+    match status {
+        TaskStatus::Failed { reason } => {
+            let reason = reason.unwrap_or_default();
+            assert!(reason.contains("boom"), "{reason}");
+        }
+        other => panic!("unexpected status {other:?}"),
+    }
+
+    let args = std::fs::read_to_string(work.join(format!("{task_id}.mp4.args"))).expect("args");
+    assert!(args.contains("delogo=x=1:y=1:w=50:h=20\n"), "{args}");
+    assert_eq!(args.matches("delogo=").count(), 1, "{args}");
+    assert!(!work.join(format!("{task_id}.mp4")).exists());
+
+    let status: String = sqlx::query("SELECT status FROM uploads WHERE task_id = $1")
+        .bind(&task_id)
+        .fetch_one(pool)
+        .await
+        .expect("select upload")
+        .get("status");
+    assert_eq!(status, "processing");
+
+    // Чужие задачи (запущенные другим процессом или до перезапуска) этот процесс не знает
+    assert_eq!(
+        remover.poll("ffmpeg-unknown").await.expect("poll"),
+        TaskStatus::Pending
+    );
+
+    let _ = std::fs::remove_dir_all(&work);
+    let _ = std::fs::remove_dir_all(&bin_dir);
+}
+
+#[actix_web::test]
+async fn invalid_watermark_region_is_rejected() {
+    let test_db = support::init_test_db().await;
+    let user_id = insert_user(&test_db.pool, "ffmpeg_region_user").await;
+    let state = web::Data::new(support::build_state(test_db.pool.clone(), "test-key").await);
+    let app = test::init_service(
+        App::new()
+            .app_data(state.clone())
+            .wrap_fn(move |req, srv| {
+                req.extensions_mut().insert(user_id);
+                srv.call(req)
+            })
+            .service(upload),
+    )
+    .await;
+
+    let boundary = "BOUNDARY";
+    let req = TestRequest::post()
+        .uri("/upload")
+        .insert_header(("content-type", format!("multipart/form-data; boundary={boundary}")))
+        .set_payload(build_form(
+            boundary,
+            &[("url", "https://cdn.example.com/video.mp4"), ("watermark_region", "10,10")],
+        ))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 400);
+
+    let uploads: i64 = sqlx::query("SELECT COUNT(*) AS n FROM uploads")
+        .fetch_one(&test_db.pool)
+        .await
+        .expect("count uploads")
+        .get("n");
+    assert_eq!(uploads, 0);
+}
+
+#[actix_web::test]
+async fn shutdown_waits_for_running_tasks_and_forgets_delivered_results() {
+    let callbacks = MockServer::start_async().await;
+    // Медленный приём колбэка: остановка должна дождаться его доставки
+    let callback = callbacks.mock(|when, then| {
+        when.method(httpmock::Method::POST).path("/api/watermark-callback");
+        then.status(200).delay(Duration::from_millis(500));
+    });
+
+    // Без хранилища задача сразу завершается ошибкой и отправляет итоговый колбэк
+    let remover = FfmpegRemover::new(FfmpegConfig::default());
+    let job = SubmitJob {
+        video_url: "https://cdn.example.com/video.mp4".to_string(),
+        callback_url: callbacks.url("/api/watermark-callback"),
+        region: None,
+        model: None,
+        extra_input: Default::default(),
+    };
+    let task_id = remover.submit(&job).await.expect("submit");
+
+    remover.shutdown(Duration::from_secs(10)).await;
+    callback.assert();
+    // Итог доставлен колбэком: больше он процессу не нужен
+    assert_eq!(remover.poll(&task_id).await.expect("poll"), TaskStatus::Pending);
+}
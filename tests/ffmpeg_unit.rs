@@ -0,0 +1,134 @@
+use serde_json::json;
+use sora_watermark_remov::providers::ffmpeg::{
+    VideoInfo, clamp_region, delogo_filter, parse_probe, parse_progress, sora_regions,
+    stored_result_url,
+};
+use sora_watermark_remov::providers::{
+    FfmpegConfig, FfmpegRemover, TaskStatus, WatermarkRegion, WatermarkRemover,
+};
+
+const HD: VideoInfo = VideoInfo {
+    width: 1280,
+    height: 720,
+    duration_us: 10_000_000,
+};
+
+fn region(x: u32, y: u32, width: u32, height: u32) -> WatermarkRegion {
+    WatermarkRegion {
+        x,
+        y,
+        width,
+        height,
+    }
+}
+
+#[test]
+fn watermark_region_is_parsed_and_validated() {
+    assert_eq!(WatermarkRegion::parse(" 12, 20,220,64 "), Ok(region(12, 20, 220, 64)));
+    assert_eq!(region(12, 20, 220, 64).to_string(), "12,20,220,64");
+    assert!(WatermarkRegion::parse("12,20,220").is_err());
+    assert!(WatermarkRegion::parse("12,20,0,64").is_err());
+    assert!(WatermarkRegion::parse("a,b,c,d").is_err());
+    assert!(WatermarkRegion::parse("0,0,100000,10").is_err());
+}
+
+#[test]
+fn probe_output_is_parsed() {
+    let info = parse_probe(
+        r#"{"programs": [], "streams": [{"width": 1280, "height": 720}], "format": {"duration": "10.000000"}}"#,
+    )
+    .expect("probe");
+    assert_eq!(info, HD);
+
+    let no_duration = parse_probe(r#"{"streams": [{"width": 640, "height": 360}], "format": {}}"#)
+        .expect("probe without duration");
+    assert_eq!(no_duration.duration_us, 0);
+
+    assert!(parse_probe(r#"{"streams": [], "format": {"duration": "1.0"}}"#).is_err());
+    assert!(parse_probe("not json").is_err());
+}
+
+#[test]
+fn regions_are_clamped_to_the_frame() {
+    // Внутри кадра — без изменений
+    assert_eq!(clamp_region(&region(10, 10, 100, 40), &HD), Some(region(10, 10, 100, 40)));
+    // У края: отступ в пиксель и обрезка по кадру
+    assert_eq!(clamp_region(&region(0, 0, 100, 40), &HD), Some(region(1, 1, 100, 40)));
+    assert_eq!(
+        clamp_region(&region(1200, 700, 200, 100), &HD),
+        Some(region(1200, 700, 79, 19))
+    );
+    // Полностью за кадром
+    assert_eq!(clamp_region(&region(1279, 10, 10, 10), &HD), None);
+    assert_eq!(clamp_region(&region(5000, 5000, 10, 10), &HD), None);
+}
+
+#[test]
+fn delogo_filter_covers_known_sora_positions() {
+    let regions = sora_regions(&HD);
+    assert_eq!(regions.len(), 3);
+    assert_eq!(regions[0], region(26, 22, 384, 58));
+
+    let filter = delogo_filter(&regions, &HD).expect("filter");
+    assert_eq!(filter.matches("delogo=").count(), 3);
+    assert!(filter.starts_with("delogo=x=26:y=22:w=384:h=58,"), "{filter}");
+
+    // Регион пользователя за кадром — фильтра нет
+    assert_eq!(delogo_filter(&[region(2000, 2000, 10, 10)], &HD), None);
+}
+
+#[test]
+fn progress_lines_are_parsed() {
+    assert_eq!(parse_progress("out_time_us=2500000", 10_000_000), Some(25));
+    assert_eq!(parse_progress("out_time_ms=5000000\n", 10_000_000), Some(50));
+    // Никогда не 100%, пока ffmpeg не сообщил о завершении
+    assert_eq!(parse_progress("out_time_us=12000000", 10_000_000), Some(99));
+    assert_eq!(parse_progress("progress=end", 0), Some(100));
+    assert_eq!(parse_progress("progress=continue", 10_000_000), None);
+    assert_eq!(parse_progress("out_time_us=N/A", 10_000_000), None);
+    assert_eq!(parse_progress("out_time_us=2500000", 0), None);
+    assert_eq!(parse_progress("frame=10", 10_000_000), None);
+}
+
+#[test]
+fn stored_results_use_the_cleaned_key() {
+    assert_eq!(
+        stored_result_url("videos", "ffmpeg-1"),
+        "s3://videos/cleaned/ffmpeg-1.mp4"
+    );
+}
+
+#[test]
+fn callbacks_are_parsed() {
+    let remover = FfmpegRemover::new(FfmpegConfig::default());
+
+    let running = remover
+        .parse_callback(&json!({ "taskId": "ffmpeg-1", "status": "running", "progress": 40 }))
+        .expect("running");
+    assert_eq!(running.status, TaskStatus::Pending);
+    assert_eq!(running.progress, Some(40));
+
+    let done = remover
+        .parse_callback(&json!({ "taskId": "ffmpeg-1", "status": "success", "resultUrl": "s3://videos/cleaned/ffmpeg-1.mp4" }))
+        .expect("success");
+    assert_eq!(
+        done.status,
+        TaskStatus::Succeeded {
+            result_url: "s3://videos/cleaned/ffmpeg-1.mp4".to_string()
+        }
+    );
+
+    let failed = remover
+        .parse_callback(&json!({ "taskId": "ffmpeg-1", "status": "fail", "error": "ffmpeg exited" }))
+        .expect("fail");
+    assert_eq!(
+        failed.status,
+        TaskStatus::Failed {
+            reason: Some("ffmpeg exited".to_string())
+        }
+    );
+
+    assert!(remover.parse_callback(&json!({ "taskId": "ffmpeg-1", "status": "success" })).is_err());
+    assert!(remover.parse_callback(&json!({ "status": "running" })).is_err());
+    assert!(remover.parse_callback(&json!({ "taskId": "ffmpeg-1", "status": "weird" })).is_err());
+}
@@ -0,0 +1,262 @@
+use aws_sdk_s3::Client as S3Client;
+use aws_sdk_s3::config::{BehaviorVersion, Credentials, Region};
+use httpmock::Method::{GET, PUT};
+use httpmock::MockServer;
+use sqlx::Row;
+use uuid::Uuid;
+
+use sora_watermark_remov::finalize::{FinalizeOutcome, finalize_task, finalize_upload};
+
+mod support;
+
+fn set_env(key: &str, value: &str) {
+    unsafe {
+        std::env::set_var(key, value);
+    }
+}
+
+fn s3_client(endpoint: &str) -> S3Client {
+    let config = aws_sdk_s3::Config::builder()
+        .behavior_version(BehaviorVersion::latest())
+        .region(Region::new("us-east-1"))
+        .credentials_provider(Credentials::new("test", "test", None, None, "tests"))
+        .endpoint_url(endpoint)
+        .force_path_style(true)
+        .build();
+    S3Client::from_conf(config)
+}
+
+#[actix_web::test]
+async fn callback_and_poller_race_stores_result_once() {
+    let kie_cdn = MockServer::start_async().await;
+    let s3 = MockServer::start_async().await;
+
+    kie_cdn.mock(|when, then| {
+        when.method(GET).path("/result.mp4");
+        then.status(200)
+            .header("Content-Type", "video/mp4")
+            .body(vec![7u8; 4096]);
+    });
+    let put = s3.mock(|when, then| {
+        when.method(PUT).path("/test-bucket/cleaned/task-final-1.mp4");
+        then.status(200).header("ETag", "\"final\"");
+    });
+
+    let test_db = support::init_test_db().await;
+    set_env("MOCK_S3", "false");
+    set_env("OUTBOUND_ALLOW_PRIVATE", "true");
+    let pool = &test_db.pool;
+
+    let user_id: i32 = sqlx::query(
+        r#"INSERT INTO users (username, email, password_hash, credits, monthly_quota)
+           VALUES ($1, $2, $3, 0, 0)
+           RETURNING id"#,
+    )
+    .bind("finalize_user")
+    .bind(format!("finalize_{}@example.com", Uuid::new_v4()))
+    .bind("hash")
+    .fetch_one(pool)
+    .await
+    .expect("insert user")
+    .get("id");
+
+    let upload_id: i32 = sqlx::query(
+        r#"INSERT INTO uploads (user_id, original_filename, original_s3_key, status, task_id)
+           VALUES ($1, 'original.mp4', 'https://cdn.example.com/original.mp4', 'processing', 'task-final-1')
+           RETURNING id"#,
+    )
+    .bind(user_id)
+    .fetch_one(pool)
+    .await
+    .expect("insert upload")
+    .get("id");
+
+    let mut state = support::build_state(test_db.pool.clone(), "test-key").await;
+    state.s3_client = s3_client(&s3.base_url());
+
+    let result_url = kie_cdn.url("/result.mp4");
+    let first = finalize_upload(&state, upload_id, "task-final-1", &result_url)
+        .await
+        .expect("finalize from callback");
+    assert_eq!(
+        first,
+        FinalizeOutcome::Finalized {
+            cleaned_url: "http://localhost/test-bucket/cleaned/task-final-1.mp4".to_string()
+        }
+    );
+
+    // Поллер увидел ту же задачу позже: повторно не качаем и событие не шлём
+    let second = finalize_task(&state, "task-final-1", &result_url)
+        .await
+        .expect("finalize from poller");
+    assert_eq!(second, FinalizeOutcome::AlreadyFinalized);
+    put.assert_hits(1);
+
+    let row = sqlx::query("SELECT status, cleaned_s3_key, cleaned_url FROM uploads WHERE id = $1")
+        .bind(upload_id)
+        .fetch_one(pool)
+        .await
+        .expect("select upload");
+    assert_eq!(row.get::<String, _>("status"), "ready");
+    assert_eq!(
+        row.get::<Option<String>, _>("cleaned_s3_key").as_deref(),
+        Some("cleaned/task-final-1.mp4")
+    );
+    assert_eq!(
+        row.get::<Option<String>, _>("cleaned_url").as_deref(),
+        Some("http://localhost/test-bucket/cleaned/task-final-1.mp4")
+    );
+}
+
+#[actix_web::test]
+async fn failed_download_leaves_upload_processing() {
+    let kie_cdn = MockServer::start_async().await;
+    kie_cdn.mock(|when, then| {
+        when.method(GET).path("/expired.mp4");
+        then.status(403);
+    });
+
+    let test_db = support::init_test_db().await;
+    set_env("MOCK_S3", "false");
+    set_env("OUTBOUND_ALLOW_PRIVATE", "true");
+    let pool = &test_db.pool;
+
+    let user_id: i32 = sqlx::query(
+        r#"INSERT INTO users (username, email, password_hash, credits, monthly_quota)
+           VALUES ($1, $2, $3, 0, 0)
+           RETURNING id"#,
+    )
+    .bind("finalize_fail_user")
+    .bind(format!("finalize_fail_{}@example.com", Uuid::new_v4()))
+    .bind("hash")
+    .fetch_one(pool)
+    .await
+    .expect("insert user")
+    .get("id");
+
+    sqlx::query(
+        r#"INSERT INTO uploads (user_id, original_filename, original_s3_key, status, task_id)
+           VALUES ($1, 'original.mp4', 'https://cdn.example.com/original.mp4', 'processing', 'task-final-2')"#,
+    )
+    .bind(user_id)
+    .execute(pool)
+    .await
+    .expect("insert upload");
+
+    let state = support::build_state(test_db.pool.clone(), "test-key").await;
+    let err = finalize_task(&state, "task-final-2", &kie_cdn.url("/expired.mp4"))
+        .await
+        .expect_err("download must fail");
+    assert!(err.contains("403"), "{err}");
+
+    let row = sqlx::query("SELECT status, cleaned_s3_key FROM uploads WHERE task_id = 'task-final-2'")
+        .fetch_one(pool)
+        .await
+        .expect("select upload");
+    assert_eq!(row.get::<String, _>("status"), "processing");
+    assert_eq!(row.get::<Option<String>, _>("cleaned_s3_key"), None);
+}
+
+#[actix_web::test]
+async fn stored_result_is_accepted_only_from_ffmpeg_for_its_task() {
+    // Хранилище не должно получить ни одного запроса: результат ffmpeg уже лежит в нём
+    let s3 = MockServer::start_async().await;
+    let put = s3.mock(|when, then| {
+        when.method(PUT);
+        then.status(200).header("ETag", "\"unexpected\"");
+    });
+
+    let test_db = support::init_test_db().await;
+    set_env("MOCK_S3", "false");
+    let pool = &test_db.pool;
+
+    let user_id: i32 = sqlx::query(
+        r#"INSERT INTO users (username, email, password_hash, credits, monthly_quota)
+           VALUES ($1, $2, $3, 0, 0)
+           RETURNING id"#,
+    )
+    .bind("finalize_stored_user")
+    .bind(format!("finalize_stored_{}@example.com", Uuid::new_v4()))
+    .bind("hash")
+    .fetch_one(pool)
+    .await
+    .expect("insert user")
+    .get("id");
+
+    sqlx::query(
+        r#"INSERT INTO uploads (user_id, original_filename, original_s3_key, status, task_id, provider)
+           VALUES ($1, 'a.mp4', 'https://cdn.example.com/a.mp4', 'processing', 'task-final-3', 'kie'),
+                  ($1, 'b.mp4', 'https://cdn.example.com/b.mp4', 'processing', 'ffmpeg-final-4', 'ffmpeg')"#,
+    )
+    .bind(user_id)
+    .execute(pool)
+    .await
+    .expect("insert uploads");
+
+    let mut state = support::build_state(test_db.pool.clone(), "test-key").await;
+    state.s3_client = s3_client(&s3.base_url());
+
+    let err = finalize_task(&state, "task-final-3", "s3://test-bucket/cleaned/task-final-3.mp4")
+        .await
+        .expect_err("kie result must be downloaded");
+    assert!(err.contains("rejected"), "{err}");
+
+    let err = finalize_task(&state, "ffmpeg-final-4", "s3://test-bucket/cleaned/task-final-3.mp4")
+        .await
+        .expect_err("foreign key");
+    assert!(err.contains("does not belong"), "{err}");
+
+    let outcome = finalize_task(&state, "ffmpeg-final-4", "s3://test-bucket/cleaned/ffmpeg-final-4.mp4")
+        .await
+        .expect("finalize");
+    assert_eq!(
+        outcome,
+        FinalizeOutcome::Finalized {
+            cleaned_url: "http://localhost/test-bucket/cleaned/ffmpeg-final-4.mp4".to_string()
+        }
+    );
+    put.assert_hits(0);
+}
+
+#[actix_web::test]
+async fn late_result_does_not_reopen_failed_upload() {
+    let test_db = support::init_test_db().await;
+    set_env("MOCK_S3", "true");
+    let pool = &test_db.pool;
+
+    let user_id: i32 = sqlx::query(
+        r#"INSERT INTO users (username, email, password_hash, credits, monthly_quota)
+           VALUES ($1, $2, $3, 1, 0)
+           RETURNING id"#,
+    )
+    .bind("finalize_late_user")
+    .bind(format!("finalize_late_{}@example.com", Uuid::new_v4()))
+    .bind("hash")
+    .fetch_one(pool)
+    .await
+    .expect("insert user")
+    .get("id");
+
+    // Загрузку уже закрыл таймаут и вернул кредит
+    sqlx::query(
+        r#"INSERT INTO uploads (user_id, original_filename, original_s3_key, status, task_id)
+           VALUES ($1, 'late.mp4', 'https://cdn.example.com/late.mp4', 'failed', 'task-final-5')"#,
+    )
+    .bind(user_id)
+    .execute(pool)
+    .await
+    .expect("insert upload");
+
+    let state = support::build_state(test_db.pool.clone(), "test-key").await;
+    let outcome = finalize_task(&state, "task-final-5", "https://cdn.example.com/late-result.mp4")
+        .await
+        .expect("finalize");
+    assert_eq!(outcome, FinalizeOutcome::Closed);
+
+    let row = sqlx::query("SELECT status, cleaned_url FROM uploads WHERE task_id = 'task-final-5'")
+        .fetch_one(pool)
+        .await
+        .expect("select upload");
+    assert_eq!(row.get::<String, _>("status"), "failed");
+    assert_eq!(row.get::<Option<String>, _>("cleaned_url"), None);
+}
@@ -0,0 +1,360 @@
+use httpmock::Method::GET;
+use httpmock::MockServer;
+use serde_json::json;
+use sqlx::{PgPool, Row};
+use std::time::Duration;
+use uuid::Uuid;
+
+use sora_watermark_remov::queue::{
+    JobQueue, NewJob, PgJobQueue, STATUS_DEAD_LETTER_QUEUE, STATUS_QUEUE, StatusQueueConfig,
+    consume_once, enqueue_pending_tasks, expire_stale_tasks,
+};
+
+mod support;
+
+fn set_env(key: &str, value: &str) {
+    unsafe {
+        std::env::set_var(key, value);
+    }
+}
+
+async fn queued_jobs(pool: &PgPool, queue: &str) -> i64 {
+    sqlx::query("SELECT COUNT(*) AS n FROM job_queue WHERE queue = $1")
+        .bind(queue)
+        .fetch_one(pool)
+        .await
+        .expect("count jobs")
+        .get("n")
+}
+
+#[actix_web::test]
+async fn enqueue_deduplicates_until_acked() {
+    let test_db = support::init_test_db().await;
+    let queue = PgJobQueue::new(test_db.pool.clone());
+
+    for _ in 0..3 {
+        queue
+            .enqueue(NewJob::new("test", json!({ "task_id": "t1" })).with_dedup_key("t1"))
+            .await
+            .expect("enqueue");
+    }
+    // Без ключа дубликаты разрешены
+    queue.enqueue(NewJob::new("test", json!({ "n": 1 }))).await.expect("enqueue");
+    queue.enqueue(NewJob::new("test", json!({ "n": 1 }))).await.expect("enqueue");
+    assert_eq!(queued_jobs(&test_db.pool, "test").await, 3);
+
+    let jobs = queue.reserve("test", 10, Duration::from_secs(60)).await.expect("reserve");
+    assert_eq!(jobs.len(), 3);
+    assert!(jobs.iter().all(|job| job.attempts == 1));
+    let deduped = jobs
+        .iter()
+        .find(|job| job.payload == json!({ "task_id": "t1" }))
+        .expect("deduped job");
+
+    queue.ack(deduped).await.expect("ack");
+    queue
+        .enqueue(NewJob::new("test", json!({ "task_id": "t1" })).with_dedup_key("t1"))
+        .await
+        .expect("enqueue again");
+    assert_eq!(queued_jobs(&test_db.pool, "test").await, 3);
+}
+
+#[actix_web::test]
+async fn reserved_jobs_are_invisible_until_timeout() {
+    let test_db = support::init_test_db().await;
+    let queue = PgJobQueue::new(test_db.pool.clone());
+
+    queue.enqueue(NewJob::new("test", json!({ "n": 1 }))).await.expect("enqueue");
+    queue.enqueue(NewJob::new("other", json!({ "n": 2 }))).await.expect("enqueue");
+    queue
+        .enqueue(NewJob::new("test", json!({ "n": 3 })).with_delay(Duration::from_secs(3600)))
+        .await
+        .expect("enqueue delayed");
+
+    let first = queue.reserve("test", 10, Duration::from_secs(1)).await.expect("reserve");
+    assert_eq!(first.len(), 1, "other queues and delayed jobs are not reserved");
+    assert_eq!(first[0].payload, json!({ "n": 1 }));
+
+    // Пока задание у воркера, второй воркер его не видит
+    assert!(queue.reserve("test", 10, Duration::from_secs(1)).await.expect("reserve").is_empty());
+
+    // Воркер «упал»: после таймаута видимости задание возвращается
+    actix_web::rt::time::sleep(Duration::from_millis(1200)).await;
+    let again = queue.reserve("test", 10, Duration::from_secs(60)).await.expect("reserve");
+    assert_eq!(again.len(), 1);
+    assert_eq!(again[0].id, first[0].id);
+    assert_eq!(again[0].attempts, 2);
+}
+
+#[actix_web::test]
+async fn concurrent_reserves_do_not_share_jobs() {
+    let test_db = support::init_test_db().await;
+    let queue = PgJobQueue::new(test_db.pool.clone());
+    for n in 0..20 {
+        queue.enqueue(NewJob::new("test", json!({ "n": n }))).await.expect("enqueue");
+    }
+
+    let (a, b) = futures_util::future::join(
+        queue.reserve("test", 15, Duration::from_secs(60)),
+        queue.reserve("test", 15, Duration::from_secs(60)),
+    )
+    .await;
+    let mut ids: Vec<u64> = a
+        .expect("reserve a")
+        .into_iter()
+        .chain(b.expect("reserve b"))
+        .map(|job| job.id)
+        .collect();
+    ids.sort();
+    ids.dedup();
+    assert_eq!(ids.len(), 20);
+}
+
+#[actix_web::test]
+async fn retry_postpones_job_and_records_error() {
+    let test_db = support::init_test_db().await;
+    let queue = PgJobQueue::new(test_db.pool.clone());
+    queue.enqueue(NewJob::new("test", json!({ "n": 1 }))).await.expect("enqueue");
+
+    let job = queue.reserve("test", 1, Duration::from_secs(60)).await.expect("reserve").remove(0);
+    queue.retry(&job, Duration::from_secs(3600), "backend down").await.expect("retry");
+
+    assert!(queue.reserve("test", 1, Duration::from_secs(60)).await.expect("reserve").is_empty());
+    let row = sqlx::query(
+        r#"SELECT attempts, last_error, locked_until IS NULL AS unlocked,
+                  run_at > NOW() + INTERVAL '50 minutes' AS postponed
+           FROM job_queue WHERE id = $1"#,
+    )
+    .bind(job.id as i64)
+    .fetch_one(&test_db.pool)
+    .await
+    .expect("job row");
+    assert_eq!(row.get::<i32, _>("attempts"), 1);
+    assert_eq!(row.get::<Option<String>, _>("last_error").as_deref(), Some("backend down"));
+    assert!(row.get::<bool, _>("unlocked"));
+    assert!(row.get::<bool, _>("postponed"));
+}
+
+async fn create_user(pool: &PgPool, credits: i32) -> i32 {
+    sqlx::query(
+        r#"INSERT INTO users (username, email, password_hash, credits, monthly_quota, quota_reset_at)
+           VALUES ($1, $2, 'hash', $3, 0, NOW() + INTERVAL '30 days')
+           RETURNING id"#,
+    )
+    .bind(format!("queue_user_{}", Uuid::new_v4().simple()))
+    .bind(format!("queue_{}@example.com", Uuid::new_v4()))
+    .bind(credits)
+    .fetch_one(pool)
+    .await
+    .expect("insert user")
+    .get("id")
+}
+
+/// Загрузка в обработке `started_secs_ago` секунд, оплаченная `credit_cost` разовыми кредитами.
+async fn create_processing_upload(
+    pool: &PgPool,
+    user_id: i32,
+    task_id: &str,
+    provider: &str,
+    started_secs_ago: f64,
+    credit_cost: i32,
+) -> i32 {
+    sqlx::query(
+        r#"INSERT INTO uploads
+           (user_id, original_filename, original_s3_key, status, task_id, provider,
+            processing_started_at, used_credit_type, credit_cost)
+           VALUES ($1, 'clip.mp4', 'https://cdn.example.com/clip.mp4', 'processing', $2, $3,
+                   NOW() - make_interval(secs => $4), 'credits', $5)
+           RETURNING id"#,
+    )
+    .bind(user_id)
+    .bind(task_id)
+    .bind(provider)
+    .bind(started_secs_ago)
+    .bind(credit_cost)
+    .fetch_one(pool)
+    .await
+    .expect("insert upload")
+    .get("id")
+}
+
+async fn upload_row(pool: &PgPool, upload_id: i32) -> sqlx::postgres::PgRow {
+    sqlx::query(
+        r#"SELECT status, status_checks, last_error,
+                  EXTRACT(EPOCH FROM next_check_at - NOW())::float8 AS next_check_in
+           FROM uploads WHERE id = $1"#,
+    )
+    .bind(upload_id)
+    .fetch_one(pool)
+    .await
+    .expect("upload")
+}
+
+#[actix_web::test]
+async fn status_checks_back_off_without_duplicates() {
+    let test_db = support::init_test_db().await;
+    let pool = &test_db.pool;
+    let queue = PgJobQueue::new(pool.clone());
+    let config = StatusQueueConfig::default();
+    let user_id = create_user(pool, 0).await;
+    let due = create_processing_upload(pool, user_id, "task-due", "kie", 120.0, 1).await;
+    // Первая проверка — через `poll_interval` после старта
+    let fresh = create_processing_upload(pool, user_id, "task-fresh", "kie", 10.0, 1).await;
+
+    assert_eq!(enqueue_pending_tasks(pool, &queue, &config).await.expect("enqueue"), 1);
+    // Следующий проход продюсера задачу не повторяет: её проверка уже перенесена
+    assert_eq!(enqueue_pending_tasks(pool, &queue, &config).await.expect("enqueue"), 0);
+    assert_eq!(queued_jobs(pool, STATUS_QUEUE).await, 1);
+
+    let row = upload_row(pool, due).await;
+    assert_eq!(row.get::<i32, _>("status_checks"), 1);
+    let next_in: f64 = row.get("next_check_in");
+    assert!((110.0..=130.0).contains(&next_in), "next check in {next_in}s");
+    assert_eq!(upload_row(pool, fresh).await.get::<i32, _>("status_checks"), 0);
+
+    // Пауза удваивается с каждой проверкой, но не дальше `max_poll_interval`
+    sqlx::query("UPDATE uploads SET next_check_at = NOW() WHERE id = $1")
+        .bind(due)
+        .execute(pool)
+        .await
+        .expect("make due");
+    sqlx::query("DELETE FROM job_queue").execute(pool).await.expect("clear queue");
+    assert_eq!(enqueue_pending_tasks(pool, &queue, &config).await.expect("enqueue"), 1);
+    let next_in: f64 = upload_row(pool, due).await.get("next_check_in");
+    assert!((230.0..=250.0).contains(&next_in), "next check in {next_in}s");
+
+    sqlx::query("UPDATE uploads SET next_check_at = NOW(), status_checks = 10 WHERE id = $1")
+        .bind(due)
+        .execute(pool)
+        .await
+        .expect("make due");
+    sqlx::query("DELETE FROM job_queue").execute(pool).await.expect("clear queue");
+    assert_eq!(enqueue_pending_tasks(pool, &queue, &config).await.expect("enqueue"), 1);
+    let next_in: f64 = upload_row(pool, due).await.get("next_check_in");
+    assert!((1790.0..=1810.0).contains(&next_in), "next check in {next_in}s");
+}
+
+#[actix_web::test]
+async fn stale_tasks_time_out_with_refund() {
+    let test_db = support::init_test_db().await;
+    let pool = &test_db.pool;
+    let user_id = create_user(pool, 5).await;
+    let stale = create_processing_upload(pool, user_id, "task-stale", "kie", 7.0 * 3600.0, 2).await;
+    let running = create_processing_upload(pool, user_id, "task-running", "kie", 600.0, 2).await;
+
+    let state = support::build_state(pool.clone(), "test-key").await;
+    let config = StatusQueueConfig::default();
+    assert_eq!(expire_stale_tasks(&state, &config).await.expect("expire"), 1);
+    assert_eq!(expire_stale_tasks(&state, &config).await.expect("expire again"), 0);
+
+    let row = upload_row(pool, stale).await;
+    assert_eq!(row.get::<String, _>("status"), "failed");
+    assert_eq!(
+        row.get::<Option<String>, _>("last_error").as_deref(),
+        Some("status check timed out")
+    );
+    assert_eq!(upload_row(pool, running).await.get::<String, _>("status"), "processing");
+
+    let credits: i32 = sqlx::query("SELECT credits FROM users WHERE id = $1")
+        .bind(user_id)
+        .fetch_one(pool)
+        .await
+        .expect("user")
+        .get("credits");
+    assert_eq!(credits, 7);
+}
+
+#[actix_web::test]
+async fn failing_jobs_back_off_then_go_to_dead_letter_queue() {
+    let test_db = support::init_test_db().await;
+    let pool = &test_db.pool;
+    let state = support::build_state(pool.clone(), "test-key").await;
+    let queue = PgJobQueue::new(pool.clone());
+    let config = StatusQueueConfig {
+        max_attempts: 2,
+        retry_delay: Duration::from_secs(30),
+        ..StatusQueueConfig::default()
+    };
+    // Неизвестный бэкенд: проверка падает каждый раз
+    queue
+        .enqueue(
+            NewJob::new(STATUS_QUEUE, json!({ "task_id": "task-lost", "provider": "gone" }))
+                .with_dedup_key("task-lost"),
+        )
+        .await
+        .expect("enqueue");
+
+    assert_eq!(consume_once(&state, &queue, &config).await.expect("consume"), 1);
+    let row = sqlx::query(
+        r#"SELECT attempts, last_error, EXTRACT(EPOCH FROM run_at - NOW())::float8 AS run_in
+           FROM job_queue WHERE queue = $1"#,
+    )
+    .bind(STATUS_QUEUE)
+    .fetch_one(pool)
+    .await
+    .expect("retried job");
+    assert_eq!(row.get::<i32, _>("attempts"), 1);
+    assert!(row.get::<Option<String>, _>("last_error").is_some());
+    let run_in: f64 = row.get("run_in");
+    assert!((20.0..=40.0).contains(&run_in), "retry in {run_in}s");
+    assert_eq!(config.job_retry_delay(2), Duration::from_secs(60));
+
+    sqlx::query("UPDATE job_queue SET run_at = NOW()")
+        .execute(pool)
+        .await
+        .expect("make due");
+    assert_eq!(consume_once(&state, &queue, &config).await.expect("consume"), 1);
+    assert_eq!(queued_jobs(pool, STATUS_QUEUE).await, 0);
+
+    let dead = queue
+        .reserve(STATUS_DEAD_LETTER_QUEUE, 10, Duration::from_secs(60))
+        .await
+        .expect("reserve dead letters");
+    assert_eq!(dead.len(), 1);
+    assert_eq!(dead[0].payload["queue"], STATUS_QUEUE);
+    assert_eq!(dead[0].payload["payload"]["task_id"], "task-lost");
+    assert_eq!(dead[0].payload["attempts"], 2);
+    assert!(dead[0].payload["error"].as_str().unwrap_or_default().contains("gone"));
+}
+
+#[actix_web::test]
+async fn status_check_runs_through_postgres_queue() {
+    let test_db = support::init_test_db().await;
+    set_env("MOCK_S3", "true");
+    let server = MockServer::start_async().await;
+    let record_info = server.mock(|when, then| {
+        when.method(GET)
+            .path("/api/v1/jobs/recordInfo")
+            .query_param("taskId", "task-queue-1");
+        then.status(200).json_body(json!({
+            "code": 200,
+            "msg": "success",
+            "data": { "taskId": "task-queue-1", "state": "fail", "failMsg": "bad video" }
+        }));
+    });
+    set_env("KIE_API_BASE_URL", &server.url(""));
+    let pool = &test_db.pool;
+    let user_id = create_user(pool, 0).await;
+    let upload_id = create_processing_upload(pool, user_id, "task-queue-1", "kie", 120.0, 1).await;
+
+    let state = support::build_state(pool.clone(), "test-key").await;
+    let queue = PgJobQueue::new(pool.clone());
+    let config = StatusQueueConfig::default();
+
+    assert_eq!(enqueue_pending_tasks(pool, &queue, &config).await.expect("enqueue"), 1);
+    assert_eq!(consume_once(&state, &queue, &config).await.expect("consume"), 1);
+    record_info.assert_hits(1);
+    assert_eq!(queued_jobs(pool, STATUS_QUEUE).await, 0);
+    let row = upload_row(pool, upload_id).await;
+    assert_eq!(row.get::<String, _>("status"), "failed");
+    assert_eq!(row.get::<Option<String>, _>("last_error").as_deref(), Some("bad video"));
+
+    // Ошибка бэкенда не оплачивается: кредит возвращён
+    let credits: i32 = sqlx::query("SELECT credits FROM users WHERE id = $1")
+        .bind(user_id)
+        .fetch_one(pool)
+        .await
+        .expect("user")
+        .get("credits");
+    assert_eq!(credits, 1);
+}
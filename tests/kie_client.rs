@@ -0,0 +1,257 @@
+use httpmock::Method::{GET, POST};
+use httpmock::MockServer;
+use serde_json::json;
+use std::time::Duration;
+
+use sora_watermark_remov::kie_client::{
+    CreateTaskInput, CreateTaskRequest, KieClient, KieConfig, KieError, KieTaskState,
+    first_result_url, retry_delay,
+};
+
+fn client(server: &MockServer) -> KieClient {
+    KieClient::new(KieConfig {
+        api_key: "test-kie".to_string(),
+        base_url: server.url(""),
+        request_timeout: Duration::from_millis(500),
+        max_retries: 2,
+        retry_base_delay: Duration::from_millis(10),
+        retry_max_delay: Duration::from_millis(50),
+        ..KieConfig::default()
+    })
+}
+
+fn request<'a>() -> CreateTaskRequest<'a> {
+    CreateTaskRequest {
+        model: "sora-watermark-remover",
+        input: CreateTaskInput {
+            video_url: "https://cdn.example.com/a.mp4",
+            extra: Default::default(),
+        },
+        callback_url: "https://api.example.com/api/watermark-callback?upload_id=1&token=t",
+    }
+}
+
+#[actix_web::test]
+async fn create_task_returns_task_id() {
+    let server = MockServer::start_async().await;
+    let mock = server.mock(|when, then| {
+        when.method(POST)
+            .path("/api/v1/jobs/createTask")
+            .header("Authorization", "Bearer test-kie")
+            .json_body(json!({
+                "model": "sora-watermark-remover",
+                "input": { "video_url": "https://cdn.example.com/a.mp4" },
+                "callBackUrl": "https://api.example.com/api/watermark-callback?upload_id=1&token=t"
+            }));
+        then.status(200)
+            .json_body(json!({ "code": 200, "msg": "success", "data": { "taskId": "task-1" } }));
+    });
+
+    let task_id = client(&server).create_task(&request()).await.expect("create task");
+    assert_eq!(task_id, "task-1");
+    mock.assert();
+}
+
+#[actix_web::test]
+async fn create_task_api_errors_are_typed() {
+    let server = MockServer::start_async().await;
+    // Ошибка в теле при HTTP 200 (нет кредитов у аккаунта KIE) — не повторяем
+    let mock = server.mock(|when, then| {
+        when.method(POST).path("/api/v1/jobs/createTask");
+        then.status(200)
+            .json_body(json!({ "code": 402, "msg": "Credits insufficient", "data": null }));
+    });
+
+    let err = client(&server).create_task(&request()).await.unwrap_err();
+    assert!(
+        matches!(&err, KieError::Api { code: 402, msg } if msg == "Credits insufficient"),
+        "{err}"
+    );
+    mock.assert_hits(1);
+}
+
+#[actix_web::test]
+async fn create_task_client_errors_are_not_retried() {
+    let server = MockServer::start_async().await;
+    let mock = server.mock(|when, then| {
+        when.method(POST).path("/api/v1/jobs/createTask");
+        then.status(401).body("Unauthorized");
+    });
+
+    let err = client(&server).create_task(&request()).await.unwrap_err();
+    assert!(matches!(&err, KieError::Http { status: 401, body } if body == "Unauthorized"), "{err}");
+    assert!(!err.is_retryable());
+    mock.assert_hits(1);
+}
+
+#[actix_web::test]
+async fn create_task_is_not_retried_after_reaching_kie() {
+    let server = MockServer::start_async().await;
+    // После 5xx задача могла создаться: повтор дал бы вторую задачу и двойное списание у KIE
+    let unavailable = server.mock(|when, then| {
+        when.method(POST).path("/api/v1/jobs/createTask");
+        then.status(503).body("unavailable");
+    });
+    let err = client(&server).create_task(&request()).await.unwrap_err();
+    assert!(matches!(err, KieError::Http { status: 503, .. }), "{err}");
+    assert!(err.is_retryable() && !err.is_unsent());
+    unavailable.assert_hits(1);
+
+    // Соединение не установилось — запрос не ушёл, его можно повторить
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("bind");
+    let closed = listener.local_addr().expect("addr");
+    drop(listener);
+    let kie = KieClient::new(KieConfig {
+        base_url: format!("http://{closed}"),
+        ..client(&server).config().clone()
+    });
+    let err = kie.create_task(&request()).await.unwrap_err();
+    assert!(matches!(err, KieError::Connect(_)), "{err}");
+    assert!(err.is_unsent());
+}
+
+#[actix_web::test]
+async fn server_errors_and_rate_limits_are_retried() {
+    let server = MockServer::start_async().await;
+    let unavailable = server.mock(|when, then| {
+        when.method(GET).path("/api/v1/jobs/recordInfo").query_param("taskId", "task-3");
+        then.status(503).body("unavailable");
+    });
+    let err = client(&server).record_info("task-3").await.unwrap_err();
+    assert!(matches!(err, KieError::Http { status: 503, .. }), "{err}");
+    // Первая попытка + два повтора
+    unavailable.assert_hits(3);
+
+    let mut limited = server.mock(|when, then| {
+        when.method(GET).path("/api/v1/jobs/recordInfo");
+        then.status(429).header("Retry-After", "0").body("slow down");
+    });
+    let err = client(&server).record_info("task-1").await.unwrap_err();
+    assert!(matches!(err, KieError::Http { status: 429, .. }), "{err}");
+    limited.assert_hits(3);
+    limited.delete();
+
+    // Код 500 в теле при HTTP 200 тоже временная ошибка
+    let body_error = server.mock(|when, then| {
+        when.method(GET).path("/api/v1/jobs/recordInfo").query_param("taskId", "task-2");
+        then.status(200).json_body(json!({ "code": 500, "msg": "Server error" }));
+    });
+    let err = client(&server).record_info("task-2").await.unwrap_err();
+    assert!(matches!(err, KieError::Api { code: 500, .. }), "{err}");
+    body_error.assert_hits(3);
+}
+
+#[actix_web::test]
+async fn malformed_responses_are_decode_errors() {
+    let server = MockServer::start_async().await;
+    server.mock(|when, then| {
+        when.method(POST).path("/api/v1/jobs/createTask");
+        then.status(200).body("<html>gateway</html>");
+    });
+    server.mock(|when, then| {
+        when.method(GET).path("/api/v1/jobs/recordInfo");
+        then.status(200).json_body(json!({ "code": 200, "msg": "success" }));
+    });
+
+    let kie = client(&server);
+    assert!(matches!(kie.create_task(&request()).await, Err(KieError::Decode(_))));
+    assert!(matches!(kie.record_info("task-1").await, Err(KieError::Decode(_))));
+}
+
+#[actix_web::test]
+async fn slow_responses_time_out() {
+    let server = MockServer::start_async().await;
+    let mock = server.mock(|when, then| {
+        when.method(GET).path("/api/v1/jobs/recordInfo");
+        then.status(200).delay(Duration::from_secs(2)).json_body(json!({ "code": 200 }));
+    });
+
+    let err = client(&server).record_info("task-1").await.unwrap_err();
+    assert!(matches!(err, KieError::Timeout), "{err}");
+    // Таймаут не повторяем: запрос мог дойти до KIE, а повтор createTask создал бы вторую задачу
+    mock.assert_hits(1);
+}
+
+#[actix_web::test]
+async fn record_info_states() {
+    let server = MockServer::start_async().await;
+    let record = |task_id: &str, data: serde_json::Value| {
+        server.mock(|when, then| {
+            when.method(GET)
+                .path("/api/v1/jobs/recordInfo")
+                .query_param("taskId", task_id);
+            then.status(200)
+                .json_body(json!({ "code": 200, "msg": "success", "data": data }));
+        });
+    };
+    record(
+        "done",
+        json!({
+            "taskId": "done",
+            "model": "sora-watermark-remover",
+            "state": "success",
+            "resultJson": "{\"resultUrls\":[\"https://cdn.kie.ai/a.mp4\",\"https://cdn.kie.ai/b.mp4\"]}",
+            "failCode": null,
+            "failMsg": null,
+            "costTime": 12000,
+            "completeTime": 1735000000000i64
+        }),
+    );
+    record(
+        "failed",
+        json!({ "taskId": "failed", "state": "fail", "failCode": "500", "failMsg": "bad video" }),
+    );
+    record("waiting", json!({ "taskId": "waiting", "state": "waiting", "resultJson": "" }));
+    record("queuing", json!({ "taskId": "queuing", "state": "queuing" }));
+    record("generating", json!({ "taskId": "generating", "state": "generating" }));
+    record("odd", json!({ "taskId": "odd", "state": "paused" }));
+
+    let kie = client(&server);
+    let done = kie.record_info("done").await.expect("done");
+    assert_eq!(done.task_state(), KieTaskState::Success);
+    assert_eq!(done.result_url().as_deref(), Some("https://cdn.kie.ai/a.mp4"));
+
+    let failed = kie.record_info("failed").await.expect("failed");
+    assert_eq!(failed.task_state(), KieTaskState::Fail);
+    assert_eq!(failed.fail_code.as_deref(), Some("500"));
+    assert_eq!(failed.fail_msg.as_deref(), Some("bad video"));
+    assert_eq!(failed.result_url(), None);
+
+    for (task_id, state) in [
+        ("waiting", KieTaskState::Waiting),
+        ("queuing", KieTaskState::Queuing),
+        ("generating", KieTaskState::Generating),
+        ("odd", KieTaskState::Unknown("paused".to_string())),
+    ] {
+        assert_eq!(kie.record_info(task_id).await.expect(task_id).task_state(), state);
+    }
+}
+
+#[test]
+fn result_json_variants() {
+    assert_eq!(
+        first_result_url(r#"{"resultUrls":["https://cdn.kie.ai/a.mp4"]}"#).as_deref(),
+        Some("https://cdn.kie.ai/a.mp4")
+    );
+    assert_eq!(first_result_url(r#"{"resultUrls":[]}"#), None);
+    assert_eq!(first_result_url("{}"), None);
+    assert_eq!(first_result_url("not json"), None);
+}
+
+#[test]
+fn retry_delay_grows_with_jitter() {
+    let config = KieConfig {
+        retry_base_delay: Duration::from_millis(100),
+        retry_max_delay: Duration::from_millis(300),
+        ..KieConfig::default()
+    };
+    for _ in 0..20 {
+        let first = retry_delay(&config, 0);
+        assert!(first >= Duration::from_millis(50) && first <= Duration::from_millis(100));
+        let second = retry_delay(&config, 1);
+        assert!(second >= Duration::from_millis(100) && second <= Duration::from_millis(200));
+        // Не больше потолка, даже для большого номера попытки
+        let capped = retry_delay(&config, 30);
+        assert!(capped >= Duration::from_millis(150) && capped <= Duration::from_millis(300));
+    }
+}
@@ -7,8 +7,16 @@ use serde_json::json;
 use sqlx::Row;
 use uuid::Uuid;
 
+use async_trait::async_trait;
+use reqwest::Url;
+use std::sync::Arc;
+
 use sora_watermark_remov::api::handlers::upload;
-use sora_watermark_remov::api::webhooks::watermark_callback;
+use sora_watermark_remov::api::webhooks::{watermark_callback_url, watermark_callback};
+use sora_watermark_remov::source_resolver::{
+    ResolveError, ResolvedSource, ResolverRegistry, SourceKind, SourceResolver,
+};
+use sora_watermark_remov::submitter::{SubmitConfig, process_due_submissions};
 
 mod support;
 
@@ -18,39 +26,55 @@ fn set_env(key: &str, value: &str) {
     }
 }
 
-fn build_multipart_body(boundary: &str, field_name: &str, filename: &str, content_type: &str, data: &[u8]) -> Vec<u8> {
+fn build_url_form(boundary: &str, url: &str) -> Vec<u8> {
     let mut body = Vec::new();
     body.extend_from_slice(format!("--{boundary}\r\n").as_bytes());
-    body.extend_from_slice(
-        format!(
-            "Content-Disposition: form-data; name=\"{field_name}\"; filename=\"{filename}\"\r\n"
-        )
-        .as_bytes(),
-    );
-    body.extend_from_slice(format!("Content-Type: {content_type}\r\n\r\n").as_bytes());
-    body.extend_from_slice(data);
+    body.extend_from_slice(b"Content-Disposition: form-data; name=\"url\"\r\n\r\n");
+    body.extend_from_slice(url.as_bytes());
     body.extend_from_slice(b"\r\n");
     body.extend_from_slice(format!("--{boundary}--\r\n").as_bytes());
     body
 }
 
+async fn user_credits(pool: &sqlx::PgPool, user_id: i32) -> i32 {
+    sqlx::query("SELECT credits FROM users WHERE id = $1")
+        .bind(user_id)
+        .fetch_one(pool)
+        .await
+        .expect("select user")
+        .get("credits")
+}
+
+async fn submit_state(pool: &sqlx::PgPool, upload_id: i32) -> sqlx::postgres::PgRow {
+    sqlx::query("SELECT status, submit_attempts, last_error FROM uploads WHERE id = $1")
+        .bind(upload_id)
+        .fetch_one(pool)
+        .await
+        .expect("select upload")
+}
+
 #[actix_web::test]
-async fn upload_sends_kie_request_and_returns_task() {
+async fn upload_queues_task_and_worker_submits_it() {
     set_env("MOCK_S3", "true");
     let server = MockServer::start_async().await;
-    set_env("KIE_API_BASE_URL", &server.url(""));
 
     let mock: Mock = server.mock(|when, then| {
         when.method(POST)
             .path("/api/v1/jobs/createTask")
-            .header("Authorization", "Bearer test-kie");
+            .header("Authorization", "Bearer test-kie")
+            .body_contains("/api/watermark-callback?upload_id=")
+            .body_contains("&token=");
         then.status(200)
             .json_body(json!({
                 "data": { "taskId": "task-123" }
             }));
     });
 
+    // Переменные окружения общие для всех тестов: выставляем под блокировкой тестовой БД
     let test_db = support::init_test_db().await;
+    set_env("KIE_API_BASE_URL", &server.url(""));
+    // Ссылка на видео указывает на локальный mock-сервер
+    set_env("OUTBOUND_ALLOW_PRIVATE", "true");
     let pool = &test_db.pool;
 
     let user_id: i32 = sqlx::query(
@@ -72,21 +96,14 @@ async fn upload_sends_kie_request_and_returns_task() {
             .app_data(state.clone())
             .wrap_fn(move |req, srv| {
                 req.extensions_mut().insert(user_id);
-                let fut = srv.call(req);
-                async move { fut.await }
+                srv.call(req)
             })
             .service(upload),
     )
     .await;
 
     let boundary = "BOUNDARY";
-    let body = build_multipart_body(
-        boundary,
-        "file",
-        "video.mp4",
-        "video/mp4",
-        b"fake-bytes",
-    );
+    let body = build_url_form(boundary, &server.url("/videos/video.mp4"));
 
     let req = TestRequest::post()
         .uri("/upload")
@@ -98,8 +115,25 @@ async fn upload_sends_kie_request_and_returns_task() {
     assert!(resp.status().is_success());
     let body = test::read_body(resp).await;
     let json: serde_json::Value = serde_json::from_slice(&body).expect("json body");
-    assert_eq!(json["task_id"], "task-123");
+    // Запрос только ставит загрузку в очередь, KIE вызывает воркер
+    assert!(json["task_id"].is_null());
+    let upload_id = json["upload_id"].as_i64().expect("upload id") as i32;
+    mock.assert_hits(0);
+
+    let claimed = process_due_submissions(&state, &SubmitConfig::default())
+        .await
+        .expect("submit");
+    assert_eq!(claimed, 1);
     mock.assert();
+
+    let row = sqlx::query("SELECT status, task_id, provider FROM uploads WHERE id = $1")
+        .bind(upload_id)
+        .fetch_one(pool)
+        .await
+        .expect("select upload");
+    assert_eq!(row.get::<String, _>("status"), "processing");
+    assert_eq!(row.get::<Option<String>, _>("task_id").as_deref(), Some("task-123"));
+    assert_eq!(row.get::<Option<String>, _>("provider").as_deref(), Some("kie"));
 }
 
 #[actix_web::test]
@@ -107,7 +141,7 @@ async fn watermark_callback_marks_upload_ready() {
     set_env("MOCK_S3", "true");
     let server = MockServer::start_async().await;
 
-    let file_mock = server.mock(|when, then| {
+    let _file_mock = server.mock(|when, then| {
         when.method(httpmock::Method::GET).path("/file.mp4");
         then.status(200).body("mock");
     });
@@ -129,17 +163,19 @@ async fn watermark_callback_marks_upload_ready() {
     .get("id");
 
     let task_id = "task-callback-1";
-    let _ = sqlx::query(
-        r#"INSERT INTO uploads (user_id, original_filename, original_s3_key, status, task_id)
-           VALUES ($1, $2, $3, 'processing', $4)"#,
+    let upload_id: i32 = sqlx::query(
+        r#"INSERT INTO uploads (user_id, original_filename, original_s3_key, status, task_id, callback_token)
+           VALUES ($1, $2, $3, 'processing', $4, 'cb-secret')
+           RETURNING id"#,
     )
     .bind(user_id)
     .bind("original.mp4")
     .bind("original/key.mp4")
     .bind(task_id)
-    .execute(pool)
+    .fetch_one(pool)
     .await
-    .expect("insert upload");
+    .expect("insert upload")
+    .get("id");
 
     let state = web::Data::new(support::build_state(test_db.pool.clone(), "test-key").await);
     let app = test::init_service(App::new().app_data(state.clone()).service(watermark_callback)).await;
@@ -154,7 +190,7 @@ async fn watermark_callback_marks_upload_ready() {
     });
 
     let req = TestRequest::post()
-        .uri("/api/watermark-callback")
+        .uri(&format!("/api/watermark-callback?upload_id={upload_id}&token=cb-secret"))
         .set_json(payload)
         .to_request();
 
@@ -170,3 +206,570 @@ async fn watermark_callback_marks_upload_ready() {
     assert_eq!(status, "ready");
     assert!(cleaned_url.ends_with("/file.mp4"));
 }
+
+#[actix_web::test]
+async fn failed_callback_closes_upload_and_refunds_credits() {
+    let test_db = support::init_test_db().await;
+    let pool = &test_db.pool;
+
+    let user_id: i32 = sqlx::query(
+        r#"INSERT INTO users (username, email, password_hash, credits, monthly_quota)
+           VALUES ($1, $2, $3, 0, 0)
+           RETURNING id"#,
+    )
+    .bind("kie_failed_cb_user")
+    .bind(format!("kie_failed_cb_{}@example.com", Uuid::new_v4()))
+    .bind("hash")
+    .fetch_one(pool)
+    .await
+    .expect("insert user")
+    .get("id");
+
+    let task_id = "task-callback-failed";
+    let upload_id: i32 = sqlx::query(
+        r#"INSERT INTO uploads
+           (user_id, original_filename, original_s3_key, status, task_id, callback_token,
+            used_credit_type, credit_cost)
+           VALUES ($1, 'original.mp4', 'original/key.mp4', 'processing', $2, 'cb-secret', 'one_time', 2)
+           RETURNING id"#,
+    )
+    .bind(user_id)
+    .bind(task_id)
+    .fetch_one(pool)
+    .await
+    .expect("insert upload")
+    .get("id");
+
+    let state = web::Data::new(support::build_state(test_db.pool.clone(), "test-key").await);
+    let app = test::init_service(App::new().app_data(state.clone()).service(watermark_callback)).await;
+
+    let payload = json!({
+        "code": 501,
+        "msg": "watermark removal failed",
+        "data": { "taskId": task_id, "state": "fail" }
+    });
+    // Повтор того же колбэка не возвращает кредиты второй раз
+    for _ in 0..2 {
+        let req = TestRequest::post()
+            .uri(&format!("/api/watermark-callback?upload_id={upload_id}&token=cb-secret"))
+            .set_json(&payload)
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 200);
+    }
+
+    let row = sqlx::query("SELECT status, last_error FROM uploads WHERE id = $1")
+        .bind(upload_id)
+        .fetch_one(pool)
+        .await
+        .expect("select upload");
+    assert_eq!(row.get::<String, _>("status"), "failed");
+    assert_eq!(
+        row.get::<Option<String>, _>("last_error").as_deref(),
+        Some("watermark removal failed")
+    );
+    assert_eq!(user_credits(pool, user_id).await, 2);
+
+    let events: i64 = sqlx::query_scalar(
+        "SELECT COUNT(*) FROM domain_events WHERE event_type = 'upload.failed' AND user_id = $1",
+    )
+    .bind(user_id)
+    .fetch_one(pool)
+    .await
+    .expect("count events");
+    assert_eq!(events, 1);
+}
+
+#[actix_web::test]
+async fn upload_reuses_cached_result_without_kie_task() {
+    set_env("MOCK_S3", "true");
+    let server = MockServer::start_async().await;
+
+    let mock: Mock = server.mock(|when, then| {
+        when.method(POST).path("/api/v1/jobs/createTask");
+        then.status(200)
+            .json_body(json!({
+                "data": { "taskId": "task-should-not-be-created" }
+            }));
+    });
+
+    let test_db = support::init_test_db().await;
+    set_env("KIE_API_BASE_URL", &server.url(""));
+    let pool = &test_db.pool;
+
+    let user_id: i32 = sqlx::query(
+        r#"INSERT INTO users (username, email, password_hash, credits, monthly_quota, free_generation_used)
+           VALUES ($1, $2, $3, 0, 0, true)
+           RETURNING id"#,
+    )
+    .bind("kie_cache_user")
+    .bind(format!("kie_cache_{}@example.com", Uuid::new_v4()))
+    .bind("hash")
+    .fetch_one(pool)
+    .await
+    .expect("insert user")
+    .get("id");
+
+    let fingerprint = sora_watermark_remov::dedup::url_fingerprint(
+        &sora_watermark_remov::dedup::normalize_source_url("https://cdn.example.com/videos/clip.mp4")
+            .expect("normalize"),
+    );
+    let source_id: i32 = sqlx::query(
+        r#"INSERT INTO uploads
+           (user_id, original_filename, original_s3_key, status, task_id, cleaned_s3_key, cleaned_url, source_fingerprint)
+           VALUES ($1, 'clip.mp4', 'https://cdn.example.com/videos/clip.mp4', 'ready', 'task-cached-1',
+                   'cleaned/task-cached-1.mp4', 'http://localhost/test-bucket/cleaned/task-cached-1.mp4', $2)
+           RETURNING id"#,
+    )
+    .bind(user_id)
+    .bind(&fingerprint)
+    .fetch_one(pool)
+    .await
+    .expect("insert ready upload")
+    .get("id");
+
+    let state = web::Data::new(support::build_state(test_db.pool.clone(), "test-key").await);
+    let app = test::init_service(
+        App::new()
+            .app_data(state.clone())
+            .wrap_fn(move |req, srv| {
+                req.extensions_mut().insert(user_id);
+                srv.call(req)
+            })
+            .service(upload),
+    )
+    .await;
+
+    // Та же ссылка, но с трекинговыми параметрами и фрагментом
+    let boundary = "BOUNDARY";
+    let body = build_url_form(
+        boundary,
+        "http://www.cdn.example.com/videos/clip.mp4?utm_source=tg#t=1",
+    );
+    let req = TestRequest::post()
+        .uri("/upload")
+        .insert_header(("content-type", format!("multipart/form-data; boundary={boundary}")))
+        .set_payload(body)
+        .to_request();
+
+    let resp = test::call_service(&app, req).await;
+    assert!(resp.status().is_success());
+    let body = test::read_body(resp).await;
+    let json: serde_json::Value = serde_json::from_slice(&body).expect("json body");
+    assert_eq!(json["cached"], true);
+    assert!(json["task_id"].is_null());
+    mock.assert_hits(0);
+
+    let upload_id = json["upload_id"].as_i64().expect("upload_id") as i32;
+    let row = sqlx::query(
+        "SELECT status, cleaned_s3_key, cached_from_upload_id, used_credit_type FROM uploads WHERE id = $1",
+    )
+    .bind(upload_id)
+    .fetch_one(pool)
+    .await
+    .expect("select upload");
+    assert_eq!(row.get::<String, _>("status"), "ready");
+    assert_eq!(
+        row.get::<Option<String>, _>("cleaned_s3_key").as_deref(),
+        Some("cleaned/task-cached-1.mp4")
+    );
+    assert_eq!(row.get::<Option<i32>, _>("cached_from_upload_id"), Some(source_id));
+    assert_eq!(row.get::<Option<String>, _>("used_credit_type"), None);
+}
+
+#[actix_web::test]
+async fn upload_rejects_private_source_urls() {
+    set_env("MOCK_S3", "true");
+    let server = MockServer::start_async().await;
+
+    let mock: Mock = server.mock(|when, then| {
+        when.method(POST).path("/api/v1/jobs/createTask");
+        then.status(200)
+            .json_body(json!({
+                "data": { "taskId": "task-should-not-be-created" }
+            }));
+    });
+
+    let test_db = support::init_test_db().await;
+    set_env("KIE_API_BASE_URL", &server.url(""));
+    set_env("OUTBOUND_ALLOW_PRIVATE", "false");
+    let pool = &test_db.pool;
+
+    let user_id: i32 = sqlx::query(
+        r#"INSERT INTO users (username, email, password_hash, credits, monthly_quota)
+           VALUES ($1, $2, $3, 1, 0)
+           RETURNING id"#,
+    )
+    .bind("kie_ssrf_user")
+    .bind(format!("kie_ssrf_{}@example.com", Uuid::new_v4()))
+    .bind("hash")
+    .fetch_one(pool)
+    .await
+    .expect("insert user")
+    .get("id");
+
+    let state = web::Data::new(support::build_state(test_db.pool.clone(), "test-key").await);
+    let app = test::init_service(
+        App::new()
+            .app_data(state.clone())
+            .wrap_fn(move |req, srv| {
+                req.extensions_mut().insert(user_id);
+                srv.call(req)
+            })
+            .service(upload),
+    )
+    .await;
+
+    for url in [
+        "http://127.0.0.1:9000/videos/video.mp4",
+        "http://169.254.169.254/latest/video.mp4",
+        "http://[::1]/video.mp4",
+    ] {
+        let boundary = "BOUNDARY";
+        let req = TestRequest::post()
+            .uri("/upload")
+            .insert_header(("content-type", format!("multipart/form-data; boundary={boundary}")))
+            .set_payload(build_url_form(boundary, url))
+            .to_request();
+
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 400, "url {url} must be rejected");
+    }
+    mock.assert_hits(0);
+
+    let credits: i32 = sqlx::query("SELECT credits FROM users WHERE id = $1")
+        .bind(user_id)
+        .fetch_one(pool)
+        .await
+        .expect("select user")
+        .get("credits");
+    assert_eq!(credits, 1);
+}
+
+#[actix_web::test]
+async fn watermark_callback_rejects_forged_requests() {
+    set_env("MOCK_S3", "true");
+    let server = MockServer::start_async().await;
+
+    let record_mock: Mock = server.mock(|when, then| {
+        when.method(httpmock::Method::GET)
+            .path("/api/v1/jobs/recordInfo")
+            .query_param("taskId", "task-forged-1");
+        then.status(200).json_body(json!({
+            "code": 200,
+            "data": { "taskId": "task-forged-1", "state": "generating" }
+        }));
+    });
+
+    let test_db = support::init_test_db().await;
+    set_env("KIE_API_BASE_URL", &server.url(""));
+    let pool = &test_db.pool;
+
+    let user_id: i32 = sqlx::query(
+        r#"INSERT INTO users (username, email, password_hash, credits, monthly_quota)
+           VALUES ($1, $2, $3, 0, 0)
+           RETURNING id"#,
+    )
+    .bind("kie_forged_user")
+    .bind(format!("kie_forged_{}@example.com", Uuid::new_v4()))
+    .bind("hash")
+    .fetch_one(pool)
+    .await
+    .expect("insert user")
+    .get("id");
+
+    let task_id = "task-forged-1";
+    let upload_id: i32 = sqlx::query(
+        r#"INSERT INTO uploads (user_id, original_filename, original_s3_key, status, task_id, callback_token)
+           VALUES ($1, 'original.mp4', 'original/key.mp4', 'processing', $2, 'real-secret')
+           RETURNING id"#,
+    )
+    .bind(user_id)
+    .bind(task_id)
+    .fetch_one(pool)
+    .await
+    .expect("insert upload")
+    .get("id");
+
+    let state = web::Data::new(support::build_state(test_db.pool.clone(), "test-key").await);
+    let app = test::init_service(App::new().app_data(state.clone()).service(watermark_callback)).await;
+
+    let payload = |task: &str| {
+        json!({
+            "code": 200,
+            "data": {
+                "taskId": task,
+                "status": "success",
+                "outputUrl": "https://evil.example.com/fake.mp4"
+            }
+        })
+    };
+
+    let valid_url = watermark_callback_url("", upload_id, "real-secret");
+    for (uri, task) in [
+        ("/api/watermark-callback".to_string(), task_id),
+        (format!("/api/watermark-callback?upload_id={upload_id}&token=guess"), task_id),
+        (valid_url.clone(), "task-of-someone-else"),
+    ] {
+        let req = TestRequest::post().uri(&uri).set_json(payload(task)).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 401, "uri {uri} task {task} must be rejected");
+    }
+
+    // Токен верный, но KIE не подтверждает завершение задачи
+    set_env("KIE_CALLBACK_VERIFY_RECORD", "true");
+    let req = TestRequest::post().uri(&valid_url).set_json(payload(task_id)).to_request();
+    let resp = test::call_service(&app, req).await;
+    set_env("KIE_CALLBACK_VERIFY_RECORD", "false");
+    assert_eq!(resp.status(), 400);
+    record_mock.assert();
+
+    let status: String = sqlx::query("SELECT status FROM uploads WHERE id = $1")
+        .bind(upload_id)
+        .fetch_one(pool)
+        .await
+        .expect("select upload")
+        .get("status");
+    assert_eq!(status, "processing");
+}
+
+#[actix_web::test]
+async fn queued_upload_survives_outage_and_refunds_after_last_attempt() {
+    set_env("MOCK_S3", "true");
+    let server = MockServer::start_async().await;
+
+    let mock: Mock = server.mock(|when, then| {
+        when.method(POST).path("/api/v1/jobs/createTask");
+        then.status(502).body("bad gateway");
+    });
+
+    let test_db = support::init_test_db().await;
+    set_env("KIE_API_BASE_URL", &server.url(""));
+    set_env("OUTBOUND_ALLOW_PRIVATE", "true");
+    let pool = &test_db.pool;
+
+    let user_id: i32 = sqlx::query(
+        r#"INSERT INTO users (username, email, password_hash, credits, monthly_quota, free_generation_used)
+           VALUES ($1, $2, $3, 1, 0, true)
+           RETURNING id"#,
+    )
+    .bind("kie_down_user")
+    .bind(format!("kie_down_{}@example.com", Uuid::new_v4()))
+    .bind("hash")
+    .fetch_one(pool)
+    .await
+    .expect("insert user")
+    .get("id");
+
+    let state = web::Data::new(support::build_state(test_db.pool.clone(), "test-key").await);
+    let app = test::init_service(
+        App::new()
+            .app_data(state.clone())
+            .wrap_fn(move |req, srv| {
+                req.extensions_mut().insert(user_id);
+                srv.call(req)
+            })
+            .service(upload),
+    )
+    .await;
+
+    let boundary = "BOUNDARY";
+    let req = TestRequest::post()
+        .uri("/upload")
+        .insert_header(("content-type", format!("multipart/form-data; boundary={boundary}")))
+        .set_payload(build_url_form(boundary, &server.url("/videos/down.mp4")))
+        .to_request();
+
+    let resp = test::call_service(&app, req).await;
+    assert!(resp.status().is_success());
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    let upload_id = body["upload_id"].as_i64().expect("upload id") as i32;
+
+    let mut config = SubmitConfig::default();
+    config.policy.max_attempts = 2;
+
+    // Первая неудача: загрузка ждёт следующей попытки, кредит зарезервирован
+    process_due_submissions(&state, &config).await.expect("submit");
+    // createTask не повторяется внутри попытки: после 502 задача могла создаться
+    mock.assert_hits(1);
+    let row = submit_state(pool, upload_id).await;
+    assert_eq!(row.get::<String, _>("status"), "queued");
+    assert_eq!(row.get::<i32, _>("submit_attempts"), 1);
+    assert!(row.get::<Option<String>, _>("last_error").is_some_and(|e| e.contains("502")));
+    assert_eq!(user_credits(pool, user_id).await, 0);
+
+    // Пауза ещё не прошла — воркер загрузку не трогает
+    assert_eq!(process_due_submissions(&state, &config).await.expect("submit"), 0);
+
+    sqlx::query("UPDATE uploads SET next_submit_at = NOW() WHERE id = $1")
+        .bind(upload_id)
+        .execute(pool)
+        .await
+        .expect("make due");
+    process_due_submissions(&state, &config).await.expect("submit");
+    mock.assert_hits(2);
+
+    // Попытки кончились: загрузка закрыта, кредит возвращён
+    let row = submit_state(pool, upload_id).await;
+    assert_eq!(row.get::<String, _>("status"), "failed");
+    assert_eq!(row.get::<i32, _>("submit_attempts"), 2);
+    assert_eq!(user_credits(pool, user_id).await, 1);
+}
+
+/// Sora-резолвер, у которого страница шаринга не отдаёт видео (приватная ссылка, антибот).
+struct UnavailableSoraResolver;
+
+#[async_trait]
+impl SourceResolver for UnavailableSoraResolver {
+    fn name(&self) -> &'static str {
+        "sora"
+    }
+
+    fn matches(&self, url: &Url) -> bool {
+        url.host_str() == Some("sora.chatgpt.com")
+    }
+
+    async fn resolve(&self, _url: &Url) -> Result<ResolvedSource, ResolveError> {
+        Err(ResolveError::Unavailable("the Sora page has no video".to_string()))
+    }
+}
+
+#[actix_web::test]
+async fn upload_rejects_unavailable_source_without_charging() {
+    let test_db = support::init_test_db().await;
+    let pool = &test_db.pool;
+
+    let user_id: i32 = sqlx::query(
+        r#"INSERT INTO users (username, email, password_hash, credits, monthly_quota)
+           VALUES ($1, $2, 'hash', 1, 0)
+           RETURNING id"#,
+    )
+    .bind("unavailable_user")
+    .bind(format!("unavailable_{}@example.com", Uuid::new_v4()))
+    .fetch_one(pool)
+    .await
+    .expect("insert user")
+    .get("id");
+
+    let mut state = support::build_state(test_db.pool.clone(), "test-key").await;
+    state.source_resolvers = Arc::new(ResolverRegistry::new().register(UnavailableSoraResolver));
+    let state = web::Data::new(state);
+    let app = test::init_service(
+        App::new()
+            .app_data(state.clone())
+            .wrap_fn(move |req, srv| {
+                req.extensions_mut().insert(user_id);
+                srv.call(req)
+            })
+            .service(upload),
+    )
+    .await;
+
+    let boundary = "BOUNDARY";
+    let req = TestRequest::post()
+        .uri("/upload")
+        .insert_header(("content-type", format!("multipart/form-data; boundary={boundary}")))
+        .set_payload(build_url_form(boundary, "https://sora.chatgpt.com/p/s_private"))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), 400);
+    let body: serde_json::Value = test::read_body_json(resp).await;
+    assert!(body["error"].as_str().unwrap_or_default().contains("not available"));
+
+    assert_eq!(user_credits(pool, user_id).await, 1);
+    let uploads: i64 = sqlx::query("SELECT COUNT(*) AS n FROM uploads WHERE user_id = $1")
+        .bind(user_id)
+        .fetch_one(pool)
+        .await
+        .expect("count uploads")
+        .get("n");
+    assert_eq!(uploads, 0);
+}
+
+/// Sora-резолвер, который каждый раз выдаёт новую подписанную ссылку на mock-сервер.
+struct FreshSoraResolver {
+    base_url: String,
+}
+
+#[async_trait]
+impl SourceResolver for FreshSoraResolver {
+    fn name(&self) -> &'static str {
+        "sora"
+    }
+
+    fn matches(&self, url: &Url) -> bool {
+        url.host_str() == Some("sora.chatgpt.com")
+    }
+
+    async fn resolve(&self, url: &Url) -> Result<ResolvedSource, ResolveError> {
+        Ok(ResolvedSource {
+            kind: SourceKind::Sora,
+            video_url: format!("{}/fresh.mp4?sig=new", self.base_url),
+            canonical_url: url.to_string(),
+            original_filename: "sora.mp4".to_string(),
+        })
+    }
+}
+
+#[actix_web::test]
+async fn submitter_resolves_short_lived_source_again() {
+    let server = MockServer::start_async().await;
+    let mock: Mock = server.mock(|when, then| {
+        when.method(POST)
+            .path("/api/v1/jobs/createTask")
+            .body_contains("/fresh.mp4?sig=new");
+        then.status(200)
+            .json_body(json!({ "data": { "taskId": "task-fresh" } }));
+    });
+
+    let test_db = support::init_test_db().await;
+    set_env("KIE_API_BASE_URL", &server.url(""));
+    set_env("OUTBOUND_ALLOW_PRIVATE", "true");
+    let pool = &test_db.pool;
+
+    let user_id: i32 = sqlx::query(
+        r#"INSERT INTO users (username, email, password_hash, credits, monthly_quota)
+           VALUES ($1, $2, 'hash', 0, 0)
+           RETURNING id"#,
+    )
+    .bind("sora_user")
+    .bind(format!("sora_{}@example.com", Uuid::new_v4()))
+    .fetch_one(pool)
+    .await
+    .expect("insert user")
+    .get("id");
+    // Сохранённая при загрузке подписанная ссылка уже протухла
+    let upload_id: i32 = sqlx::query(
+        r#"INSERT INTO uploads
+           (user_id, original_filename, original_s3_key, status, used_credit_type, callback_token,
+            source_kind, source_url, next_submit_at)
+           VALUES ($1, 'sora.mp4', 'https://cdn.example.com/expired.mp4?sig=old', 'queued',
+                   'one_time', 'token', 'sora', 'https://sora.chatgpt.com/p/s_abc', NOW())
+           RETURNING id"#,
+    )
+    .bind(user_id)
+    .fetch_one(pool)
+    .await
+    .expect("insert upload")
+    .get("id");
+
+    let mut state = support::build_state(test_db.pool.clone(), "test-key").await;
+    state.source_resolvers = Arc::new(ResolverRegistry::new().register(FreshSoraResolver {
+        base_url: server.url(""),
+    }));
+
+    let claimed = process_due_submissions(&state, &SubmitConfig::default())
+        .await
+        .expect("submit");
+    assert_eq!(claimed, 1);
+    mock.assert();
+
+    let row = sqlx::query("SELECT status, task_id, original_s3_key FROM uploads WHERE id = $1")
+        .bind(upload_id)
+        .fetch_one(pool)
+        .await
+        .expect("select upload");
+    assert_eq!(row.get::<String, _>("status"), "processing");
+    assert_eq!(row.get::<Option<String>, _>("task_id").as_deref(), Some("task-fresh"));
+    assert!(row.get::<String, _>("original_s3_key").ends_with("/fresh.mp4?sig=new"));
+}
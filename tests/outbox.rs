@@ -0,0 +1,253 @@
+use async_trait::async_trait;
+use sqlx::{PgPool, Row};
+use std::sync::{Arc, Mutex};
+use uuid::Uuid;
+
+use sora_watermark_remov::AppState;
+use sora_watermark_remov::outbox::{
+    self, DomainEvent, OutboxConfig, StoredEvent, Subscriber, Subscribers,
+};
+
+mod support;
+
+async fn create_user(pool: &PgPool) -> i32 {
+    sqlx::query(
+        r#"INSERT INTO users (username, email, password_hash, credits, monthly_quota)
+           VALUES ($1, $2, 'test-hash', 0, 0)
+           RETURNING id"#,
+    )
+    .bind(format!("user_{}", Uuid::new_v4().simple()))
+    .bind(format!("outbox_{}@example.com", Uuid::new_v4()))
+    .fetch_one(pool)
+    .await
+    .expect("insert user")
+    .get("id")
+}
+
+/// Запоминает id полученных событий; первые `failures` вызовов падают.
+struct Recorder {
+    name: &'static str,
+    failures: Mutex<u32>,
+    seen: Mutex<Vec<i64>>,
+}
+
+impl Recorder {
+    fn new(name: &'static str, failures: u32) -> Arc<Self> {
+        Arc::new(Self {
+            name,
+            failures: Mutex::new(failures),
+            seen: Mutex::new(Vec::new()),
+        })
+    }
+
+    fn seen(&self) -> Vec<i64> {
+        self.seen.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl Subscriber for Recorder {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    async fn handle(&self, _: &AppState, event: &StoredEvent) -> Result<(), String> {
+        self.seen.lock().unwrap().push(event.id);
+        let mut failures = self.failures.lock().unwrap();
+        if *failures > 0 {
+            *failures -= 1;
+            return Err("boom".to_string());
+        }
+        Ok(())
+    }
+}
+
+fn test_config() -> OutboxConfig {
+    let mut config = OutboxConfig::default();
+    config.policy.max_attempts = 2;
+    config
+}
+
+/// Повтор после ошибки назначается с задержкой; в тестах делаем его «уже пора».
+async fn make_due(pool: &PgPool) {
+    sqlx::query("UPDATE domain_events SET next_attempt_at = NOW()")
+        .execute(pool)
+        .await
+        .expect("make due");
+}
+
+#[actix_web::test]
+async fn only_committed_events_are_dispatched_in_order() {
+    let test_db = support::init_test_db().await;
+    let pool = &test_db.pool;
+    let user_id = create_user(pool).await;
+    let state = support::build_state(pool.clone(), "test-key").await;
+
+    let mut tx = pool.begin().await.expect("begin");
+    outbox::record(
+        &mut *tx,
+        &DomainEvent::UploadCompleted {
+            upload_id: 1,
+            user_id,
+        },
+    )
+    .await
+    .expect("record");
+    tx.rollback().await.expect("rollback");
+
+    let mut tx = pool.begin().await.expect("begin");
+    let first = outbox::record(
+        &mut *tx,
+        &DomainEvent::PaymentSucceeded {
+            transaction_id: 7,
+            user_id,
+        },
+    )
+    .await
+    .expect("record");
+    let second = outbox::record(
+        &mut *tx,
+        &DomainEvent::SubscriptionCanceled {
+            subscription_id: 3,
+            user_id,
+        },
+    )
+    .await
+    .expect("record");
+    tx.commit().await.expect("commit");
+
+    let recorder = Recorder::new("recorder", 0);
+    let subscribers = Subscribers::new(vec![recorder.clone()]);
+    let claimed = outbox::dispatch_pending(&state, &subscribers, &test_config())
+        .await
+        .expect("dispatch");
+    assert_eq!(claimed, 2);
+    assert_eq!(recorder.seen(), [first, second]);
+
+    let row = sqlx::query(
+        r#"SELECT payload::text AS payload, processed_at IS NOT NULL AS processed
+           FROM domain_events WHERE id = $1"#,
+    )
+    .bind(first)
+    .fetch_one(pool)
+    .await
+    .expect("event");
+    let processed: bool = row.get("processed");
+    assert!(processed);
+    let payload: String = row.get("payload");
+    let payload: serde_json::Value = serde_json::from_str(&payload).expect("json");
+    assert_eq!(payload["type"], "payment.succeeded");
+    assert_eq!(payload["transaction_id"], 7);
+
+    // Обработанные события второй раз не забираются
+    let claimed = outbox::dispatch_pending(&state, &subscribers, &test_config())
+        .await
+        .expect("dispatch");
+    assert_eq!(claimed, 0);
+}
+
+#[actix_web::test]
+async fn failed_subscriber_is_retried_alone() {
+    let test_db = support::init_test_db().await;
+    let pool = &test_db.pool;
+    let user_id = create_user(pool).await;
+    let state = support::build_state(pool.clone(), "test-key").await;
+
+    let id = outbox::record(
+        pool,
+        &DomainEvent::UploadCompleted {
+            upload_id: 1,
+            user_id,
+        },
+    )
+    .await
+    .expect("record");
+
+    let healthy = Recorder::new("healthy", 0);
+    let flaky = Recorder::new("flaky", 1);
+    let subscribers = Subscribers::new(vec![healthy.clone(), flaky.clone()]);
+
+    outbox::dispatch_pending(&state, &subscribers, &test_config())
+        .await
+        .expect("dispatch");
+    let row = sqlx::query(
+        "SELECT attempts, last_error, processed_at IS NULL AS pending FROM domain_events WHERE id = $1",
+    )
+    .bind(id)
+    .fetch_one(pool)
+    .await
+    .expect("event");
+    let pending: bool = row.get("pending");
+    assert!(pending);
+    assert_eq!(row.get::<i32, _>("attempts"), 1);
+    assert_eq!(
+        row.get::<Option<String>, _>("last_error").as_deref(),
+        Some("flaky: boom")
+    );
+
+    // До срока повтора событие не забирается
+    let claimed = outbox::dispatch_pending(&state, &subscribers, &test_config())
+        .await
+        .expect("dispatch");
+    assert_eq!(claimed, 0);
+
+    make_due(pool).await;
+    outbox::dispatch_pending(&state, &subscribers, &test_config())
+        .await
+        .expect("dispatch");
+    assert_eq!(healthy.seen(), [id]);
+    assert_eq!(flaky.seen(), [id, id]);
+
+    let subscribers: Vec<String> = sqlx::query(
+        "SELECT subscriber FROM domain_event_deliveries WHERE event_id = $1 ORDER BY subscriber",
+    )
+    .bind(id)
+    .fetch_all(pool)
+    .await
+    .expect("deliveries")
+    .into_iter()
+    .map(|row| row.get("subscriber"))
+    .collect();
+    assert_eq!(subscribers, ["flaky", "healthy"]);
+}
+
+#[actix_web::test]
+async fn event_is_dead_after_max_attempts() {
+    let test_db = support::init_test_db().await;
+    let pool = &test_db.pool;
+    let user_id = create_user(pool).await;
+    let state = support::build_state(pool.clone(), "test-key").await;
+
+    let id = outbox::record(
+        pool,
+        &DomainEvent::UploadFailed {
+            upload_id: 1,
+            user_id,
+            reason: Some("timeout".to_string()),
+            refunded: true,
+        },
+    )
+    .await
+    .expect("record");
+
+    let broken = Recorder::new("broken", u32::MAX);
+    let subscribers = Subscribers::new(vec![broken.clone()]);
+    for _ in 0..3 {
+        make_due(pool).await;
+        outbox::dispatch_pending(&state, &subscribers, &test_config())
+            .await
+            .expect("dispatch");
+    }
+    assert_eq!(broken.seen(), [id, id]);
+
+    let row = sqlx::query(
+        "SELECT attempts, dead_at IS NOT NULL AS dead, processed_at IS NULL AS unprocessed FROM domain_events WHERE id = $1",
+    )
+    .bind(id)
+    .fetch_one(pool)
+    .await
+    .expect("event");
+    assert_eq!(row.get::<i32, _>("attempts"), 2);
+    assert!(row.get::<bool, _>("dead"));
+    assert!(row.get::<bool, _>("unprocessed"));
+}
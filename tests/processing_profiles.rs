@@ -0,0 +1,258 @@
+use actix_web::dev::Service;
+use actix_web::test::TestRequest;
+use actix_web::{App, HttpMessage, test, web};
+use httpmock::Method::POST;
+use httpmock::MockServer;
+use serde_json::json;
+use sqlx::{PgPool, Row};
+use uuid::Uuid;
+
+use sora_watermark_remov::api::handlers::upload;
+use sora_watermark_remov::api::products::list_processing_profiles;
+use sora_watermark_remov::billing::consume_credit;
+use sora_watermark_remov::submitter::{SubmitConfig, process_due_submissions};
+
+mod support;
+
+fn set_env(key: &str, value: &str) {
+    unsafe {
+        std::env::set_var(key, value);
+    }
+}
+
+fn build_form(boundary: &str, fields: &[(&str, &str)]) -> Vec<u8> {
+    let mut body = Vec::new();
+    for (name, value) in fields {
+        body.extend_from_slice(format!("--{boundary}\r\n").as_bytes());
+        body.extend_from_slice(
+            format!("Content-Disposition: form-data; name=\"{name}\"\r\n\r\n").as_bytes(),
+        );
+        body.extend_from_slice(value.as_bytes());
+        body.extend_from_slice(b"\r\n");
+    }
+    body.extend_from_slice(format!("--{boundary}--\r\n").as_bytes());
+    body
+}
+
+/// Пользователь с разовыми кредитами; `plan` — slug продукта активной подписки.
+async fn create_user(pool: &PgPool, credits: i32, plan: Option<&str>) -> i32 {
+    let user_id: i32 = sqlx::query(
+        r#"INSERT INTO users (username, email, password_hash, credits, monthly_quota, quota_reset_at)
+           VALUES ($1, $2, $3, $4, 0, NOW() + INTERVAL '30 days')
+           RETURNING id"#,
+    )
+    .bind(format!("profile_user_{}", Uuid::new_v4().simple()))
+    .bind(format!("profile_test_{}@example.com", Uuid::new_v4()))
+    .bind("hash")
+    .bind(credits)
+    .fetch_one(pool)
+    .await
+    .expect("insert user")
+    .get("id");
+
+    if let Some(plan) = plan {
+        sqlx::query(
+            r#"INSERT INTO subscriptions (user_id, product_id, provider, status, current_period_end)
+               SELECT $1, id, 'lava', 'active', NOW() + INTERVAL '30 days'
+               FROM products WHERE slug = $2"#,
+        )
+        .bind(user_id)
+        .bind(plan)
+        .execute(pool)
+        .await
+        .expect("insert subscription");
+    }
+    user_id
+}
+
+/// Закрытый профиль `hd` стоимостью 3 кредита, доступный только на `sub_pro`.
+async fn create_hd_profile(pool: &PgPool) {
+    sqlx::query(
+        r#"INSERT INTO processing_profiles (slug, name, kie_model, kie_input, credit_cost, is_public)
+           VALUES ('hd', 'HD', 'sora-watermark-remover-hd', '{"upscale": true}', 3, false)"#,
+    )
+    .execute(pool)
+    .await
+    .expect("insert profile");
+    sqlx::query(
+        r#"INSERT INTO product_processing_profiles (product_id, profile_id)
+           SELECT p.id, pp.id FROM products p, processing_profiles pp
+           WHERE p.slug = 'sub_pro' AND pp.slug = 'hd'"#,
+    )
+    .execute(pool)
+    .await
+    .expect("link profile");
+}
+
+async fn post_upload(
+    pool: &PgPool,
+    user_id: i32,
+    fields: &[(&str, &str)],
+) -> (u16, serde_json::Value) {
+    let state = web::Data::new(support::build_state(pool.clone(), "test-key").await);
+    let app = test::init_service(
+        App::new()
+            .app_data(state)
+            .wrap_fn(move |req, srv| {
+                req.extensions_mut().insert(user_id);
+                srv.call(req)
+            })
+            .service(upload),
+    )
+    .await;
+
+    let boundary = "BOUNDARY";
+    let req = TestRequest::post()
+        .uri("/upload")
+        .insert_header(("content-type", format!("multipart/form-data; boundary={boundary}")))
+        .set_payload(build_form(boundary, fields))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    let status = resp.status().as_u16();
+    let body = test::read_body(resp).await;
+    (status, serde_json::from_slice(&body).unwrap_or(serde_json::Value::Null))
+}
+
+async fn user_credits(pool: &PgPool, user_id: i32) -> i32 {
+    sqlx::query("SELECT credits FROM users WHERE id = $1")
+        .bind(user_id)
+        .fetch_one(pool)
+        .await
+        .expect("select credits")
+        .get("credits")
+}
+
+#[actix_web::test]
+async fn plan_profile_sets_model_input_and_cost() {
+    set_env("MOCK_S3", "true");
+    let server = MockServer::start_async().await;
+    let kie = server.mock(|when, then| {
+        when.method(POST)
+            .path("/api/v1/jobs/createTask")
+            .body_contains("\"model\":\"sora-watermark-remover-hd\"")
+            .body_contains("\"upscale\":true");
+        then.status(200).json_body(json!({ "data": { "taskId": "task-hd" } }));
+    });
+
+    let test_db = support::init_test_db().await;
+    set_env("KIE_API_BASE_URL", &server.url(""));
+    set_env("OUTBOUND_ALLOW_PRIVATE", "true");
+    let pool = &test_db.pool;
+    create_hd_profile(pool).await;
+    let user_id = create_user(pool, 4, Some("sub_pro")).await;
+
+    let video = server.url("/videos/hd.mp4");
+    let (status, body) = post_upload(pool, user_id, &[("url", &video), ("profile", "hd")]).await;
+    assert_eq!(status, 200, "{body}");
+    assert_eq!(body["processing_profile"], "hd");
+    assert_eq!(body["credits_charged"], 3);
+
+    // Модель и параметры профиля доходят до KIE через воркер отправки
+    let state = support::build_state(pool.clone(), "test-key").await;
+    process_due_submissions(&state, &SubmitConfig::default())
+        .await
+        .expect("submit");
+    kie.assert();
+
+    let row = sqlx::query("SELECT processing_profile, model, credit_cost FROM uploads WHERE task_id = 'task-hd'")
+        .fetch_one(pool)
+        .await
+        .expect("select upload");
+    assert_eq!(row.get::<Option<String>, _>("processing_profile").as_deref(), Some("hd"));
+    assert_eq!(row.get::<Option<String>, _>("model").as_deref(), Some("sora-watermark-remover-hd"));
+    assert_eq!(row.get::<i32, _>("credit_cost"), 3);
+    assert_eq!(user_credits(pool, user_id).await, 1);
+
+    // Оставшегося кредита на профиль не хватает
+    let other = server.url("/videos/other.mp4");
+    let (status, _) = post_upload(pool, user_id, &[("url", &other), ("profile", "hd")]).await;
+    assert_eq!(status, 402);
+    assert_eq!(user_credits(pool, user_id).await, 1);
+}
+
+#[actix_web::test]
+async fn credits_spent_after_the_check_are_not_charged_twice() {
+    let test_db = support::init_test_db().await;
+    let pool = &test_db.pool;
+    let user_id = create_user(pool, 2, None).await;
+
+    // Проверка `can_remove_watermark` уже пройдена, но кредиты успел потратить другой запрос
+    let mut tx = pool.begin().await.expect("begin");
+    let charged = consume_credit(&mut tx, user_id, "one_time", 3)
+        .await
+        .expect("consume credit");
+    assert_eq!(charged, None);
+    tx.rollback().await.expect("rollback");
+    assert_eq!(user_credits(pool, user_id).await, 2);
+
+    let mut tx = pool.begin().await.expect("begin");
+    let charged = consume_credit(&mut tx, user_id, "one_time", 2)
+        .await
+        .expect("consume credit");
+    assert!(charged.is_some());
+    tx.commit().await.expect("commit");
+    assert_eq!(user_credits(pool, user_id).await, 0);
+}
+
+#[actix_web::test]
+async fn unavailable_profile_is_rejected() {
+    set_env("MOCK_S3", "true");
+    let test_db = support::init_test_db().await;
+    set_env("OUTBOUND_ALLOW_PRIVATE", "true");
+    let pool = &test_db.pool;
+    create_hd_profile(pool).await;
+    let user_id = create_user(pool, 5, None).await;
+
+    for profile in ["hd", "missing"] {
+        let (status, body) = post_upload(
+            pool,
+            user_id,
+            &[("url", "https://cdn.example.com/video.mp4"), ("profile", profile)],
+        )
+        .await;
+        assert_eq!(status, 400, "{profile}");
+        assert_eq!(body["error"], "Unknown or unavailable processing profile");
+    }
+
+    let uploads: i64 = sqlx::query("SELECT COUNT(*) AS n FROM uploads")
+        .fetch_one(pool)
+        .await
+        .expect("count uploads")
+        .get("n");
+    assert_eq!(uploads, 0);
+    assert_eq!(user_credits(pool, user_id).await, 5);
+}
+
+#[actix_web::test]
+async fn profiles_are_listed_per_plan() {
+    let test_db = support::init_test_db().await;
+    let pool = &test_db.pool;
+    create_hd_profile(pool).await;
+    let basic_user = create_user(pool, 0, None).await;
+    let pro_user = create_user(pool, 0, Some("sub_pro")).await;
+
+    let state = web::Data::new(support::build_state(pool.clone(), "test-key").await);
+    for (user_id, expected) in [(basic_user, vec!["standard"]), (pro_user, vec!["standard", "hd"])] {
+        let app = test::init_service(
+            App::new()
+                .app_data(state.clone())
+                .wrap_fn(move |req, srv| {
+                    req.extensions_mut().insert(user_id);
+                    srv.call(req)
+                })
+                .service(list_processing_profiles),
+        )
+        .await;
+        let req = TestRequest::get().uri("/processing-profiles").to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        let slugs: Vec<&str> = body
+            .as_array()
+            .expect("array")
+            .iter()
+            .map(|p| p["slug"].as_str().unwrap())
+            .collect();
+        assert_eq!(slugs, expected);
+        // Настройки KIE наружу не отдаём
+        assert!(body[0].get("kie_model").is_none());
+    }
+}
@@ -0,0 +1,182 @@
+use async_trait::async_trait;
+use serde_json::Value;
+use sora_watermark_remov::providers::router::{parse_routes, parse_weights};
+use sora_watermark_remov::providers::{
+    CallbackResult, ProviderError, RemoverRegistry, RouteContext, RoutingConfig, SubmitJob,
+    TaskStatus, WatermarkRemover,
+};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+/// Бэкенд с заданным именем, который либо всегда падает, либо всегда принимает задачу.
+struct Stub {
+    name: &'static str,
+    fail: bool,
+    calls: Arc<AtomicUsize>,
+}
+
+impl Stub {
+    fn new(name: &'static str, fail: bool) -> (Self, Arc<AtomicUsize>) {
+        let calls = Arc::new(AtomicUsize::new(0));
+        (
+            Self {
+                name,
+                fail,
+                calls: calls.clone(),
+            },
+            calls,
+        )
+    }
+}
+
+#[async_trait]
+impl WatermarkRemover for Stub {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    async fn submit(&self, _job: &SubmitJob) -> Result<String, ProviderError> {
+        self.calls.fetch_add(1, Ordering::SeqCst);
+        if self.fail {
+            Err(ProviderError::Api {
+                status: 502,
+                message: "down".to_string(),
+            })
+        } else {
+            Ok(format!("{}-task", self.name))
+        }
+    }
+
+    async fn poll(&self, _task_id: &str) -> Result<TaskStatus, ProviderError> {
+        Ok(TaskStatus::Pending)
+    }
+
+    fn parse_callback(&self, _body: &Value) -> Result<CallbackResult, ProviderError> {
+        Err(ProviderError::Unsupported("callback"))
+    }
+
+    async fn cancel(&self, _task_id: &str) -> Result<(), ProviderError> {
+        Ok(())
+    }
+}
+
+fn job() -> SubmitJob {
+    SubmitJob {
+        video_url: "https://cdn.example.com/a.mp4".to_string(),
+        callback_url: "https://api.example.com/api/watermark-callback".to_string(),
+        region: None,
+        model: None,
+        extra_input: Default::default(),
+    }
+}
+
+fn names(registry: &RemoverRegistry, ctx: &RouteContext<'_>) -> Vec<&'static str> {
+    registry.candidates(ctx).iter().map(|r| r.name()).collect()
+}
+
+#[test]
+fn config_strings_are_parsed() {
+    assert_eq!(
+        parse_weights("kie:90, Backup:10,broken,zero:0"),
+        vec![
+            ("kie".to_string(), 90),
+            ("backup".to_string(), 10),
+            ("zero".to_string(), 0)
+        ]
+    );
+    let routes = parse_routes("sub_pro:premium, sora:kie,:x");
+    assert_eq!(routes.get("sub_pro").map(String::as_str), Some("premium"));
+    assert_eq!(routes.get("sora").map(String::as_str), Some("kie"));
+    assert_eq!(routes.len(), 2);
+}
+
+#[test]
+fn plan_and_source_routes_come_before_weights() {
+    let routing = RoutingConfig {
+        weights: parse_weights("primary:100,backup:0"),
+        plan_routes: parse_routes("sub_pro:premium"),
+        source_routes: parse_routes("dropbox:backup"),
+        ..RoutingConfig::default()
+    };
+    let registry = RemoverRegistry::new()
+        .register(Stub::new("primary", false).0)
+        .register(Stub::new("backup", false).0)
+        .register(Stub::new("premium", false).0)
+        .with_routing(routing);
+
+    assert_eq!(names(&registry, &RouteContext::default()), vec!["primary"]);
+    assert_eq!(
+        names(
+            &registry,
+            &RouteContext {
+                plan: Some("sub_pro"),
+                source_kind: Some("dropbox"),
+            }
+        ),
+        vec!["premium", "backup", "primary"]
+    );
+}
+
+#[test]
+fn without_weights_only_default_provider_is_used() {
+    let registry = RemoverRegistry::new()
+        .register(Stub::new("primary", false).0)
+        .register(Stub::new("backup", false).0);
+    assert_eq!(names(&registry, &RouteContext::default()), vec!["primary"]);
+}
+
+#[actix_web::test]
+async fn failover_and_unhealthy_providers_go_last() {
+    let (primary, primary_calls) = Stub::new("primary", true);
+    let (backup, backup_calls) = Stub::new("backup", false);
+    let routing = RoutingConfig {
+        weights: parse_weights("primary:100,backup:1"),
+        failure_threshold: 2,
+        cooldown: Duration::from_secs(60),
+        ..RoutingConfig::default()
+    };
+    let registry = RemoverRegistry::new()
+        .register(primary)
+        .register(backup)
+        .with_routing(routing);
+    let ctx = RouteContext::default();
+
+    // Весь выбор по весам приходится на primary: ошибка -> переключение на backup
+    for _ in 0..2 {
+        let order = names(&registry, &ctx);
+        assert_eq!(order.first(), Some(&"primary"));
+        let (remover, task_id) = registry.submit(&ctx, &job()).await.expect("failover");
+        assert_eq!(remover.name(), "backup");
+        assert_eq!(task_id, "backup-task");
+    }
+    assert_eq!(primary_calls.load(Ordering::SeqCst), 2);
+
+    // После двух ошибок подряд primary нездоров и больше не пробуется первым
+    assert!(!registry.is_healthy("primary"));
+    assert_eq!(names(&registry, &ctx), vec!["backup", "primary"]);
+    registry.submit(&ctx, &job()).await.expect("backup");
+    assert_eq!(primary_calls.load(Ordering::SeqCst), 2);
+    assert_eq!(backup_calls.load(Ordering::SeqCst), 3);
+}
+
+#[actix_web::test]
+async fn all_providers_failing_returns_error() {
+    let (primary, _) = Stub::new("primary", true);
+    let (backup, backup_calls) = Stub::new("backup", true);
+    let registry = RemoverRegistry::new()
+        .register(primary)
+        .register(backup)
+        .with_routing(RoutingConfig {
+            weights: parse_weights("primary:1,backup:1"),
+            ..RoutingConfig::default()
+        });
+
+    let err = registry
+        .submit(&RouteContext::default(), &job())
+        .await
+        .err()
+        .expect("all providers down");
+    assert!(matches!(err, ProviderError::Api { status: 502, .. }));
+    assert_eq!(backup_calls.load(Ordering::SeqCst), 1);
+}
@@ -0,0 +1,155 @@
+use serde_json::json;
+use sora_watermark_remov::providers::{
+    DEFAULT_BACKENDS, FfmpegConfig, FfmpegRemover, KieRemover, MockRemover, RemoverRegistry,
+    SubmitJob, TaskStatus, WatermarkRemover, parse_backends,
+};
+
+fn job(video_url: &str) -> SubmitJob {
+    SubmitJob {
+        video_url: video_url.to_string(),
+        callback_url: "https://api.example.com/api/watermark-callback?upload_id=1&token=t".to_string(),
+        region: None,
+        model: None,
+        extra_input: Default::default(),
+    }
+}
+
+#[test]
+fn kie_callback_with_output_url_or_result_json() {
+    let kie = KieRemover::new("key");
+
+    let parsed = kie
+        .parse_callback(&json!({
+            "code": 200,
+            "data": { "taskId": "t1", "state": "success", "outputUrl": "https://cdn.kie.ai/a.mp4" }
+        }))
+        .expect("parse");
+    assert_eq!(parsed.task_id, "t1");
+    assert_eq!(
+        parsed.status,
+        TaskStatus::Succeeded {
+            result_url: "https://cdn.kie.ai/a.mp4".to_string()
+        }
+    );
+
+    let parsed = kie
+        .parse_callback(&json!({
+            "code": 200,
+            "msg": "success",
+            "data": {
+                "taskId": "t2",
+                "state": "success",
+                "resultJson": "{\"resultUrls\":[\"https://cdn.kie.ai/b.mp4\"]}"
+            }
+        }))
+        .expect("parse");
+    assert_eq!(
+        parsed.status,
+        TaskStatus::Succeeded {
+            result_url: "https://cdn.kie.ai/b.mp4".to_string()
+        }
+    );
+}
+
+#[test]
+fn kie_failed_and_malformed_callbacks() {
+    let kie = KieRemover::new("key");
+
+    let parsed = kie
+        .parse_callback(&json!({
+            "code": 501,
+            "msg": "internal error",
+            "data": { "taskId": "t3", "state": "fail", "failMsg": "bad video" }
+        }))
+        .expect("parse");
+    assert_eq!(
+        parsed.status,
+        TaskStatus::Failed {
+            reason: Some("internal error".to_string())
+        }
+    );
+
+    let parsed = kie
+        .parse_callback(&json!({
+            "code": 200,
+            "data": { "taskId": "t4", "state": "fail", "failMsg": "bad video" }
+        }))
+        .expect("parse");
+    assert_eq!(
+        parsed.status,
+        TaskStatus::Failed {
+            reason: Some("bad video".to_string())
+        }
+    );
+
+    // Успех без ссылки на результат и колбэк без taskId — ошибка разбора
+    assert!(
+        kie.parse_callback(&json!({ "code": 200, "data": { "taskId": "t5", "state": "success" } }))
+            .is_err()
+    );
+    assert!(kie.parse_callback(&json!({ "code": 200, "data": {} })).is_err());
+}
+
+#[actix_web::test]
+async fn mock_provider_is_deterministic() {
+    let mock = MockRemover::new().with_result_url("https://cdn.example.com/clean.mp4");
+
+    let first = mock.submit(&job("https://cdn.example.com/a.mp4")).await.expect("submit");
+    let again = mock.submit(&job("https://cdn.example.com/a.mp4")).await.expect("submit");
+    let other = mock.submit(&job("https://cdn.example.com/b.mp4")).await.expect("submit");
+    assert_eq!(first, again);
+    assert_ne!(first, other);
+    assert!(first.starts_with("mock-"));
+
+    assert_eq!(
+        mock.poll(&first).await.expect("poll"),
+        TaskStatus::Succeeded {
+            result_url: "https://cdn.example.com/clean.mp4".to_string()
+        }
+    );
+
+    mock.cancel(&other).await.expect("cancel");
+    assert!(matches!(
+        mock.poll(&other).await.expect("poll"),
+        TaskStatus::Failed { .. }
+    ));
+
+    assert!(MockRemover::failing().submit(&job("https://cdn.example.com/a.mp4")).await.is_err());
+}
+
+#[test]
+fn registry_resolves_provider_per_upload() {
+    let registry = RemoverRegistry::new()
+        .register(KieRemover::new("key"))
+        .register(MockRemover::new());
+    assert_eq!(registry.default_remover().name(), "kie");
+
+    let registry = registry.with_default("mock");
+    assert_eq!(registry.default_remover().name(), "mock");
+
+    // Старые загрузки без provider обрабатывал KIE
+    assert_eq!(registry.for_upload(None).expect("kie").name(), "kie");
+    assert_eq!(registry.for_upload(Some("mock")).expect("mock").name(), "mock");
+    assert!(registry.for_upload(Some("unknown")).is_none());
+}
+
+#[test]
+fn mock_backend_is_enabled_only_explicitly() {
+    assert_eq!(DEFAULT_BACKENDS, ["kie"]);
+    assert_eq!(parse_backends(""), ["kie"]);
+    assert_eq!(parse_backends("kie,ffmpeg"), ["kie", "ffmpeg"]);
+    // Неизвестные имена пропускаются, повторы не дублируются
+    assert_eq!(parse_backends(" KIE, mock ,unknown,kie"), ["kie", "mock"]);
+    assert_eq!(parse_backends("unknown"), ["kie"]);
+}
+
+#[test]
+fn callback_verification_applies_only_to_kie() {
+    unsafe {
+        std::env::set_var("KIE_CALLBACK_VERIFY_RECORD", "true");
+    }
+    assert!(KieRemover::new("key").verify_callbacks());
+    // У mock и ffmpeg нет отдельного источника правды: сверять колбэк не с чем
+    assert!(!MockRemover::new().verify_callbacks());
+    assert!(!FfmpegRemover::new(FfmpegConfig::default()).verify_callbacks());
+}
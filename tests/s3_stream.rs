@@ -0,0 +1,203 @@
+use aws_sdk_s3::Client as S3Client;
+use aws_sdk_s3::config::{BehaviorVersion, Credentials, Region};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use httpmock::Method::{GET, POST, PUT};
+use httpmock::MockServer;
+use sha2::{Digest, Sha256};
+use sora_watermark_remov::s3_utils::{StreamUploadOptions, stream_to_s3};
+use sora_watermark_remov::safe_http::{self, FetchPolicy};
+
+const PART: usize = 5 * 1024 * 1024;
+
+fn s3_client(endpoint: &str) -> S3Client {
+    let config = aws_sdk_s3::Config::builder()
+        .behavior_version(BehaviorVersion::latest())
+        .region(Region::new("us-east-1"))
+        .credentials_provider(Credentials::new("test", "test", None, None, "tests"))
+        .endpoint_url(endpoint)
+        .force_path_style(true)
+        .build();
+    S3Client::from_conf(config)
+}
+
+fn local_policy() -> FetchPolicy {
+    FetchPolicy {
+        allow_private: true,
+        ..FetchPolicy::default()
+    }
+}
+
+fn sha256_b64(data: &[u8]) -> String {
+    BASE64.encode(Sha256::digest(data))
+}
+
+/// Детерминированное «видео», чтобы части отличались друг от друга
+fn video_bytes(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i % 251) as u8).collect()
+}
+
+#[actix_web::test]
+async fn large_result_is_uploaded_in_checksummed_parts() {
+    let source = MockServer::start_async().await;
+    let s3 = MockServer::start_async().await;
+
+    let video = video_bytes(2 * PART + 1024);
+    source.mock(|when, then| {
+        when.method(GET).path("/result.mp4");
+        then.status(200)
+            .header("Content-Type", "video/quicktime")
+            .body(video.clone());
+    });
+
+    let create = s3.mock(|when, then| {
+        when.method(POST)
+            .path("/bucket/cleaned/task-1.mp4")
+            .query_param_exists("uploads")
+            .header("content-type", "video/quicktime");
+        then.status(200).body(
+            "<InitiateMultipartUploadResult><Bucket>bucket</Bucket><Key>cleaned/task-1.mp4</Key>\
+             <UploadId>up-1</UploadId></InitiateMultipartUploadResult>",
+        );
+    });
+    let parts: Vec<_> = video
+        .chunks(PART)
+        .enumerate()
+        .map(|(i, chunk)| {
+            let checksum = sha256_b64(chunk);
+            let number = (i + 1).to_string();
+            s3.mock(|when, then| {
+                when.method(PUT)
+                    .path("/bucket/cleaned/task-1.mp4")
+                    .query_param("uploadId", "up-1")
+                    .query_param("partNumber", &number)
+                    .header("x-amz-checksum-sha256", &checksum);
+                then.status(200)
+                    .header("ETag", format!("\"etag-{number}\""))
+                    .header("x-amz-checksum-sha256", &checksum);
+            })
+        })
+        .collect();
+    let complete = s3.mock(|when, then| {
+        when.method(POST)
+            .path("/bucket/cleaned/task-1.mp4")
+            .query_param("uploadId", "up-1")
+            .body_contains("<PartNumber>3</PartNumber>")
+            .body_contains("etag-3");
+        then.status(200).body(
+            "<CompleteMultipartUploadResult><Bucket>bucket</Bucket><Key>cleaned/task-1.mp4</Key>\
+             <ETag>\"final\"</ETag></CompleteMultipartUploadResult>",
+        );
+    });
+
+    let resp = safe_http::get(&source.url("/result.mp4"), &local_policy())
+        .await
+        .expect("fetch source");
+    let options = StreamUploadOptions {
+        part_size: PART,
+        ..StreamUploadOptions::default()
+    };
+    let object = stream_to_s3(
+        &s3_client(&s3.base_url()),
+        "bucket",
+        "cleaned/task-1.mp4",
+        resp,
+        &options,
+    )
+    .await
+    .expect("stream to s3");
+
+    assert_eq!(object.size, video.len() as u64);
+    assert_eq!(object.parts, 3);
+    assert_eq!(object.content_type, "video/quicktime");
+    assert_eq!(object.sha256, hex::encode(Sha256::digest(&video)));
+    create.assert();
+    for part in &parts {
+        part.assert();
+    }
+    complete.assert();
+}
+
+#[actix_web::test]
+async fn small_result_uses_single_put_with_source_headers() {
+    let source = MockServer::start_async().await;
+    let s3 = MockServer::start_async().await;
+
+    let video = video_bytes(64 * 1024);
+    source.mock(|when, then| {
+        when.method(GET).path("/small.mp4");
+        then.status(200).body(video.clone());
+    });
+    let put = s3.mock(|when, then| {
+        when.method(PUT)
+            .path("/bucket/cleaned/task-2.mp4")
+            .header("content-type", "video/mp4")
+            .header("content-length", video.len().to_string())
+            .header("x-amz-checksum-sha256", sha256_b64(&video));
+        then.status(200).header("ETag", "\"small\"");
+    });
+
+    let resp = safe_http::get(&source.url("/small.mp4"), &local_policy())
+        .await
+        .expect("fetch source");
+    let object = stream_to_s3(
+        &s3_client(&s3.base_url()),
+        "bucket",
+        "cleaned/task-2.mp4",
+        resp,
+        &StreamUploadOptions::default(),
+    )
+    .await
+    .expect("stream to s3");
+
+    assert_eq!(object.parts, 1);
+    assert_eq!(object.size, video.len() as u64);
+    put.assert();
+}
+
+#[actix_web::test]
+async fn failed_part_aborts_multipart_upload() {
+    let source = MockServer::start_async().await;
+    let s3 = MockServer::start_async().await;
+
+    source.mock(|when, then| {
+        when.method(GET).path("/result.mp4");
+        then.status(200).body(video_bytes(PART + 10));
+    });
+    s3.mock(|when, then| {
+        when.method(POST).path("/bucket/key.mp4").query_param_exists("uploads");
+        then.status(200).body(
+            "<InitiateMultipartUploadResult><Bucket>bucket</Bucket><Key>key.mp4</Key>\
+             <UploadId>up-2</UploadId></InitiateMultipartUploadResult>",
+        );
+    });
+    // S3 посчитал другую контрольную сумму — часть считается повреждённой
+    let part = s3.mock(|when, then| {
+        when.method(PUT).path("/bucket/key.mp4").query_param("partNumber", "1");
+        then.status(200)
+            .header("ETag", "\"bad\"")
+            .header("x-amz-checksum-sha256", sha256_b64(b"something else"));
+    });
+    let abort = s3.mock(|when, then| {
+        when.method(httpmock::Method::DELETE)
+            .path("/bucket/key.mp4")
+            .query_param("uploadId", "up-2");
+        then.status(204);
+    });
+
+    let resp = safe_http::get(&source.url("/result.mp4"), &local_policy())
+        .await
+        .expect("fetch source");
+    let options = StreamUploadOptions {
+        part_size: PART,
+        part_retries: 2,
+        ..StreamUploadOptions::default()
+    };
+    let err = stream_to_s3(&s3_client(&s3.base_url()), "bucket", "key.mp4", resp, &options)
+        .await
+        .expect_err("must fail");
+
+    assert!(err.contains("checksum mismatch"), "{err}");
+    part.assert_hits(2);
+    abort.assert();
+}
@@ -0,0 +1,224 @@
+use httpmock::Method::GET;
+use httpmock::MockServer;
+use sora_watermark_remov::safe_http::{self, FetchError, FetchPolicy, is_public_ip, parse_host_list};
+use std::net::IpAddr;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+fn ip(s: &str) -> IpAddr {
+    s.parse().expect("ip")
+}
+
+/// Политика для mock-сервера на 127.0.0.1
+fn local_policy() -> FetchPolicy {
+    FetchPolicy {
+        allow_private: true,
+        ..FetchPolicy::default()
+    }
+}
+
+#[test]
+fn internal_addresses_are_not_public() {
+    for addr in [
+        "127.0.0.1",
+        "10.1.2.3",
+        "172.16.0.1",
+        "192.168.1.1",
+        "169.254.169.254",
+        "100.64.0.1",
+        "0.0.0.0",
+        "255.255.255.255",
+        "::1",
+        "::",
+        "fe80::1",
+        "fd00::1",
+        "::ffff:127.0.0.1",
+        "::ffff:10.0.0.1",
+        "64:ff9b::a9fe:a9fe",
+        // IPv4-совместимые, 6to4 и Teredo с внутренним IPv4 внутри
+        "::127.0.0.1",
+        "::169.254.169.254",
+        "2002:7f00:1::1",
+        "2002:c0a8:101::1",
+        "2001:0:4136:e378:8000:63bf:3fff:fdd2",
+    ] {
+        assert!(!is_public_ip(ip(addr)), "{addr} must be blocked");
+    }
+
+    for addr in [
+        "8.8.8.8",
+        "104.18.1.1",
+        "2606:4700::1111",
+        "::ffff:1.1.1.1",
+        "::8.8.8.8",
+        "2002:808:808::1",
+    ] {
+        assert!(is_public_ip(ip(addr)), "{addr} must be allowed");
+    }
+}
+
+#[test]
+fn host_list_is_normalized() {
+    assert_eq!(
+        parse_host_list(" TempFile.aiquickdraw.com, .kie.ai ,,"),
+        vec!["tempfile.aiquickdraw.com".to_string(), "kie.ai".to_string()]
+    );
+}
+
+#[actix_web::test]
+async fn scheme_allowlist_and_private_addresses_are_checked() {
+    let policy = FetchPolicy::default();
+
+    assert!(matches!(
+        safe_http::validate_url("file:///etc/passwd", &policy).await,
+        Err(FetchError::SchemeNotAllowed(_))
+    ));
+    assert!(matches!(
+        safe_http::validate_url("http://127.0.0.1:8080/video.mp4", &policy).await,
+        Err(FetchError::BlockedAddress { .. })
+    ));
+    assert!(matches!(
+        safe_http::validate_url("http://localhost/video.mp4", &policy).await,
+        Err(FetchError::BlockedAddress { .. })
+    ));
+
+    let kie_only = FetchPolicy::default().with_allowed_hosts(vec!["kie.ai".to_string()]);
+    assert!(matches!(
+        safe_http::validate_url("https://evil.example.com/video.mp4", &kie_only).await,
+        Err(FetchError::HostNotAllowed(_))
+    ));
+    assert!(matches!(
+        safe_http::validate_url("https://notkie.ai/video.mp4", &kie_only).await,
+        Err(FetchError::HostNotAllowed(_))
+    ));
+}
+
+#[actix_web::test]
+async fn redirects_are_followed_and_rechecked() {
+    let server = MockServer::start_async().await;
+    server.mock(|when, then| {
+        when.method(GET).path("/start");
+        then.status(302).header("Location", "/final");
+    });
+    server.mock(|when, then| {
+        when.method(GET).path("/final");
+        then.status(200).body("video");
+    });
+    server.mock(|when, then| {
+        when.method(GET).path("/to-metadata");
+        then.status(302)
+            .header("Location", "http://169.254.169.254/latest/meta-data/");
+    });
+
+    let resp = safe_http::get(&server.url("/start"), &local_policy())
+        .await
+        .expect("fetch");
+    assert_eq!(resp.url().path(), "/final");
+    assert_eq!(resp.bytes().await.expect("body"), b"video");
+
+    // Публичная политика блокирует и сам mock-сервер, и редирект на metadata-адрес
+    let public = FetchPolicy::default();
+    assert!(matches!(
+        safe_http::get(&server.url("/start"), &public).await,
+        Err(FetchError::BlockedAddress { .. })
+    ));
+
+    let no_redirects = FetchPolicy {
+        max_redirects: 0,
+        ..local_policy()
+    };
+    assert!(matches!(
+        safe_http::get(&server.url("/start"), &no_redirects).await,
+        Err(FetchError::TooManyRedirects)
+    ));
+
+    // Приватные адреса разрешены только для mock-сервера; для проверки редиректа
+    // ограничиваем хосты адресом mock-сервера
+    let only_mock = local_policy().with_allowed_hosts(vec!["127.0.0.1".to_string()]);
+    assert!(matches!(
+        safe_http::get(&server.url("/to-metadata"), &only_mock).await,
+        Err(FetchError::HostNotAllowed(_))
+    ));
+}
+
+#[actix_web::test]
+async fn response_size_is_capped() {
+    let server = MockServer::start_async().await;
+    server.mock(|when, then| {
+        when.method(GET).path("/big.mp4");
+        then.status(200).body(vec![0u8; 64 * 1024]);
+    });
+    server.mock(|when, then| {
+        when.method(GET).path("/missing.mp4");
+        then.status(404);
+    });
+
+    let policy = local_policy().with_max_bytes(1024);
+    let result = match safe_http::get(&server.url("/big.mp4"), &policy).await {
+        Ok(resp) => resp.bytes().await.map(|_| ()),
+        Err(e) => Err(e),
+    };
+    assert!(matches!(result, Err(FetchError::TooLarge { limit: 1024 })));
+
+    assert!(matches!(
+        safe_http::get(&server.url("/missing.mp4"), &local_policy()).await,
+        Err(FetchError::Status(404))
+    ));
+}
+
+/// Сервер, который отдаёт `chunks` кусков тела с паузой `gap` между ними.
+async fn trickle_server(chunks: usize, gap: Duration) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+    let addr = listener.local_addr().expect("addr");
+    actix_web::rt::spawn(async move {
+        while let Ok((mut socket, _)) = listener.accept().await {
+            actix_web::rt::spawn(async move {
+                let mut buf = [0u8; 1024];
+                let _ = socket.read(&mut buf).await;
+                let head = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: video/mp4\r\nContent-Length: {chunks}\r\n\r\n"
+                );
+                let _ = socket.write_all(head.as_bytes()).await;
+                for _ in 0..chunks {
+                    actix_web::rt::time::sleep(gap).await;
+                    if socket.write_all(b"x").await.is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+    });
+    format!("http://{addr}/video.mp4")
+}
+
+async fn download(url: &str, policy: &FetchPolicy) -> Result<Vec<u8>, FetchError> {
+    safe_http::get(url, policy).await?.bytes().await
+}
+
+#[actix_web::test]
+async fn slow_downloads_are_limited_by_idle_time_not_total_time() {
+    let url = trickle_server(6, Duration::from_millis(150)).await;
+    let streaming = FetchPolicy {
+        read_timeout: Duration::from_millis(500),
+        ..local_policy()
+    };
+
+    // Тело идёт почти секунду, но без простоев: общего срока у скачивания нет
+    let body = download(&url, &streaming).await.expect("download");
+    assert_eq!(body.len(), 6);
+
+    // Общий срок — только для небольших ответов
+    let deadline = streaming.clone().with_timeout(Duration::from_millis(300));
+    assert!(matches!(
+        download(&url, &deadline).await,
+        Err(FetchError::Http(e)) if e.is_timeout()
+    ));
+
+    // Простой дольше read_timeout обрывает скачивание
+    let stalled = trickle_server(2, Duration::from_millis(800)).await;
+    assert!(matches!(
+        download(&stalled, &streaming).await,
+        Err(FetchError::Timeout)
+    ));
+}
@@ -0,0 +1,99 @@
+use sora_watermark_remov::source_resolver::{
+    ResolveError, ResolverRegistry, SourceKind, extract_drive_confirm_url, extract_sora_video_url,
+};
+
+fn fixture(name: &str) -> String {
+    let path = format!("{}/tests/fixtures/{name}", env!("CARGO_MANIFEST_DIR"));
+    std::fs::read_to_string(&path).unwrap_or_else(|e| panic!("read fixture {path}: {e}"))
+}
+
+#[test]
+fn sora_og_video_meta_is_extracted() {
+    let url = extract_sora_video_url(&fixture("sora_share_og.html")).expect("video url");
+    assert_eq!(
+        url,
+        "https://videos.openai.com/vg-assets/assets%2Ftask_01k%2Fmd.mp4?st=2025-10-10T10%3A00%3A00Z&se=2025-10-16T10%3A00%3A00Z&sig=abc123"
+    );
+}
+
+#[test]
+fn sora_video_tag_is_used_without_og_video() {
+    let url = extract_sora_video_url(&fixture("sora_share_video_tag.html")).expect("video url");
+    assert_eq!(
+        url,
+        "https://videos.openai.com/vg-assets/assets%2Ftask_02x%2Fsrc.mp4?se=2025-10-16&sig=xyz"
+    );
+}
+
+#[test]
+fn sora_challenge_page_has_no_video() {
+    assert!(extract_sora_video_url(&fixture("sora_share_no_video.html")).is_none());
+}
+
+#[test]
+fn drive_virus_scan_form_is_turned_into_download_url() {
+    let url = extract_drive_confirm_url(&fixture("gdrive_virus_scan.html")).expect("confirm url");
+    assert_eq!(
+        url,
+        "https://drive.usercontent.google.com/download?id=1AbCdEfGhIjKlMnOpQrStUvWxYz012345&export=download&confirm=t&uuid=5f2c3a1e-8d7b-4c6a-9e0f-1a2b3c4d5e6f"
+    );
+}
+
+#[actix_web::test]
+async fn direct_video_url_keeps_query_and_derives_filename() {
+    let registry = ResolverRegistry::default();
+    let source = registry
+        .resolve("https://cdn.example.com/media/My%20Clip.MP4?token=abc#t=3")
+        .await
+        .expect("resolve");
+
+    assert_eq!(source.kind, SourceKind::Direct);
+    assert_eq!(source.video_url, "https://cdn.example.com/media/My%20Clip.MP4?token=abc");
+    assert_eq!(source.original_filename, "MyClip.MP4");
+}
+
+#[actix_web::test]
+async fn dropbox_link_is_switched_to_direct_download() {
+    let registry = ResolverRegistry::default();
+    let source = registry
+        .resolve("https://www.dropbox.com/scl/fi/abc123/clip.mov?rlkey=xyz&dl=0")
+        .await
+        .expect("resolve");
+
+    assert_eq!(source.kind, SourceKind::Dropbox);
+    assert_eq!(
+        source.video_url,
+        "https://www.dropbox.com/scl/fi/abc123/clip.mov?rlkey=xyz&dl=1"
+    );
+    assert_eq!(
+        source.canonical_url,
+        "https://www.dropbox.com/scl/fi/abc123/clip.mov?rlkey=xyz"
+    );
+    assert_eq!(source.original_filename, "clip.mov");
+}
+
+#[actix_web::test]
+async fn invalid_links_are_rejected_before_processing() {
+    let registry = ResolverRegistry::default();
+
+    assert!(matches!(
+        registry.resolve("not a url").await,
+        Err(ResolveError::InvalidUrl(_))
+    ));
+    assert!(matches!(
+        registry.resolve("ftp://cdn.example.com/clip.mp4").await,
+        Err(ResolveError::InvalidUrl(_))
+    ));
+    assert!(matches!(
+        registry.resolve("https://sora.chatgpt.com/explore").await,
+        Err(ResolveError::MalformedLink(_))
+    ));
+    assert!(matches!(
+        registry.resolve("https://drive.google.com/drive/folders").await,
+        Err(ResolveError::MalformedLink(_))
+    ));
+    assert!(matches!(
+        registry.resolve("https://example.com/watch?v=1").await,
+        Err(ResolveError::UnsupportedSource(_))
+    ));
+}
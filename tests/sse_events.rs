@@ -0,0 +1,178 @@
+use actix_web::body::MessageBody;
+use actix_web::test::TestRequest;
+use actix_web::{App, test, web};
+use jsonwebtoken::{EncodingKey, Header, encode};
+use sqlx::{PgPool, Row};
+use std::pin::Pin;
+use std::time::Duration;
+use uuid::Uuid;
+
+use sora_watermark_remov::api::auth::JwtMiddleware;
+use sora_watermark_remov::api::events::events_sse;
+use sora_watermark_remov::ws::notify_upload;
+
+mod support;
+
+const JWT_SECRET: &str = "sse-test-secret";
+
+fn set_env(key: &str, value: &str) {
+    unsafe {
+        std::env::set_var(key, value);
+    }
+}
+
+fn token_for(user_id: i32) -> String {
+    set_env("JWT_SECRET", JWT_SECRET);
+    let claims = serde_json::json!({
+        "sub": user_id,
+        "exp": chrono::Utc::now().timestamp() + 3600,
+    });
+    encode(
+        &Header::default(),
+        &claims,
+        &EncodingKey::from_secret(JWT_SECRET.as_ref()),
+    )
+    .expect("token")
+}
+
+async fn create_user_with_upload(pool: &PgPool) -> (i32, i32) {
+    let user_id: i32 = sqlx::query(
+        r#"INSERT INTO users (username, email, password_hash, credits, monthly_quota)
+           VALUES ($1, $2, 'hash', 0, 0)
+           RETURNING id"#,
+    )
+    .bind(format!("sse_user_{}", Uuid::new_v4().simple()))
+    .bind(format!("sse_{}@example.com", Uuid::new_v4()))
+    .fetch_one(pool)
+    .await
+    .expect("insert user")
+    .get("id");
+    let upload_id: i32 = sqlx::query(
+        r#"INSERT INTO uploads (user_id, original_filename, original_s3_key, status)
+           VALUES ($1, 'clip.mp4', 'https://cdn.example.com/clip.mp4', 'queued')
+           RETURNING id"#,
+    )
+    .bind(user_id)
+    .fetch_one(pool)
+    .await
+    .expect("insert upload")
+    .get("id");
+    (user_id, upload_id)
+}
+
+/// Читает поток, пока в нём не появится `needle`.
+async fn read_until<B: MessageBody + Unpin>(body: &mut B, received: &mut String, needle: &str) {
+    let read = async {
+        while !received.contains(needle) {
+            let chunk = std::future::poll_fn(|cx| Pin::new(&mut *body).poll_next(cx))
+                .await
+                .expect("stream is open");
+            let chunk = chunk.map_err(|_| "body error").expect("chunk");
+            received.push_str(std::str::from_utf8(&chunk).expect("utf-8"));
+        }
+    };
+    actix_web::rt::time::timeout(Duration::from_secs(5), read)
+        .await
+        .unwrap_or_else(|_| panic!("{needle:?} not received, got {received:?}"));
+}
+
+#[actix_web::test]
+async fn events_require_token() {
+    let test_db = support::init_test_db().await;
+    set_env("JWT_SECRET", JWT_SECRET);
+    let state = web::Data::new(support::build_state(test_db.pool.clone(), "test-key").await);
+    let app = test::init_service(
+        App::new()
+            .app_data(state)
+            .service(web::scope("/api").wrap(JwtMiddleware).service(events_sse)),
+    )
+    .await;
+
+    let err = test::try_call_service(&app, TestRequest::get().uri("/api/events").to_request())
+        .await
+        .expect_err("unauthorized");
+    assert_eq!(err.as_response_error().status_code(), 401);
+}
+
+#[actix_web::test]
+async fn events_resume_from_last_event_id_and_stream_live_updates() {
+    let test_db = support::init_test_db().await;
+    let pool = &test_db.pool;
+    let state = web::Data::new(support::build_state(pool.clone(), "test-key").await);
+    let (user_id, upload_id) = create_user_with_upload(pool).await;
+
+    notify_upload(pool, &state.ws_hub, upload_id).await;
+    let seen: i64 = sqlx::query("SELECT MAX(id) AS id FROM ws_events")
+        .fetch_one(pool)
+        .await
+        .expect("max id")
+        .get("id");
+    sqlx::query("UPDATE uploads SET status = 'processing' WHERE id = $1")
+        .bind(upload_id)
+        .execute(pool)
+        .await
+        .expect("update upload");
+    notify_upload(pool, &state.ws_hub, upload_id).await;
+
+    let app = test::init_service(
+        App::new()
+            .app_data(state.clone())
+            .service(web::scope("/api").wrap(JwtMiddleware).service(events_sse)),
+    )
+    .await;
+    let req = TestRequest::get()
+        .uri("/api/events")
+        .insert_header(("Authorization", format!("Bearer {}", token_for(user_id))))
+        .insert_header(("Last-Event-ID", seen.to_string()))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert!(resp.status().is_success());
+    assert_eq!(
+        resp.headers().get("content-type").unwrap(),
+        "text/event-stream"
+    );
+
+    let mut body = resp.into_body();
+    let mut received = String::new();
+    read_until(&mut body, &mut received, r#""status":"processing""#).await;
+    assert!(received.contains(&format!("id: {}\n", seen + 1)), "{received}");
+    assert!(!received.contains(r#""status":"queued""#), "{received}");
+
+    sqlx::query("UPDATE uploads SET status = 'ready' WHERE id = $1")
+        .bind(upload_id)
+        .execute(pool)
+        .await
+        .expect("update upload");
+    notify_upload(pool, &state.ws_hub, upload_id).await;
+    read_until(&mut body, &mut received, r#""status":"ready""#).await;
+    assert!(received.contains(r#"data: {"id":"#), "{received}");
+    assert!(received.contains(r#""event":"upload.updated""#), "{received}");
+}
+
+#[actix_web::test]
+async fn events_send_keep_alive_comments() {
+    let test_db = support::init_test_db().await;
+    let pool = &test_db.pool;
+    set_env("WS_HEARTBEAT_INTERVAL_SECS", "1");
+    let state = web::Data::new(support::build_state(pool.clone(), "test-key").await);
+    let (user_id, _) = create_user_with_upload(pool).await;
+
+    let app = test::init_service(
+        App::new()
+            .app_data(state)
+            .service(web::scope("/api").wrap(JwtMiddleware).service(events_sse)),
+    )
+    .await;
+    let req = TestRequest::get()
+        .uri("/api/events")
+        .insert_header(("Authorization", format!("Bearer {}", token_for(user_id))))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    unsafe {
+        std::env::remove_var("WS_HEARTBEAT_INTERVAL_SECS");
+    }
+
+    let mut body = resp.into_body();
+    let mut received = String::new();
+    read_until(&mut body, &mut received, ": keep-alive\n\n").await;
+}
@@ -1,12 +1,21 @@
+// Общие помощники для интеграционных тестов; не каждому тесту нужны все.
+#![allow(dead_code)]
+
 use aws_config::meta::region::RegionProviderChain;
 use aws_sdk_s3::Client as S3Client;
 use sqlx::PgPool;
 use std::env;
-use std::sync::OnceLock;
+use std::sync::{Arc, OnceLock};
 use tokio::sync::{Mutex, MutexGuard};
 
-use sora_watermark_remov::{AppState, ws::WsHub};
 use actix::Actor;
+use sora_watermark_remov::{
+    AppState,
+    kie_client::KieClient,
+    providers::{RemoverRegistry, ResultStorage},
+    source_resolver::ResolverRegistry,
+    ws::WsHub,
+};
 
 fn split_db_url(url: &str) -> Result<(String, String), String> {
     let (base, query) = match url.split_once('?') {
@@ -44,15 +53,12 @@ pub struct TestDb {
 pub async fn init_test_db() -> TestDb {
     dotenvy::dotenv().ok();
     let test_url = env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL must be set");
-    let (admin_url, db_name) =
-        split_db_url(&test_url).expect("invalid TEST_DATABASE_URL format");
+    let (admin_url, db_name) = split_db_url(&test_url).expect("invalid TEST_DATABASE_URL format");
 
     let lock = TEST_DB_LOCK.get_or_init(|| Mutex::new(()));
     let guard = lock.lock().await;
 
-    let admin_pool = PgPool::connect(&admin_url)
-        .await
-        .expect("connect admin db");
+    let admin_pool = PgPool::connect(&admin_url).await.expect("connect admin db");
 
     let _ = sqlx::query("SELECT pg_advisory_lock(424242)")
         .execute(&admin_pool)
@@ -79,11 +85,12 @@ pub async fn init_test_db() -> TestDb {
 
     admin_pool.close().await;
 
-    let pool = PgPool::connect(&test_url)
-        .await
-        .expect("connect test db");
+    let pool = PgPool::connect(&test_url).await.expect("connect test db");
     sqlx::migrate!().run(&pool).await.expect("migrations");
-    TestDb { pool, _guard: guard }
+    TestDb {
+        pool,
+        _guard: guard,
+    }
 }
 
 pub async fn build_state(pool: PgPool, lava_webhook_key: &str) -> AppState {
@@ -93,6 +100,14 @@ pub async fn build_state(pool: PgPool, lava_webhook_key: &str) -> AppState {
         .load()
         .await;
     let s3_client = S3Client::from_conf(aws_sdk_s3::config::Builder::from(&aws_config).build());
+    let kie = Arc::new(KieClient::from_env("test-kie"));
+    let removers = RemoverRegistry::from_env(
+        kie.clone(),
+        ResultStorage {
+            client: s3_client.clone(),
+            bucket: "test-bucket".to_string(),
+        },
+    );
 
     AppState {
         pool,
@@ -100,9 +115,16 @@ pub async fn build_state(pool: PgPool, lava_webhook_key: &str) -> AppState {
         s3_bucket: "test-bucket".to_string(),
         s3_public_base_url: "http://localhost".to_string(),
         kie_api_key: "test-kie".to_string(),
+        kie: kie.clone(),
         callback_base_url: "http://localhost".to_string(),
         lava_api_key: "test-lava".to_string(),
         lava_webhook_key: lava_webhook_key.to_string(),
         ws_hub: WsHub::new().start(),
+        source_resolvers: Arc::new(ResolverRegistry::default()),
+        removers: Arc::new(removers),
+        submit_wakeup: Default::default(),
+        outbox_wakeup: Default::default(),
+        webhook_wakeup: Default::default(),
+        email_wakeup: Default::default(),
     }
 }
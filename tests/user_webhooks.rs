@@ -0,0 +1,340 @@
+use actix_web::dev::Service;
+use actix_web::http::StatusCode;
+use actix_web::test::TestRequest;
+use actix_web::{App, HttpMessage, HttpRequest, HttpResponse, HttpServer, test, web};
+use serde_json::{Value, json};
+use sqlx::{PgPool, Row};
+use std::net::TcpListener;
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::sync::{Arc, Mutex};
+use uuid::Uuid;
+
+use sora_watermark_remov::AppState;
+use sora_watermark_remov::api::user_webhooks::{
+    create_webhook, list_webhook_deliveries, redeliver_webhook,
+};
+use sora_watermark_remov::outbox::{self, DomainEvent, OutboxConfig, StoredEvent, Subscribers};
+use sora_watermark_remov::user_webhooks::{self, EndpointUpdate, WebhookConfig};
+
+mod support;
+
+fn set_env(key: &str, value: &str) {
+    unsafe {
+        std::env::set_var(key, value);
+    }
+}
+
+async fn create_user(pool: &PgPool) -> i32 {
+    sqlx::query(
+        r#"INSERT INTO users (username, email, password_hash, credits, monthly_quota)
+           VALUES ($1, $2, 'test-hash', 0, 0)
+           RETURNING id"#,
+    )
+    .bind(format!("user_{}", Uuid::new_v4().simple()))
+    .bind(format!("hooks_{}@example.com", Uuid::new_v4()))
+    .fetch_one(pool)
+    .await
+    .expect("insert user")
+    .get("id")
+}
+
+struct Received {
+    event: String,
+    timestamp: String,
+    signature: String,
+    body: String,
+}
+
+/// Сервер пользователя: запоминает запросы и отвечает статусом из `status`.
+#[derive(Clone)]
+struct Receiver {
+    requests: Arc<Mutex<Vec<Received>>>,
+    status: Arc<AtomicU16>,
+}
+
+impl Receiver {
+    fn set_status(&self, status: u16) {
+        self.status.store(status, Ordering::SeqCst);
+    }
+
+    fn count(&self) -> usize {
+        self.requests.lock().unwrap().len()
+    }
+}
+
+async fn receive(req: HttpRequest, body: String, receiver: web::Data<Receiver>) -> HttpResponse {
+    let header = |name: &str| {
+        req.headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default()
+            .to_string()
+    };
+    receiver.requests.lock().unwrap().push(Received {
+        event: header("x-webhook-event"),
+        timestamp: header("x-webhook-timestamp"),
+        signature: header("x-webhook-signature"),
+        body,
+    });
+    let status = StatusCode::from_u16(receiver.status.load(Ordering::SeqCst)).unwrap();
+    HttpResponse::build(status).body("received")
+}
+
+fn start_receiver(status: u16) -> (String, Receiver) {
+    let receiver = Receiver {
+        requests: Arc::new(Mutex::new(Vec::new())),
+        status: Arc::new(AtomicU16::new(status)),
+    };
+    let listener = TcpListener::bind("127.0.0.1:0").expect("bind receiver");
+    let addr = listener.local_addr().expect("receiver addr");
+    let server = HttpServer::new({
+        let receiver = web::Data::new(receiver.clone());
+        move || {
+            App::new()
+                .app_data(receiver.clone())
+                .default_service(web::to(receive))
+        }
+    })
+    .workers(1)
+    .listen(listener)
+    .expect("listen")
+    .run();
+    actix_web::rt::spawn(server);
+    (format!("http://{addr}/hooks"), receiver)
+}
+
+async fn record_and_dispatch(state: &AppState, event: DomainEvent) -> i64 {
+    let id = outbox::record(&state.pool, &event).await.expect("record");
+    outbox::dispatch_pending(state, &Subscribers::from_env(), &OutboxConfig::default())
+        .await
+        .expect("dispatch outbox");
+    id
+}
+
+/// Повтор назначается с задержкой; в тестах делаем его «уже пора».
+async fn make_due(pool: &PgPool) {
+    sqlx::query("UPDATE webhook_deliveries SET next_attempt_at = NOW() WHERE status = 'pending'")
+        .execute(pool)
+        .await
+        .expect("make due");
+}
+
+#[actix_web::test]
+async fn upload_completed_is_signed_delivered_and_redelivered() {
+    let test_db = support::init_test_db().await;
+    set_env("OUTBOUND_ALLOW_PRIVATE", "true");
+    let pool = &test_db.pool;
+    let user_id = create_user(pool).await;
+    let upload_id: i32 = sqlx::query(
+        r#"INSERT INTO uploads (user_id, original_filename, original_s3_key, status, cleaned_url)
+           VALUES ($1, 'clip.mp4', 'https://cdn.example.com/clip.mp4', 'ready',
+                   'https://cdn.example.com/clean.mp4')
+           RETURNING id"#,
+    )
+    .bind(user_id)
+    .fetch_one(pool)
+    .await
+    .expect("insert upload")
+    .get("id");
+    let (url, receiver) = start_receiver(200);
+    let state = web::Data::new(support::build_state(pool.clone(), "test-key").await);
+
+    let app = test::init_service(
+        App::new()
+            .app_data(state.clone())
+            .wrap_fn(move |req, srv| {
+                req.extensions_mut().insert(user_id);
+                srv.call(req)
+            })
+            .service(create_webhook)
+            .service(list_webhook_deliveries)
+            .service(redeliver_webhook),
+    )
+    .await;
+
+    let req = TestRequest::post()
+        .uri("/webhooks")
+        .set_json(json!({ "url": url, "event_types": ["upload.unknown"] }))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+    let req = TestRequest::post()
+        .uri("/webhooks")
+        .set_json(json!({ "url": url, "event_types": ["upload.completed"] }))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::CREATED);
+    let created: Value = test::read_body_json(resp).await;
+    let endpoint_id = created["endpoint"]["id"].as_i64().expect("endpoint id");
+    let secret = created["secret"].as_str().expect("secret").to_string();
+    assert!(secret.starts_with("whsec_"));
+
+    let event = DomainEvent::UploadCompleted { upload_id, user_id };
+    let event_id = record_and_dispatch(&state, event.clone()).await;
+    // На этот тип адрес не подписан
+    record_and_dispatch(
+        &state,
+        DomainEvent::PaymentSucceeded {
+            transaction_id: 1,
+            user_id,
+        },
+    )
+    .await;
+    let claimed = user_webhooks::send_pending(&state, &WebhookConfig::default())
+        .await
+        .expect("send");
+    assert_eq!(claimed, 1);
+
+    {
+        let requests = receiver.requests.lock().unwrap();
+        assert_eq!(requests.len(), 1);
+        let received = &requests[0];
+        assert_eq!(received.event, "upload.completed");
+        let timestamp: i64 = received.timestamp.parse().expect("timestamp");
+        assert_eq!(
+            received.signature,
+            user_webhooks::sign(&secret, timestamp, &received.body)
+        );
+        let body: Value = serde_json::from_str(&received.body).expect("json body");
+        assert_eq!(body["id"], event_id);
+        assert_eq!(body["type"], "upload.completed");
+        assert_eq!(body["data"]["upload_id"], upload_id);
+        assert_eq!(
+            body["data"]["upload"]["cleaned_url"],
+            "https://cdn.example.com/clean.mp4"
+        );
+    }
+
+    // Повторная передача события из outbox не создаёт вторую доставку
+    let stored = StoredEvent {
+        id: event_id,
+        attempts: 2,
+        created_at: None,
+        event,
+    };
+    assert_eq!(user_webhooks::enqueue_event(pool, &stored).await, Ok(0));
+
+    let req = TestRequest::get()
+        .uri(&format!("/webhooks/{endpoint_id}/deliveries"))
+        .to_request();
+    let deliveries: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(deliveries.as_array().map(Vec::len), Some(1));
+    assert_eq!(deliveries[0]["status"], "succeeded");
+    assert_eq!(deliveries[0]["response_status"], 200);
+    assert_eq!(deliveries[0]["response_body"], "received");
+    let delivery_id = deliveries[0]["id"].as_i64().expect("delivery id");
+
+    let req = TestRequest::post()
+        .uri(&format!(
+            "/webhooks/{endpoint_id}/deliveries/{delivery_id}/redeliver"
+        ))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::ACCEPTED);
+    let redelivery: Value = test::read_body_json(resp).await;
+    assert_eq!(redelivery["redelivery_of"], delivery_id);
+    assert_eq!(redelivery["status"], "pending");
+
+    user_webhooks::send_pending(&state, &WebhookConfig::default())
+        .await
+        .expect("send");
+    let requests = receiver.requests.lock().unwrap();
+    assert_eq!(requests.len(), 2);
+    assert_eq!(requests[0].body, requests[1].body);
+}
+
+#[actix_web::test]
+async fn failing_endpoint_is_retried_then_disabled() {
+    let test_db = support::init_test_db().await;
+    set_env("OUTBOUND_ALLOW_PRIVATE", "true");
+    let pool = &test_db.pool;
+    let user_id = create_user(pool).await;
+    let (url, receiver) = start_receiver(500);
+    let state = support::build_state(pool.clone(), "test-key").await;
+    let (endpoint, _) = user_webhooks::create_endpoint(pool, user_id, &url, &[])
+        .await
+        .expect("create endpoint");
+    let mut config = WebhookConfig {
+        disable_after_failures: 3,
+        ..WebhookConfig::default()
+    };
+    config.policy.max_attempts = 2;
+    let payment = |transaction_id| DomainEvent::PaymentSucceeded {
+        transaction_id,
+        user_id,
+    };
+
+    record_and_dispatch(&state, payment(1)).await;
+    user_webhooks::send_pending(&state, &config)
+        .await
+        .expect("send");
+    let deliveries = user_webhooks::list_deliveries(pool, endpoint.id, 10, None)
+        .await
+        .expect("deliveries");
+    assert_eq!(deliveries[0].status, "pending");
+    assert_eq!(deliveries[0].attempts, 1);
+    assert_eq!(deliveries[0].response_status, Some(500));
+    assert_eq!(
+        deliveries[0].last_error.as_deref(),
+        Some("unexpected status 500")
+    );
+
+    // До срока повтора доставка не забирается, после последней попытки — `failed`
+    assert_eq!(user_webhooks::send_pending(&state, &config).await, Ok(0));
+    make_due(pool).await;
+    user_webhooks::send_pending(&state, &config)
+        .await
+        .expect("send");
+    let deliveries = user_webhooks::list_deliveries(pool, endpoint.id, 10, None)
+        .await
+        .expect("deliveries");
+    assert_eq!(deliveries[0].status, "failed");
+    assert_eq!(deliveries[0].attempts, 2);
+
+    // Третья неудача подряд выключает адрес; его доставка ждёт включения
+    record_and_dispatch(&state, payment(2)).await;
+    user_webhooks::send_pending(&state, &config)
+        .await
+        .expect("send");
+    let disabled = user_webhooks::get_endpoint(pool, user_id, endpoint.id)
+        .await
+        .expect("endpoint")
+        .expect("exists");
+    assert!(!disabled.is_active);
+    assert_eq!(disabled.consecutive_failures, 3);
+    assert!(disabled.disabled_at.is_some());
+    assert!(disabled.disabled_reason.is_some());
+
+    make_due(pool).await;
+    assert_eq!(user_webhooks::send_pending(&state, &config).await, Ok(0));
+    record_and_dispatch(&state, payment(3)).await;
+    let deliveries = user_webhooks::list_deliveries(pool, endpoint.id, 10, None)
+        .await
+        .expect("deliveries");
+    assert_eq!(deliveries.len(), 2);
+    assert_eq!(receiver.count(), 3);
+
+    receiver.set_status(204);
+    let update = EndpointUpdate {
+        is_active: Some(true),
+        ..EndpointUpdate::default()
+    };
+    let enabled = user_webhooks::update_endpoint(pool, user_id, endpoint.id, &update)
+        .await
+        .expect("update")
+        .expect("exists");
+    assert!(enabled.is_active);
+    assert_eq!(enabled.consecutive_failures, 0);
+    assert!(enabled.disabled_reason.is_none());
+
+    user_webhooks::send_pending(&state, &config)
+        .await
+        .expect("send");
+    let deliveries = user_webhooks::list_deliveries(pool, endpoint.id, 10, None)
+        .await
+        .expect("deliveries");
+    assert_eq!(deliveries[0].status, "succeeded");
+    assert_eq!(deliveries[0].response_status, Some(204));
+    assert_eq!(receiver.count(), 4);
+}
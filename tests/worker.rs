@@ -0,0 +1,170 @@
+use serde_json::json;
+use sqlx::Row;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+use sora_watermark_remov::queue::{self, PgJobQueue, StatusQueueConfig};
+use sora_watermark_remov::submitter::{SubmitConfig, run_submit_worker};
+use sora_watermark_remov::worker::{
+    RetryPolicy, WorkerConfig, join_workers, prune_dead_letters, run_maintenance_once,
+    shutdown_channel, spawn_workers,
+};
+
+mod support;
+
+#[test]
+fn retry_policy_reads_prefixed_environment_and_backs_off() {
+    unsafe {
+        std::env::set_var("POLICY_TEST_MAX_ATTEMPTS", "3");
+        std::env::set_var("POLICY_TEST_RETRY_BASE_SECS", "0");
+        std::env::set_var("POLICY_TEST_BATCH_SIZE", "oops");
+    }
+    let policy = RetryPolicy::from_env("POLICY_TEST", SubmitConfig::default().policy);
+    assert_eq!(policy.max_attempts, 3);
+    // Ноль не даёт воркеру крутиться без паузы, мусор оставляет значение по умолчанию
+    assert_eq!(policy.retry_base_delay, Duration::from_secs(1));
+    assert_eq!(policy.batch_size, SubmitConfig::default().policy.batch_size);
+
+    let policy = SubmitConfig::default().policy;
+    assert_eq!(policy.retry_delay(1), Duration::from_secs(10));
+    assert_eq!(policy.retry_delay(3), Duration::from_secs(40));
+    assert_eq!(policy.retry_delay(100), policy.retry_max_delay);
+}
+
+#[actix_web::test]
+async fn shutdown_interrupts_waiting_workers() {
+    let test_db = support::init_test_db().await;
+    let state = support::build_state(test_db.pool.clone(), "test-key").await;
+    let (trigger, shutdown) = shutdown_channel();
+
+    let long = Duration::from_secs(3600);
+    let mut submit_config = SubmitConfig::default();
+    submit_config.policy.poll_interval = long;
+    let handles = vec![
+        actix_web::rt::spawn(run_submit_worker(
+            state.clone(),
+            submit_config,
+            shutdown.clone(),
+        )),
+        actix_web::rt::spawn(queue::run_status_scheduler(
+            state.clone(),
+            Arc::new(PgJobQueue::new(test_db.pool.clone())),
+            StatusQueueConfig {
+                scheduler_interval: long,
+                ..StatusQueueConfig::default()
+            },
+            shutdown.clone(),
+        )),
+        actix_web::rt::spawn(queue::run_status_consumer(
+            state.clone(),
+            Arc::new(PgJobQueue::new(test_db.pool.clone())),
+            StatusQueueConfig::default(),
+            shutdown.clone(),
+        )),
+    ];
+    // Воркеры успели сделать первый проход и ждут
+    actix_web::rt::time::sleep(Duration::from_millis(300)).await;
+
+    let started = Instant::now();
+    trigger.trigger();
+    assert!(join_workers(handles, Duration::from_secs(5)).await);
+    assert!(started.elapsed() < Duration::from_secs(3), "{:?}", started.elapsed());
+}
+
+#[actix_web::test]
+async fn disabled_workers_are_not_started() {
+    let test_db = support::init_test_db().await;
+    let state = support::build_state(test_db.pool.clone(), "test-key").await;
+    let (_trigger, shutdown) = shutdown_channel();
+    let config = WorkerConfig {
+        submit: false,
+        status_queue: false,
+        outbox: false,
+        webhooks: false,
+        emails: false,
+        maintenance: false,
+        ..WorkerConfig::default()
+    };
+    assert!(spawn_workers(&state, &config, &shutdown).is_empty());
+}
+
+#[actix_web::test]
+async fn maintenance_expires_tasks_and_prunes_old_dead_letters() {
+    let test_db = support::init_test_db().await;
+    let pool = &test_db.pool;
+
+    let user_id: i32 = sqlx::query(
+        r#"INSERT INTO users (username, email, password_hash, credits, monthly_quota, quota_reset_at)
+           VALUES ($1, $2, 'hash', 0, 0, NOW() + INTERVAL '30 days')
+           RETURNING id"#,
+    )
+    .bind(format!("worker_user_{}", Uuid::new_v4().simple()))
+    .bind(format!("worker_{}@example.com", Uuid::new_v4()))
+    .fetch_one(pool)
+    .await
+    .expect("insert user")
+    .get("id");
+    let upload_id: i32 = sqlx::query(
+        r#"INSERT INTO uploads
+           (user_id, original_filename, original_s3_key, status, task_id, provider, processing_started_at)
+           VALUES ($1, 'clip.mp4', 'https://cdn.example.com/clip.mp4', 'processing', 'task-old', 'kie',
+                   NOW() - INTERVAL '2 days')
+           RETURNING id"#,
+    )
+    .bind(user_id)
+    .fetch_one(pool)
+    .await
+    .expect("insert upload")
+    .get("id");
+
+    for (queue, age_days) in [
+        ("kie.status.check.dead", 30),
+        ("kie.status.check.dead", 1),
+        ("kie.status.check", 30),
+    ] {
+        sqlx::query(
+            r#"INSERT INTO job_queue (queue, payload, created_at)
+               VALUES ($1, $2::jsonb, NOW() - make_interval(days => $3))"#,
+        )
+        .bind(queue)
+        .bind(json!({ "task_id": "t" }).to_string())
+        .bind(age_days)
+        .execute(pool)
+        .await
+        .expect("insert job");
+    }
+
+    let state = support::build_state(pool.clone(), "test-key").await;
+    run_maintenance_once(&state, &WorkerConfig::default(), &StatusQueueConfig::default())
+        .await
+        .expect("maintenance");
+
+    let status: String = sqlx::query("SELECT status FROM uploads WHERE id = $1")
+        .bind(upload_id)
+        .fetch_one(pool)
+        .await
+        .expect("upload")
+        .get("status");
+    assert_eq!(status, "failed");
+
+    let left: Vec<(String, i32)> = sqlx::query(
+        r#"SELECT queue, EXTRACT(DAY FROM NOW() - created_at)::int AS age
+           FROM job_queue ORDER BY queue, age"#,
+    )
+    .fetch_all(pool)
+    .await
+    .expect("jobs")
+    .into_iter()
+    .map(|row| (row.get("queue"), row.get("age")))
+    .collect();
+    assert_eq!(
+        left,
+        [
+            ("kie.status.check".to_string(), 30),
+            ("kie.status.check.dead".to_string(), 1),
+        ]
+    );
+
+    assert_eq!(prune_dead_letters(pool, Duration::ZERO).await.expect("prune all"), 1);
+}
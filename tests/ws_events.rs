@@ -0,0 +1,170 @@
+use serde_json::Value;
+use sqlx::{PgPool, Row};
+use std::time::Duration;
+use uuid::Uuid;
+
+use sora_watermark_remov::ws::{Replay, WsConfig, load_replay, notify_upload, prune_ws_events};
+
+mod support;
+
+async fn create_user(pool: &PgPool) -> i32 {
+    sqlx::query(
+        r#"INSERT INTO users (username, email, password_hash, credits, monthly_quota)
+           VALUES ($1, $2, 'hash', 0, 0)
+           RETURNING id"#,
+    )
+    .bind(format!("ws_user_{}", Uuid::new_v4().simple()))
+    .bind(format!("ws_{}@example.com", Uuid::new_v4()))
+    .fetch_one(pool)
+    .await
+    .expect("insert user")
+    .get("id")
+}
+
+async fn create_upload(pool: &PgPool, user_id: i32, status: &str) -> i32 {
+    sqlx::query(
+        r#"INSERT INTO uploads (user_id, original_filename, original_s3_key, status)
+           VALUES ($1, 'clip.mp4', 'https://cdn.example.com/clip.mp4', $2)
+           RETURNING id"#,
+    )
+    .bind(user_id)
+    .bind(status)
+    .fetch_one(pool)
+    .await
+    .expect("insert upload")
+    .get("id")
+}
+
+async fn set_status(pool: &PgPool, upload_id: i32, status: &str) {
+    sqlx::query("UPDATE uploads SET status = $2 WHERE id = $1")
+        .bind(upload_id)
+        .bind(status)
+        .execute(pool)
+        .await
+        .expect("update upload");
+}
+
+async fn last_event_id(pool: &PgPool) -> i64 {
+    sqlx::query("SELECT COALESCE(MAX(id), 0) AS id FROM ws_events")
+        .fetch_one(pool)
+        .await
+        .expect("max id")
+        .get("id")
+}
+
+#[actix_web::test]
+async fn missed_events_are_replayed_in_order() {
+    let test_db = support::init_test_db().await;
+    let pool = &test_db.pool;
+    let state = support::build_state(pool.clone(), "test-key").await;
+    let user_id = create_user(pool).await;
+    let other_user = create_user(pool).await;
+    let upload_id = create_upload(pool, user_id, "queued").await;
+    let other_upload = create_upload(pool, other_user, "queued").await;
+
+    notify_upload(pool, &state.ws_hub, upload_id).await;
+    let seen = last_event_id(pool).await;
+
+    // Клиент отключился, а загрузка тем временем дошла до конца
+    set_status(pool, upload_id, "processing").await;
+    notify_upload(pool, &state.ws_hub, upload_id).await;
+    notify_upload(pool, &state.ws_hub, other_upload).await;
+    set_status(pool, upload_id, "ready").await;
+    notify_upload(pool, &state.ws_hub, upload_id).await;
+
+    let Replay::Events(events) = load_replay(pool, user_id, seen, &WsConfig::default())
+        .await
+        .expect("replay")
+    else {
+        panic!("expected events");
+    };
+    assert_eq!(events.len(), 2);
+    assert!(events[0].0 > seen && events[1].0 > events[0].0);
+
+    let messages: Vec<Value> = events
+        .iter()
+        .map(|(_, message)| serde_json::from_str(message).expect("json"))
+        .collect();
+    assert_eq!(messages[0]["id"], events[0].0);
+    assert_eq!(messages[0]["event"], "upload.updated");
+    assert_eq!(messages[0]["version"], 1);
+    assert_eq!(messages[0]["data"]["id"], upload_id);
+    assert_eq!(messages[0]["data"]["status"], "processing");
+    assert_eq!(messages[1]["data"]["status"], "ready");
+
+    let Replay::Events(nothing) = load_replay(pool, user_id, events[1].0, &WsConfig::default())
+        .await
+        .expect("replay")
+    else {
+        panic!("expected events");
+    };
+    assert!(nothing.is_empty());
+}
+
+#[actix_web::test]
+async fn too_many_missed_events_fall_back_to_snapshot() {
+    let test_db = support::init_test_db().await;
+    let pool = &test_db.pool;
+    let state = support::build_state(pool.clone(), "test-key").await;
+    let user_id = create_user(pool).await;
+    let first = create_upload(pool, user_id, "processing").await;
+    let second = create_upload(pool, user_id, "processing").await;
+
+    notify_upload(pool, &state.ws_hub, first).await;
+    let seen = last_event_id(pool).await;
+    for _ in 0..3 {
+        notify_upload(pool, &state.ws_hub, second).await;
+    }
+    set_status(pool, first, "ready").await;
+    notify_upload(pool, &state.ws_hub, first).await;
+
+    let config = WsConfig {
+        replay_limit: 3,
+        ..WsConfig::default()
+    };
+    let Replay::Snapshot { id, uploads } = load_replay(pool, user_id, seen, &config)
+        .await
+        .expect("replay")
+    else {
+        panic!("expected snapshot");
+    };
+    assert_eq!(id, last_event_id(pool).await);
+    let mut statuses: Vec<(i32, String)> =
+        uploads.into_iter().map(|upload| (upload.id, upload.status)).collect();
+    statuses.sort();
+    assert_eq!(
+        statuses,
+        [(first, "ready".to_string()), (second, "processing".to_string())]
+    );
+}
+
+#[actix_web::test]
+async fn pruned_log_falls_back_to_snapshot() {
+    let test_db = support::init_test_db().await;
+    let pool = &test_db.pool;
+    let state = support::build_state(pool.clone(), "test-key").await;
+    let user_id = create_user(pool).await;
+    let upload_id = create_upload(pool, user_id, "queued").await;
+
+    notify_upload(pool, &state.ws_hub, upload_id).await;
+    let seen = last_event_id(pool).await;
+    notify_upload(pool, &state.ws_hub, upload_id).await;
+    notify_upload(pool, &state.ws_hub, upload_id).await;
+
+    sqlx::query("UPDATE ws_events SET created_at = NOW() - INTERVAL '2 days' WHERE id <= $1 + 1")
+        .bind(seen)
+        .execute(pool)
+        .await
+        .expect("age events");
+    assert_eq!(
+        prune_ws_events(pool, Duration::from_secs(24 * 3600))
+            .await
+            .expect("prune"),
+        2
+    );
+
+    let replay = load_replay(pool, user_id, seen, &WsConfig::default())
+        .await
+        .expect("replay");
+    assert!(matches!(replay, Replay::Snapshot { .. }), "{replay:?}");
+}
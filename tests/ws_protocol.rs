@@ -0,0 +1,119 @@
+use serde_json::{Value, json};
+
+use sora_watermark_remov::ws_protocol::{
+    ClientMessage, ErrorCode, Subscriptions, Topic, parse_client_message, parse_topics, reply,
+};
+
+fn upload_event(upload_id: i32) -> String {
+    json!({
+        "id": 10,
+        "event": "upload.updated",
+        "version": 1,
+        "data": { "id": upload_id, "status": "processing" }
+    })
+    .to_string()
+}
+
+fn account_event(event: &str) -> String {
+    json!({ "id": 11, "event": event, "version": 1, "data": { "credits": 3 } }).to_string()
+}
+
+#[test]
+fn parses_auth_and_keeps_request_id() {
+    let request = parse_client_message(
+        r#"{"type":"auth","request_id":"a-1","token":"jwt","last_event_id":42}"#,
+    )
+    .expect("auth");
+    assert_eq!(request.request_id, Some(json!("a-1")));
+    assert_eq!(
+        request.message,
+        ClientMessage::Auth {
+            token: "jwt".into(),
+            last_event_id: Some(42),
+        }
+    );
+
+    let request = parse_client_message(r#"{"type":"snapshot"}"#).expect("snapshot");
+    assert_eq!(request.request_id, None);
+    assert_eq!(request.message, ClientMessage::Snapshot { limit: None });
+
+    let reply: Value =
+        serde_json::from_str(&reply(&Some(json!(7)), json!({ "pong": true }))).expect("reply json");
+    assert_eq!(
+        reply,
+        json!({ "type": "reply", "request_id": 7, "data": { "pong": true } })
+    );
+}
+
+#[test]
+fn invalid_messages_return_errors_with_request_id() {
+    let error = parse_client_message("not json").expect_err("invalid json");
+    assert_eq!(error.code, ErrorCode::InvalidMessage);
+    assert_eq!(error.request_id, None);
+
+    let error = parse_client_message(r#"{"type":"reboot","request_id":3}"#).expect_err("unknown");
+    assert_eq!(error.code, ErrorCode::UnknownType);
+    let body: Value = serde_json::from_str(&error.to_json()).expect("error json");
+    assert_eq!(body["type"], "error");
+    assert_eq!(body["request_id"], 3);
+    assert_eq!(body["error"]["code"], "unknown_type");
+
+    let error =
+        parse_client_message(r#"{"type":"subscribe","request_id":4}"#).expect_err("missing topics");
+    assert_eq!(error.code, ErrorCode::InvalidMessage);
+    assert_eq!(error.request_id, Some(json!(4)));
+
+    let error = parse_client_message(r#"[1, 2]"#).expect_err("not an object");
+    assert_eq!(error.code, ErrorCode::InvalidMessage);
+}
+
+#[test]
+fn parses_topics() {
+    let topics = parse_topics(
+        &None,
+        &["uploads".into(), "upload:7".into(), "account".into()],
+    )
+    .expect("topics");
+    assert_eq!(topics, [Topic::Uploads, Topic::Upload(7), Topic::Account]);
+    assert_eq!(Topic::Upload(7).to_string(), "upload:7");
+
+    let error = parse_topics(&Some(json!(1)), &["upload:abc".into()]).expect_err("bad topic");
+    assert_eq!(error.code, ErrorCode::InvalidTopic);
+    assert_eq!(error.request_id, Some(json!(1)));
+
+    let error = parse_topics(&None, &[]).expect_err("empty");
+    assert_eq!(error.code, ErrorCode::InvalidTopic);
+}
+
+#[test]
+fn subscriptions_filter_events() {
+    let mut subscriptions = Subscriptions::default();
+    // Без подписок приходит всё
+    assert_eq!(subscriptions.topics(), None);
+    assert!(subscriptions.matches(&upload_event(1)));
+    assert!(subscriptions.matches(&account_event("credits.updated")));
+
+    subscriptions.subscribe(vec![Topic::Upload(7)]);
+    assert!(subscriptions.matches(&upload_event(7)));
+    assert!(!subscriptions.matches(&upload_event(8)));
+    assert!(!subscriptions.matches(&account_event("payment.succeeded")));
+    assert!(
+        subscriptions.matches(
+            &json!({ "id": 12, "event": "snapshot", "version": 1, "data": { "uploads": [] } })
+                .to_string()
+        )
+    );
+
+    subscriptions.subscribe(vec![Topic::Account, Topic::Uploads]);
+    assert!(subscriptions.matches(&upload_event(8)));
+    assert!(subscriptions.matches(&account_event("subscription.canceled")));
+    assert_eq!(
+        subscriptions.topics(),
+        Some(vec!["uploads".into(), "upload:7".into(), "account".into()])
+    );
+
+    subscriptions.unsubscribe(&[Topic::Uploads, Topic::Account]);
+    assert!(!subscriptions.matches(&upload_event(8)));
+    assert!(!subscriptions.matches(&account_event("credits.updated")));
+    assert_eq!(subscriptions.topics(), Some(vec!["upload:7".into()]));
+}